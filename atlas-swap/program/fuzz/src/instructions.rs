@@ -463,7 +463,12 @@ fn get_swap_curve(curve_type: CurveType) -> SwapCurve {
             CurveType::ConstantPrice => Box::new(ConstantPriceCurve {
                 token_b_price: 10_000_000,
             }),
-            CurveType::Stable => Box::new(StableCurve { amp: 100 }),
+            CurveType::Stable => Box::new(StableCurve {
+                amp: 100,
+                min_trade_amount: 0,
+                token_a_decimals: 0,
+                token_b_decimals: 0,
+            }),
             CurveType::Offset => Box::new(OffsetCurve {
                 token_b_offset: 100_000_000_000,
             }),
@@ -558,6 +558,7 @@ impl NativeTokenSwap {
                 pool_token_amount,
                 minimum_token_a_amount: 0,
                 minimum_token_b_amount: 0,
+                deadline: 0,
             };
             self.withdraw_all_token_types(
                 &mut pool_account,
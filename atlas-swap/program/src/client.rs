@@ -0,0 +1,1027 @@
+//! Off-chain helpers for quoting and routing trades, not used by the program
+
+use crate::curve::{
+    base::SwapCurve,
+    calculator::{RoundDirection, TradeDirection, BASIS_POINTS},
+    fees::{calculate_fee, DynamicFee, Fees},
+};
+use crate::error::SwapError;
+use crate::pda::find_global_state_address;
+use crate::processor::Processor;
+use crate::state::{GlobalState, SwapVersion};
+use crate::token_2022;
+use solana_program::{
+    clock::UnixTimestamp,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+
+/// Everything needed to quote a pool without touching the chain: its curve,
+/// the fees from the global state and the current reserve balances
+#[derive(Debug, PartialEq)]
+pub struct PoolSnapshot {
+    /// Curve of the pool, as stored in the swap account
+    pub swap_curve: SwapCurve,
+    /// Fees of the pool's tier in the global state when the snapshot was
+    /// taken, nothing during a fee holiday
+    pub fees: Fees,
+    /// Dynamic stable fee in force when the snapshot was taken, which
+    /// replaces the stable return fee of `fees` on every swap
+    pub dynamic_fee: Option<DynamicFee>,
+    /// Amount of token A in the pool
+    pub token_a_amount: u64,
+    /// Amount of token B in the pool
+    pub token_b_amount: u64,
+    /// Supply of the pool token
+    pub pool_supply: u64,
+}
+
+impl PoolSnapshot {
+    /// Get the fees the program charges on a swap of `amount_in`
+    pub fn get_trade_fees(&self, amount_in: u64, trade_direction: TradeDirection) -> Option<Fees> {
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (self.token_a_amount, self.token_b_amount),
+            TradeDirection::BtoA => (self.token_b_amount, self.token_a_amount),
+        };
+        Processor::trade_fees(
+            self.fees.clone(),
+            self.dynamic_fee.as_ref(),
+            &self.swap_curve,
+            u128::from(swap_source_amount),
+            u128::from(swap_destination_amount),
+            u128::from(amount_in),
+            trade_direction,
+        )
+        .ok()
+    }
+
+    /// Get the amount of destination token received for `amount_in` of the
+    /// source token, fees included
+    pub fn get_swap_quote(&self, amount_in: u64, trade_direction: TradeDirection) -> Option<u64> {
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (self.token_a_amount, self.token_b_amount),
+            TradeDirection::BtoA => (self.token_b_amount, self.token_a_amount),
+        };
+        let result = self.swap_curve.swap(
+            u128::from(amount_in),
+            u128::from(swap_source_amount),
+            u128::from(swap_destination_amount),
+            trade_direction,
+            &self.get_trade_fees(amount_in, trade_direction)?,
+        )?;
+        u64::try_from(result.destination_amount_swapped).ok()
+    }
+
+    /// Get the part of `amount_in` a swap with `allow_partial` fills within
+    /// `max_slippage_bps`, the same amount the program transfers, so callers
+    /// learn the fill before sending the swap.  Zero when nothing fills, in
+    /// which case the swap fails.
+    pub fn get_partial_fill(
+        &self,
+        amount_in: u64,
+        trade_direction: TradeDirection,
+        max_slippage_bps: u16,
+    ) -> Option<u64> {
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (self.token_a_amount, self.token_b_amount),
+            TradeDirection::BtoA => (self.token_b_amount, self.token_a_amount),
+        };
+        // like the program, the fill keeps the fees of the requested amount
+        let filled = Processor::max_amount_in_within_slippage(
+            &self.swap_curve,
+            u128::from(amount_in),
+            u128::from(swap_source_amount),
+            u128::from(swap_destination_amount),
+            trade_direction,
+            &self.get_trade_fees(amount_in, trade_direction)?,
+            max_slippage_bps,
+        );
+        u64::try_from(filled).ok()
+    }
+
+    /// Get the amount of destination token the user actually receives when
+    /// both mints may charge a transfer fee.
+    ///
+    /// The input transfer fee is withheld before the tokens reach the pool,
+    /// so only the remainder is swapped, and the output transfer fee is then
+    /// withheld from the swapped amount.  The program does not take reserves
+    /// whose mint charges a transfer fee yet, see `token_2022`, so on-chain
+    /// pools quote the same as `get_swap_quote` under the default fees.
+    pub fn get_swap_quote_with_transfer_fee(
+        &self,
+        amount_in: u64,
+        trade_direction: TradeDirection,
+        source_transfer_fee: &TransferFee,
+        destination_transfer_fee: &TransferFee,
+    ) -> Option<u64> {
+        let amount_received = source_transfer_fee.amount_after_fee(u128::from(amount_in))?;
+        let amount_out =
+            self.get_swap_quote(u64::try_from(amount_received).ok()?, trade_direction)?;
+        let amount_out = destination_transfer_fee.amount_after_fee(u128::from(amount_out))?;
+        u64::try_from(amount_out).ok()
+    }
+
+    /// Quote every amount of `amounts_in` against the current reserves, eg.
+    /// to chart the output curve of the pool.  Each sample is independent,
+    /// and is None where `get_swap_quote` fails.
+    pub fn sample_outputs(
+        &self,
+        amounts_in: &[u64],
+        trade_direction: TradeDirection,
+    ) -> Vec<Option<u64>> {
+        amounts_in
+            .iter()
+            .map(|amount_in| self.get_swap_quote(*amount_in, trade_direction))
+            .collect()
+    }
+}
+
+/// Transfer fee charged by a mint on every transfer, laid out like the
+/// Token-2022 transfer fee extension.  Mints without the extension use the
+/// default, which charges nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransferFee {
+    /// Fee in basis points of the transferred amount
+    pub transfer_fee_basis_points: u16,
+    /// Maximum fee charged on a single transfer
+    pub maximum_fee: u64,
+}
+
+impl TransferFee {
+    /// Calculate the fee withheld when transferring `amount`, rounded up as
+    /// done by Token-2022
+    pub fn calculate_fee(&self, amount: u128) -> Option<u128> {
+        if self.transfer_fee_basis_points == 0 || amount == 0 {
+            return Some(0);
+        }
+        let fee = calculate_fee(
+            amount,
+            u128::from(self.transfer_fee_basis_points),
+            BASIS_POINTS,
+            RoundDirection::Ceiling,
+        )?;
+        Some(std::cmp::min(fee, u128::from(self.maximum_fee)))
+    }
+
+    /// Amount received once the fee on a transfer of `amount` is withheld
+    pub fn amount_after_fee(&self, amount: u128) -> Option<u128> {
+        amount.checked_sub(self.calculate_fee(amount)?)
+    }
+}
+
+/// Load a `PoolSnapshot` of the pool at `swap_key`, with the fees the program
+/// charges at `now`, the unix timestamp of the cluster's clock.
+///
+/// `fetch` resolves to the data of the account at the given address,
+/// typically through an async RPC client.  It is called for the swap account,
+/// both reserves, the pool mint and the global state, so callers that batch
+/// requests can serve them from a single `getMultipleAccounts` response.
+/// Reserves are read whether they belong to spl_token or Token-2022.
+pub async fn get_pool_snapshot<F, Fut>(
+    program_id: &Pubkey,
+    swap_key: &Pubkey,
+    now: UnixTimestamp,
+    mut fetch: F,
+) -> Result<PoolSnapshot, ProgramError>
+where
+    F: FnMut(Pubkey) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, ProgramError>>,
+{
+    let token_swap = SwapVersion::unpack(&fetch(*swap_key).await?)?;
+    let token_a = token_2022::unpack_account(&fetch(*token_swap.token_a_account()).await?)?;
+    let token_b = token_2022::unpack_account(&fetch(*token_swap.token_b_account()).await?)?;
+    let pool_mint = spl_token::state::Mint::unpack(&fetch(*token_swap.pool_mint()).await?)?;
+    let (global_state_key, _) = find_global_state_address(program_id);
+    let global_state = GlobalState::try_from_account_data(&fetch(global_state_key).await?)?;
+    // SwapCurve is only Clone in tests, copy it through its packed form
+    let mut packed_curve = [0u8; SwapCurve::LEN];
+    token_swap.swap_curve().pack_into_slice(&mut packed_curve);
+    Ok(PoolSnapshot {
+        swap_curve: SwapCurve::unpack_from_slice(&packed_curve)?,
+        fees: global_state
+            .fees_at(now, token_swap.fee_tier_index())
+            .ok_or(SwapError::InvalidFee)?,
+        dynamic_fee: global_state.dynamic_fee_at(now),
+        token_a_amount: token_a.amount,
+        token_b_amount: token_b.amount,
+        pool_supply: pool_mint.supply,
+    })
+}
+
+/// Split `amount_in` between two pools of the same pair so that the total
+/// output is maximized, ie. the total price impact is minimized.
+///
+/// The output of a swap is concave in its input on every supported curve, so
+/// the total output is concave in the split and a ternary search over the
+/// amount sent to `pool_a` finds the optimum.  The quotes round to whole
+/// tokens, which flattens the output around the optimum, so the split found
+/// may yield a few tokens less than the best one.  Returns the amounts to
+/// send to `pool_a` and `pool_b` respectively.
+pub fn optimal_split(
+    amount_in: u64,
+    pool_a: &PoolSnapshot,
+    pool_b: &PoolSnapshot,
+    trade_direction: TradeDirection,
+) -> (u64, u64) {
+    // a pool that cannot quote an amount, eg. zero, contributes nothing
+    let total_output = |to_pool_a: u64| {
+        let output_a = pool_a.get_swap_quote(to_pool_a, trade_direction).unwrap_or(0);
+        let output_b = pool_b
+            .get_swap_quote(amount_in - to_pool_a, trade_direction)
+            .unwrap_or(0);
+        u128::from(output_a) + u128::from(output_b)
+    };
+
+    let mut low = 0u64;
+    let mut high = amount_in;
+    while high - low > 2 {
+        let third = (high - low) / 3;
+        let mid_low = low + third;
+        let mid_high = high - third;
+        if total_output(mid_low) < total_output(mid_high) {
+            low = mid_low + 1;
+        } else {
+            high = mid_high;
+        }
+    }
+    let to_pool_a = (low..=high)
+        .max_by_key(|to_pool_a| total_output(*to_pool_a))
+        .unwrap_or(low);
+    (to_pool_a, amount_in - to_pool_a)
+}
+
+/// Update an exponential moving average of the pool price with a new spot
+/// price observed `elapsed` seconds after the previous update.
+///
+/// The weight of the previous average halves every `half_life` seconds, so
+/// an average left alone for one half life moves halfway to the spot price.
+/// Prices are fixed-point values in whatever scale the caller uses, they only
+/// need to share it.  Integrators building an oracle on top of the pool call this on
+/// every observation, a `half_life` of zero tracks the spot price exactly.
+/// There is no average before the first observation, which seeds it by
+/// passing its spot price as `prev_ema`.
+pub fn compute_ema(prev_ema: u128, spot_price: u128, elapsed: u64, half_life: u64) -> u128 {
+    if half_life == 0 {
+        return spot_price;
+    }
+    let prev_weight = 0.5f64.powf(elapsed as f64 / half_life as f64);
+    if prev_ema >= spot_price {
+        spot_price + ((prev_ema - spot_price) as f64 * prev_weight) as u128
+    } else {
+        spot_price - ((spot_price - prev_ema) as f64 * prev_weight) as u128
+    }
+}
+
+/// Get the prices of token A in token B at which the impermanent loss of a
+/// liquidity position equals the fees it has earned, below and above the
+/// entry price.
+///
+/// Assumes a constant product pool whose reserves were `entry_reserve_a` and
+/// `entry_reserve_b` when the position, a `lp_share` fraction of the pool, was
+/// opened, and measures both the loss and `fees_earned` in token B.  Between
+/// the two prices the fees outweigh the loss.  Returns `(lower, upper)`, in
+/// token B per token A, the lower price being zero when the fees cover any
+/// drop.
+pub fn breakeven_price(
+    entry_reserve_a: f64,
+    entry_reserve_b: f64,
+    fees_earned: f64,
+    lp_share: f64,
+) -> (f64, f64) {
+    // the loss against holding is lp_share * (sqrt(p) * sqrt(a) - sqrt(b))^2
+    // at price p, so it equals the fees where sqrt(p) is the entry sqrt price
+    // plus or minus sqrt(fees / (lp_share * a))
+    let entry_sqrt_price = (entry_reserve_b / entry_reserve_a).sqrt();
+    let offset = (fees_earned / (lp_share * entry_reserve_a)).sqrt();
+    let lower = (entry_sqrt_price - offset).max(0.0);
+    let upper = entry_sqrt_price + offset;
+    (lower * lower, upper * upper)
+}
+
+/// Get the value of one pool token, given the value of one unit of each
+/// token, eg. dollar prices per base unit from an external feed.
+///
+/// This is the value of the reserves divided by the pool token supply, so it
+/// trusts the reserves rather than the curve's own price.  A pool with no
+/// supply is worth zero per pool token.
+pub fn lp_price(
+    swap_token_a_amount: u64,
+    swap_token_b_amount: u64,
+    pool_supply: u64,
+    price_a: f64,
+    price_b: f64,
+) -> f64 {
+    if pool_supply == 0 {
+        return 0.0;
+    }
+    let pool_value = swap_token_a_amount as f64 * price_a + swap_token_b_amount as f64 * price_b;
+    pool_value / pool_supply as f64
+}
+
+/// Get how far a completed swap executed below the spot price before the
+/// trade, in basis points, fees included.
+///
+/// `spot_price_before` is the destination tokens per source token scaled by
+/// `10^precision`, as returned by `SwapCurve::spot_price`.  A swap that
+/// received at least the spot quote reports zero.  Returns None if the
+/// spot quote overflows or is zero.
+pub fn realized_slippage_bps(
+    amount_in: u64,
+    destination_amount: u64,
+    spot_price_before: u128,
+    precision: u32,
+) -> Option<u128> {
+    let spot_quote = u128::from(amount_in)
+        .checked_mul(spot_price_before)?
+        .checked_div(10u128.checked_pow(precision)?)?;
+    if spot_quote == 0 {
+        return None;
+    }
+    let shortfall = spot_quote.saturating_sub(u128::from(destination_amount));
+    shortfall.checked_mul(BASIS_POINTS)?.checked_div(spot_quote)
+}
+
+/// One swap of a route found by `PoolGraph::best_route`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteHop {
+    /// Swap account of the pool traded against
+    pub swap: Pubkey,
+    /// Direction of the trade within the pool
+    pub trade_direction: TradeDirection,
+    /// Amount received from this hop, the input of the next one
+    pub amount_out: u64,
+}
+
+/// Pools of a known set indexed by mint, to route trades between mints that
+/// share no pool.  Quotes come from each pool's `PoolSnapshot`, so the graph
+/// is only as fresh as the snapshots it was built from.
+#[derive(Debug, Default)]
+pub struct PoolGraph {
+    pools: Vec<(Pubkey, Pubkey, Pubkey, PoolSnapshot)>,
+    pools_by_mint: HashMap<Pubkey, Vec<usize>>,
+}
+
+impl PoolGraph {
+    /// Create an empty graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the pool at `swap` trading `mint_a` against `mint_b`
+    pub fn add_pool(
+        &mut self,
+        swap: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        snapshot: PoolSnapshot,
+    ) {
+        let index = self.pools.len();
+        self.pools.push((swap, mint_a, mint_b, snapshot));
+        self.pools_by_mint.entry(mint_a).or_default().push(index);
+        self.pools_by_mint.entry(mint_b).or_default().push(index);
+    }
+
+    /// Find the route of at most `max_hops` swaps from `from` to `to` that
+    /// yields the most of `to` for `amount_in`.  Routes never visit a mint
+    /// twice.  Returns None if no route exists or every route fails to quote.
+    pub fn best_route(
+        &self,
+        from: &Pubkey,
+        to: &Pubkey,
+        amount_in: u64,
+        max_hops: usize,
+    ) -> Option<Vec<RouteHop>> {
+        let mut best = None;
+        let mut route = vec![];
+        let mut visited = vec![*from];
+        self.search(from, to, amount_in, max_hops, &mut visited, &mut route, &mut best);
+        best
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        mint: &Pubkey,
+        to: &Pubkey,
+        amount_in: u64,
+        hops_left: usize,
+        visited: &mut Vec<Pubkey>,
+        route: &mut Vec<RouteHop>,
+        best: &mut Option<Vec<RouteHop>>,
+    ) {
+        if hops_left == 0 {
+            return;
+        }
+        for index in self.pools_by_mint.get(mint).into_iter().flatten() {
+            let (swap, mint_a, mint_b, snapshot) = &self.pools[*index];
+            let (trade_direction, next_mint) = if mint == mint_a {
+                (TradeDirection::AtoB, mint_b)
+            } else {
+                (TradeDirection::BtoA, mint_a)
+            };
+            if visited.contains(next_mint) {
+                continue;
+            }
+            let amount_out = match snapshot.get_swap_quote(amount_in, trade_direction) {
+                Some(amount_out) => amount_out,
+                None => continue,
+            };
+            route.push(RouteHop {
+                swap: *swap,
+                trade_direction,
+                amount_out,
+            });
+            if next_mint == to {
+                let improves = best
+                    .as_ref()
+                    .and_then(|best: &Vec<RouteHop>| best.last())
+                    .map_or(true, |last| amount_out > last.amount_out);
+                if improves {
+                    *best = Some(route.clone());
+                }
+            } else {
+                visited.push(*next_mint);
+                self.search(next_mint, to, amount_out, hops_left - 1, visited, route, best);
+                visited.pop();
+            }
+            route.pop();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{
+        base::CurveType, constant_product::ConstantProductCurve, stable::StableCurve,
+    };
+    use crate::state::SwapV1;
+    use spl_token::state::{Account, AccountState, Mint};
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    const TEST_FEES: Fees = Fees {
+        constant_product_return_fee_numerator: 25,
+        constant_product_fixed_fee_numerator: 5,
+        stable_return_fee_numerator: 4,
+        stable_fixed_fee_numerator: 1,
+        fee_denominator: 10_000,
+    };
+
+    fn constant_product(
+        token_a_amount: u64,
+        token_b_amount: u64,
+        pool_supply: u64,
+    ) -> PoolSnapshot {
+        PoolSnapshot {
+            swap_curve: SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(ConstantProductCurve {}),
+            },
+            fees: TEST_FEES,
+            dynamic_fee: None,
+            token_a_amount,
+            token_b_amount,
+            pool_supply,
+        }
+    }
+
+    /// Polls a future once, enough for fetches served from memory
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn noop_raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                noop_raw_waker()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut future = Box::pin(future);
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("fetch did not resolve"),
+        }
+    }
+
+    const TIER_FEES: Fees = Fees {
+        constant_product_return_fee_numerator: 50,
+        constant_product_fixed_fee_numerator: 10,
+        stable_return_fee_numerator: 8,
+        stable_fixed_fee_numerator: 2,
+        fee_denominator: 10_000,
+    };
+
+    const HOLIDAY: (UnixTimestamp, UnixTimestamp) = (1_000, 2_000);
+
+    /// Accounts of a stable pool of the fee tier 1, with its token B reserve
+    /// under Token-2022 and a dynamic stable fee from 2 to 50
+    fn stable_pool_accounts(program_id: &Pubkey, swap_key: &Pubkey) -> HashMap<Pubkey, Vec<u8>> {
+        let (token_a_key, token_b_key, pool_mint_key) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut accounts = HashMap::new();
+
+        let mut swap = vec![0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(
+            SwapVersion::SwapV1(SwapV1 {
+                is_initialized: true,
+                token_program_id: spl_token::id(),
+                token_a: token_a_key,
+                token_b: token_b_key,
+                pool_mint: pool_mint_key,
+                swap_curve: SwapCurve {
+                    curve_type: CurveType::Stable,
+                    calculator: Box::new(StableCurve {
+                        amp: 100,
+                        min_trade_amount: 0,
+                        token_a_decimals: 6,
+                        token_b_decimals: 6,
+                    }),
+                },
+                fee_tier_index: 1,
+                token_b_program_id: token_2022::id(),
+                ..SwapV1::default()
+            }),
+            &mut swap,
+        )
+        .unwrap();
+        accounts.insert(*swap_key, swap);
+
+        let reserve = |amount| {
+            let mut data = vec![0u8; Account::LEN];
+            Account {
+                amount,
+                state: AccountState::Initialized,
+                ..Account::default()
+            }
+            .pack_into_slice(&mut data);
+            data
+        };
+        accounts.insert(token_a_key, reserve(1_000_000_000));
+        // account type, then an ImmutableOwner entry without a value
+        let mut token_b = reserve(1_500_000_000);
+        token_b.extend_from_slice(&[2, 7, 0, 0, 0]);
+        accounts.insert(token_b_key, token_b);
+
+        let mut pool_mint = vec![0u8; Mint::LEN];
+        Mint {
+            supply: 2_000_000_000,
+            decimals: 9,
+            is_initialized: true,
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut pool_mint);
+        accounts.insert(pool_mint_key, pool_mint);
+
+        let mut global_state = vec![0u8; GlobalState::LEN];
+        GlobalState {
+            is_initialized: true,
+            fees: TEST_FEES,
+            fee_tiers: [TIER_FEES, Fees::default(), Fees::default()],
+            fee_holiday_start: HOLIDAY.0,
+            fee_holiday_end: HOLIDAY.1,
+            dynamic_fee_min_numerator: 2,
+            dynamic_fee_max_numerator: 50,
+            ..GlobalState::default()
+        }
+        .pack_into_slice(&mut global_state);
+        accounts.insert(find_global_state_address(program_id).0, global_state);
+        accounts
+    }
+
+    fn load_snapshot(
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+        swap_key: &Pubkey,
+        now: UnixTimestamp,
+    ) -> Result<PoolSnapshot, ProgramError> {
+        block_on(get_pool_snapshot(&crate::id(), swap_key, now, |key| {
+            std::future::ready(
+                accounts
+                    .get(&key)
+                    .cloned()
+                    .ok_or(ProgramError::NotEnoughAccountKeys),
+            )
+        }))
+    }
+
+    #[test]
+    fn pool_snapshot_from_accounts() {
+        let swap_key = Pubkey::new_unique();
+        let accounts = stable_pool_accounts(&crate::id(), &swap_key);
+        let snapshot = load_snapshot(&accounts, &swap_key, HOLIDAY.1).unwrap();
+        assert_eq!(snapshot.swap_curve.curve_type, CurveType::Stable);
+        assert_eq!(snapshot.token_a_amount, 1_000_000_000);
+        assert_eq!(snapshot.token_b_amount, 1_500_000_000);
+        assert_eq!(snapshot.pool_supply, 2_000_000_000);
+        // the pool's tier, not the global fees
+        assert_eq!(snapshot.fees, TIER_FEES);
+        assert_eq!(
+            snapshot.dynamic_fee,
+            Some(DynamicFee {
+                min_numerator: 2,
+                max_numerator: 50,
+            })
+        );
+
+        // the dynamic fee replaces the tier's stable return fee, and grows
+        // with the imbalance the trade leaves behind
+        let small = snapshot.get_trade_fees(1_000, TradeDirection::AtoB).unwrap();
+        let large = snapshot
+            .get_trade_fees(1_000_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(small.stable_return_fee_numerator >= 2);
+        assert!(small.stable_return_fee_numerator < large.stable_return_fee_numerator);
+        assert!(large.stable_return_fee_numerator <= 50);
+        assert_eq!(large.stable_fixed_fee_numerator, TIER_FEES.stable_fixed_fee_numerator);
+
+        // the quote charges the dynamic fee, more than the tier's fee here
+        let fixed_quote = snapshot
+            .swap_curve
+            .swap(
+                1_000_000_000,
+                1_000_000_000,
+                1_500_000_000,
+                TradeDirection::AtoB,
+                &TIER_FEES,
+            )
+            .unwrap()
+            .destination_amount_swapped;
+        let quote = snapshot
+            .get_swap_quote(1_000_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(u128::from(quote) < fixed_quote);
+    }
+
+    #[test]
+    fn pool_snapshot_during_fee_holiday() {
+        let swap_key = Pubkey::new_unique();
+        let accounts = stable_pool_accounts(&crate::id(), &swap_key);
+        let snapshot = load_snapshot(&accounts, &swap_key, HOLIDAY.0).unwrap();
+        assert_eq!(
+            snapshot.fees,
+            Fees {
+                fee_denominator: TIER_FEES.fee_denominator,
+                ..Fees::default()
+            }
+        );
+        assert_eq!(snapshot.dynamic_fee, None);
+        let fees = snapshot
+            .get_trade_fees(1_000_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(fees.stable_return_fee_numerator, 0);
+    }
+
+    #[test]
+    fn pool_snapshot_rejects_missing_accounts() {
+        let swap_key = Pubkey::new_unique();
+        let mut accounts = stable_pool_accounts(&crate::id(), &swap_key);
+        assert_eq!(
+            load_snapshot(&accounts, &Pubkey::new_unique(), 0),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        accounts.remove(&find_global_state_address(&crate::id()).0);
+        assert_eq!(
+            load_snapshot(&accounts, &swap_key, 0),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn transfer_fee_calculation() {
+        // zero basis points charge nothing, whatever the cap
+        let free = TransferFee {
+            transfer_fee_basis_points: 0,
+            maximum_fee: u64::MAX,
+        };
+        assert_eq!(free.calculate_fee(1_000_000), Some(0));
+        assert_eq!(TransferFee::default().amount_after_fee(1_000_000), Some(1_000_000));
+
+        // 1% of 1_001 is 10.01, rounded up like Token-2022
+        let one_percent = TransferFee {
+            transfer_fee_basis_points: 100,
+            maximum_fee: u64::MAX,
+        };
+        assert_eq!(one_percent.calculate_fee(1_000), Some(10));
+        assert_eq!(one_percent.calculate_fee(1_001), Some(11));
+        assert_eq!(one_percent.amount_after_fee(1_001), Some(990));
+
+        // the cap bounds the fee of large transfers
+        let capped = TransferFee {
+            transfer_fee_basis_points: 100,
+            maximum_fee: 5,
+        };
+        assert_eq!(capped.calculate_fee(400), Some(4));
+        assert_eq!(capped.calculate_fee(1_000_000), Some(5));
+        assert_eq!(capped.amount_after_fee(1_000_000), Some(999_995));
+    }
+
+    #[test]
+    fn swap_quote_with_transfer_fee() {
+        let pool = constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let free = TransferFee::default();
+        assert_eq!(
+            pool.get_swap_quote_with_transfer_fee(10_000, TradeDirection::AtoB, &free, &free),
+            pool.get_swap_quote(10_000, TradeDirection::AtoB)
+        );
+
+        // only what reaches the pool is swapped, and the output is charged
+        // again on its way to the user
+        let one_percent = TransferFee {
+            transfer_fee_basis_points: 100,
+            maximum_fee: u64::MAX,
+        };
+        let swapped = pool.get_swap_quote(9_900, TradeDirection::AtoB).unwrap();
+        assert_eq!(
+            pool.get_swap_quote_with_transfer_fee(
+                10_000,
+                TradeDirection::AtoB,
+                &one_percent,
+                &free,
+            ),
+            Some(swapped)
+        );
+        assert_eq!(
+            pool.get_swap_quote_with_transfer_fee(
+                10_000,
+                TradeDirection::AtoB,
+                &one_percent,
+                &one_percent
+            ),
+            u64::try_from(one_percent.amount_after_fee(u128::from(swapped)).unwrap()).ok()
+        );
+    }
+
+    #[test]
+    fn best_route_through_pool_graph() {
+        let (usdc, sol, msol) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (direct, usdc_sol, msol_sol) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut graph = PoolGraph::new();
+        // a shallow direct pool, and two deep pools through sol, the second
+        // one listing msol as its token A
+        graph.add_pool(direct, usdc, msol, constant_product(100_000, 100_000, 1));
+        graph.add_pool(usdc_sol, usdc, sol, constant_product(1_000_000_000, 1_000_000_000, 1));
+        graph.add_pool(msol_sol, msol, sol, constant_product(1_000_000_000, 1_000_000_000, 1));
+        let amount_in = 1_000_000;
+
+        // a single hop can only take the direct pool
+        let direct_out = constant_product(100_000, 100_000, 1)
+            .get_swap_quote(amount_in, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(
+            graph.best_route(&usdc, &msol, amount_in, 1),
+            Some(vec![RouteHop {
+                swap: direct,
+                trade_direction: TradeDirection::AtoB,
+                amount_out: direct_out,
+            }])
+        );
+
+        // with two hops the deep pools beat the direct one
+        let sol_out = constant_product(1_000_000_000, 1_000_000_000, 1)
+            .get_swap_quote(amount_in, TradeDirection::AtoB)
+            .unwrap();
+        let msol_out = constant_product(1_000_000_000, 1_000_000_000, 1)
+            .get_swap_quote(sol_out, TradeDirection::BtoA)
+            .unwrap();
+        assert!(msol_out > direct_out);
+        let two_hops = vec![
+            RouteHop {
+                swap: usdc_sol,
+                trade_direction: TradeDirection::AtoB,
+                amount_out: sol_out,
+            },
+            RouteHop {
+                swap: msol_sol,
+                trade_direction: TradeDirection::BtoA,
+                amount_out: msol_out,
+            },
+        ];
+        assert_eq!(graph.best_route(&usdc, &msol, amount_in, 2), Some(two_hops.clone()));
+
+        // the three pools form a cycle, which routes never go around: more
+        // hops find nothing better and no route leads back to the source
+        assert_eq!(graph.best_route(&usdc, &msol, amount_in, 3), Some(two_hops));
+        assert_eq!(graph.best_route(&usdc, &usdc, amount_in, 3), None);
+    }
+
+    #[test]
+    fn best_route_between_disconnected_mints() {
+        let (usdc, sol, btc, eth) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut graph = PoolGraph::new();
+        graph.add_pool(Pubkey::new_unique(), usdc, sol, constant_product(1_000_000, 1_000_000, 1));
+        graph.add_pool(Pubkey::new_unique(), btc, eth, constant_product(1_000_000, 1_000_000, 1));
+        assert_eq!(graph.best_route(&usdc, &btc, 1_000, 4), None);
+        assert_eq!(graph.best_route(&usdc, &Pubkey::new_unique(), 1_000, 4), None);
+        assert!(graph.best_route(&usdc, &sol, 1_000, 4).is_some());
+        assert_eq!(graph.best_route(&usdc, &sol, 1_000, 0), None);
+    }
+
+    /// Total output of sending `split` to the two pools
+    fn split_output(
+        split: (u64, u64),
+        pool_a: &PoolSnapshot,
+        pool_b: &PoolSnapshot,
+    ) -> u64 {
+        pool_a.get_swap_quote(split.0, TradeDirection::AtoB).unwrap_or(0)
+            + pool_b.get_swap_quote(split.1, TradeDirection::AtoB).unwrap_or(0)
+    }
+
+    #[test]
+    fn optimal_split_between_equal_pools() {
+        let pool = constant_product(10_000_000, 10_000_000, 1);
+        let (to_a, to_b) = optimal_split(1_000_000, &pool, &pool, TradeDirection::AtoB);
+        assert_eq!(to_a + to_b, 1_000_000);
+        // rounded quotes are flat around the even split, which the search
+        // lands close to, within a token of its output
+        assert!(to_a > 499_000 && to_a < 501_000);
+        assert!(
+            split_output((to_a, to_b), &pool, &pool) + 1
+                >= split_output((500_000, 500_000), &pool, &pool)
+        );
+    }
+
+    #[test]
+    fn optimal_split_favors_the_deeper_pool() {
+        let shallow = constant_product(1_000_000, 1_000_000, 1);
+        let deep = constant_product(4_000_000, 4_000_000, 1);
+        let split = optimal_split(1_000_000, &shallow, &deep, TradeDirection::AtoB);
+        assert_eq!(split.0 + split.1, 1_000_000);
+        assert!(split.1 > split.0);
+        let best = split_output(split, &shallow, &deep);
+        for to_shallow in (0..=1_000_000).step_by(10_000) {
+            let output = split_output((to_shallow, 1_000_000 - to_shallow), &shallow, &deep);
+            assert!(best + 5 >= output);
+        }
+    }
+
+    #[test]
+    fn optimal_split_skips_an_empty_pool() {
+        let pool = constant_product(1_000_000, 1_000_000, 1);
+        let empty = constant_product(0, 0, 0);
+        assert_eq!(optimal_split(10_000, &pool, &empty, TradeDirection::AtoB), (10_000, 0));
+        assert_eq!(optimal_split(10_000, &empty, &pool, TradeDirection::AtoB), (0, 10_000));
+    }
+
+    #[test]
+    fn optimal_split_larger_than_both_pools() {
+        let pool_a = constant_product(1_000_000, 1_000_000, 1);
+        let pool_b = constant_product(2_000_000, 2_000_000, 1);
+        let amount_in = 100_000_000;
+        let split = optimal_split(amount_in, &pool_a, &pool_b, TradeDirection::AtoB);
+        assert_eq!(split.0 + split.1, amount_in);
+        // neither pool can be drained, and splitting still beats either pool
+        let output = split_output(split, &pool_a, &pool_b);
+        assert!(output < 3_000_000);
+        assert!(output >= split_output((amount_in, 0), &pool_a, &pool_b));
+        assert!(output >= split_output((0, amount_in), &pool_a, &pool_b));
+    }
+
+    #[test]
+    fn ema_first_sample_and_constant_input() {
+        // the first observation seeds the average with its own price
+        assert_eq!(compute_ema(1_000, 1_000, 0, 60), 1_000);
+        assert_eq!(compute_ema(1_000, 1_000, 3_600, 60), 1_000);
+
+        // a constant price keeps the average in place, and draws any other
+        // average towards it without overshooting
+        let mut ema = 2_000;
+        for _ in 0..10 {
+            let next = compute_ema(ema, 1_000, 60, 60);
+            assert!(next < ema && next >= 1_000);
+            ema = next;
+        }
+        assert!(ema - 1_000 <= 2);
+    }
+
+    #[test]
+    fn ema_weight_bounds() {
+        // no time elapsed keeps the previous average, alpha 0
+        assert_eq!(compute_ema(2_000, 1_000, 0, 60), 2_000);
+        // a zero half life or a very long gap takes the spot price, alpha 1
+        assert_eq!(compute_ema(2_000, 1_000, 60, 0), 1_000);
+        assert_eq!(compute_ema(2_000, 1_000, u64::MAX, 60), 1_000);
+        // one half life moves halfway, from either side
+        assert_eq!(compute_ema(2_000, 1_000, 60, 60), 1_500);
+        assert_eq!(compute_ema(1_000, 2_000, 60, 60), 1_500);
+    }
+
+    #[test]
+    fn sample_outputs_of_constant_product() {
+        let pool = constant_product(1_000_000, 2_000_000, 1);
+        let amounts_in: Vec<u64> = (1..=10).map(|step| step * 50_000).collect();
+        let outputs = pool
+            .sample_outputs(&amounts_in, TradeDirection::AtoB)
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .unwrap();
+        for (amount_in, output) in amounts_in.iter().zip(&outputs) {
+            assert_eq!(pool.get_swap_quote(*amount_in, TradeDirection::AtoB), Some(*output));
+        }
+        // outputs grow with the input, by less for every equal step
+        for window in outputs.windows(3) {
+            assert!(window[0] < window[1] && window[1] < window[2]);
+            assert!(window[2] - window[1] < window[1] - window[0]);
+        }
+        assert!(outputs[9] < 2_000_000);
+    }
+
+    #[test]
+    fn sample_outputs_of_zero_input() {
+        let pool = constant_product(1_000_000, 2_000_000, 1);
+        let samples = pool.sample_outputs(&[0, 1_000], TradeDirection::AtoB);
+        // a zero input has no quote, the program refuses such a swap, and
+        // leaves the other samples alone
+        assert_eq!(samples[0], None);
+        assert!(samples[1].unwrap() > 0);
+        assert!(pool.sample_outputs(&[], TradeDirection::AtoB).is_empty());
+    }
+
+    /// Loss of a constant product position against holding its entry
+    /// amounts, in token B at `price`
+    fn impermanent_loss(
+        entry_reserve_a: f64,
+        entry_reserve_b: f64,
+        lp_share: f64,
+        price: f64,
+    ) -> f64 {
+        let held = entry_reserve_a * price + entry_reserve_b;
+        let pooled = 2.0 * (entry_reserve_a * entry_reserve_b * price).sqrt();
+        lp_share * (held - pooled)
+    }
+
+    #[test]
+    fn breakeven_price_matches_the_loss() {
+        let (reserve_a, reserve_b, lp_share, fees) = (1_000.0, 4_000.0, 0.1, 2.5);
+        let (lower, upper) = breakeven_price(reserve_a, reserve_b, fees, lp_share);
+        // entry price 4, offset sqrt(2.5 / 100) = 0.158...
+        assert!(lower < 4.0 && upper > 4.0);
+        for price in [lower, upper].iter() {
+            let loss = impermanent_loss(reserve_a, reserve_b, lp_share, *price);
+            assert!((loss - fees).abs() < 1e-9, "{} {}", price, loss);
+        }
+        // between the two the fees outweigh the loss, outside they do not
+        assert!(impermanent_loss(reserve_a, reserve_b, lp_share, 4.2) < fees);
+        assert!(impermanent_loss(reserve_a, reserve_b, lp_share, upper * 1.01) > fees);
+        assert!(impermanent_loss(reserve_a, reserve_b, lp_share, lower * 0.99) > fees);
+    }
+
+    #[test]
+    fn breakeven_price_bounds() {
+        // no fees break even at the entry price only
+        let (lower, upper) = breakeven_price(1_000.0, 4_000.0, 0.0, 0.1);
+        assert!((lower - 4.0).abs() < 1e-12 && (upper - 4.0).abs() < 1e-12);
+        // fees beyond the loss of a total drop leave no lower bound
+        let (lower, upper) = breakeven_price(1_000.0, 4_000.0, 1_000.0, 0.1);
+        assert_eq!(lower, 0.0);
+        assert!(upper > 4.0);
+    }
+
+    #[test]
+    fn lp_price_of_a_known_pool() {
+        // 1_000 A at 2.0 and 4_000 B at 0.5 are worth 4_000, over 500 tokens
+        assert_eq!(lp_price(1_000, 4_000, 500, 2.0, 0.5), 8.0);
+        // the whole supply is worth the whole pool
+        let price = lp_price(123_456, 654_321, 777, 1.5, 0.25);
+        let pool_value = 123_456.0 * 1.5 + 654_321.0 * 0.25;
+        assert!((price * 777.0 - pool_value).abs() < 1e-6);
+    }
+
+    #[test]
+    fn lp_price_of_zero_supply() {
+        assert_eq!(lp_price(1_000, 4_000, 0, 2.0, 0.5), 0.0);
+        assert_eq!(lp_price(0, 0, 0, 2.0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn realized_slippage_against_the_spot_quote() {
+        // a spot price of 2.0 at 6 decimals quotes 2_000 for 1_000
+        let spot_price = 2_000_000;
+        assert_eq!(realized_slippage_bps(1_000, 2_000, spot_price, 6), Some(0));
+        // worse than the quote, 5% and 0.05%
+        assert_eq!(realized_slippage_bps(1_000, 1_900, spot_price, 6), Some(500));
+        assert_eq!(realized_slippage_bps(1_000_000, 1_999_000, spot_price, 6), Some(5));
+        // better than the quote reports no slippage
+        assert_eq!(realized_slippage_bps(1_000, 2_100, spot_price, 6), Some(0));
+        // nothing received is a total loss
+        assert_eq!(realized_slippage_bps(1_000, 0, spot_price, 6), Some(10_000));
+    }
+
+    #[test]
+    fn realized_slippage_without_a_spot_quote() {
+        assert_eq!(realized_slippage_bps(1_000, 2_000, 0, 6), None);
+        assert_eq!(realized_slippage_bps(0, 0, 2_000_000, 6), None);
+        assert_eq!(realized_slippage_bps(1, 0, 1, 6), None);
+        assert_eq!(realized_slippage_bps(u64::MAX, 0, u128::MAX, 6), None);
+        assert_eq!(realized_slippage_bps(1_000, 2_000, 2, 40), None);
+    }
+}
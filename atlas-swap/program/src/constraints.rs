@@ -8,7 +8,7 @@ use crate::{
     error::SwapError,
 };
 
-use solana_program::program_error::ProgramError;
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
 
 const MINIMUM_FEES: &Fees = &Fees {
     constant_product_return_fee_numerator: 0,
@@ -17,7 +17,11 @@ const MINIMUM_FEES: &Fees = &Fees {
     stable_fixed_fee_numerator: 0,
     fee_denominator: 10000,
 };
-const VALID_CURVE_TYPES: &[CurveType] = &[CurveType::Stable, CurveType::ConstantProduct];
+const VALID_CURVE_TYPES: &[CurveType] = &[
+    CurveType::Stable,
+    CurveType::ConstantProduct,
+    CurveType::Weighted,
+];
 
 
 /// Encodes fee constraints, used in multihost environments where the program
@@ -36,11 +40,7 @@ pub struct SwapConstraints<'a> {
 impl<'a> SwapConstraints<'a> {
     /// Checks that the provided curve is valid for the given constraints
     pub fn validate_curve(&self, swap_curve: &SwapCurve) -> Result<(), ProgramError> {
-        if self
-            .valid_curve_types
-            .iter()
-            .any(|x| *x == swap_curve.curve_type)
-        {
+        if self.valid_curve_types.contains(&swap_curve.curve_type) {
             Ok(())
         } else {
             Err(SwapError::UnsupportedCurveType.into())
@@ -51,30 +51,72 @@ impl<'a> SwapConstraints<'a> {
     pub fn validate_fees(&self, fees: &Fees) -> Result<(), ProgramError> {
         // msg!("{}, {}, {}, {}",fees.constant_product_return_fee_numerator,fees.constant_product_fixed_fee_numerator, fees.stable_return_fee_numerator, fees.stable_fixed_fee_numerator);
         // msg!("{}, {}, {}, {}",self.fees.constant_product_return_fee_numerator,self.fees.constant_product_fixed_fee_numerator, self.fees.stable_return_fee_numerator, self.fees.stable_fixed_fee_numerator);
-        if fees.constant_product_return_fee_numerator >= self.fees.constant_product_return_fee_numerator
-            && fees.constant_product_fixed_fee_numerator >= self.fees.constant_product_fixed_fee_numerator
-            && fees.stable_return_fee_numerator >= self.fees.stable_return_fee_numerator
-            && fees.stable_fixed_fee_numerator >= self.fees.stable_fixed_fee_numerator
-            && fees.fee_denominator == self.fees.fee_denominator
-        {
-            Ok(())
-        } else {
-            Err(SwapError::InvalidFee.into())
+        // every pool prices its fees against this denominator, so it can never
+        // differ from the constraint, whatever the numerators are
+        if fees.fee_denominator != self.fees.fee_denominator {
+            msg!("fee denominator must be {}", self.fees.fee_denominator);
+            return Err(SwapError::InvalidFee.into());
+        }
+        let numerators = [
+            (
+                "constant product return fee",
+                fees.constant_product_return_fee_numerator,
+                self.fees.constant_product_return_fee_numerator,
+            ),
+            (
+                "constant product fixed fee",
+                fees.constant_product_fixed_fee_numerator,
+                self.fees.constant_product_fixed_fee_numerator,
+            ),
+            (
+                "stable return fee",
+                fees.stable_return_fee_numerator,
+                self.fees.stable_return_fee_numerator,
+            ),
+            (
+                "stable fixed fee",
+                fees.stable_fixed_fee_numerator,
+                self.fees.stable_fixed_fee_numerator,
+            ),
+        ];
+        for (name, numerator, minimum) in numerators.iter() {
+            if numerator < minimum {
+                msg!("{} numerator is below the minimum of {}", name, minimum);
+                return Err(SwapError::InvalidFee.into());
+            }
         }
+        Ok(())
     }
 }
 
-/// swap tag for seeds
+/// swap tag for seeds.  The misspelling is part of the deployed global
+/// state address and must not be corrected, see `GLOBAL_STATE_SEED`.
 pub const SWAP_TAG:&str = "atals-swap";
 
-/// rent sysvar program id
-pub const RENT_SYSVAR_ID:&str = "SysvarRent111111111111111111111111111111111";
-
-/// system program id
-pub const SYSTEM_PROGRAM_ID:&str = "11111111111111111111111111111111";
+/// Seed of the global state account, which lives at the program address of
+/// `[b"atals-swap", program_id]`.  Clients must use these exact (misspelled)
+/// bytes to derive the same address, any other seed is rejected.
+pub const GLOBAL_STATE_SEED: &[u8] = SWAP_TAG.as_bytes();
 
 /// initial program owner address
-pub const INITIAL_PROGRAM_OWNER: &str = "ArNRr3C8DATnfbGFTQyPe38cBgXwkfon1CHSxgesb1rG";
+pub mod initial_program_owner {
+    solana_program::declare_id!("ArNRr3C8DATnfbGFTQyPe38cBgXwkfon1CHSxgesb1rG");
+}
+
+/// initial program owner address, the only signer accepted when the global
+/// state is first created
+pub const INITIAL_PROGRAM_OWNER: Pubkey = initial_program_owner::ID;
+
+/// initial fee owner address
+pub mod initial_fee_owner {
+    solana_program::declare_id!("ArNRr3C8DATnfbGFTQyPe38cBgXwkfon1CHSxgesb1rG");
+}
+
+/// initial fee owner address, set when the global state is first created
+pub const INITIAL_FEE_OWNER_KEY: Pubkey = initial_fee_owner::ID;
+
+/// initial fees, set when the global state is first created
+pub const INITIAL_FEES: &Fees = MINIMUM_FEES;
 
 /// swap contraints
 pub const SWAP_CONSTRAINTS:SwapConstraints = SwapConstraints {
@@ -83,4 +125,39 @@ pub const SWAP_CONSTRAINTS:SwapConstraints = SwapConstraints {
 };
 
 /// minimum lp supply
-pub const MIN_LP_SUPPLY:u128 = 100000;
\ No newline at end of file
+pub const MIN_LP_SUPPLY:u128 = 100000;
+
+/// number of fee tiers a pool can pick at creation besides the global fees
+pub const FEE_TIER_COUNT: usize = 3;
+
+/// maximum difference, in basis points, between the ratio of a deposit's
+/// maxima and the ratio of the pool reserves
+pub const DEPOSIT_RATIO_TOLERANCE_BPS: u128 = 100;
+
+/// maximum number of pools seeded by a single batch deposit, keeps the
+/// account list and compute usage within a single transaction
+pub const MAX_BATCH_DEPOSITS: usize = 4;
+
+/// seed tag of the pool metadata account, derived together with the swap key
+pub const POOL_METADATA_TAG:&str = "pool-metadata";
+
+/// seed tag of a liquidity lock account, derived together with the swap key
+/// and the owner of the locked pool tokens
+pub const LIQUIDITY_LOCK_TAG:&str = "liquidity-lock";
+
+/// seed tag of the escrow pool token account of a liquidity lock, derived
+/// together with the swap key and the owner of the locked pool tokens
+pub const LIQUIDITY_ESCROW_TAG:&str = "liquidity-escrow";
+
+/// seed tag of the fee snapshot account, derived together with the swap key
+pub const FEE_SNAPSHOT_TAG:&str = "fee-snapshot";
+
+/// number of epochs a fee snapshot account keeps, the oldest entry is
+/// overwritten once it is full
+pub const FEE_SNAPSHOT_CAPACITY: usize = 32;
+
+/// maximum length in bytes of a pool's display name
+pub const MAX_POOL_NAME_LEN: usize = 32;
+
+/// maximum length in bytes of a pool's display symbol
+pub const MAX_POOL_SYMBOL_LEN: usize = 10;
\ No newline at end of file
@@ -6,15 +6,20 @@ use solana_program::{
 };
 
 use crate::curve::{
-    calculator::{CurveCalculator, SwapWithoutFeesResult, TradeDirection},
+    calculator::{
+        CurveCalculator, RoundDirection, SwapWithoutFeesResult, TradeDirection, BASIS_POINTS,
+    },
     constant_price::ConstantPriceCurve,
     constant_product::ConstantProductCurve,
     fees::Fees,
     offset::OffsetCurve,
     stable::StableCurve,
+    weighted::WeightedCurve,
 };
 use crate::error::SwapError;
+use crate::state::{SwapV1, SwapVersion};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use spl_math::precise_number::PreciseNumber;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Debug;
 
@@ -34,6 +39,9 @@ pub enum CurveType {
     Stable,
     /// Offset curve, like Uniswap, but the token B side has a faked offset
     Offset,
+    /// Balancer-style weighted product, like Uniswap, but each token holds
+    /// its own share of the pool's value
+    Weighted,
 }
 
 /// Encodes all results of swapping from a source token to a destination token
@@ -105,6 +113,71 @@ impl SwapCurve {
         })
     }
 
+    /// Get the realized price of a trade net of fees, as the fraction
+    /// `(destination amount, source amount)` of output per unit of input.
+    ///
+    /// The source amount is what the swap actually consumes, fees included,
+    /// which may be less than `source_amount` when the curve rounds.
+    pub fn effective_price(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<(u128, u128)> {
+        let result = self.swap(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )?;
+        if result.source_amount_swapped == 0 {
+            return None;
+        }
+        Some((result.destination_amount_swapped, result.source_amount_swapped))
+    }
+
+    /// Get the spot price of the source token in destination tokens, scaled
+    /// by `10^precision` and rounded down, eg. a price of 1.5 is 1_500_000 at
+    /// precision 6.
+    ///
+    /// The curves compute prices with 12 decimals, so digits beyond that are
+    /// always zero at higher precisions.  Returns None if `10^precision` or
+    /// the scaled price does not fit in a u128, which for precision 18 means
+    /// a price above roughly 3.4 * 10^20.
+    pub fn spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        precision: u32,
+    ) -> Option<u128> {
+        let scale = PreciseNumber::new(10u128.checked_pow(precision)?)?;
+        self.calculator
+            .spot_price(swap_source_amount, swap_destination_amount, trade_direction)?
+            .checked_mul(&scale)?
+            .floor()?
+            .to_imprecise()
+    }
+
+    /// Get the total value locked in the pool in token A terms, valuing the
+    /// token B reserve at the curve's spot price, rounded down.
+    ///
+    /// This is a spot valuation for display only: the spot price moves with
+    /// any trade, so a single large swap in the same block can inflate or
+    /// deflate it at will.  Never use it to price deposits or collateral.
+    pub fn tvl_in_a(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        let token_b_value = self
+            .calculator
+            .spot_price(swap_token_b_amount, swap_token_a_amount, TradeDirection::BtoA)?
+            .checked_mul(&PreciseNumber::new(swap_token_b_amount)?)?
+            .floor()?
+            .to_imprecise()?;
+        swap_token_a_amount.checked_add(token_b_value)
+    }
+
     /// Get the amount of pool tokens for the deposited amount of token A or B
     pub fn deposit_single_token_type(
         &self,
@@ -133,6 +206,84 @@ impl SwapCurve {
         )
     }
 
+    /// Get the spread, in basis points, between selling and buying token A
+    /// for `probe_amount` of it, fees included.
+    ///
+    /// `probe_amount` of token A is sold for token B, and the token B received
+    /// is then quoted back into token A against the same reserves.  The share
+    /// of the probe lost on the way is the spread a market maker would see
+    /// quoting both sides, so it grows as the pool gets shallower.
+    pub fn directional_spread_bps(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        probe_amount: u128,
+        fees: &Fees,
+    ) -> Option<u128> {
+        let sold = self.swap(
+            probe_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            TradeDirection::AtoB,
+            fees,
+        )?;
+        let bought = self.swap(
+            sold.destination_amount_swapped,
+            swap_token_b_amount,
+            swap_token_a_amount,
+            TradeDirection::BtoA,
+            fees,
+        )?;
+        probe_amount
+            .saturating_sub(bought.destination_amount_swapped)
+            .checked_mul(BASIS_POINTS)?
+            .checked_div(probe_amount)
+    }
+
+    /// Get the price impact, in basis points, of depositing `source_amount` of
+    /// token A or B on one side only.
+    ///
+    /// A single-sided deposit effectively swaps half of the input for the
+    /// other token, so it receives fewer pool tokens than a deposit of the same
+    /// value made without moving the price.  The zero-impact reference assumes
+    /// both sides of the pool are worth the same at the spot price, ie. the
+    /// deposit is worth `source_amount / (2 * swap_source_amount)` of the pool.
+    /// This is exact for the constant product curve and an approximation for
+    /// the others.  Deposits that receive more than the reference report zero.
+    pub fn deposit_price_impact_bps(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        // Use a notional supply sized so that a zero-impact deposit mints
+        // exactly `source_amount * BASIS_POINTS` pool tokens, which keeps the
+        // rounding of the real calculation well below one basis point
+        let pool_supply = swap_source_amount
+            .checked_mul(2)?
+            .checked_mul(BASIS_POINTS)?;
+        let reference_pool_tokens = source_amount.checked_mul(BASIS_POINTS)?;
+        let pool_tokens = self.calculator.deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )?;
+        reference_pool_tokens
+            .saturating_sub(pool_tokens)
+            .checked_mul(BASIS_POINTS)?
+            .checked_div(reference_pool_tokens)
+    }
+
     /// Get the amount of pool tokens for the withdrawn amount of token A or B
     pub fn withdraw_single_token_type_exact_out(
         &self,
@@ -160,6 +311,71 @@ impl SwapCurve {
             trade_direction,
         )
     }
+
+    /// Get the amounts of token A and B a withdrawal of `pool_tokens` pays
+    /// out, as the processor computes them: rounded down and never exceeding
+    /// the reserves.  A withdrawal taking the pool below its `min_lp_supply`
+    /// floor is refused with `None`.
+    pub fn simulate_withdraw(
+        &self,
+        pool_tokens: u128,
+        pool_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        min_lp_supply: u128,
+    ) -> Option<(u128, u128)> {
+        if pool_tokens > pool_supply.checked_sub(min_lp_supply)? {
+            return None;
+        }
+        let results = self.calculator.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Floor,
+        )?;
+        Some((
+            std::cmp::min(results.token_a_amount, swap_token_a_amount),
+            std::cmp::min(results.token_b_amount, swap_token_b_amount),
+        ))
+    }
+
+    /// Get the amounts of token A and B a deposit must bring in to mint
+    /// exactly `pool_tokens`, the inverse of the deposit calculation.  The
+    /// amounts are rounded up, as the processor prices deposits, so a deposit
+    /// of the returned amounts always mints the requested pool tokens.
+    pub fn amounts_for_pool_tokens(
+        &self,
+        pool_tokens: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+    ) -> Option<(u128, u128)> {
+        let results = self.calculator.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Ceiling,
+        )?;
+        Some((results.token_a_amount, results.token_b_amount))
+    }
+
+    /// Read the curve type of a swap account without unpacking the curve
+    pub fn peek_type(account_data: &[u8]) -> Option<CurveType> {
+        SwapVersion::unpack_curve_type(account_data).ok()
+    }
+
+    /// Read the raw calculator parameters of a swap account, the bytes
+    /// following the curve type, without allocating a calculator.  Their
+    /// layout depends on the curve type, see each calculator's `Pack`.
+    pub fn peek_params(account_data: &[u8]) -> Option<&[u8]> {
+        if *account_data.first()? != 1 {
+            return None;
+        }
+        let start = 1 + SwapV1::CURVE_TYPE_OFFSET + 1;
+        account_data.get(start..start + Self::LEN - 1)
+    }
 }
 
 /// Default implementation for SwapCurve cannot be derived because of
@@ -227,6 +443,7 @@ impl Pack for SwapCurve {
                 }
                 CurveType::Stable => Box::new(StableCurve::unpack_from_slice(calculator)?),
                 CurveType::Offset => Box::new(OffsetCurve::unpack_from_slice(calculator)?),
+                CurveType::Weighted => Box::new(WeightedCurve::unpack_from_slice(calculator)?),
             },
         })
     }
@@ -257,7 +474,294 @@ impl TryFrom<u8> for CurveType {
             1 => Ok(CurveType::ConstantPrice),
             2 => Ok(CurveType::Stable),
             3 => Ok(CurveType::Offset),
+            4 => Ok(CurveType::Weighted),
             _ => Err(ProgramError::InvalidAccountData),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Test helpers for swap curves
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Any curve type with arbitrary, not necessarily valid, parameters
+    pub fn any_swap_curve() -> impl Strategy<Value = SwapCurve> {
+        (0u8..5, any::<u64>(), any::<u64>()).prop_map(|(curve_type, first, second)| {
+            let curve_type = CurveType::try_from(curve_type).unwrap();
+            let calculator: Box<dyn CurveCalculator> = match curve_type {
+                CurveType::ConstantProduct => Box::new(ConstantProductCurve {}),
+                CurveType::ConstantPrice => Box::new(ConstantPriceCurve {
+                    token_b_price: first,
+                }),
+                CurveType::Stable => Box::new(StableCurve {
+                    amp: first,
+                    min_trade_amount: second,
+                    token_a_decimals: first as u8,
+                    token_b_decimals: second as u8,
+                }),
+                CurveType::Offset => Box::new(OffsetCurve {
+                    token_b_offset: first,
+                }),
+                CurveType::Weighted => Box::new(WeightedCurve {
+                    weight_a: first,
+                    weight_b: second,
+                }),
+            };
+            SwapCurve {
+                curve_type,
+                calculator,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test::any_swap_curve, *};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn pack_swap_curve(swap_curve in any_swap_curve()) {
+            let mut packed = [0u8; SwapCurve::LEN];
+            Pack::pack_into_slice(&swap_curve, &mut packed);
+            let unpacked = SwapCurve::unpack_from_slice(&packed).unwrap();
+            prop_assert_eq!(unpacked.curve_type, swap_curve.curve_type);
+            let mut repacked = [0u8; SwapCurve::LEN];
+            Pack::pack_into_slice(&unpacked, &mut repacked);
+            prop_assert_eq!(repacked, packed);
+        }
+
+        #[test]
+        fn peek_swap_curve(swap_curve in any_swap_curve()) {
+            let mut packed = [0u8; SwapCurve::LEN];
+            Pack::pack_into_slice(&swap_curve, &mut packed);
+            let mut account_data = vec![0u8; SwapVersion::LATEST_LEN];
+            account_data[0] = 1;
+            let start = 1 + SwapV1::CURVE_TYPE_OFFSET;
+            account_data[start..start + SwapCurve::LEN].copy_from_slice(&packed);
+            prop_assert_eq!(SwapCurve::peek_type(&account_data), Some(swap_curve.curve_type));
+            prop_assert_eq!(SwapCurve::peek_params(&account_data), Some(&packed[1..]));
+        }
+    }
+
+    fn constant_product() -> SwapCurve {
+        SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        }
+    }
+
+    #[test]
+    fn deposit_price_impact_of_a_small_deposit() {
+        let curve = constant_product();
+        assert_eq!(
+            curve.deposit_price_impact_bps(0, 1_000_000, 1_000_000, TradeDirection::AtoB),
+            Some(0)
+        );
+        // a deposit this small against the reserves barely moves the price
+        let reserve = 1_000_000_000;
+        assert_eq!(
+            curve.deposit_price_impact_bps(1_000, reserve, reserve, TradeDirection::AtoB),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn deposit_price_impact_of_a_one_sided_deposit() {
+        let curve = constant_product();
+        // doubling the token A reserve mints sqrt(2) - 1 of the supply against
+        // a zero-impact reference of one half, ie. 1 - 2 * (sqrt(2) - 1)
+        assert_eq!(
+            curve.deposit_price_impact_bps(1_000_000, 1_000_000, 4_000_000, TradeDirection::AtoB),
+            Some(1_715)
+        );
+        assert_eq!(
+            curve.deposit_price_impact_bps(4_000_000, 1_000_000, 4_000_000, TradeDirection::BtoA),
+            Some(1_715)
+        );
+        let small = curve
+            .deposit_price_impact_bps(100_000, 1_000_000, 4_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(small > 0 && small < 1_715);
+    }
+
+    #[test]
+    fn effective_price_without_fees() {
+        let curve = constant_product();
+        let fees = Fees::default();
+        assert_eq!(
+            curve.effective_price(100, 100, 100, TradeDirection::AtoB, &fees),
+            Some((50, 100))
+        );
+        assert_eq!(
+            curve.effective_price(0, 100, 100, TradeDirection::AtoB, &fees),
+            None
+        );
+    }
+
+    #[test]
+    fn effective_price_net_of_fees() {
+        let curve = constant_product();
+        let fees = Fees {
+            constant_product_return_fee_numerator: 25,
+            fee_denominator: 100,
+            ..Fees::default()
+        };
+        // 25 tokens of fees leave 75 to trade, of which the curve only needs
+        // 73 for 42 tokens out, so the price is quoted against 98 and not 100
+        assert_eq!(
+            curve.effective_price(100, 100, 100, TradeDirection::AtoB, &fees),
+            Some((42, 98))
+        );
+    }
+
+    #[test]
+    fn peek_short_or_unknown_account_data() {
+        let mut account_data = vec![0u8; SwapVersion::LATEST_LEN];
+        account_data[0] = 1;
+        let start = 1 + SwapV1::CURVE_TYPE_OFFSET;
+        account_data[start] = CurveType::Stable as u8;
+
+        assert_eq!(SwapCurve::peek_type(&[]), None);
+        assert_eq!(SwapCurve::peek_params(&[]), None);
+        // stops right before the curve type
+        assert_eq!(SwapCurve::peek_type(&account_data[..start]), None);
+        // holds the curve type but only part of the parameters
+        let truncated = &account_data[..start + SwapCurve::LEN - 1];
+        assert_eq!(SwapCurve::peek_type(truncated), Some(CurveType::Stable));
+        assert_eq!(SwapCurve::peek_params(truncated), None);
+        // unknown curve type
+        account_data[start] = 5;
+        assert_eq!(SwapCurve::peek_type(&account_data), None);
+        // unknown version
+        account_data[0] = 0;
+        account_data[start] = CurveType::Stable as u8;
+        assert_eq!(SwapCurve::peek_type(&account_data), None);
+        assert_eq!(SwapCurve::peek_params(&account_data), None);
+    }
+
+    #[test]
+    fn spot_price_at_precision_6_and_18() {
+        let curve = constant_product();
+        // typical reserves, 2m and 3m tokens with 9 decimals
+        let source = 2_000_000_000_000_000;
+        let destination = 3_000_000_000_000_000;
+        assert_eq!(
+            curve.spot_price(source, destination, TradeDirection::AtoB, 6),
+            Some(1_500_000)
+        );
+        assert_eq!(
+            curve.spot_price(source, destination, TradeDirection::AtoB, 18),
+            Some(1_500_000_000_000_000_000)
+        );
+        // only the first 12 decimals carry digits
+        assert_eq!(
+            curve.spot_price(3, 1, TradeDirection::AtoB, 6),
+            Some(333_333)
+        );
+        assert_eq!(
+            curve.spot_price(3, 1, TradeDirection::AtoB, 18),
+            Some(333_333_333_333_000_000)
+        );
+    }
+
+    #[test]
+    fn spot_price_overflow() {
+        let curve = constant_product();
+        // 10^39 does not fit in a u128
+        assert_eq!(curve.spot_price(1, 1, TradeDirection::AtoB, 39), None);
+        // a price of 10^21 scaled by 10^18 does not either
+        let destination = 1_000_000_000_000_000_000_000;
+        assert_eq!(
+            curve.spot_price(1, destination, TradeDirection::AtoB, 18),
+            None
+        );
+        assert_eq!(
+            curve.spot_price(1, destination, TradeDirection::AtoB, 6),
+            Some(destination * 1_000_000)
+        );
+    }
+
+    #[test]
+    fn directional_spread_grows_as_the_pool_gets_shallower() {
+        let curve = constant_product();
+        let fees = Fees::default();
+        let probe = 1_000_000;
+        assert_eq!(
+            curve.directional_spread_bps(1_000_000_000_000, 1_000_000_000_000, probe, &fees),
+            Some(0)
+        );
+        let reserve = 1_000_000_000;
+        assert_eq!(
+            curve.directional_spread_bps(reserve, reserve, probe, &fees),
+            Some(19)
+        );
+        assert_eq!(
+            curve.directional_spread_bps(reserve, reserve, 100 * probe, &fees),
+            Some(1_666)
+        );
+        assert_eq!(
+            curve.directional_spread_bps(reserve, reserve, 0, &fees),
+            None
+        );
+    }
+
+    #[test]
+    fn directional_spread_includes_fees_on_both_legs() {
+        let curve = constant_product();
+        let fees = Fees {
+            constant_product_return_fee_numerator: 30,
+            fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        // a 0.3% fee on the way out and on the way back
+        assert_eq!(
+            curve.directional_spread_bps(1_000_000_000_000, 1_000_000_000_000, 1_000_000, &fees),
+            Some(59)
+        );
+    }
+
+    #[test]
+    fn tvl_in_a_values_b_at_the_spot_price() {
+        let curve = constant_product();
+        assert_eq!(curve.tvl_in_a(1_000, 4_000), Some(2_000));
+        // a third of token A per token B values the reserve at 999.99...
+        assert_eq!(curve.tvl_in_a(1_000, 3_000), Some(1_999));
+        assert_eq!(curve.tvl_in_a(0, 0), None);
+    }
+
+    #[test]
+    fn tvl_in_a_of_a_constant_price_pool() {
+        let curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Box::new(ConstantPriceCurve { token_b_price: 3 }),
+        };
+        assert_eq!(curve.tvl_in_a(1_000, 1_000), Some(4_000));
+        assert_eq!(curve.tvl_in_a(1_000, 0), Some(1_000));
+    }
+
+    #[test]
+    fn amounts_for_pool_tokens_round_up() {
+        let curve = constant_product();
+        assert_eq!(
+            curve.amounts_for_pool_tokens(100, 1_000, 3_000, 1_000),
+            Some((100, 300))
+        );
+        // 142.8... and 428.5... are rounded up, in favor of the pool
+        assert_eq!(
+            curve.amounts_for_pool_tokens(100, 1_000, 3_000, 700),
+            Some((143, 429))
+        );
+        // withdrawing the same pool tokens never pays out more
+        assert_eq!(
+            curve.simulate_withdraw(100, 700, 1_000, 3_000, 0),
+            Some((142, 428))
+        );
+        // nor takes the pool below its floor
+        assert_eq!(curve.simulate_withdraw(100, 700, 1_000, 3_000, 601), None);
+        assert_eq!(curve.amounts_for_pool_tokens(100, 1_000, 3_000, 0), None);
+    }
+}
@@ -16,6 +16,10 @@ pub const INITIAL_SWAP_POOL_AMOUNT: u64 = 1_000_000_000;
 /// equivalent pool tokens for the owner trading fee.
 pub const TOKENS_IN_POOL: u128 = 2;
 
+/// Number of basis points in a whole, used by helpers that report ratios
+/// such as price impact.
+pub const BASIS_POINTS: u128 = 10_000;
+
 /// Helper function for mapping to SwapError::CalculationFailure
 pub fn map_zero_to_none(x: u128) -> Option<u128> {
     if x == 0 {
@@ -96,14 +100,56 @@ pub trait CurveCalculator: Debug + DynPack {
         trade_direction: TradeDirection,
     ) -> Option<SwapWithoutFeesResult>;
 
+    /// Get the smallest amount of source token a swap accepts, so that curves
+    /// sensitive to dust can refuse it.  The default implementation has no
+    /// minimum.
+    fn min_trade_amount(&self) -> u128 {
+        0
+    }
+
+    /// Scale the source and destination reserves of a trade to the same
+    /// decimals, for curves pricing both tokens at par.  The default
+    /// implementation leaves them as they are.
+    fn normalize_reserves(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<(u128, u128)> {
+        Some((swap_source_amount, swap_destination_amount))
+    }
+
+    /// Get the decimals of the token A and B mints the curve depends on,
+    /// which initialization checks against the mints.  The default
+    /// implementation depends on none.
+    fn reserve_decimals(&self) -> Option<(u8, u8)> {
+        None
+    }
+
     /// Get the supply for a new pool
     /// The default implementation is a Balancer-style fixed initial supply
     fn new_pool_supply(&self) -> u64 {
         INITIAL_SWAP_POOL_AMOUNT
     }
 
+    /// Get the amount of pool tokens to mint to the creator of a new pool,
+    /// given the reserves it was seeded with.
+    /// The default implementation mints the fixed `initial_supply` configured
+    /// in the global state, regardless of the reserves
+    fn initial_pool_supply(
+        &self,
+        _swap_token_a_amount: u128,
+        _swap_token_b_amount: u128,
+        initial_supply: u128,
+    ) -> Option<u128> {
+        Some(initial_supply)
+    }
+
     /// Get the amount of trading tokens for the given amount of pool tokens,
     /// provided the total trading tokens and supply of pool tokens.
+    ///
+    /// Amounts that round to zero are returned as zero, so that callers can
+    /// tell them apart from `None`, which only means the math overflowed.
     fn pool_tokens_to_trading_tokens(
         &self,
         pool_tokens: u128,
@@ -113,6 +159,28 @@ pub trait CurveCalculator: Debug + DynPack {
         round_direction: RoundDirection,
     ) -> Option<TradingTokenResult>;
 
+    /// Get the smallest amounts of token A and B a deposit must bring in to
+    /// mint a single pool token, so that clients can refuse dust deposits.
+    ///
+    /// Deposits are priced by rounding the trading tokens up, so this is the
+    /// ceiling of one pool token's share of each reserve.  A share worth less
+    /// than one token is returned as zero, like the processor computes it,
+    /// and such a deposit is refused, so ask for more pool tokens instead.
+    fn min_deposit_for_nonzero_lp(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_token_supply: u128,
+    ) -> Option<TradingTokenResult> {
+        self.pool_tokens_to_trading_tokens(
+            1,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Ceiling,
+        )
+    }
+
     /// Get the amount of pool tokens for the deposited amount of token A or B.
     ///
     /// This is used for single-sided deposits.  It essentially performs a swap
@@ -150,6 +218,122 @@ pub trait CurveCalculator: Debug + DynPack {
         trade_direction: TradeDirection,
     ) -> Option<u128>;
 
+    /// Get the marginal price of the source token in destination tokens, ie.
+    /// the amount of destination token an infinitesimal trade would receive
+    /// per source token, without fees.
+    fn spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber>;
+
+    /// Get the amount of source token, without fees, that moves the spot price
+    /// of the pool down to `target_price`, expressed like `spot_price`.
+    ///
+    /// Selling the source token only lowers its price, so a target at or
+    /// above the current spot price needs no input.  The default
+    /// implementation bisects over the input, which works for any curve with
+    /// a monotonic spot price, and returns the smallest input reaching the
+    /// target.
+    fn amount_in_to_target_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        target_price: &PreciseNumber,
+    ) -> Option<u128> {
+        let reaches_target = |source_amount: u128| -> Option<bool> {
+            let result = self.swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            )?;
+            let price = self.spot_price(
+                swap_source_amount.checked_add(result.source_amount_swapped)?,
+                swap_destination_amount.checked_sub(result.destination_amount_swapped)?,
+                trade_direction,
+            )?;
+            Some(!price.greater_than(target_price))
+        };
+        let spot_price =
+            self.spot_price(swap_source_amount, swap_destination_amount, trade_direction)?;
+        if !target_price.less_than(&spot_price) {
+            return Some(0);
+        }
+        // find an input large enough, then narrow down to the smallest one
+        let mut high = std::cmp::max(swap_source_amount, 1);
+        while !reaches_target(high).unwrap_or(false) {
+            high = high.checked_mul(2)?;
+            if high > u64::MAX as u128 {
+                return None;
+            }
+        }
+        let mut low = 0;
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if reaches_target(mid).unwrap_or(false) {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        Some(high)
+    }
+
+    /// Get the value of `pool_tokens` denominated in token A only.
+    ///
+    /// The proportional share of both reserves is withdrawn on paper, and the
+    /// token B side is converted to token A at the curve's spot price, so the
+    /// result is the portfolio value of the share rather than what a real
+    /// withdrawal followed by a swap would realize.
+    fn pool_token_value_in_a(
+        &self,
+        pool_tokens: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_token_supply: u128,
+    ) -> Option<u128> {
+        let TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        } = self.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Floor,
+        )?;
+        let token_b_value = self
+            .spot_price(swap_token_b_amount, swap_token_a_amount, TradeDirection::BtoA)?
+            .checked_mul(&PreciseNumber::new(token_b_amount)?)?
+            .floor()?
+            .to_imprecise()?;
+        token_a_amount.checked_add(token_b_value)
+    }
+
+    /// Get the amount of pool tokens worth `value_a` in token A terms, the
+    /// inverse of `pool_token_value_in_a`, rounded down so the pool tokens
+    /// are never worth more than asked for.
+    fn lp_for_value_in_a(
+        &self,
+        value_a: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_token_supply: u128,
+    ) -> Option<u128> {
+        let total_value = self.pool_token_value_in_a(
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_token_supply,
+        )?;
+        value_a
+            .checked_mul(pool_token_supply)?
+            .checked_div(map_zero_to_none(total_value)?)
+    }
+
     /// Validate that the given curve has no invalid parameters
     fn validate(&self) -> Result<(), SwapError>;
     
@@ -177,6 +361,14 @@ pub trait CurveCalculator: Debug + DynPack {
         true
     }
 
+    /// Whether a single token type can be deposited by swapping part of it
+    /// for the other side, as `ZapDeposit` does.  The swap moves the price,
+    /// so curves that value pool tokens by anything other than a
+    /// proportional share of the reserves must refuse it.
+    fn allows_single_sided_deposit(&self) -> bool {
+        self.allows_deposits()
+    }
+
     /// Calculates the total normalized value of the curve given the liquidity
     /// parameters.
     ///
@@ -194,3 +386,177 @@ pub trait CurveCalculator: Debug + DynPack {
         swap_token_b_amount: u128,
     ) -> Option<PreciseNumber>;
 }
+
+/// Test helpers for implementing generic curve checks
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// Checks that a swap never lowers the normalized value of the reserves,
+    /// the invariant of the curve, so that trading can't drain the pool.
+    /// Swaps refused by the curve pass.
+    pub fn check_curve_value_from_swap(
+        curve: &dyn CurveCalculator,
+        source_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        let results = match curve.swap_without_fees(
+            source_token_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        ) {
+            Some(results) => results,
+            None => return,
+        };
+        // the processor refuses to pay out the whole destination reserve
+        if results.destination_amount_swapped >= swap_destination_amount {
+            return;
+        }
+        let new_swap_source_amount = swap_source_amount
+            .checked_add(results.source_amount_swapped)
+            .unwrap();
+        let new_swap_destination_amount = swap_destination_amount
+            .checked_sub(results.destination_amount_swapped)
+            .unwrap();
+        let value = |source_amount: u128, destination_amount: u128| {
+            let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+                TradeDirection::AtoB => (source_amount, destination_amount),
+                TradeDirection::BtoA => (destination_amount, source_amount),
+            };
+            curve
+                .normalized_value(swap_token_a_amount, swap_token_b_amount)
+                .unwrap()
+        };
+        let previous_value = value(swap_source_amount, swap_destination_amount);
+        let new_value = value(new_swap_source_amount, new_swap_destination_amount);
+        assert!(
+            new_value.greater_than_or_equal(&previous_value),
+            "value fell from {:?} to {:?}",
+            previous_value,
+            new_value
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{constant_product::ConstantProductCurve, offset::OffsetCurve};
+
+    #[test]
+    fn pool_token_value_in_a_of_a_share() {
+        let curve = ConstantProductCurve {};
+        // token B is worth a quarter of token A, so each side holds 1_000
+        assert_eq!(
+            curve.pool_token_value_in_a(100, 1_000, 4_000, 100),
+            Some(2_000)
+        );
+        assert_eq!(
+            curve.pool_token_value_in_a(10, 1_000, 4_000, 100),
+            Some(200)
+        );
+        // 333 token A and 1_333 token B, worth 333.25 token A, rounded down
+        assert_eq!(curve.pool_token_value_in_a(1, 1_000, 4_000, 3), Some(666));
+        assert_eq!(curve.pool_token_value_in_a(0, 1_000, 4_000, 100), Some(0));
+        assert_eq!(curve.pool_token_value_in_a(10, 1_000, 4_000, 0), None);
+    }
+
+    fn price(numerator: u128, denominator: u128) -> PreciseNumber {
+        PreciseNumber::new(numerator)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(denominator).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn amount_in_to_target_price_bisects_to_the_smallest_input() {
+        // without an offset the curve is a constant product one, but it keeps
+        // the default bisection instead of the closed form
+        let curve = OffsetCurve { token_b_offset: 0 };
+        let direction = TradeDirection::AtoB;
+        let (source, destination) = (1_000_000u128, 1_000_000u128);
+        let target = price(1, 4);
+        let amount = curve
+            .amount_in_to_target_price(source, destination, direction, &target)
+            .unwrap();
+        // sqrt(1_000_000 * 1_000_000 * 4) - 1_000_000
+        assert!((999_999..=1_000_001).contains(&amount), "{}", amount);
+        let reached = |amount: u128| {
+            let result = curve
+                .swap_without_fees(amount, source, destination, direction)
+                .unwrap();
+            let price = curve
+                .spot_price(
+                    source + result.source_amount_swapped,
+                    destination - result.destination_amount_swapped,
+                    direction,
+                )
+                .unwrap();
+            !price.greater_than(&target)
+        };
+        assert!(reached(amount));
+        assert!(!reached(amount - 1));
+        let closed_form = ConstantProductCurve {}
+            .amount_in_to_target_price(source, destination, direction, &target)
+            .unwrap();
+        assert!(amount <= closed_form + 1 && closed_form <= amount + 1);
+    }
+
+    #[test]
+    fn amount_in_to_target_price_at_or_above_spot() {
+        let curve = OffsetCurve { token_b_offset: 0 };
+        let direction = TradeDirection::AtoB;
+        for target in [price(1, 1), price(2, 1)].iter() {
+            assert_eq!(
+                curve.amount_in_to_target_price(1_000, 1_000, direction, target),
+                Some(0)
+            );
+        }
+        // no input is large enough to push the price down to zero
+        assert_eq!(
+            curve.amount_in_to_target_price(1_000, 1_000, direction, &price(0, 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn min_deposit_for_nonzero_lp_of_one_pool_token() {
+        let curve = ConstantProductCurve {};
+        // one pool token is worth 3.33... token A and 13.33... token B
+        let result = curve.min_deposit_for_nonzero_lp(1_000, 4_000, 300).unwrap();
+        assert_eq!(result.token_a_amount, 4);
+        assert_eq!(result.token_b_amount, 14);
+        let result = curve.min_deposit_for_nonzero_lp(1_000, 4_000, 100).unwrap();
+        assert_eq!(result.token_a_amount, 10);
+        assert_eq!(result.token_b_amount, 40);
+        // once a pool token is worth less than one token the share rounds to
+        // zero instead of charging a whole token
+        let result = curve
+            .min_deposit_for_nonzero_lp(1_000, 4_000, 10_000)
+            .unwrap();
+        assert_eq!(result.token_a_amount, 0);
+        assert_eq!(result.token_b_amount, 0);
+        assert!(curve.min_deposit_for_nonzero_lp(1_000, 4_000, 0).is_none());
+    }
+
+    #[test]
+    fn lp_for_value_in_a_inverts_pool_token_value_in_a() {
+        let curve = ConstantProductCurve {};
+        // the whole pool is worth 2_000 token A
+        assert_eq!(curve.lp_for_value_in_a(200, 1_000, 4_000, 100), Some(10));
+        assert_eq!(curve.lp_for_value_in_a(2_000, 1_000, 4_000, 100), Some(100));
+        // 9.95 pool tokens, rounded down
+        assert_eq!(curve.lp_for_value_in_a(199, 1_000, 4_000, 100), Some(9));
+        for value in [1, 7, 199, 1_234, 1_999].iter() {
+            let pool_tokens = curve.lp_for_value_in_a(*value, 1_000, 4_000, 100).unwrap();
+            let realized = curve
+                .pool_token_value_in_a(pool_tokens, 1_000, 4_000, 100)
+                .unwrap();
+            assert!(realized <= *value);
+        }
+        assert_eq!(curve.lp_for_value_in_a(200, 0, 0, 100), None);
+    }
+}
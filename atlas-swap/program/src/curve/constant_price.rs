@@ -194,7 +194,21 @@ impl CurveCalculator for ConstantPriceCurve {
         }
     }
     fn get_curve_type(&self) ->CurveType{
-        return CurveType::ConstantPrice;
+        CurveType::ConstantPrice
+    }
+
+    /// The price is fixed, `token_b_price` token A for one token B
+    fn spot_price(
+        &self,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber> {
+        let token_b_price = PreciseNumber::new(self.token_b_price as u128)?;
+        match trade_direction {
+            TradeDirection::AtoB => PreciseNumber::new(1)?.checked_div(&token_b_price),
+            TradeDirection::BtoA => Some(token_b_price),
+        }
     }
     fn validate_supply(&self, token_a_amount: u64, _token_b_amount: u64) -> Result<(), SwapError> {
         if token_a_amount == 0 {
@@ -203,6 +217,13 @@ impl CurveCalculator for ConstantPriceCurve {
         Ok(())
     }
 
+    /// Pool tokens of the constant price curve are valued against the total
+    /// value of both reserves rather than a proportional share of each, so a
+    /// swap followed by a proportional deposit would misprice them.
+    fn allows_single_sided_deposit(&self) -> bool {
+        false
+    }
+
     /// The total normalized value of the constant price curve adds the total
     /// value of the token B side to the token A side.
     ///
@@ -219,8 +240,8 @@ impl CurveCalculator for ConstantPriceCurve {
     ) -> Option<PreciseNumber> {
         let swap_token_b_value = swap_token_b_amount.checked_mul(self.token_b_price as u128)?;
         // special logic in case we're close to the limits, avoid overflowing u128
-        let value = if swap_token_b_value.saturating_sub(std::u64::MAX.into())
-            > (std::u128::MAX.saturating_sub(std::u64::MAX.into()))
+        let value = if swap_token_b_value.saturating_sub(u64::MAX.into())
+            > (u128::MAX.saturating_sub(u64::MAX.into()))
         {
             swap_token_b_value
                 .checked_div(2)?
@@ -263,4 +284,32 @@ impl DynPack for ConstantPriceCurve {
         let token_b_price = array_mut_ref![output, 0, 8];
         *token_b_price = self.token_b_price.to_le_bytes();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::test::check_curve_value_from_swap;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            token_b_price in 1..u64::MAX,
+        ) {
+            let curve = ConstantPriceCurve { token_b_price };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_curve_value_from_swap(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                );
+            }
+        }
+    }
+}
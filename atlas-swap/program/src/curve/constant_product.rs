@@ -13,7 +13,9 @@ use {
         program_error::ProgramError,
         program_pack::{IsInitialized, Pack, Sealed},
     },
-    spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber},
+    spl_math::{
+        approximations::sqrt, checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber,
+    },
 };
 
 /// ConstantProductCurve struct implementing CurveCalculator
@@ -25,6 +27,12 @@ pub struct ConstantProductCurve;
 /// This is guaranteed to work for all values such that:
 ///  - 1 <= swap_source_amount * swap_destination_amount <= u128::MAX
 ///  - 1 <= source_amount <= u64::MAX
+///
+/// The calculation does not depend on the trade direction, so swapping the
+/// same amount into a balanced pool gives the same output either way.  The new
+/// destination reserve is rounded up, and the source amount is then reduced to
+/// what that rounded reserve actually requires, so every rounding favors the
+/// pool and swapping the output back never returns more than was put in.
 pub fn swap(
     source_amount: u128,
     swap_source_amount: u128,
@@ -171,6 +179,13 @@ pub fn normalized_value(
         .sqrt()
 }
 
+/// Get the initial pool token supply of a constant product pool, the integer
+/// square root of the reserve product, as done by Uniswap V2.  This way a pool
+/// token's share reflects the value deposited rather than an arbitrary amount.
+pub fn initial_pool_supply(swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+    map_zero_to_none(sqrt(swap_token_a_amount.checked_mul(swap_token_b_amount)?)?)
+}
+
 impl CurveCalculator for ConstantProductCurve {
     /// Constant product swap ensures x * y = constant
     fn swap_without_fees(
@@ -183,6 +198,16 @@ impl CurveCalculator for ConstantProductCurve {
         swap(source_amount, swap_source_amount, swap_destination_amount)
     }
 
+    /// Mint the geometric mean of the reserves instead of a fixed supply
+    fn initial_pool_supply(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        _initial_supply: u128,
+    ) -> Option<u128> {
+        initial_pool_supply(swap_token_a_amount, swap_token_b_amount)
+    }
+
     /// The constant product implementation is a simple ratio calculation for how many
     /// trading tokens correspond to a certain number of pool tokens
     fn pool_tokens_to_trading_tokens(
@@ -247,11 +272,42 @@ impl CurveCalculator for ConstantProductCurve {
         normalized_value(swap_token_a_amount, swap_token_b_amount)
     }
 
+    /// The spot price of the constant product curve is the reserve ratio
+    fn spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber> {
+        PreciseNumber::new(swap_destination_amount)?
+            .checked_div(&PreciseNumber::new(swap_source_amount)?)
+    }
+
+    /// The price after selling `in` is x * y / (x + in)^2, so the input
+    /// reaching the target is sqrt(x * y / target) - x, rounded up
+    fn amount_in_to_target_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+        target_price: &PreciseNumber,
+    ) -> Option<u128> {
+        let source = PreciseNumber::new(swap_source_amount)?;
+        let new_source = PreciseNumber::new(swap_destination_amount)?
+            .checked_div(target_price)?
+            .checked_mul(&source)?
+            .sqrt()?;
+        if !new_source.greater_than(&source) {
+            return Some(0);
+        }
+        new_source.checked_sub(&source)?.ceiling()?.to_imprecise()
+    }
+
     fn validate(&self) -> Result<(), SwapError> {
         Ok(())
     }
     fn get_curve_type(&self) ->CurveType{
-        return CurveType::ConstantProduct;
+        CurveType::ConstantProduct
     }
 }
 
@@ -268,9 +324,11 @@ impl Pack for ConstantProductCurve {
         (self as &dyn DynPack).pack_into_slice(output);
     }
 
+    // the check is kept for a future parameter, the curve has none yet
+    #[allow(clippy::absurd_extreme_comparisons)]
     fn unpack_from_slice(input: &[u8]) -> Result<ConstantProductCurve, ProgramError> {
-        if input.len() < Self::LEN{
-            return Err(SwapError::InvalidInstruction.into());    
+        if input.len() < Self::LEN {
+            return Err(SwapError::InvalidInstruction.into());
         }
 
         Ok(Self {})
@@ -279,4 +337,107 @@ impl Pack for ConstantProductCurve {
 
 impl DynPack for ConstantProductCurve {
     fn pack_into_slice(&self, _output: &mut [u8]) {}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::test::check_curve_value_from_swap;
+    use proptest::prelude::*;
+
+    #[test]
+    fn initial_pool_supply_is_the_sqrt_of_the_reserve_product() {
+        assert_eq!(initial_pool_supply(1_000_000, 1_000_000), Some(1_000_000));
+        assert_eq!(initial_pool_supply(1_000, 4_000), Some(2_000));
+        // sqrt(200) is 14.14..., rounded down
+        assert_eq!(initial_pool_supply(10, 20), Some(14));
+        assert_eq!(initial_pool_supply(0, 1_000), None);
+        assert_eq!(initial_pool_supply(u128::MAX, 2), None);
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
+            let curve = ConstantProductCurve {};
+            check_curve_value_from_swap(
+                &curve,
+                source_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB,
+            );
+        }
+
+        #[test]
+        fn invariant_does_not_decrease_from_swap(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
+            let (swap_source_amount, swap_destination_amount) =
+                (swap_source_amount as u128, swap_destination_amount as u128);
+            if let Some(result) = swap(
+                source_token_amount as u128,
+                swap_source_amount,
+                swap_destination_amount,
+            ) {
+                let invariant = swap_source_amount * swap_destination_amount;
+                let new_invariant = (swap_source_amount + result.source_amount_swapped)
+                    * (swap_destination_amount - result.destination_amount_swapped);
+                prop_assert!(new_invariant >= invariant);
+                prop_assert!(result.source_amount_swapped <= source_token_amount as u128);
+            }
+        }
+
+        #[test]
+        fn balanced_swap_is_symmetric(
+            source_token_amount in 1..u64::MAX,
+            swap_amount in 1..u64::MAX,
+        ) {
+            let curve = ConstantProductCurve {};
+            let (source_token_amount, swap_amount) =
+                (source_token_amount as u128, swap_amount as u128);
+            let a_to_b = curve.swap_without_fees(
+                source_token_amount,
+                swap_amount,
+                swap_amount,
+                TradeDirection::AtoB,
+            );
+            let b_to_a = curve.swap_without_fees(
+                source_token_amount,
+                swap_amount,
+                swap_amount,
+                TradeDirection::BtoA,
+            );
+            prop_assert_eq!(a_to_b, b_to_a);
+        }
+
+        #[test]
+        fn swap_round_trip_does_not_create_value(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
+            let (swap_source_amount, swap_destination_amount) =
+                (swap_source_amount as u128, swap_destination_amount as u128);
+            if let Some(there) = swap(
+                source_token_amount as u128,
+                swap_source_amount,
+                swap_destination_amount,
+            ) {
+                // swap the output back against the moved reserves
+                if let Some(back) = swap(
+                    there.destination_amount_swapped,
+                    swap_destination_amount - there.destination_amount_swapped,
+                    swap_source_amount + there.source_amount_swapped,
+                ) {
+                    prop_assert!(back.destination_amount_swapped <= there.source_amount_swapped);
+                }
+            }
+        }
+    }
+}
@@ -9,9 +9,9 @@ use solana_program::{
 use crate::{
     curve::{
         base::{SwapCurve, CurveType},
+        calculator::RoundDirection,
     },
 };
-
 use std::convert::TryFrom;
 
 /// Encapsulates all fee information and calculations for swap operations
@@ -34,21 +34,25 @@ pub struct Fees {
 }
 
 /// Helper function for calculating swap fee
+///
+/// Rounding up never under-collects, and is what the pool fees use, it also
+/// charges at least one token on any trade with a nonzero fee.  Rounding down
+/// favors the trader and may return a zero fee.
 pub fn calculate_fee(
     token_amount: u128,
     fee_numerator: u128,
     fee_denominator: u128,
+    round_direction: RoundDirection,
 ) -> Option<u128> {
     if fee_numerator == 0 || token_amount == 0 {
         Some(0)
     } else {
-        let fee = token_amount
-            .checked_mul(fee_numerator)?
-            .checked_div(fee_denominator)?;
-        if fee == 0 {
-            Some(1) // minimum fee of one token
-        } else {
-            Some(fee)
+        let numerator = token_amount.checked_mul(fee_numerator)?;
+        match round_direction {
+            RoundDirection::Floor => numerator.checked_div(fee_denominator),
+            RoundDirection::Ceiling => numerator
+                .checked_add(fee_denominator.checked_sub(1)?)?
+                .checked_div(fee_denominator),
         }
     }
 }
@@ -66,43 +70,29 @@ pub fn calculate_fee(
 impl Fees {
     /// Calculate the withdraw fee in pool tokens
     pub fn return_fee(&self, trading_tokens: u128,swap_curve: &SwapCurve) -> Option<u128> {
-        let mut return_fee_numerator = 0;
-        match swap_curve.curve_type {
-            CurveType::ConstantProduct => {
-                return_fee_numerator = self.constant_product_return_fee_numerator;
-            }
-            CurveType::Stable => {
-                return_fee_numerator = self.stable_return_fee_numerator;
-            }
-            _ => {
-                return_fee_numerator = self.constant_product_return_fee_numerator;
-            }
-        }
+        let return_fee_numerator = match swap_curve.curve_type {
+            CurveType::Stable => self.stable_return_fee_numerator,
+            _ => self.constant_product_return_fee_numerator,
+        };
         calculate_fee(
             trading_tokens,
-            u128::try_from(return_fee_numerator).ok()?,
-            u128::try_from(self.fee_denominator).ok()?,
+            u128::from(return_fee_numerator),
+            u128::from(self.fee_denominator),
+            RoundDirection::Ceiling,
         )
     }
 
     /// Calculate the trading fee in trading tokens
     pub fn fixed_fee(&self, trading_tokens: u128,swap_curve: &SwapCurve) -> Option<u128> {
-        let mut fixed_fee_numerator = 0;
-        match swap_curve.curve_type {
-            CurveType::ConstantProduct => {
-                fixed_fee_numerator = self.constant_product_fixed_fee_numerator;
-            }
-            CurveType::Stable => {
-                fixed_fee_numerator = self.stable_fixed_fee_numerator;
-            }
-            _ => {
-                fixed_fee_numerator = self.constant_product_fixed_fee_numerator;
-            }
-        }
+        let fixed_fee_numerator = match swap_curve.curve_type {
+            CurveType::Stable => self.stable_fixed_fee_numerator,
+            _ => self.constant_product_fixed_fee_numerator,
+        };
         calculate_fee(
             trading_tokens,
-            u128::try_from(fixed_fee_numerator).ok()?,
-            u128::try_from(self.fee_denominator).ok()?,
+            u128::from(fixed_fee_numerator),
+            u128::from(self.fee_denominator),
+            RoundDirection::Ceiling,
         )
     }
     
@@ -130,6 +120,41 @@ impl Fees {
     }
 }
 
+/// Range of the dynamic stable return fee set in the global state, which
+/// replaces the stable return fee of the pool's tier on every swap
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DynamicFee {
+    /// return fee numerator of a swap leaving the pool balanced
+    pub min_numerator: u64,
+    /// return fee numerator of a swap leaving a single token in the pool
+    pub max_numerator: u64,
+}
+
+impl DynamicFee {
+    /// Get the stable return fee numerator of a swap leaving the reserves at
+    /// the given amounts.  It grows linearly from the minimum for a balanced
+    /// pool to the maximum for a pool holding a single token, so that
+    /// draining the scarce side costs more.
+    pub fn stable_return_fee_numerator(
+        &self,
+        new_swap_source_amount: u128,
+        new_swap_destination_amount: u128,
+    ) -> Option<u64> {
+        let total = new_swap_source_amount.checked_add(new_swap_destination_amount)?;
+        let imbalance = if new_swap_source_amount > new_swap_destination_amount {
+            new_swap_source_amount - new_swap_destination_amount
+        } else {
+            new_swap_destination_amount - new_swap_source_amount
+        };
+        let range = self.max_numerator.checked_sub(self.min_numerator)?;
+        let increase = u128::from(range)
+            .checked_mul(imbalance)?
+            .checked_div(total)?;
+        self.min_numerator
+            .checked_add(u64::try_from(increase).ok()?)
+    }
+}
+
 /// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
 impl IsInitialized for Fees {
     fn is_initialized(&self) -> bool {
@@ -156,8 +181,8 @@ impl Pack for Fees {
     }
 
     fn unpack_from_slice(input: &[u8]) -> Result<Fees, ProgramError> {
-        if input.len() < Self::LEN{
-            return Err(SwapError::InvalidInstruction.into());    
+        if input.len() != Self::LEN {
+            return Err(SwapError::InvalidFee.into());
         }
         let input = array_ref![input, 0, 40];
         #[allow(clippy::ptr_offset_with_cast)]
@@ -176,4 +201,80 @@ impl Pack for Fees {
             fee_denominator: u64::from_le_bytes(*fee_denominator),
         })
     }
-}
\ No newline at end of file
+}
+
+/// Test helpers for fees
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Fees with arbitrary, not necessarily valid, numerators
+    pub fn any_fees() -> impl Strategy<Value = Fees> {
+        any::<[u64; 5]>().prop_map(|numerators| Fees {
+            constant_product_return_fee_numerator: numerators[0],
+            constant_product_fixed_fee_numerator: numerators[1],
+            stable_return_fee_numerator: numerators[2],
+            stable_fixed_fee_numerator: numerators[3],
+            fee_denominator: numerators[4],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test::any_fees, *};
+    use proptest::prelude::*;
+
+    #[test]
+    fn calculate_fee_rounding() {
+        // 25 / 10_000 of 399 tokens is 0.9975, of 400 tokens exactly 1
+        let fee = |amount, round_direction| calculate_fee(amount, 25, 10_000, round_direction);
+        assert_eq!(fee(1, RoundDirection::Floor), Some(0));
+        assert_eq!(fee(1, RoundDirection::Ceiling), Some(1));
+        assert_eq!(fee(399, RoundDirection::Floor), Some(0));
+        assert_eq!(fee(399, RoundDirection::Ceiling), Some(1));
+        assert_eq!(fee(400, RoundDirection::Floor), Some(1));
+        assert_eq!(fee(400, RoundDirection::Ceiling), Some(1));
+        assert_eq!(fee(401, RoundDirection::Floor), Some(1));
+        assert_eq!(fee(401, RoundDirection::Ceiling), Some(2));
+
+        // nothing is charged without a fee or an amount, whatever the rounding
+        for round_direction in [RoundDirection::Floor, RoundDirection::Ceiling].iter() {
+            assert_eq!(fee(0, *round_direction), Some(0));
+            assert_eq!(calculate_fee(1_000, 0, 10_000, *round_direction), Some(0));
+        }
+    }
+
+    #[test]
+    fn unpack_fees_len() {
+        let fees = Fees {
+            constant_product_return_fee_numerator: 25,
+            constant_product_fixed_fee_numerator: 5,
+            stable_return_fee_numerator: 4,
+            stable_fixed_fee_numerator: 1,
+            fee_denominator: 10_000,
+        };
+        let mut packed = [0u8; Fees::LEN + 1];
+        Pack::pack_into_slice(&fees, &mut packed[..Fees::LEN]);
+        assert_eq!(Fees::unpack_from_slice(&packed[..Fees::LEN]).unwrap(), fees);
+        assert_eq!(
+            Fees::unpack_from_slice(&packed[..Fees::LEN - 1]),
+            Err(SwapError::InvalidFee.into())
+        );
+        assert_eq!(
+            Fees::unpack_from_slice(&packed),
+            Err(SwapError::InvalidFee.into())
+        );
+        assert_eq!(Fees::unpack_from_slice(&[]), Err(SwapError::InvalidFee.into()));
+    }
+
+    proptest! {
+        #[test]
+        fn pack_fees(fees in any_fees()) {
+            let mut packed = [0u8; Fees::LEN];
+            Pack::pack_into_slice(&fees, &mut packed);
+            prop_assert_eq!(Fees::unpack_from_slice(&packed).unwrap(), fees);
+        }
+    }
+}
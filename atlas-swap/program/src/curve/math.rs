@@ -0,0 +1,160 @@
+//! Shared math helpers for the curves and routing
+
+use {spl_math::uint::U256, std::convert::TryFrom};
+
+/// Number of decimals amounts are normalized to, so that amounts of tokens
+/// with different decimals can be compared directly
+pub const NORMALIZED_DECIMALS: u8 = 18;
+
+/// Scale `amount` of a token with `decimals` to NORMALIZED_DECIMALS.  Tokens
+/// with more decimals than that lose their extra precision, rounded down.
+pub fn normalize_amount(amount: u64, decimals: u8) -> u128 {
+    let amount = u128::from(amount);
+    if decimals <= NORMALIZED_DECIMALS {
+        // a u64 times 10^18 always fits in a u128
+        amount * 10u128.pow(u32::from(NORMALIZED_DECIMALS - decimals))
+    } else {
+        10u128
+            .checked_pow(u32::from(decimals - NORMALIZED_DECIMALS))
+            .map_or(0, |scale| amount / scale)
+    }
+}
+
+/// Scale a normalized `amount` back to a token with `decimals`, rounding
+/// down.  Returns None if the result does not fit in a u64.
+pub fn denormalize_amount(amount: u128, decimals: u8) -> Option<u64> {
+    let amount = if decimals <= NORMALIZED_DECIMALS {
+        amount.checked_div(10u128.pow(u32::from(NORMALIZED_DECIMALS - decimals)))?
+    } else {
+        amount.checked_mul(10u128.checked_pow(u32::from(decimals - NORMALIZED_DECIMALS))?)?
+    };
+    u64::try_from(amount).ok()
+}
+
+/// Iterate `step` from `initial` until two consecutive values differ by at
+/// most `tolerance`, for at most `max_iterations` steps so that the loop fits
+/// in the compute budget.  Returns None if a step fails or the values have not
+/// converged within the cap, which callers map to `CalculationFailure`.
+pub fn newton_solve<F>(initial: U256, max_iterations: u8, tolerance: U256, step: F) -> Option<U256>
+where
+    F: Fn(&U256) -> Option<U256>,
+{
+    let mut value = initial;
+    for _ in 0..max_iterations {
+        let next = step(&value)?;
+        let difference = if next > value { next - value } else { value - next };
+        if difference <= tolerance {
+            return Some(next);
+        }
+        value = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_common_decimals() {
+        // one whole token of each is the same normalized amount
+        assert_eq!(normalize_amount(1_000_000, 6), 10u128.pow(18));
+        assert_eq!(normalize_amount(100_000_000, 8), 10u128.pow(18));
+        assert_eq!(normalize_amount(1_000_000_000, 9), 10u128.pow(18));
+        assert_eq!(normalize_amount(7, 18), 7);
+        // beyond 18 decimals the extra precision is dropped
+        assert_eq!(normalize_amount(1_999, 21), 1);
+    }
+
+    #[test]
+    fn denormalize_rounds_down() {
+        let one_and_a_bit = 10u128.pow(18) + 9_999_999_999;
+        assert_eq!(denormalize_amount(one_and_a_bit, 6), Some(1_000_000));
+        assert_eq!(denormalize_amount(one_and_a_bit, 8), Some(100_000_000));
+        assert_eq!(denormalize_amount(one_and_a_bit, 9), Some(1_000_000_009));
+        assert_eq!(denormalize_amount(10u128.pow(12) - 1, 6), Some(0));
+    }
+
+    #[test]
+    fn normalize_round_trip() {
+        for decimals in [0u8, 6, 8, 9, 18].iter() {
+            for amount in [0u64, 1, 123_456_789, u64::MAX].iter() {
+                assert_eq!(
+                    denormalize_amount(normalize_amount(*amount, *decimals), *decimals),
+                    Some(*amount)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn normalize_overflow() {
+        // the largest amount at the fewest decimals still fits in a u128
+        assert_eq!(normalize_amount(u64::MAX, 0), u128::from(u64::MAX) * 10u128.pow(18));
+        // a scale past u128 leaves nothing of the amount
+        assert_eq!(normalize_amount(u64::MAX, u8::MAX), 0);
+        // results past a u64, or scales past a u128, are refused
+        assert_eq!(denormalize_amount(u128::from(u64::MAX) + 1, 18), None);
+        assert_eq!(denormalize_amount(u128::MAX, 6), None);
+        assert_eq!(denormalize_amount(1, 19), Some(10));
+        assert_eq!(denormalize_amount(1, u8::MAX), None);
+    }
+
+    /// Newton step of the integer square root of `n`
+    fn sqrt_step(n: u128) -> impl Fn(&U256) -> Option<U256> {
+        move |x| Some((*x + U256::from(n) / *x) / 2)
+    }
+
+    #[test]
+    fn newton_solve_converges() {
+        let n = 10u128.pow(12);
+        assert_eq!(
+            newton_solve(U256::from(n), 64, U256::one(), sqrt_step(n)),
+            Some(U256::from(1_000_000))
+        );
+        // a wider tolerance stops earlier, within it of the root
+        let rough = newton_solve(U256::from(n), 64, U256::from(1_000), sqrt_step(n)).unwrap();
+        assert!(rough >= U256::from(1_000_000) && rough <= U256::from(1_001_000));
+        // a fixed point converges on the first step
+        assert_eq!(
+            newton_solve(U256::from(7), 1, U256::zero(), |x| Some(*x)),
+            Some(U256::from(7))
+        );
+    }
+
+    #[test]
+    fn newton_solve_stops_at_the_cap() {
+        let n = 10u128.pow(12);
+        let steps = std::cell::Cell::new(0u8);
+        let counted = |x: &U256| {
+            steps.set(steps.get() + 1);
+            sqrt_step(n)(x)
+        };
+        let converged = newton_solve(U256::from(n), 64, U256::one(), counted);
+        assert!(converged.is_some());
+        let needed = steps.get();
+
+        // one step short of what convergence takes fails, after exactly the
+        // cap of steps
+        steps.set(0);
+        assert_eq!(newton_solve(U256::from(n), needed - 1, U256::one(), counted), None);
+        assert_eq!(steps.get(), needed - 1);
+        assert_eq!(newton_solve(U256::from(n), needed, U256::one(), counted), converged);
+        assert_eq!(newton_solve(U256::from(n), 0, U256::one(), counted), None);
+    }
+
+    #[test]
+    fn newton_solve_reports_non_convergence() {
+        // a step bouncing between 0 and 100 never settles
+        let oscillating = |x: &U256| Some(U256::from(100) - *x);
+        assert_eq!(newton_solve(U256::zero(), u8::MAX, U256::from(99), oscillating), None);
+        // a failed step fails the solve right away
+        let steps = std::cell::Cell::new(0u8);
+        let failing = |_: &U256| {
+            steps.set(steps.get() + 1);
+            None
+        };
+        assert_eq!(newton_solve(U256::one(), u8::MAX, U256::one(), failing), None);
+        assert_eq!(steps.get(), 1);
+    }
+}
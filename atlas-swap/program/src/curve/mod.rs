@@ -5,5 +5,7 @@ pub mod calculator;
 pub mod constant_price;
 pub mod constant_product;
 pub mod fees;
+pub mod math;
 pub mod offset;
 pub mod stable;
+pub mod weighted;
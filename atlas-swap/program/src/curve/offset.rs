@@ -36,6 +36,7 @@ impl CurveCalculator for OffsetCurve {
     /// This is guaranteed to work for all values such that:
     ///  - 1 <= source_amount <= u64::MAX
     ///  - 1 <= (swap_source_amount * (swap_destination_amount + token_b_offset)) <= u128::MAX
+    ///
     /// If the offset and token B are both close to u64::MAX, there can be
     /// overflow errors with the invariant.
     fn swap_without_fees(
@@ -125,7 +126,7 @@ impl CurveCalculator for OffsetCurve {
         }
     }
     fn get_curve_type(&self) ->CurveType{
-        return CurveType::Offset;
+        CurveType::Offset
     }
     fn validate_supply(&self, token_a_amount: u64, _token_b_amount: u64) -> Result<(), SwapError> {
         if token_a_amount == 0 {
@@ -156,6 +157,28 @@ impl CurveCalculator for OffsetCurve {
             swap_token_b_amount.checked_add(token_b_offset)?,
         )
     }
+
+    /// The reserve ratio, with the offset added to the token B side
+    fn spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber> {
+        let token_b_offset = self.token_b_offset as u128;
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_source_amount,
+                swap_destination_amount.checked_add(token_b_offset)?,
+            ),
+            TradeDirection::BtoA => (
+                swap_source_amount.checked_add(token_b_offset)?,
+                swap_destination_amount,
+            ),
+        };
+        PreciseNumber::new(swap_destination_amount)?
+            .checked_div(&PreciseNumber::new(swap_source_amount)?)
+    }
 }
 
 /// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
@@ -189,3 +212,31 @@ impl DynPack for OffsetCurve {
         *token_b_offset = self.token_b_offset.to_le_bytes();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::test::check_curve_value_from_swap;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            token_b_offset in 1..u64::MAX,
+        ) {
+            let curve = OffsetCurve { token_b_offset };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_curve_value_from_swap(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                );
+            }
+        }
+    }
+}
@@ -2,13 +2,14 @@
 use {
     crate::{
         curve::base::CurveType,
+        curve::math::{denormalize_amount, newton_solve, normalize_amount, NORMALIZED_DECIMALS},
         curve::calculator::{
-            CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
-            TradingTokenResult,
+            map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+            TradeDirection, TradingTokenResult, BASIS_POINTS,
         },
         error::SwapError,
     },
-    arrayref::{array_mut_ref, array_ref},
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
     solana_program::{
         program_error::ProgramError,
         program_pack::{IsInitialized, Pack, Sealed},
@@ -44,6 +45,14 @@ fn checked_u8_mul(a: &U256, b: u8) -> Option<U256> {
 pub struct StableCurve {
     /// Amplifier constant
     pub amp: u64,
+    /// Smallest amount of source token accepted by a swap, zero for no
+    /// minimum
+    pub min_trade_amount: u64,
+    /// Decimals of the token A mint, reserves of different decimals are
+    /// scaled to the same decimals before they are priced at par
+    pub token_a_decimals: u8,
+    /// Decimals of the token B mint
+    pub token_b_decimals: u8,
 }
 
 /// d = (leverage * sum_x + d_product * n_coins) * initial_d / ((leverage - 1) * initial_d + (n_coins + 1) * d_product)
@@ -73,26 +82,16 @@ fn compute_d(leverage: u64, amount_a: u128, amount_b: u128) -> Option<u128> {
     if sum_x == 0 {
         Some(0)
     } else {
-        let mut d_previous: U256;
-        let mut d: U256 = sum_x.into();
-
         // Newton's method to approximate D
-        for _ in 0..ITERATIONS {
-            let mut d_product = d;
-            d_product = d_product
-                .checked_mul(d)?
-                .checked_div(amount_a_times_coins)?;
-            d_product = d_product
-                .checked_mul(d)?
+        let d = newton_solve(sum_x.into(), ITERATIONS, U256::one(), |d| {
+            let d_product = d
+                .checked_mul(*d)?
+                .checked_div(amount_a_times_coins)?
+                .checked_mul(*d)?
                 .checked_div(amount_b_times_coins)?;
-            d_previous = d;
             //d = (leverage * sum_x + d_p * n_coins) * d / ((leverage - 1) * d + (n_coins + 1) * d_p);
-            d = calculate_step(&d, leverage, sum_x, &d_product)?;
-            // Equality with the precision of 1
-            if d == d_previous {
-                break;
-            }
-        }
+            calculate_step(d, leverage, sum_x, &d_product)
+        })?;
         u128::try_from(d).ok()
     }
 }
@@ -120,17 +119,84 @@ fn compute_new_destination_amount(
     let b = new_source_amount.checked_add(d_val.checked_div(leverage)?)?;
 
     // Solve for y by approximating: y**2 + b*y = c
-    let mut y_prev: U256;
-    let mut y = d_val;
-    for _ in 0..ITERATIONS {
-        y_prev = y;
-        y = (checked_u8_power(&y, 2)?.checked_add(c)?)
-            .checked_div(checked_u8_mul(&y, 2)?.checked_add(b)?.checked_sub(d_val)?)?;
-        if y == y_prev {
-            break;
+    let y = newton_solve(d_val, ITERATIONS, U256::one(), |y| {
+        (checked_u8_power(y, 2)?.checked_add(c)?)
+            .checked_div(checked_u8_mul(y, 2)?.checked_add(b)?.checked_sub(d_val)?)
+    })?;
+    u128::try_from(y).ok()
+}
+
+impl StableCurve {
+    /// Decimals of the source and destination tokens of a trade
+    fn decimals_in_direction(&self, trade_direction: TradeDirection) -> (u8, u8) {
+        match trade_direction {
+            TradeDirection::AtoB => (self.token_a_decimals, self.token_b_decimals),
+            TradeDirection::BtoA => (self.token_b_decimals, self.token_a_decimals),
         }
     }
-    u128::try_from(y).ok()
+
+    /// 10^(NORMALIZED_DECIMALS - d) for the larger decimals d of the two
+    /// tokens, the zeros every normalized reserve of this pool ends with
+    fn shared_scale(&self) -> Option<u128> {
+        let common_decimals = self.token_a_decimals.max(self.token_b_decimals);
+        10u128.checked_pow(u32::from(NORMALIZED_DECIMALS.checked_sub(common_decimals)?))
+    }
+
+    /// Scale `amount` of the token with `decimals` to the larger decimals of
+    /// the two tokens: normalized, then stripped of the shared zeros so that
+    /// D**3 stays as far in range as for raw amounts.  Tokens of the same
+    /// decimals are priced as they are.
+    fn normalize(&self, amount: u128, decimals: u8) -> Option<u128> {
+        if self.token_a_decimals == self.token_b_decimals {
+            return Some(amount);
+        }
+        normalize_amount(u64::try_from(amount).ok()?, decimals).checked_div(self.shared_scale()?)
+    }
+
+    /// Scale an amount given by `normalize` back to the token with
+    /// `decimals`, rounding down
+    fn denormalize(&self, amount: u128, decimals: u8) -> Option<u128> {
+        if self.token_a_decimals == self.token_b_decimals {
+            return Some(amount);
+        }
+        denormalize_amount(amount.checked_mul(self.shared_scale()?)?, decimals).map(u128::from)
+    }
+
+    /// Scale both reserves with `normalize`
+    fn normalize_reserves_ab(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<(u128, u128)> {
+        Some((
+            self.normalize(swap_token_a_amount, self.token_a_decimals)?,
+            self.normalize(swap_token_b_amount, self.token_b_decimals)?,
+        ))
+    }
+
+    /// Has the reserve ratio diverged from 1:1 by more than `threshold_bps`,
+    /// for monitoring stable pools.  The divergence is the shortfall of the
+    /// smaller reserve relative to the larger one, once both are normalized
+    /// from the decimals of their mints.  An empty pool is not depegged.
+    pub fn is_depegged(
+        reserve_a: u64,
+        token_a_decimals: u8,
+        reserve_b: u64,
+        token_b_decimals: u8,
+        threshold_bps: u16,
+    ) -> bool {
+        let reserve_a = normalize_amount(reserve_a, token_a_decimals);
+        let reserve_b = normalize_amount(reserve_b, token_b_decimals);
+        let (smaller, larger) = if reserve_a < reserve_b {
+            (reserve_a, reserve_b)
+        } else {
+            (reserve_b, reserve_a)
+        };
+        // (larger - smaller) / larger > threshold_bps / BASIS_POINTS, in U256
+        // so that no reserve can overflow it
+        U256::from(larger - smaller).saturating_mul(U256::from(BASIS_POINTS))
+            > U256::from(larger).saturating_mul(U256::from(threshold_bps))
+    }
 }
 
 impl CurveCalculator for StableCurve {
@@ -140,18 +206,30 @@ impl CurveCalculator for StableCurve {
         source_amount: u128,
         swap_source_amount: u128,
         swap_destination_amount: u128,
-        _trade_direction: TradeDirection,
+        trade_direction: TradeDirection,
     ) -> Option<SwapWithoutFeesResult> {
         let leverage = self.amp.checked_mul(N_COINS as u64)?;
+        let (source_decimals, destination_decimals) = self.decimals_in_direction(trade_direction);
+        let swap_source_amount = self.normalize(swap_source_amount, source_decimals)?;
+        let swap_destination_amount =
+            self.normalize(swap_destination_amount, destination_decimals)?;
 
-        let new_source_amount = swap_source_amount.checked_add(source_amount)?;
+        let new_source_amount =
+            swap_source_amount.checked_add(self.normalize(source_amount, source_decimals)?)?;
+        // Newton's method may stop a unit below the new reserve, rounding it
+        // up keeps the invariant from falling
         let new_destination_amount = compute_new_destination_amount(
             leverage,
             new_source_amount,
             compute_d(leverage, swap_source_amount, swap_destination_amount)?,
-        )?;
+        )?
+        .checked_add(1)?;
 
-        let amount_swapped = swap_destination_amount.checked_sub(new_destination_amount)?;
+        // scaling back rounds down, in the pool's favour
+        let amount_swapped = self.denormalize(
+            swap_destination_amount.checked_sub(new_destination_amount)?,
+            destination_decimals,
+        )?;
 
         Some(SwapWithoutFeesResult {
             source_amount_swapped: source_amount,
@@ -159,6 +237,26 @@ impl CurveCalculator for StableCurve {
         })
     }
 
+    /// Stable pools carry their minimum trade size in the curve parameters
+    fn min_trade_amount(&self) -> u128 {
+        u128::from(self.min_trade_amount)
+    }
+
+    /// Mint the invariant D of the reserves, the value of the pool if both
+    /// tokens were at par, so that seeding a pool with existing liquidity
+    /// gives pool tokens in proportion to it
+    fn initial_pool_supply(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        _initial_supply: u128,
+    ) -> Option<u128> {
+        let leverage = self.amp.checked_mul(N_COINS as u64)?;
+        let (swap_token_a_amount, swap_token_b_amount) =
+            self.normalize_reserves_ab(swap_token_a_amount, swap_token_b_amount)?;
+        map_zero_to_none(compute_d(leverage, swap_token_a_amount, swap_token_b_amount)?)
+    }
+
     /// Re-implementation of `remove_liquidty`:
     ///
     /// <https://github.com/curvefi/curve-contract/blob/80bbe179083c9a7062e4c482b0be3bfb7501f2bd/contracts/pool-templates/base/SwapTemplateBase.vy#L513>
@@ -217,6 +315,10 @@ impl CurveCalculator for StableCurve {
             return Some(0);
         }
         let leverage = self.amp.checked_mul(N_COINS as u64)?;
+        let source_amount =
+            self.normalize(source_amount, self.decimals_in_direction(trade_direction).0)?;
+        let (swap_token_a_amount, swap_token_b_amount) =
+            self.normalize_reserves_ab(swap_token_a_amount, swap_token_b_amount)?;
         let d0 = PreciseNumber::new(compute_d(
             leverage,
             swap_token_a_amount,
@@ -250,6 +352,10 @@ impl CurveCalculator for StableCurve {
             return Some(0);
         }
         let leverage = self.amp.checked_mul(N_COINS as u64)?;
+        let source_amount =
+            self.normalize(source_amount, self.decimals_in_direction(trade_direction).0)?;
+        let (swap_token_a_amount, swap_token_b_amount) =
+            self.normalize_reserves_ab(swap_token_a_amount, swap_token_b_amount)?;
         let d0 = PreciseNumber::new(compute_d(
             leverage,
             swap_token_a_amount,
@@ -276,6 +382,8 @@ impl CurveCalculator for StableCurve {
         swap_token_a_amount: u128,
         swap_token_b_amount: u128,
     ) -> Option<PreciseNumber> {
+        let (swap_token_a_amount, swap_token_b_amount) =
+            self.normalize_reserves_ab(swap_token_a_amount, swap_token_b_amount)?;
         #[cfg(not(any(test, feature = "fuzz")))]
         {
             let leverage = self.amp.checked_mul(N_COINS as u64)?;
@@ -310,12 +418,82 @@ impl CurveCalculator for StableCurve {
         }
     }
 
+    /// Ratio of the partial derivatives of the invariant
+    /// `Ann * (x + y) + D = Ann * D + D**3 / (4 * x * y)`, which gives
+    /// `(Ann + D**3 / (4 * x**2 * y)) / (Ann + D**3 / (4 * x * y**2))`
+    /// for source reserve `x` and destination reserve `y`, in normalized
+    /// amounts and scaled back to the raw amounts of the two tokens
+    fn spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber> {
+        let (source_decimals, destination_decimals) = self.decimals_in_direction(trade_direction);
+        // a normalized amount is the raw amount times this scale
+        let scale = |decimals| self.normalize(1, decimals).and_then(PreciseNumber::new);
+        let raw_price = |price: PreciseNumber| {
+            price
+                .checked_mul(&scale(source_decimals)?)?
+                .checked_div(&scale(destination_decimals)?)
+        };
+        let swap_source_amount = self.normalize(swap_source_amount, source_decimals)?;
+        let swap_destination_amount =
+            self.normalize(swap_destination_amount, destination_decimals)?;
+        let leverage = self.amp.checked_mul(N_COINS as u64)?;
+        let d = PreciseNumber::new(compute_d(
+            leverage,
+            swap_source_amount,
+            swap_destination_amount,
+        )?)?;
+        let leverage = PreciseNumber::new(leverage as u128)?;
+        let x = PreciseNumber::new(swap_source_amount)?;
+        let y = PreciseNumber::new(swap_destination_amount)?;
+        let n_coins_squared = PreciseNumber::new(N_COINS_SQUARED as u128)?;
+        // D**3 / (4 * x * y), kept as a product of ratios to stay in range
+        let d_cubed_over_4xy = d
+            .checked_div(&x)?
+            .checked_mul(&d.checked_div(&y)?)?
+            .checked_mul(&d.checked_div(&n_coins_squared)?)?;
+        let numerator = leverage.checked_add(&d_cubed_over_4xy.checked_div(&x)?)?;
+        let denominator = leverage.checked_add(&d_cubed_over_4xy.checked_div(&y)?)?;
+        raw_price(numerator.checked_div(&denominator)?)
+    }
+
+    fn normalize_reserves(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<(u128, u128)> {
+        let (source_decimals, destination_decimals) = self.decimals_in_direction(trade_direction);
+        Some((
+            self.normalize(swap_source_amount, source_decimals)?,
+            self.normalize(swap_destination_amount, destination_decimals)?,
+        ))
+    }
+
+    /// Stable pools that scale their reserves record the decimals of both
+    /// mints, zero for both prices the raw amounts at par
+    fn reserve_decimals(&self) -> Option<(u8, u8)> {
+        if self.token_a_decimals == 0 && self.token_b_decimals == 0 {
+            None
+        } else {
+            Some((self.token_a_decimals, self.token_b_decimals))
+        }
+    }
+
     fn validate(&self) -> Result<(), SwapError> {
         // TODO are all amps valid?
+        if self.token_a_decimals > NORMALIZED_DECIMALS
+            || self.token_b_decimals > NORMALIZED_DECIMALS
+        {
+            return Err(SwapError::InvalidCurve);
+        }
         Ok(())
     }
     fn get_curve_type(&self) ->CurveType{
-        return CurveType::Stable;
+        CurveType::Stable
     }
 }
 
@@ -327,7 +505,7 @@ impl IsInitialized for StableCurve {
 }
 impl Sealed for StableCurve {}
 impl Pack for StableCurve {
-    const LEN: usize = 8;
+    const LEN: usize = 18;
     fn pack_into_slice(&self, output: &mut [u8]) {
         (self as &dyn DynPack).pack_into_slice(output);
     }
@@ -337,16 +515,197 @@ impl Pack for StableCurve {
             return Err(SwapError::InvalidInstruction.into());    
         }
 
-        let amp = array_ref![input, 0, 8];
+        let input = array_ref![input, 0, 18];
+        let (amp, min_trade_amount, token_a_decimals, token_b_decimals) =
+            array_refs![input, 8, 8, 1, 1];
         Ok(Self {
             amp: u64::from_le_bytes(*amp),
+            min_trade_amount: u64::from_le_bytes(*min_trade_amount),
+            token_a_decimals: token_a_decimals[0],
+            token_b_decimals: token_b_decimals[0],
         })
     }
 }
 
 impl DynPack for StableCurve {
     fn pack_into_slice(&self, output: &mut [u8]) {
-        let amp = array_mut_ref![output, 0, 8];
+        let output = array_mut_ref![output, 0, 18];
+        let (amp, min_trade_amount, token_a_decimals, token_b_decimals) =
+            mut_array_refs![output, 8, 8, 1, 1];
         *amp = self.amp.to_le_bytes();
+        *min_trade_amount = self.min_trade_amount.to_le_bytes();
+        token_a_decimals[0] = self.token_a_decimals;
+        token_b_decimals[0] = self.token_b_decimals;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Do the reserves hold an invariant of at least `d`, checked exactly:
+    /// leverage * (a + b) + d >= leverage * d + d^3 / (4 * a * b)
+    fn invariant_at_least(leverage: u64, amount_a: u128, amount_b: u128, d: u128) -> bool {
+        let (leverage, amount_a, amount_b, d) = (
+            U256::from(leverage),
+            U256::from(amount_a),
+            U256::from(amount_b),
+            U256::from(d),
+        );
+        let product = U256::from(4) * amount_a * amount_b;
+        product * (leverage * (amount_a + amount_b) + d) >= product * leverage * d + d * d * d
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn swap_normalizes_mismatched_decimals() {
+        let curve = |token_a_decimals, token_b_decimals| StableCurve {
+            amp: 100,
+            min_trade_amount: 0,
+            token_a_decimals,
+            token_b_decimals,
+        };
+        let par = curve(9, 9);
+        let mixed = curve(6, 9);
+        // 1_000 tokens on each side, a trade of one token
+        let at_par = par
+            .swap_without_fees(
+                1_000_000_000,
+                1_000_000_000_000,
+                1_000_000_000_000,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        let a_to_b = mixed
+            .swap_without_fees(1_000_000, 1_000_000_000, 1_000_000_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(a_to_b.source_amount_swapped, 1_000_000);
+        assert_eq!(a_to_b.destination_amount_swapped, at_par.destination_amount_swapped);
+        let b_to_a = mixed
+            .swap_without_fees(
+                1_000_000_000,
+                1_000_000_000_000,
+                1_000_000_000,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        // scaled back to 6 decimals, rounded down
+        assert_eq!(b_to_a.destination_amount_swapped, at_par.destination_amount_swapped / 1_000);
+
+        // the spot price is one whole token for one whole token
+        let price = mixed
+            .spot_price(1_000_000_000, 1_000_000_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(price.almost_eq(&PreciseNumber::new(1_000).unwrap(), U256::from(1_000_000_000u64)));
+        assert_eq!(
+            mixed.normalize_reserves(1_000_000_000, 1_000_000_000_000, TradeDirection::AtoB),
+            Some((1_000_000_000_000, 1_000_000_000_000))
+        );
+        assert_eq!(mixed.reserve_decimals(), Some((6, 9)));
+        assert_eq!(curve(0, 0).reserve_decimals(), None);
+    }
+
+    #[test]
+    fn depegged_past_threshold() {
+        // at peg, in the same and in different decimals
+        assert!(!StableCurve::is_depegged(1_000_000, 6, 1_000_000, 6, 0));
+        assert!(!StableCurve::is_depegged(1_000_000, 6, 1_000_000_000, 9, 0));
+        // a 1% shortfall is within 100 bps, one unit more is past it
+        assert!(!StableCurve::is_depegged(990_000, 6, 1_000_000, 6, 100));
+        assert!(StableCurve::is_depegged(989_999, 6, 1_000_000, 6, 100));
+        assert!(!StableCurve::is_depegged(1_000_000_000, 9, 990_000, 6, 100));
+        assert!(StableCurve::is_depegged(1_000_000_000, 9, 989_999, 6, 100));
+        // raw amounts of different decimals would look 99.9% depegged
+        assert!(!StableCurve::is_depegged(1_000_000, 6, 1_000_000_000, 9, 1));
+        assert!(StableCurve::is_depegged(1_000_000, 6, 1_000_000, 9, 9_000));
+        assert!(!StableCurve::is_depegged(0, 6, 0, 9, 0));
+        assert!(!StableCurve::is_depegged(u64::MAX, 0, u64::MAX, 0, 0));
+    }
+
+    #[test]
+    fn pack_stable_curve_decimals() {
+        let curve = StableCurve {
+            amp: 100,
+            min_trade_amount: 5,
+            token_a_decimals: 6,
+            token_b_decimals: 9,
+        };
+        let mut packed = [0u8; StableCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed);
+        assert_eq!(StableCurve::unpack_from_slice(&packed).unwrap(), curve);
+        // curves packed before the decimals were recorded price at par
+        let legacy =
+            StableCurve::unpack_from_slice(&[packed[..16].to_vec(), vec![0, 0]].concat()).unwrap();
+        assert_eq!(legacy.reserve_decimals(), None);
+        assert_eq!(curve.validate(), Ok(()));
+        let too_fine = StableCurve {
+            token_b_decimals: NORMALIZED_DECIMALS + 1,
+            ..curve
+        };
+        assert_eq!(too_fine.validate(), Err(SwapError::InvalidCurve));
+    }
+
+    proptest! {
+        #[test]
+        fn invariant_does_not_decrease_from_swap(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            amp in 1..1_000u64,
+        ) {
+            let curve = StableCurve {
+                amp,
+                min_trade_amount: 0,
+                token_a_decimals: 0,
+                token_b_decimals: 0,
+            };
+            let leverage = amp * N_COINS as u64;
+            let (swap_source_amount, swap_destination_amount) =
+                (swap_source_amount as u128, swap_destination_amount as u128);
+            if let Some(result) = curve.swap_without_fees(
+                source_token_amount as u128,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            ) {
+                let d = compute_d(leverage, swap_source_amount, swap_destination_amount).unwrap();
+                prop_assert!(invariant_at_least(
+                    leverage,
+                    swap_source_amount + result.source_amount_swapped,
+                    swap_destination_amount - result.destination_amount_swapped,
+                    d,
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn swap_rounds_new_destination_reserve_up() {
+        // Newton's method stops a unit below the destination reserve that
+        // keeps D for this trade, paying out one token too many
+        let curve = StableCurve {
+            amp: 13,
+            ..StableCurve::default()
+        };
+        let leverage = 13 * N_COINS as u64;
+        let (source_amount, swap_source_amount, swap_destination_amount) =
+            (735_516, 239_313, 10_854);
+        let new_source_amount = swap_source_amount + source_amount;
+        let d = compute_d(leverage, swap_source_amount, swap_destination_amount).unwrap();
+        let newton_destination_amount =
+            compute_new_destination_amount(leverage, new_source_amount, d).unwrap();
+        assert!(!invariant_at_least(leverage, new_source_amount, newton_destination_amount, d));
+
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        let new_destination_amount = swap_destination_amount - result.destination_amount_swapped;
+        assert_eq!(new_destination_amount, newton_destination_amount + 1);
+        assert!(invariant_at_least(leverage, new_source_amount, new_destination_amount, d));
+    }
+}
@@ -0,0 +1,508 @@
+//! The Balancer weighted product invariant calculator.
+
+use {
+    crate::{
+        curve::{
+            base::CurveType,
+            calculator::{
+                map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+                TradeDirection, TradingTokenResult,
+            },
+            constant_product::pool_tokens_to_trading_tokens,
+        },
+        error::SwapError,
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{precise_number::PreciseNumber, uint::U256},
+};
+
+/// The weights of both tokens must sum to this value, so a weight of 80 is
+/// 80% of the pool's value
+pub const WEIGHT_DENOMINATOR: u64 = 100;
+
+/// Number of binary digits of the fractional part of an exponent used by
+/// `pow_fraction`, each one costs a square root
+const FRACTION_BITS: u8 = 16;
+
+/// Fixed-point scale of `ratio_pow_ceiling`
+const RATIO_ONE: u128 = 1_000_000_000_000_000_000;
+
+/// WeightedCurve struct implementing CurveCalculator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WeightedCurve {
+    /// Weight of token A, out of WEIGHT_DENOMINATOR
+    pub weight_a: u64,
+    /// Weight of token B, out of WEIGHT_DENOMINATOR
+    pub weight_b: u64,
+}
+
+/// Raise `base` to the power `numerator / denominator`.
+///
+/// The integer part of the exponent is exact, the fractional part is expanded
+/// in binary and each digit multiplies in a repeated square root of the base,
+/// up to FRACTION_BITS digits.  The truncated exponent is rounded in the given
+/// direction, so that callers can always round against the trader.
+pub fn pow_fraction(
+    base: &PreciseNumber,
+    numerator: u128,
+    denominator: u128,
+    round_direction: RoundDirection,
+) -> Option<PreciseNumber> {
+    let mut result = base.checked_pow(numerator.checked_div(denominator)?)?;
+    let mut remainder = numerator.checked_rem(denominator)?;
+    let mut root = base.clone();
+    for _ in 0..FRACTION_BITS {
+        if remainder == 0 {
+            break;
+        }
+        root = root.sqrt()?;
+        remainder = remainder.checked_mul(2)?;
+        if remainder >= denominator {
+            remainder = remainder.checked_sub(denominator)?;
+            result = result.checked_mul(&root)?;
+        }
+    }
+    if remainder > 0 && round_direction == RoundDirection::Ceiling {
+        result = result.checked_mul(&root)?;
+    }
+    Some(result)
+}
+
+/// Fixed-point product of two values scaled by RATIO_ONE, rounded up
+fn mul_ceiling(lhs: U256, rhs: U256) -> Option<U256> {
+    let one = U256::from(RATIO_ONE);
+    lhs.checked_mul(rhs)?
+        .checked_add(one.checked_sub(U256::one())?)?
+        .checked_div(one)
+}
+
+/// Fixed-point square root of a value scaled by RATIO_ONE, rounded up
+fn sqrt_ceiling(value: U256) -> Option<U256> {
+    let radicand = value.checked_mul(U256::from(RATIO_ONE))?;
+    if radicand.is_zero() {
+        return Some(radicand);
+    }
+    let mut root = radicand;
+    let mut next = radicand.checked_add(U256::one())? >> 1;
+    while next < root {
+        root = next;
+        next = root.checked_add(radicand.checked_div(root)?)? >> 1;
+    }
+    if root.checked_mul(root)? < radicand {
+        root.checked_add(U256::one())
+    } else {
+        Some(root)
+    }
+}
+
+/// Raise `numerator / denominator`, at most one, to the power
+/// `exponent_numerator / exponent_denominator`, as a value scaled by
+/// RATIO_ONE that is never below the exact power.
+///
+/// Unlike `pow_fraction`, every step rounds up, and truncating the fractional
+/// part of the exponent to FRACTION_BITS digits only raises a power of a base
+/// below one, so the bound holds whatever precision is lost on the way.
+fn ratio_pow_ceiling(
+    numerator: u128,
+    denominator: u128,
+    exponent_numerator: u128,
+    exponent_denominator: u128,
+) -> Option<U256> {
+    let one = U256::from(RATIO_ONE);
+    let base = U256::from(numerator)
+        .checked_mul(one)?
+        .checked_add(U256::from(denominator).checked_sub(U256::one())?)?
+        .checked_div(U256::from(denominator))?;
+    let mut result = one;
+    for _ in 0..exponent_numerator.checked_div(exponent_denominator)? {
+        result = mul_ceiling(result, base)?;
+    }
+    let mut remainder = exponent_numerator.checked_rem(exponent_denominator)?;
+    let mut root = base;
+    for _ in 0..FRACTION_BITS {
+        if remainder == 0 {
+            break;
+        }
+        root = sqrt_ceiling(root)?;
+        remainder = remainder.checked_mul(2)?;
+        if remainder >= exponent_denominator {
+            remainder = remainder.checked_sub(exponent_denominator)?;
+            result = mul_ceiling(result, root)?;
+        }
+    }
+    Some(result)
+}
+
+impl WeightedCurve {
+    /// Weights of the source and destination tokens of a trade
+    fn weights(&self, trade_direction: TradeDirection) -> (u128, u128) {
+        match trade_direction {
+            TradeDirection::AtoB => (self.weight_a as u128, self.weight_b as u128),
+            TradeDirection::BtoA => (self.weight_b as u128, self.weight_a as u128),
+        }
+    }
+}
+
+impl CurveCalculator for WeightedCurve {
+    /// Weighted product swap ensures x^w_x * y^w_y = constant, so
+    /// out = y * (1 - (x / (x + in))^(w_x / w_y))
+    ///
+    /// The power is bounded from above and the output rounded down, so the
+    /// invariant of the pool never decreases.
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let (source_weight, destination_weight) = self.weights(trade_direction);
+        let new_swap_source_amount = swap_source_amount.checked_add(source_amount)?;
+        let factor = ratio_pow_ceiling(
+            swap_source_amount,
+            new_swap_source_amount,
+            source_weight,
+            destination_weight,
+        )?;
+        let one = U256::from(RATIO_ONE);
+        let destination_amount_swapped = U256::from(swap_destination_amount)
+            .checked_mul(one.checked_sub(factor)?)?
+            .checked_div(one)?
+            .as_u128();
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped: map_zero_to_none(destination_amount_swapped)?,
+        })
+    }
+
+    /// Withdrawals of both sides are proportional, like the constant product
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    /// Balancer single asset deposit, supply * ((1 + in / x)^w - 1), with w
+    /// the normalized weight of the deposited token
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let (source_weight, _) = self.weights(trade_direction);
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        let one = PreciseNumber::new(1)?;
+        let base = one.checked_add(
+            &PreciseNumber::new(source_amount)?
+                .checked_div(&PreciseNumber::new(swap_source_amount)?)?,
+        )?;
+        // the base is above one, a floored exponent mints fewer pool tokens
+        let growth = pow_fraction(
+            &base,
+            source_weight,
+            WEIGHT_DENOMINATOR as u128,
+            RoundDirection::Floor,
+        )?
+        .checked_sub(&one)?;
+        PreciseNumber::new(pool_supply)?
+            .checked_mul(&growth)?
+            .floor()?
+            .to_imprecise()
+    }
+
+    /// Balancer single asset withdrawal, supply * (1 - (1 - out / x)^w), with
+    /// w the normalized weight of the withdrawn token
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let (source_weight, _) = self.weights(trade_direction);
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        let one = PreciseNumber::new(1)?;
+        let base = one.checked_sub(
+            &PreciseNumber::new(source_amount)?
+                .checked_div(&PreciseNumber::new(swap_source_amount)?)?,
+        )?;
+        // the base is below one, a rounded up exponent burns more pool tokens
+        let remaining = pow_fraction(
+            &base,
+            source_weight,
+            WEIGHT_DENOMINATOR as u128,
+            RoundDirection::Ceiling,
+        )?;
+        PreciseNumber::new(pool_supply)?
+            .checked_mul(&one.checked_sub(&remaining)?)?
+            .ceiling()?
+            .to_imprecise()
+    }
+
+    /// The spot price of a weighted pool is the ratio of the reserves, each
+    /// divided by its weight
+    fn spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber> {
+        let (source_weight, destination_weight) = self.weights(trade_direction);
+        PreciseNumber::new(swap_destination_amount.checked_mul(source_weight)?)?
+            .checked_div(&PreciseNumber::new(swap_source_amount.checked_mul(destination_weight)?)?)
+    }
+
+    /// The weighted geometric mean of the reserves, x^(w_a) * y^(w_b) with
+    /// normalized weights, which has the dimension of tokens ^ 1
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let weight_denominator = WEIGHT_DENOMINATOR as u128;
+        pow_fraction(
+            &PreciseNumber::new(swap_token_a_amount)?,
+            self.weight_a as u128,
+            weight_denominator,
+            RoundDirection::Floor,
+        )?
+        .checked_mul(&pow_fraction(
+            &PreciseNumber::new(swap_token_b_amount)?,
+            self.weight_b as u128,
+            weight_denominator,
+            RoundDirection::Floor,
+        )?)
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.weight_a == 0
+            || self.weight_b == 0
+            || self.weight_a.checked_add(self.weight_b) != Some(WEIGHT_DENOMINATOR)
+        {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+    fn get_curve_type(&self) ->CurveType{
+        CurveType::Weighted
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for WeightedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for WeightedCurve {}
+impl Pack for WeightedCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<WeightedCurve, ProgramError> {
+        if input.len() < Self::LEN{
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        let input = array_ref![input, 0, 16];
+        let (weight_a, weight_b) = array_refs![input, 8, 8];
+        Ok(Self {
+            weight_a: u64::from_le_bytes(*weight_a),
+            weight_b: u64::from_le_bytes(*weight_b),
+        })
+    }
+}
+
+impl DynPack for WeightedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (weight_a, weight_b) = mut_array_refs![output, 8, 8];
+        *weight_a = self.weight_a.to_le_bytes();
+        *weight_b = self.weight_b.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::constant_product;
+    use proptest::prelude::*;
+
+    fn destination_amount(curve: &WeightedCurve, trade_direction: TradeDirection) -> u128 {
+        curve
+            .swap_without_fees(1_000, 1_000_000, 1_000_000, trade_direction)
+            .unwrap()
+            .destination_amount_swapped
+    }
+
+    #[test]
+    fn swap_with_weights() {
+        let even = WeightedCurve {
+            weight_a: 50,
+            weight_b: 50,
+        };
+        // equal weights are the constant product
+        let constant_product = constant_product::swap(1_000, 1_000_000, 1_000_000).unwrap();
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+            assert_eq!(
+                destination_amount(&even, *trade_direction),
+                constant_product.destination_amount_swapped
+            );
+        }
+
+        // at equal reserves token A of an 80/20 pool is worth four token B
+        let uneven = WeightedCurve {
+            weight_a: 80,
+            weight_b: 20,
+        };
+        let even_amount = destination_amount(&even, TradeDirection::AtoB);
+        let a_to_b = destination_amount(&uneven, TradeDirection::AtoB);
+        assert!(a_to_b > 3 * even_amount && a_to_b < 4 * even_amount);
+        let b_to_a = destination_amount(&uneven, TradeDirection::BtoA);
+        assert!(5 * b_to_a > even_amount && 4 * b_to_a < even_amount);
+    }
+
+    #[test]
+    fn validate_weights() {
+        let curve = |weight_a, weight_b| WeightedCurve { weight_a, weight_b };
+        assert_eq!(curve(80, 20).validate(), Ok(()));
+        assert_eq!(curve(1, 99).validate(), Ok(()));
+        assert_eq!(curve(0, 100).validate(), Err(SwapError::InvalidCurve));
+        assert_eq!(curve(100, 0).validate(), Err(SwapError::InvalidCurve));
+        assert_eq!(curve(60, 60).validate(), Err(SwapError::InvalidCurve));
+        assert_eq!(curve(u64::MAX, 101).validate(), Err(SwapError::InvalidCurve));
+    }
+
+    #[test]
+    fn pack_weighted_curve() {
+        let curve = WeightedCurve {
+            weight_a: 80,
+            weight_b: 20,
+        };
+        let mut packed = [0u8; WeightedCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed);
+        assert_eq!(WeightedCurve::unpack_from_slice(&packed).unwrap(), curve);
+        assert_eq!(
+            WeightedCurve::unpack_from_slice(&packed[..WeightedCurve::LEN - 1]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+    }
+
+    /// Product of `lhs^lhs_exponent * rhs^rhs_exponent` as little endian u32
+    /// limbs, exact however large
+    fn big_product(lhs: u128, lhs_exponent: u128, rhs: u128, rhs_exponent: u128) -> Vec<u32> {
+        let limbs = |value: u128| (0..4).map(|i| (value >> (32 * i)) as u32).collect::<Vec<_>>();
+        let multiply = |a: &[u32], b: &[u32]| {
+            let mut product = vec![0u32; a.len() + b.len()];
+            for (i, a_limb) in a.iter().enumerate() {
+                let mut carry = 0u64;
+                for (j, b_limb) in b.iter().enumerate() {
+                    let sum = u64::from(*a_limb) * u64::from(*b_limb)
+                        + u64::from(product[i + j])
+                        + carry;
+                    product[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+                product[i + b.len()] = carry as u32;
+            }
+            while product.len() > 1 && product.last() == Some(&0) {
+                product.pop();
+            }
+            product
+        };
+        let mut product = vec![1u32];
+        for _ in 0..lhs_exponent {
+            product = multiply(&product, &limbs(lhs));
+        }
+        for _ in 0..rhs_exponent {
+            product = multiply(&product, &limbs(rhs));
+        }
+        product
+    }
+
+    /// Compares limbs as returned by `big_product`
+    fn big_at_least(lhs: &[u32], rhs: &[u32]) -> bool {
+        lhs.len() > rhs.len() || (lhs.len() == rhs.len() && lhs.iter().rev().ge(rhs.iter().rev()))
+    }
+
+    #[test]
+    fn ratio_pow_ceiling_bounds_the_power() {
+        let one = U256::from(RATIO_ONE);
+        assert_eq!(ratio_pow_ceiling(1, 2, 1, 1), Some(one / 2));
+        assert_eq!(ratio_pow_ceiling(1, 4, 1, 2), Some(one / 2));
+        assert_eq!(ratio_pow_ceiling(1, 2, 3, 1), Some(one / 8));
+        // 1/3 rounds up, and so does each power of it
+        assert_eq!(ratio_pow_ceiling(1, 3, 1, 1), Some(one / 3 + 1));
+        assert_eq!(ratio_pow_ceiling(1, 3, 2, 1), Some(one / 9 + 1));
+        assert_eq!(ratio_pow_ceiling(7, 7, 99, 1), Some(one));
+    }
+
+    proptest! {
+        #[test]
+        fn swap_never_decreases_the_invariant(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            weight_a in 1..WEIGHT_DENOMINATOR,
+        ) {
+            let curve = WeightedCurve {
+                weight_a,
+                weight_b: WEIGHT_DENOMINATOR - weight_a,
+            };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                let (source_weight, destination_weight) = curve.weights(*trade_direction);
+                if let Some(result) = curve.swap_without_fees(
+                    u128::from(source_token_amount),
+                    u128::from(swap_source_amount),
+                    u128::from(swap_destination_amount),
+                    *trade_direction,
+                ) {
+                    prop_assert!(
+                        result.destination_amount_swapped < u128::from(swap_destination_amount)
+                    );
+                    // x^w_x * y^w_y, exactly, before and after the swap
+                    let old_invariant = big_product(
+                        u128::from(swap_source_amount),
+                        source_weight,
+                        u128::from(swap_destination_amount),
+                        destination_weight,
+                    );
+                    let new_invariant = big_product(
+                        u128::from(swap_source_amount) + result.source_amount_swapped,
+                        source_weight,
+                        u128::from(swap_destination_amount) - result.destination_amount_swapped,
+                        destination_weight,
+                    );
+                    prop_assert!(big_at_least(&new_invariant, &old_invariant));
+                }
+            }
+        }
+    }
+}
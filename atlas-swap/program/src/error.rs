@@ -138,6 +138,60 @@ pub enum SwapError {
     /// Lamport balance below rent-exempt threshold.
     #[error("Lamport balance below rent-exempt threshold")]
     NotRentExempt,
+
+    /// The deposit would push a reserve above the pool size cap.
+    #[error("Deposit would exceed the pool size cap")]
+    PoolCapExceeded,
+
+    /// The initial supply is inconsistent with the lp decimals.
+    #[error("Initial supply is inconsistent with the lp decimals")]
+    InvalidInitialSupply,
+
+    /// An account the instruction writes to was passed as readonly.
+    #[error("Account must be writable")]
+    AccountNotWritable,
+
+    /// The locked pool tokens cannot be released before the unlock time.
+    #[error("Liquidity is still locked")]
+    StillLocked,
+
+    /// Admin instructions must be called directly, not through another program.
+    #[error("Instruction cannot be invoked through a cross-program invocation")]
+    CpiNotAllowed,
+
+    /// The reserves and pool tokens held by the swap authority cannot be rescued.
+    #[error("Cannot rescue the reserves or pool tokens")]
+    CannotRescueReserve,
+
+    /// The instruction landed after the deadline it was signed with.
+    #[error("The deadline of the instruction has passed")]
+    DeadlineExceeded,
+
+    /// Lamports were given to wrap without a native mint reserve and a
+    /// creator to fund it.
+    #[error("Lamports can only be wrapped into a native mint reserve")]
+    InvalidNativeAmount,
+
+    /// The deposit mints more pool tokens than a single deposit may.
+    #[error("The deposit mints more pool tokens than allowed")]
+    DepositTooLarge,
+
+    /// The source account holds less than the amount to swap.
+    #[error("The source account holds less than the swap amount")]
+    InsufficientFunds,
+
+    /// A Token-2022 account or mint carries an extension the pool refuses.
+    #[error("Token-2022 extension not supported")]
+    UnsupportedTokenExtension,
+
+    /// The fee counters were already recorded in the current epoch.
+    #[error("Fee snapshot already taken this epoch")]
+    FeeSnapshotTaken,
+
+    /// The withdrawal would leave fewer pool tokens than the pool's minimum
+    /// LP supply.
+    #[error("The withdrawal would leave less than the minimum LP supply")]
+    BelowMinLpSupply,
 }
 impl From<SwapError> for ProgramError {
     fn from(e: SwapError) -> Self {
@@ -2,8 +2,16 @@
 
 #![allow(clippy::too_many_arguments)]
 
-use crate::curve::{base::SwapCurve, fees::Fees};
+use crate::constraints::{
+    FEE_TIER_COUNT, MAX_BATCH_DEPOSITS, MAX_POOL_NAME_LEN, MAX_POOL_SYMBOL_LEN,
+};
+use crate::curve::{base::SwapCurve, calculator::TradeDirection, fees::Fees};
 use crate::error::SwapError;
+use crate::pda::{
+    find_fee_snapshot_address, find_global_state_address, find_liquidity_escrow_address,
+    find_liquidity_lock_address, find_pool_metadata_address,
+};
+use crate::state::{SwapState, SwapV1};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
@@ -26,10 +34,31 @@ pub struct Initialize {
     /// swap curve info for pool, including CurveType and anything
     /// else that may be required
     pub swap_curve: SwapCurve,
+    /// Fee tier of the global state the pool charges, zero for the global
+    /// fees.  Optional in the instruction data, and must precede
+    /// `min_lp_supply` when that is given.
+    pub fee_tier_index: u8,
+    /// Pool tokens withdrawals must leave in the pool, optional in the
+    /// instruction data, `None` keeps the default `MIN_LP_SUPPLY`
+    pub min_lp_supply: Option<u64>,
+    /// Lamports of the creator to wrap into the token A reserve when it
+    /// holds the native mint, optional in the instruction data, zero wraps
+    /// nothing
+    pub native_amount: u64,
+    /// Bump seed of the swap authority as stored by the client, optional in
+    /// the instruction data and only given after `native_amount`.  A nonce
+    /// that is not the canonical bump seed is rejected, `None` lets the
+    /// program derive it.
+    pub nonce: Option<u8>,
 }
 
 
 /// Set Global State data
+///
+/// The fields from `max_reserve_a` on are optional trailing fields of the
+/// instruction data, so that clients built for an older layout keep working.
+/// An absent field keeps the value stored in the global state.  The fields
+/// are positional, a field can only be given along with all those before it.
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub struct SetGlobalState {
@@ -49,6 +78,34 @@ pub struct SetGlobalState {
     ///Fee ratio
     pub fees: Fees,
 
+    /// maximum token A reserve of a pool, zero disables the cap
+    pub max_reserve_a: Option<u64>,
+
+    /// maximum token B reserve of a pool, zero disables the cap
+    pub max_reserve_b: Option<u64>,
+
+    /// start of the fee holiday, a unix timestamp
+    pub fee_holiday_start: Option<i64>,
+
+    /// end of the fee holiday, a unix timestamp, both zero disables it
+    pub fee_holiday_end: Option<i64>,
+
+    /// stable return fee numerator of a balanced stable pool
+    pub dynamic_fee_min_numerator: Option<u64>,
+
+    /// stable return fee numerator of a fully imbalanced stable pool, zero
+    /// disables the dynamic fee
+    pub dynamic_fee_max_numerator: Option<u64>,
+
+    /// fees a pool can pick at creation, all zero marks an unused tier
+    pub fee_tiers: Option<[Fees; FEE_TIER_COUNT]>,
+
+    /// maximum pool tokens a single deposit may mint, zero disables the limit
+    pub max_lp_per_deposit: Option<u64>,
+
+    /// decimals a pool mint may have besides `lp_decimals`, bit `d` allows
+    /// `d` decimals
+    pub allowed_lp_decimals: Option<u32>,
 }
 
 
@@ -61,6 +118,61 @@ pub struct Swap {
     pub amount_in: u64,
     /// Minimum amount of DESTINATION token to output, prevents excessive slippage
     pub minimum_amount_out: u64,
+    /// Maximum slippage from the spot price in basis points, optional in the
+    /// instruction data, zero disables the check
+    pub max_slippage_bps: u16,
+    /// Fill as much of `amount_in` as `max_slippage_bps` allows instead of
+    /// failing, optional in the instruction data.  The filled and unfilled
+    /// amounts are returned as [SwapReturnData](struct.SwapReturnData.html),
+    /// and `minimum_amount_out` scales with the fill.
+    /// `PoolSnapshot::get_partial_fill` quotes the fill before sending.
+    pub allow_partial: bool,
+}
+
+/// Return data of a swap, read by the caller with `get_return_data`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapReturnData {
+    /// Source amount taken from the user, fees included
+    pub filled: u64,
+    /// Part of `amount_in` left with the user, by a partial fill or when the
+    /// curve needs less than asked for to pay out the rounded output
+    pub unfilled: u64,
+    /// Token A reserve after the swap
+    pub new_reserve_a: u64,
+    /// Token B reserve after the swap
+    pub new_reserve_b: u64,
+}
+
+impl SwapReturnData {
+    /// Size of the packed return data
+    pub const LEN: usize = 32;
+
+    /// Packs the return data into the bytes given to `set_return_data`
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.filled.to_le_bytes());
+        buf.extend_from_slice(&self.unfilled.to_le_bytes());
+        buf.extend_from_slice(&self.new_reserve_a.to_le_bytes());
+        buf.extend_from_slice(&self.new_reserve_b.to_le_bytes());
+        buf
+    }
+
+    /// Unpacks the return data of a swap
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (filled, rest) = SwapInstruction::unpack_u64(input)?;
+        let (unfilled, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (new_reserve_a, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (new_reserve_b, _) = SwapInstruction::unpack_u64(rest)?;
+        Ok(Self {
+            filled,
+            unfilled,
+            new_reserve_a,
+            new_reserve_b,
+        })
+    }
 }
 
 /// DepositAllTokenTypes instruction data
@@ -77,6 +189,93 @@ pub struct DepositAllTokenTypes {
     pub maximum_token_b_amount: u64,
 }
 
+/// BatchDeposit instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchDeposit {
+    /// One deposit per pool, in the same order as the pools' account groups.
+    /// At most `MAX_BATCH_DEPOSITS` entries.
+    pub deposits: Vec<DepositAllTokenTypes>,
+}
+
+/// SetPoolMetadata instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetPoolMetadata {
+    /// Display name of the pool, at most `MAX_POOL_NAME_LEN` bytes
+    pub name: String,
+    /// Display symbol of the pool, at most `MAX_POOL_SYMBOL_LEN` bytes
+    pub symbol: String,
+}
+
+/// RescueTokens instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RescueTokens {
+    /// Mint of the stray tokens, which must match the rescued account
+    pub mint: Pubkey,
+}
+
+/// ZapDeposit instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZapDeposit {
+    /// Amount of the single source token to put into the pool
+    pub source_amount: u64,
+    /// Minimum amount of pool tokens to mint, prevents excessive slippage
+    pub minimum_pool_token_amount: u64,
+}
+
+/// Return data of a zap deposit, read by the caller with `get_return_data`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZapDepositReturnData {
+    /// Pool tokens minted to the user
+    pub pool_token_amount: u64,
+    /// Source amount swapped for the other token, fees included
+    pub swap_amount: u64,
+}
+
+impl ZapDepositReturnData {
+    /// Size of the packed return data
+    pub const LEN: usize = 16;
+
+    /// Packs the return data into the bytes given to `set_return_data`
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.pool_token_amount.to_le_bytes());
+        buf.extend_from_slice(&self.swap_amount.to_le_bytes());
+        buf
+    }
+
+    /// Unpacks the return data of a zap deposit
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (pool_token_amount, rest) = SwapInstruction::unpack_u64(input)?;
+        let (swap_amount, _) = SwapInstruction::unpack_u64(rest)?;
+        Ok(Self {
+            pool_token_amount,
+            swap_amount,
+        })
+    }
+}
+
+/// LockLiquidity instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockLiquidity {
+    /// Amount of pool tokens to lock
+    pub amount: u64,
+    /// Unix timestamp from which the pool tokens can be unlocked
+    pub unlock_ts: i64,
+}
+
 /// WithdrawAllTokenTypes instruction data
 #[cfg_attr(feature = "fuzz", derive(Arbitrary))]
 #[repr(C)]
@@ -89,6 +288,9 @@ pub struct WithdrawAllTokenTypes {
     pub minimum_token_a_amount: u64,
     /// Minimum amount of token B to receive, prevents excessive slippage
     pub minimum_token_b_amount: u64,
+    /// Unix timestamp after which the withdrawal fails, optional in the
+    /// instruction data, zero disables the check
+    pub deadline: i64,
 }
 
 /// Deposit one token type, exact amount in instruction data
@@ -118,67 +320,104 @@ pub struct WithdrawSingleTokenTypeExactAmountOut {
 /// Instructions supported by the token swap program.
 #[repr(C)]
 #[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum SwapInstruction {
     ///   Initializes a new swap
     ///
     ///   0. `[writable, signer]` New Token-swap to create.
     ///   1. `[]` swap authority derived from `create_program_address(&[Token-swap account])`
-    ///   2. `[]` token_a Account. Must be non zero, owned by swap authority.
-    ///   3. `[]` token_b Account. Must be non zero, owned by swap authority.
-    ///   4. `[writable]` Pool Token Mint. Must be empty, owned by swap authority.
-    ///   5. `[]` Pool Token Account to deposit trading and withdraw fees.
-    ///   Must be empty, not owned by swap authority
+    ///   2. `[]` global state
+    ///   3. `[]` token_a Account. Must be non zero, owned by swap authority.
+    ///   4. `[]` token_b Account. Must be non zero, owned by swap authority.
+    ///   5. `[writable]` Pool Token Mint. Must be a freshly created mint with no
+    ///      supply, owned by swap authority. Mints from other pools are rejected.
     ///   6. `[writable]` Pool Token Account to deposit the initial pool token
-    ///   supply.  Must be empty, not owned by swap authority.
+    ///      supply.  Must be empty, not owned by swap authority.
     ///   7. '[]` Token program id
+    ///   8. '[]` Rent sysvar
+    ///   9. `[optional, signer]` Pool creator. When given, the initial pool
+    ///      token account must be owned by the creator.
+    ///   10. `[optional]` System program, required with a `native_amount`
+    ///
+    ///   With a `native_amount`, token_a must be a writable native mint
+    ///   account and the creator a writable signer whose lamports are
+    ///   wrapped into it before the pool is seeded.
+    ///
+    ///   The pool mint belongs to spl_token.  A reserve may belong to
+    ///   Token-2022 instead, later instructions then move its tokens with
+    ///   that program through `TransferChecked`.  Only Token-2022 accounts
+    ///   carrying no extension besides `ImmutableOwner`, and mints carrying
+    ///   none, are accepted, and native wrapping stays an spl_token feature.
+    ///
+    ///   A stable curve recording the decimals of its reserves also takes the
+    ///   token A and token B mints after the optional accounts, so that the
+    ///   decimals can be checked against them.
     Initialize(Initialize),
 
     ///   Swap the tokens in the pool.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap, counts the fees paid to the fee owner
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
-    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
-    ///   4. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
-    ///   5. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
-    ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
-    ///   7. `[writable]` Pool token mint, to generate trading fees
-    ///   8. `[writable]` Fee account, to receive trading fees
-    ///   9. '[]` Token program id
-    ///   10 `[optional, writable]` Host fee account to receive additional trading fees
+    ///   3. `[]` global state
+    ///   4. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   5. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   6. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   7. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   8. `[writable]` Pool token mint, to generate trading fees
+    ///   9. `[writable]` Fee account, to receive trading fees
+    ///   10. '[]` Token program id
+    ///   11 `[optional, writable]` Host fee account to receive additional trading fees
+    ///
+    ///   When a reserve belongs to another token program than the pool, that
+    ///   program must also be passed after the accounts above, followed by
+    ///   the mint of each Token-2022 reserve, see `push_reserve_accounts`.
+    ///
+    ///   Sets a `SwapReturnData` as return data.
     Swap(Swap),
 
     ///   Deposit both types of tokens into the pool.  The output is a "pool"
     ///   token representing ownership in the pool. Inputs are converted to
-    ///   the current ratio.
+    ///   the current ratio, and fail with `ZeroTradingTokens` if either side
+    ///   rounds to zero, so a deposit can never leave a one-sided reserve.
     ///
     ///   0. `[]` Token-swap
     ///   1. `[]` swap authority
-    ///   2. `[]` user transfer authority
-    ///   3. `[writable]` token_a user transfer authority can transfer amount,
-    ///   4. `[writable]` token_b user transfer authority can transfer amount,
-    ///   5. `[writable]` token_a Base Account to deposit into.
-    ///   6. `[writable]` token_b Base Account to deposit into.
-    ///   7. `[writable]` Pool MINT account, swap authority is the owner.
-    ///   8. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
-    ///   9. '[]` Token program id
+    ///   2. `[]` global state
+    ///   3. `[signer]` user transfer authority
+    ///   4. `[writable]` token_a user transfer authority can transfer amount,
+    ///   5. `[writable]` token_b user transfer authority can transfer amount,
+    ///   6. `[writable]` token_a Base Account to deposit into.
+    ///   7. `[writable]` token_b Base Account to deposit into.
+    ///   8. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   9. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   10. '[]` Token program id
+    ///   11. `[optional]` Token program of a reserve, when it is not the pool's
+    ///   12. `[optional]` Mint of each Token-2022 reserve
     DepositAllTokenTypes(DepositAllTokenTypes),
 
     ///   Withdraw both types of tokens from the pool at the current ratio, given
     ///   pool tokens.  The pool tokens are burned in exchange for an equivalent
     ///   amount of token A and B.
     ///
+    ///   Withdrawals must leave the pool's minimum LP supply, except for a
+    ///   SOURCE account holding the entire supply and burning all of it, which
+    ///   empties both reserves.  The next deposit into an emptied pool seeds
+    ///   it again with the deposited maximum amounts.
+    ///
     ///   0. `[]` Token-swap
     ///   1. `[]` swap authority
-    ///   2. `[]` user transfer authority
-    ///   3. `[writable]` Pool mint account, swap authority is the owner
-    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
-    ///   5. `[writable]` token_a Swap Account to withdraw FROM.
-    ///   6. `[writable]` token_b Swap Account to withdraw FROM.
-    ///   7. `[writable]` token_a user Account to credit.
-    ///   8. `[writable]` token_b user Account to credit.
-    ///   9. `[writable]` Fee account, to receive withdrawal fees
-    ///   10 '[]` Token program id
+    ///   2. `[]` global state
+    ///   3. `[signer]` user transfer authority
+    ///   4. `[writable]` Pool mint account, swap authority is the owner
+    ///   5. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   6. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   7. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   8. `[writable]` token_a user Account to credit.
+    ///   9. `[writable]` token_b user Account to credit.
+    ///   10. '[]` Token program id
+    ///   11. `[optional]` Token program of a reserve, when it is not the pool's
+    ///   12. `[optional]` Mint of each Token-2022 reserve
     WithdrawAllTokenTypes(WithdrawAllTokenTypes),
 
     ///   Set global program state
@@ -190,8 +429,113 @@ pub enum SwapInstruction {
     ///   4. `[]` initial supply
     ///   5. `[]` fees
     ///   6. `[]` swap curve.
+    ///
+    ///   Accounts expected:
+    ///   0. `[writable]` program state account
+    ///   1. `[signer, writable]` current owner of this contract, pays for
+    ///      creating the state account or growing one of an older layout
+    ///   2. `[]` system program
+    ///   3. `[]` rent sysvar
+    ///   4. `[]` instructions sysvar, the instruction cannot be called through a CPI
     SetGlobalStateInstruction(SetGlobalState),
 
+    ///   Deposit both types of tokens into several pools at once, failing as
+    ///   a whole if any single deposit fails.
+    ///
+    ///   Takes one group of `DepositAllTokenTypes` accounts per deposit, in
+    ///   the same order as the deposits in the instruction data.  The groups
+    ///   have no room for a second token program, so pools whose reserves
+    ///   belong to different token programs cannot be batched.
+    BatchDeposit(BatchDeposit),
+
+    ///   Create or update the display name and symbol of a pool.
+    ///
+    ///   0. `[writable]` pool metadata account, derived from the swap key
+    ///   1. `[]` Token-swap
+    ///   2. `[]` global state
+    ///   3. `[signer, writable]` owner of this contract, pays for the account
+    ///   4. `[]` system program
+    ///   5. `[]` rent sysvar
+    ///   6. `[]` instructions sysvar, the instruction cannot be called through a CPI
+    SetPoolMetadata(SetPoolMetadata),
+
+    ///   Lock pool tokens in an escrow until `unlock_ts`.  An owner has a
+    ///   single lock per pool, which must be unlocked before locking again.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` liquidity lock account, derived from the swap and owner keys
+    ///   3. `[signer, writable]` owner of the pool tokens, pays for the account
+    ///   4. `[writable]` pool token account to lock from, owner can transfer amount
+    ///   5. `[writable]` escrow pool token account, derived from the swap and
+    ///      owner keys and created for the swap authority if empty
+    ///   6. `[]` Pool token mint
+    ///   7. `[]` Token program id
+    ///   8. `[]` system program
+    ///   9. `[]` rent sysvar
+    LockLiquidity(LockLiquidity),
+
+    ///   Release locked pool tokens once the unlock time has passed, closing
+    ///   the lock and escrow accounts to their owner.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` liquidity lock account
+    ///   3. `[signer, writable]` owner of the lock, receives the rent of the closed accounts
+    ///   4. `[writable]` escrow pool token account of the lock
+    ///   5. `[writable]` pool token account to release into
+    ///   6. `[]` Token program id
+    Unlock,
+
+    ///   Move the full balance of a token account owned by the swap authority
+    ///   that is neither a reserve nor holds pool tokens, eg. tokens sent to
+    ///   the authority by mistake.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` global state
+    ///   3. `[signer]` owner of this contract
+    ///   4. `[writable]` stray token account, swap authority is the owner
+    ///   5. `[writable]` token account to move the tokens to
+    ///   6. `[]` Token program id
+    ///   7. `[]` instructions sysvar, the instruction cannot be called through a CPI
+    RescueTokens(RescueTokens),
+
+    ///   Deposit a single token type for a balanced position.  Half of the
+    ///   source amount is swapped for the other token at the current price,
+    ///   fees included, and both sides are then deposited proportionally.
+    ///   Whatever the swap returns beyond the proportional share is refunded.
+    ///
+    ///   0. `[writable]` Token-swap, counts the fees paid to the fee owner
+    ///   1. `[]` swap authority
+    ///   2. `[]` global state
+    ///   3. `[signer]` user transfer authority
+    ///   4. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   5. `[writable]` token_(B|A) Account to refund the leftover of the other token
+    ///   6. `[writable]` token_a Base Account to deposit into.
+    ///   7. `[writable]` token_b Base Account to deposit into.
+    ///   8. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   9. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   10. `[writable]` Fee account of the SOURCE token, to receive trading fees
+    ///   11. '[]` Token program id
+    ///   12. `[optional]` Token program of a reserve, when it is not the pool's
+    ///   13. `[optional]` Mint of each Token-2022 reserve
+    ///
+    ///   Sets a `ZapDepositReturnData` as return data.
+    ZapDeposit(ZapDeposit),
+
+    ///   Record the fee counters of the swap for the current epoch in its fee
+    ///   snapshot account, a ring buffer of the last `FEE_SNAPSHOT_CAPACITY`
+    ///   epochs.  Anyone can record, once per epoch, and the first call
+    ///   creates the account.  Swap accounts allocated before the counters
+    ///   existed record zeros.
+    ///
+    ///   0. `[writable]` fee snapshot account, derived from the swap key
+    ///   1. `[]` Token-swap
+    ///   2. `[signer, writable]` payer of the fee snapshot account
+    ///   3. `[]` system program
+    ///   4. `[]` rent sysvar
+    FeeSnapshot,
 }
 
 impl SwapInstruction {
@@ -202,22 +546,59 @@ impl SwapInstruction {
         msg!("unpack instruction tag {}", tag);
         Ok(match tag {
             0 => {
-                let swap_curve = SwapCurve::unpack_unchecked(rest)?;
-                msg!("unpack instruction rest.len() {}", rest.len());
-                // if rest.len() == 1 {
-                    Self::Initialize(Initialize {
-                        swap_curve,
-                    })
-                // } else {
-                //     return Err(SwapError::InvalidInstruction.into());
-                // }
+                if rest.len() < SwapCurve::LEN {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+                let (swap_curve, rest) = rest.split_at(SwapCurve::LEN);
+                let swap_curve = SwapCurve::unpack_unchecked(swap_curve)?;
+                let (fee_tier_index, rest) = match rest.split_first() {
+                    Some((&fee_tier_index, rest)) => (fee_tier_index, rest),
+                    None => (0, rest),
+                };
+                let (min_lp_supply, rest) = match rest.split_first() {
+                    None => (None, rest),
+                    Some((0, rest)) => (None, Self::unpack_u64(rest)?.1),
+                    Some((1, rest)) => {
+                        let (min_lp_supply, rest) = Self::unpack_u64(rest)?;
+                        (Some(min_lp_supply), rest)
+                    }
+                    Some(_) => return Err(SwapError::InvalidInstruction.into()),
+                };
+                let (native_amount, rest) = if rest.is_empty() {
+                    (0, rest)
+                } else {
+                    Self::unpack_u64(rest)?
+                };
+                let nonce = rest.first().copied();
+                Self::Initialize(Initialize {
+                    swap_curve,
+                    fee_tier_index,
+                    min_lp_supply,
+                    native_amount,
+                    nonce,
+                })
             }
             1 => {
                 let (amount_in, rest) = Self::unpack_u64(rest)?;
-                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let max_slippage_bps = match rest.get(..2) {
+                    Some(bytes) => u16::from_le_bytes(
+                        bytes
+                            .try_into()
+                            .map_err(|_| SwapError::InvalidInstruction)?,
+                    ),
+                    None => 0,
+                };
+                let allow_partial = match rest.get(2) {
+                    None | Some(0) => false,
+                    Some(1) => true,
+                    Some(_) => return Err(SwapError::InvalidInstruction.into()),
+                };
                 Self::Swap(Swap {
                     amount_in,
                     minimum_amount_out,
+                    max_slippage_bps,
+                    allow_partial,
                 })
             }
             2 => {
@@ -233,39 +614,161 @@ impl SwapInstruction {
             3 => {
                 let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
                 let (minimum_token_a_amount, rest) = Self::unpack_u64(rest)?;
-                let (minimum_token_b_amount, _rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let deadline = if rest.is_empty() {
+                    0
+                } else {
+                    Self::unpack_i64(rest)?.0
+                };
                 Self::WithdrawAllTokenTypes(WithdrawAllTokenTypes {
                     pool_token_amount,
                     minimum_token_a_amount,
                     minimum_token_b_amount,
+                    deadline,
                 })
             }
             4 => {// Upgrade Program State
-                let (owner_vec, rest) = rest.split_at(32);
-                let owner = Pubkey::new(owner_vec);
-                let (fee_owner_vec, rest) = rest.split_at(32);
-                let fee_owner = Pubkey::new(fee_owner_vec);
+                let (owner, rest) = Self::unpack_pubkey(rest)?;
+                let (fee_owner, rest) = Self::unpack_pubkey(rest)?;
 
                 let (initial_supply, rest) = Self::unpack_u64(rest)?;
                 let (&lp_decimals, rest) = rest.split_first().ok_or(SwapError::InvalidInstruction)?;
                 if rest.len() >= Fees::LEN {
-                    let (fees, _rest) = rest.split_at(Fees::LEN);
+                    let (fees, rest) = rest.split_at(Fees::LEN);
                     let fees = Fees::unpack_unchecked(fees)?;
+                    let (max_reserve_a, rest) = Self::unpack_optional(rest, Self::unpack_u64)?;
+                    let (max_reserve_b, rest) = Self::unpack_optional(rest, Self::unpack_u64)?;
+                    let (fee_holiday_start, rest) = Self::unpack_optional(rest, Self::unpack_i64)?;
+                    let (fee_holiday_end, rest) = Self::unpack_optional(rest, Self::unpack_i64)?;
+                    let (dynamic_fee_min_numerator, rest) =
+                        Self::unpack_optional(rest, Self::unpack_u64)?;
+                    let (dynamic_fee_max_numerator, rest) =
+                        Self::unpack_optional(rest, Self::unpack_u64)?;
+                    let (fee_tiers, rest) = Self::unpack_optional(rest, Self::unpack_fee_tiers)?;
+                    let (max_lp_per_deposit, rest) = Self::unpack_optional(rest, Self::unpack_u64)?;
+                    let (allowed_lp_decimals, _rest) =
+                        Self::unpack_optional(rest, Self::unpack_u32)?;
                     Self::SetGlobalStateInstruction(SetGlobalState {
                         owner,
                         fee_owner,
                         initial_supply,
                         lp_decimals,
                         fees,
+                        max_reserve_a,
+                        max_reserve_b,
+                        fee_holiday_start,
+                        fee_holiday_end,
+                        dynamic_fee_min_numerator,
+                        dynamic_fee_max_numerator,
+                        fee_tiers,
+                        max_lp_per_deposit,
+                        allowed_lp_decimals,
                     })
                 } else {
                     return Err(SwapError::InvalidInstruction.into());
                 }
             }
+            5 => {
+                let (&count, mut rest) = rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let count = count as usize;
+                if count == 0 || count > MAX_BATCH_DEPOSITS {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+                let mut deposits = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let (pool_token_amount, next) = Self::unpack_u64(rest)?;
+                    let (maximum_token_a_amount, next) = Self::unpack_u64(next)?;
+                    let (maximum_token_b_amount, next) = Self::unpack_u64(next)?;
+                    deposits.push(DepositAllTokenTypes {
+                        pool_token_amount,
+                        maximum_token_a_amount,
+                        maximum_token_b_amount,
+                    });
+                    rest = next;
+                }
+                Self::BatchDeposit(BatchDeposit { deposits })
+            }
+            6 => {
+                let (name, rest) = Self::unpack_str(rest, MAX_POOL_NAME_LEN)?;
+                let (symbol, _rest) = Self::unpack_str(rest, MAX_POOL_SYMBOL_LEN)?;
+                Self::SetPoolMetadata(SetPoolMetadata { name, symbol })
+            }
+            7 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (unlock_ts, _rest) = Self::unpack_i64(rest)?;
+                Self::LockLiquidity(LockLiquidity { amount, unlock_ts })
+            }
+            8 => Self::Unlock,
+            9 => {
+                let (mint, _rest) = Self::unpack_pubkey(rest)?;
+                Self::RescueTokens(RescueTokens { mint })
+            }
+            10 => {
+                let (source_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_pool_token_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::ZapDeposit(ZapDeposit {
+                    source_amount,
+                    minimum_pool_token_amount,
+                })
+            }
+            11 => Self::FeeSnapshot,
             _ => return Err(SwapError::InvalidInstruction.into()),
         })
     }
 
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        if input.len() >= 32 {
+            let (key, rest) = input.split_at(32);
+            Ok((Pubkey::new(key), rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    /// Unpacks an optional trailing field, absent when the input ends before it
+    #[allow(clippy::type_complexity)]
+    fn unpack_optional<'a, T>(
+        input: &'a [u8],
+        unpack: fn(&'a [u8]) -> Result<(T, &'a [u8]), ProgramError>,
+    ) -> Result<(Option<T>, &'a [u8]), ProgramError> {
+        if input.is_empty() {
+            return Ok((None, input));
+        }
+        let (value, rest) = unpack(input)?;
+        Ok((Some(value), rest))
+    }
+
+    fn unpack_fee_tiers(mut input: &[u8]) -> Result<([Fees; FEE_TIER_COUNT], &[u8]), ProgramError> {
+        let mut fee_tiers = <[Fees; FEE_TIER_COUNT]>::default();
+        for fee_tier in fee_tiers.iter_mut() {
+            if input.len() < Fees::LEN {
+                return Err(SwapError::InvalidInstruction.into());
+            }
+            let (fees, rest) = input.split_at(Fees::LEN);
+            *fee_tier = Fees::unpack_unchecked(fees)?;
+            input = rest;
+        }
+        Ok((fee_tiers, input))
+    }
+
+    fn unpack_u32(input: &[u8]) -> Result<(u32, &[u8]), ProgramError> {
+        if input.len() >= 4 {
+            let (value, rest) = input.split_at(4);
+            let value = value
+                .try_into()
+                .map(u32::from_le_bytes)
+                .map_err(|_| SwapError::InvalidInstruction)?;
+            Ok((value, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_i64(input: &[u8]) -> Result<(i64, &[u8]), ProgramError> {
+        let (value, rest) = Self::unpack_u64(input)?;
+        Ok((value as i64, rest))
+    }
+
     fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
         if input.len() >= 8 {
             let (amount, rest) = input.split_at(8);
@@ -280,25 +783,51 @@ impl SwapInstruction {
         }
     }
 
+    fn unpack_str(input: &[u8], max_len: usize) -> Result<(String, &[u8]), ProgramError> {
+        let (&len, rest) = input.split_first().ok_or(SwapError::InvalidInstruction)?;
+        let len = len as usize;
+        if len > max_len || rest.len() < len {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let (value, rest) = rest.split_at(len);
+        let value = String::from_utf8(value.to_vec()).map_err(|_| SwapError::InvalidInput)?;
+        Ok((value, rest))
+    }
+
     /// Packs a [SwapInstruction](enum.SwapInstruction.html) into a byte buffer.
     pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
-        match &*self {
+        match self {
             Self::Initialize(Initialize {
-                swap_curve
+                swap_curve,
+                fee_tier_index,
+                min_lp_supply,
+                native_amount,
+                nonce,
             }) => {
                 buf.push(0);
                 let mut swap_curve_slice = [0u8; SwapCurve::LEN];
                 Pack::pack_into_slice(swap_curve, &mut swap_curve_slice[..]);
                 buf.extend_from_slice(&swap_curve_slice);
+                buf.push(*fee_tier_index);
+                buf.push(min_lp_supply.is_some() as u8);
+                buf.extend_from_slice(&min_lp_supply.unwrap_or(0).to_le_bytes());
+                buf.extend_from_slice(&native_amount.to_le_bytes());
+                if let Some(nonce) = nonce {
+                    buf.push(*nonce);
+                }
             }
             Self::Swap(Swap {
                 amount_in,
                 minimum_amount_out,
+                max_slippage_bps,
+                allow_partial,
             }) => {
                 buf.push(1);
                 buf.extend_from_slice(&amount_in.to_le_bytes());
                 buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                buf.extend_from_slice(&max_slippage_bps.to_le_bytes());
+                buf.push(*allow_partial as u8);
             }
             Self::DepositAllTokenTypes(DepositAllTokenTypes {
                 pool_token_amount,
@@ -314,11 +843,13 @@ impl SwapInstruction {
                 pool_token_amount,
                 minimum_token_a_amount,
                 minimum_token_b_amount,
+                deadline,
             }) => {
                 buf.push(3);
                 buf.extend_from_slice(&pool_token_amount.to_le_bytes());
                 buf.extend_from_slice(&minimum_token_a_amount.to_le_bytes());
                 buf.extend_from_slice(&minimum_token_b_amount.to_le_bytes());
+                buf.extend_from_slice(&deadline.to_le_bytes());
             }
             Self::SetGlobalStateInstruction(SetGlobalState {
                 owner,
@@ -326,6 +857,15 @@ impl SwapInstruction {
                 initial_supply,
                 lp_decimals,
                 fees,
+                max_reserve_a,
+                max_reserve_b,
+                fee_holiday_start,
+                fee_holiday_end,
+                dynamic_fee_min_numerator,
+                dynamic_fee_max_numerator,
+                fee_tiers,
+                max_lp_per_deposit,
+                allowed_lp_decimals,
             }) => {
                 buf.push(4);
                 buf.extend_from_slice(owner.as_ref());
@@ -335,13 +875,76 @@ impl SwapInstruction {
                 let mut fees_slice = [0u8; Fees::LEN];
                 Pack::pack_into_slice(fees, &mut fees_slice[..]);
                 buf.extend_from_slice(&fees_slice);
+                let optional_fields = [
+                    max_reserve_a.map(|value| value.to_le_bytes().to_vec()),
+                    max_reserve_b.map(|value| value.to_le_bytes().to_vec()),
+                    fee_holiday_start.map(|value| value.to_le_bytes().to_vec()),
+                    fee_holiday_end.map(|value| value.to_le_bytes().to_vec()),
+                    dynamic_fee_min_numerator.map(|value| value.to_le_bytes().to_vec()),
+                    dynamic_fee_max_numerator.map(|value| value.to_le_bytes().to_vec()),
+                    fee_tiers.as_ref().map(|fee_tiers| {
+                        let mut fee_tiers_slice = vec![0u8; FEE_TIER_COUNT * Fees::LEN];
+                        for (fee_tier, output) in
+                            fee_tiers.iter().zip(fee_tiers_slice.chunks_mut(Fees::LEN))
+                        {
+                            Pack::pack_into_slice(fee_tier, output);
+                        }
+                        fee_tiers_slice
+                    }),
+                    max_lp_per_deposit.map(|value| value.to_le_bytes().to_vec()),
+                    allowed_lp_decimals.map(|value| value.to_le_bytes().to_vec()),
+                ];
+                // the fields are positional, nothing after an absent one can
+                // be expressed
+                for field in optional_fields.iter().take_while(|field| field.is_some()).flatten() {
+                    buf.extend_from_slice(field);
+                }
+            }
+            Self::BatchDeposit(BatchDeposit { deposits }) => {
+                buf.push(5);
+                buf.push(deposits.len() as u8);
+                for deposit in deposits {
+                    buf.extend_from_slice(&deposit.pool_token_amount.to_le_bytes());
+                    buf.extend_from_slice(&deposit.maximum_token_a_amount.to_le_bytes());
+                    buf.extend_from_slice(&deposit.maximum_token_b_amount.to_le_bytes());
+                }
+            }
+            Self::SetPoolMetadata(SetPoolMetadata { name, symbol }) => {
+                buf.push(6);
+                buf.push(name.len() as u8);
+                buf.extend_from_slice(name.as_bytes());
+                buf.push(symbol.len() as u8);
+                buf.extend_from_slice(symbol.as_bytes());
+            }
+            Self::LockLiquidity(LockLiquidity { amount, unlock_ts }) => {
+                buf.push(7);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&unlock_ts.to_le_bytes());
+            }
+            Self::Unlock => buf.push(8),
+            Self::RescueTokens(RescueTokens { mint }) => {
+                buf.push(9);
+                buf.extend_from_slice(mint.as_ref());
             }
+            Self::ZapDeposit(ZapDeposit {
+                source_amount,
+                minimum_pool_token_amount,
+            }) => {
+                buf.push(10);
+                buf.extend_from_slice(&source_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+            }
+            Self::FeeSnapshot => buf.push(11),
         }
         buf
     }
 }
 
 /// Creates an 'initialize' instruction.
+///
+/// `token_a_pubkey` and `token_b_pubkey` fix which reserve is A and which is
+/// B for the lifetime of the pool, every later instruction must pass them in
+/// the same order.
 pub fn initialize(
     program_id: &Pubkey,
     token_program_id: &Pubkey,
@@ -350,25 +953,55 @@ pub fn initialize(
     token_a_pubkey: &Pubkey,
     token_b_pubkey: &Pubkey,
     pool_pubkey: &Pubkey,
-    fee_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    creator_pubkey: Option<&Pubkey>,
+    reserve_mints: Option<(&Pubkey, &Pubkey)>,
     swap_curve: SwapCurve,
+    fee_tier_index: u8,
+    min_lp_supply: Option<u64>,
+    native_amount: u64,
+    nonce: Option<u8>,
 ) -> Result<Instruction, ProgramError> {
+    if native_amount > 0 && creator_pubkey.is_none() {
+        return Err(SwapError::InvalidNativeAmount.into());
+    }
     let init_data = SwapInstruction::Initialize(Initialize {
-        swap_curve
+        swap_curve,
+        fee_tier_index,
+        min_lp_supply,
+        native_amount,
+        nonce,
     });
     let data = init_data.pack();
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*swap_pubkey, true),
         AccountMeta::new_readonly(*authority_pubkey, false),
-        AccountMeta::new_readonly(*token_a_pubkey, false),
+        AccountMeta::new_readonly(global_state_pubkey, false),
+        AccountMeta {
+            pubkey: *token_a_pubkey,
+            is_signer: false,
+            is_writable: native_amount > 0,
+        },
         AccountMeta::new_readonly(*token_b_pubkey, false),
         AccountMeta::new(*pool_pubkey, false),
-        AccountMeta::new_readonly(*fee_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
     ];
+    if let Some(creator_pubkey) = creator_pubkey {
+        if native_amount > 0 {
+            accounts.push(AccountMeta::new(*creator_pubkey, true));
+            accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        } else {
+            accounts.push(AccountMeta::new_readonly(*creator_pubkey, true));
+        }
+    }
+    if let Some((token_a_mint_pubkey, token_b_mint_pubkey)) = reserve_mints {
+        accounts.push(AccountMeta::new_readonly(*token_a_mint_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*token_b_mint_pubkey, false));
+    }
 
     Ok(Instruction {
         program_id: *program_id,
@@ -378,6 +1011,10 @@ pub fn initialize(
 }
 
 /// Creates a 'deposit_all_token_types' instruction.
+///
+/// The user and swap accounts must be given in the pool's A, B order, the
+/// program rejects reserves passed the other way round with
+/// `IncorrectSwapAccount`.
 pub fn deposit_all_token_types(
     program_id: &Pubkey,
     token_program_id: &Pubkey,
@@ -391,12 +1028,15 @@ pub fn deposit_all_token_types(
     pool_mint_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
     instruction: DepositAllTokenTypes,
+    swap_state: &SwapV1,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::DepositAllTokenTypes(instruction).pack();
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(global_state_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*deposit_token_a_pubkey, false),
         AccountMeta::new(*deposit_token_b_pubkey, false),
@@ -406,6 +1046,37 @@ pub fn deposit_all_token_types(
         AccountMeta::new(*destination_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
     ];
+    push_reserve_accounts(&mut accounts, swap_state);
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'batch_deposit' instruction out of 'deposit_all_token_types'
+/// instructions, one per pool.
+pub fn batch_deposit(
+    program_id: &Pubkey,
+    deposits: &[Instruction],
+) -> Result<Instruction, ProgramError> {
+    if deposits.is_empty() || deposits.len() > MAX_BATCH_DEPOSITS {
+        return Err(SwapError::InvalidInstruction.into());
+    }
+    let mut batch = Vec::with_capacity(deposits.len());
+    let mut accounts = vec![];
+    for deposit in deposits {
+        if deposit.program_id != *program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        match SwapInstruction::unpack(&deposit.data)? {
+            SwapInstruction::DepositAllTokenTypes(instruction) => batch.push(instruction),
+            _ => return Err(SwapError::InvalidInstruction.into()),
+        }
+        accounts.extend_from_slice(&deposit.accounts);
+    }
+    let data = SwapInstruction::BatchDeposit(BatchDeposit { deposits: batch }).pack();
 
     Ok(Instruction {
         program_id: *program_id,
@@ -415,6 +1086,10 @@ pub fn deposit_all_token_types(
 }
 
 /// Creates a 'withdraw_all_token_types' instruction.
+///
+/// The swap and destination accounts must be given in the pool's A, B order,
+/// the program rejects reserves passed the other way round with
+/// `IncorrectSwapAccount`.
 pub fn withdraw_all_token_types(
     program_id: &Pubkey,
     token_program_id: &Pubkey,
@@ -422,19 +1097,21 @@ pub fn withdraw_all_token_types(
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
     pool_mint_pubkey: &Pubkey,
-    fee_account_pubkey: &Pubkey,
     source_pubkey: &Pubkey,
     swap_token_a_pubkey: &Pubkey,
     swap_token_b_pubkey: &Pubkey,
     destination_token_a_pubkey: &Pubkey,
     destination_token_b_pubkey: &Pubkey,
     instruction: WithdrawAllTokenTypes,
+    swap_state: &SwapV1,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::WithdrawAllTokenTypes(instruction).pack();
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(global_state_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*pool_mint_pubkey, false),
         AccountMeta::new(*source_pubkey, false),
@@ -442,9 +1119,9 @@ pub fn withdraw_all_token_types(
         AccountMeta::new(*swap_token_b_pubkey, false),
         AccountMeta::new(*destination_token_a_pubkey, false),
         AccountMeta::new(*destination_token_b_pubkey, false),
-        AccountMeta::new(*fee_account_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
     ];
+    push_reserve_accounts(&mut accounts, swap_state);
 
     Ok(Instruction {
         program_id: *program_id,
@@ -470,11 +1147,13 @@ pub fn swap(
     instruction: Swap,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::Swap(instruction).pack();
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
 
     let mut accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new_readonly(global_state_pubkey, false),
         AccountMeta::new(*source_pubkey, false),
         AccountMeta::new(*swap_source_pubkey, false),
         AccountMeta::new(*swap_destination_pubkey, false),
@@ -494,31 +1173,54 @@ pub fn swap(
     })
 }
 
+/// Checks the accounts of a 'swap' instruction against what the processor
+/// expects, so clients can catch a malformed instruction before sending it.
+/// Only the shape is checked: the number of accounts, the user transfer
+/// authority signing, and the accounts written by the swap being writable.
+/// The optional trailing accounts, a host fee account, the token program of a
+/// reserve outside the pool's token program and the mints of Token-2022
+/// reserves, are only counted.
+pub fn validate_swap_accounts(accounts: &[AccountMeta]) -> Result<(), ProgramError> {
+    const REQUIRED_ACCOUNTS: usize = 11;
+    const OPTIONAL_ACCOUNTS: usize = 4;
+    const USER_TRANSFER_AUTHORITY: usize = 2;
+    const WRITABLE: [usize; 6] = [0, 4, 5, 6, 7, 9];
+
+    if accounts.len() < REQUIRED_ACCOUNTS {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    if accounts.len() > REQUIRED_ACCOUNTS + OPTIONAL_ACCOUNTS {
+        return Err(SwapError::InvalidInstruction.into());
+    }
+    if !accounts[USER_TRANSFER_AUTHORITY].is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let writable_ok = WRITABLE
+        .iter()
+        .map(|index| &accounts[*index])
+        .all(|meta| meta.is_writable);
+    if !writable_ok {
+        return Err(SwapError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
 /// Creates an 'SetGlobalStateInstruction' instruction.
 pub fn set_global_state(
     program_id: &Pubkey,
     state_account_pubkey: &Pubkey,
     current_owner_pubkey: &Pubkey,
-    owner_pubkey: &Pubkey,
-    fee_owner_pubkey: &Pubkey,
-    initial_supply: u64,
-    lp_decimals: u8,
-    fees: Fees,
+    instruction: SetGlobalState,
 ) -> Result<Instruction, ProgramError> {
-    let init_data = SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
-        owner:*owner_pubkey,
-        fee_owner:*fee_owner_pubkey,
-        initial_supply,
-        lp_decimals,
-        fees,
-    });
+    let init_data = SwapInstruction::SetGlobalStateInstruction(instruction);
     let data = init_data.pack();
 
     let accounts = vec![
         AccountMeta::new(*state_account_pubkey, false),
-        AccountMeta::new_readonly(*current_owner_pubkey, true),
+        AccountMeta::new(*current_owner_pubkey, true),
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::instructions::id(), false),
     ];
 
     Ok(Instruction {
@@ -527,3 +1229,818 @@ pub fn set_global_state(
         data,
     })
 }
+
+/// Creates a 'set_pool_metadata' instruction.
+pub fn set_pool_metadata(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    name: String,
+    symbol: String,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetPoolMetadata(SetPoolMetadata { name, symbol }).pack();
+    let (metadata_pubkey, _) = find_pool_metadata_address(program_id, swap_pubkey);
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
+
+    let accounts = vec![
+        AccountMeta::new(metadata_pubkey, false),
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(global_state_pubkey, false),
+        AccountMeta::new(*owner_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::instructions::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'lock_liquidity' instruction.
+pub fn lock_liquidity(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    instruction: LockLiquidity,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::LockLiquidity(instruction).pack();
+    let (lock_pubkey, _) = find_liquidity_lock_address(program_id, swap_pubkey, owner_pubkey);
+    let (escrow_pubkey, _) = find_liquidity_escrow_address(program_id, swap_pubkey, owner_pubkey);
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(lock_pubkey, false),
+        AccountMeta::new(*owner_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(escrow_pubkey, false),
+        AccountMeta::new_readonly(*pool_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'unlock' instruction.
+pub fn unlock(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Unlock.pack();
+    let (lock_pubkey, _) = find_liquidity_lock_address(program_id, swap_pubkey, owner_pubkey);
+    let (escrow_pubkey, _) = find_liquidity_escrow_address(program_id, swap_pubkey, owner_pubkey);
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(lock_pubkey, false),
+        AccountMeta::new(*owner_pubkey, true),
+        AccountMeta::new(escrow_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'rescue_tokens' instruction.
+pub fn rescue_tokens(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    stray_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    mint: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::RescueTokens(RescueTokens { mint }).pack();
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(global_state_pubkey, false),
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new(*stray_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(sysvar::instructions::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'zap_deposit' instruction.
+///
+/// The swap accounts must be given in the pool's A, B order, whichever token
+/// `source_pubkey` holds.
+pub fn zap_deposit(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    refund_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: ZapDeposit,
+    swap_state: &SwapV1,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::ZapDeposit(instruction).pack();
+    let (global_state_pubkey, _) = find_global_state_address(program_id);
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(global_state_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*refund_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    push_reserve_accounts(&mut accounts, swap_state);
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'fee_snapshot' instruction.
+pub fn fee_snapshot(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::FeeSnapshot.pack();
+    let (snapshot_pubkey, _) = find_fee_snapshot_address(program_id, swap_pubkey);
+
+    let accounts = vec![
+        AccountMeta::new(snapshot_pubkey, false),
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Appends the accounts a pool's reserves need beyond its own token program:
+/// the program of each reserve outside it, then the mint of each Token-2022
+/// reserve, which moves its tokens through `TransferChecked`.  Nothing is
+/// appended for a pool of spl_token reserves.
+pub fn push_reserve_accounts(accounts: &mut Vec<AccountMeta>, swap_state: &SwapV1) {
+    let reserves = [
+        (swap_state.token_a_program_id(), swap_state.token_a_mint()),
+        (swap_state.token_b_program_id(), swap_state.token_b_mint()),
+    ];
+    for (reserve_program_id, _) in reserves.iter() {
+        if !accounts.iter().any(|meta| meta.pubkey == **reserve_program_id) {
+            accounts.push(AccountMeta::new_readonly(**reserve_program_id, false));
+        }
+    }
+    for (reserve_program_id, mint) in reserves.iter() {
+        if crate::token_2022::check_id(reserve_program_id) {
+            accounts.push(AccountMeta::new_readonly(**mint, false));
+        }
+    }
+}
+
+/// Creates a 'swap' instruction selling token A for token B, taking the
+/// reserves, pool mint and token program from the parsed swap state.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_a_to_b(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_state: &SwapV1,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_a_pubkey: &Pubkey,
+    destination_b_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: Swap,
+) -> Result<Instruction, ProgramError> {
+    swap_in_direction(
+        program_id,
+        swap_pubkey,
+        swap_state,
+        user_transfer_authority_pubkey,
+        source_a_pubkey,
+        destination_b_pubkey,
+        pool_fee_pubkey,
+        TradeDirection::AtoB,
+        instruction,
+    )
+}
+
+/// Creates a 'swap' instruction selling token B for token A, taking the
+/// reserves, pool mint and token program from the parsed swap state.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_b_to_a(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_state: &SwapV1,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_b_pubkey: &Pubkey,
+    destination_a_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: Swap,
+) -> Result<Instruction, ProgramError> {
+    swap_in_direction(
+        program_id,
+        swap_pubkey,
+        swap_state,
+        user_transfer_authority_pubkey,
+        source_b_pubkey,
+        destination_a_pubkey,
+        pool_fee_pubkey,
+        TradeDirection::BtoA,
+        instruction,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn swap_in_direction(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_state: &SwapV1,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    trade_direction: TradeDirection,
+    instruction: Swap,
+) -> Result<Instruction, ProgramError> {
+    let authority_pubkey =
+        Pubkey::create_program_address(&[swap_pubkey.as_ref(), &[swap_state.nonce]], program_id)
+            .map_err(|_| SwapError::InvalidProgramAddress)?;
+    let (swap_source_pubkey, swap_destination_pubkey) = match trade_direction {
+        TradeDirection::AtoB => (&swap_state.token_a, &swap_state.token_b),
+        TradeDirection::BtoA => (&swap_state.token_b, &swap_state.token_a),
+    };
+    let mut swap_instruction = swap(
+        program_id,
+        &swap_state.token_program_id,
+        swap_pubkey,
+        &authority_pubkey,
+        user_transfer_authority_pubkey,
+        source_pubkey,
+        swap_source_pubkey,
+        swap_destination_pubkey,
+        destination_pubkey,
+        &swap_state.pool_mint,
+        pool_fee_pubkey,
+        None,
+        instruction,
+    )?;
+    push_reserve_accounts(&mut swap_instruction.accounts, swap_state);
+    Ok(swap_instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{base::CurveType, constant_product::ConstantProductCurve};
+
+    const TEST_FEES: Fees = Fees {
+        constant_product_return_fee_numerator: 25,
+        constant_product_fixed_fee_numerator: 5,
+        stable_return_fee_numerator: 4,
+        stable_fixed_fee_numerator: 1,
+        fee_denominator: 10_000,
+    };
+
+    fn legacy_set_global_state() -> SetGlobalState {
+        SetGlobalState {
+            owner: Pubkey::new_unique(),
+            fee_owner: Pubkey::new_unique(),
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: TEST_FEES,
+            max_reserve_a: None,
+            max_reserve_b: None,
+            fee_holiday_start: None,
+            fee_holiday_end: None,
+            dynamic_fee_min_numerator: None,
+            dynamic_fee_max_numerator: None,
+            fee_tiers: None,
+            max_lp_per_deposit: None,
+            allowed_lp_decimals: None,
+        }
+    }
+
+    #[test]
+    fn pack_set_global_state_legacy_layout() {
+        let instruction = legacy_set_global_state();
+        let mut expect = vec![4];
+        expect.extend_from_slice(instruction.owner.as_ref());
+        expect.extend_from_slice(instruction.fee_owner.as_ref());
+        expect.extend_from_slice(&instruction.initial_supply.to_le_bytes());
+        expect.push(instruction.lp_decimals);
+        let mut fees_slice = [0u8; Fees::LEN];
+        TEST_FEES.pack_into_slice(&mut fees_slice);
+        expect.extend_from_slice(&fees_slice);
+
+        let instruction = SwapInstruction::SetGlobalStateInstruction(instruction);
+        let packed = instruction.pack();
+        assert_eq!(packed, expect);
+        assert_eq!(SwapInstruction::unpack(&packed).unwrap(), instruction);
+    }
+
+    #[test]
+    fn pack_set_global_state_latest_layout() {
+        let instruction = SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
+            max_reserve_a: Some(1),
+            max_reserve_b: Some(2),
+            fee_holiday_start: Some(-3),
+            fee_holiday_end: Some(4),
+            dynamic_fee_min_numerator: Some(5),
+            dynamic_fee_max_numerator: Some(6),
+            fee_tiers: Some([TEST_FEES, Fees::default(), TEST_FEES]),
+            max_lp_per_deposit: Some(7),
+            allowed_lp_decimals: Some(1 << 6),
+            ..legacy_set_global_state()
+        });
+        let packed = instruction.pack();
+        assert_eq!(
+            packed.len(),
+            1 + 32 + 32 + 8 + 1 + Fees::LEN + 6 * 8 + FEE_TIER_COUNT * Fees::LEN + 8 + 4
+        );
+        assert_eq!(SwapInstruction::unpack(&packed).unwrap(), instruction);
+    }
+
+    #[test]
+    fn pack_set_global_state_partial_layout() {
+        // a client that only knows the reserve caps
+        let instruction = SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
+            max_reserve_a: Some(1),
+            max_reserve_b: Some(2),
+            ..legacy_set_global_state()
+        });
+        let packed = instruction.pack();
+        assert_eq!(SwapInstruction::unpack(&packed).unwrap(), instruction);
+
+        // fields after an absent one cannot be expressed
+        let legacy = legacy_set_global_state();
+        let instruction = SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
+            max_reserve_a: Some(1),
+            max_lp_per_deposit: Some(7),
+            owner: legacy.owner,
+            fee_owner: legacy.fee_owner,
+            ..legacy_set_global_state()
+        });
+        let packed = instruction.pack();
+        let expect = SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
+            max_reserve_a: Some(1),
+            ..legacy
+        });
+        assert_eq!(SwapInstruction::unpack(&packed).unwrap(), expect);
+    }
+
+    fn test_swap() -> Swap {
+        Swap {
+            amount_in: 1_000,
+            minimum_amount_out: 900,
+            max_slippage_bps: 50,
+            allow_partial: false,
+        }
+    }
+
+    /// A pool of spl_token reserves as parsed by a client
+    fn test_swap_state(program_id: &Pubkey, swap_pubkey: &Pubkey) -> SwapV1 {
+        let (_, nonce) = crate::pda::find_swap_authority_address(program_id, swap_pubkey);
+        SwapV1 {
+            is_initialized: true,
+            nonce,
+            token_program_id: spl_token::id(),
+            token_a: Pubkey::new_unique(),
+            token_b: Pubkey::new_unique(),
+            pool_mint: Pubkey::new_unique(),
+            token_a_mint: Pubkey::new_unique(),
+            token_b_mint: Pubkey::new_unique(),
+            token_a_program_id: spl_token::id(),
+            token_b_program_id: spl_token::id(),
+            ..SwapV1::default()
+        }
+    }
+
+    #[test]
+    fn withdraw_all_token_types_accounts() {
+        let program_id = crate::id();
+        let keys = (0..9).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let instruction = withdraw_all_token_types(
+            &program_id,
+            &spl_token::id(),
+            &keys[0],
+            &keys[1],
+            &keys[2],
+            &keys[3],
+            &keys[4],
+            &keys[5],
+            &keys[6],
+            &keys[7],
+            &keys[8],
+            WithdrawAllTokenTypes {
+                pool_token_amount: 1,
+                minimum_token_a_amount: 0,
+                minimum_token_b_amount: 0,
+                deadline: 0,
+            },
+            &test_swap_state(&program_id, &keys[0]),
+        )
+        .unwrap();
+        // the order the processor reads them in, there is no fee account
+        let (global_state, _) = find_global_state_address(&program_id);
+        assert_eq!(
+            instruction.accounts,
+            vec![
+                AccountMeta::new_readonly(keys[0], false),
+                AccountMeta::new_readonly(keys[1], false),
+                AccountMeta::new_readonly(global_state, false),
+                AccountMeta::new_readonly(keys[2], true),
+                AccountMeta::new(keys[3], false),
+                AccountMeta::new(keys[4], false),
+                AccountMeta::new(keys[5], false),
+                AccountMeta::new(keys[6], false),
+                AccountMeta::new(keys[7], false),
+                AccountMeta::new(keys[8], false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_builders_target_reserves() {
+        let program_id = crate::id();
+        let swap_pubkey = Pubkey::new_unique();
+        let swap_state = test_swap_state(&program_id, &swap_pubkey);
+        let (authority, _) = crate::pda::find_swap_authority_address(&program_id, &swap_pubkey);
+        let user = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let pool_fee = Pubkey::new_unique();
+
+        let a_to_b = swap_a_to_b(
+            &program_id,
+            &swap_pubkey,
+            &swap_state,
+            &user,
+            &source,
+            &destination,
+            &pool_fee,
+            test_swap(),
+        )
+        .unwrap();
+        let b_to_a = swap_b_to_a(
+            &program_id,
+            &swap_pubkey,
+            &swap_state,
+            &user,
+            &source,
+            &destination,
+            &pool_fee,
+            test_swap(),
+        )
+        .unwrap();
+        for (instruction, swap_source, swap_destination) in [
+            (&a_to_b, swap_state.token_a, swap_state.token_b),
+            (&b_to_a, swap_state.token_b, swap_state.token_a),
+        ]
+        .iter()
+        {
+            let keys = instruction
+                .accounts
+                .iter()
+                .map(|meta| meta.pubkey)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                keys,
+                vec![
+                    swap_pubkey,
+                    authority,
+                    user,
+                    find_global_state_address(&program_id).0,
+                    source,
+                    *swap_source,
+                    *swap_destination,
+                    destination,
+                    swap_state.pool_mint,
+                    pool_fee,
+                    spl_token::id(),
+                ]
+            );
+            validate_swap_accounts(&instruction.accounts).unwrap();
+            assert_eq!(
+                SwapInstruction::unpack(&instruction.data).unwrap(),
+                SwapInstruction::Swap(test_swap())
+            );
+        }
+    }
+
+    #[test]
+    fn swap_builders_pass_reserve_token_programs() {
+        let program_id = crate::id();
+        let swap_pubkey = Pubkey::new_unique();
+        let mut swap_state = test_swap_state(&program_id, &swap_pubkey);
+        let user = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let pool_fee = Pubkey::new_unique();
+
+        let instruction = swap_a_to_b(
+            &program_id,
+            &swap_pubkey,
+            &swap_state,
+            &user,
+            &source,
+            &destination,
+            &pool_fee,
+            test_swap(),
+        )
+        .unwrap();
+        assert_eq!(instruction.accounts.len(), 11);
+
+        // a Token-2022 reserve also needs its mint for `TransferChecked`
+        swap_state.token_b_program_id = crate::token_2022::id();
+        let reserve_accounts = [
+            AccountMeta::new_readonly(crate::token_2022::id(), false),
+            AccountMeta::new_readonly(swap_state.token_b_mint, false),
+        ];
+        let instruction = swap_a_to_b(
+            &program_id,
+            &swap_pubkey,
+            &swap_state,
+            &user,
+            &source,
+            &destination,
+            &pool_fee,
+            test_swap(),
+        )
+        .unwrap();
+        assert_eq!(instruction.accounts[11..], reserve_accounts[..]);
+        validate_swap_accounts(&instruction.accounts).unwrap();
+
+        let deposit = deposit_all_token_types(
+            &program_id,
+            &spl_token::id(),
+            &swap_pubkey,
+            &Pubkey::new_unique(),
+            &user,
+            &source,
+            &destination,
+            &swap_state.token_a,
+            &swap_state.token_b,
+            &swap_state.pool_mint,
+            &Pubkey::new_unique(),
+            DepositAllTokenTypes {
+                pool_token_amount: 1,
+                maximum_token_a_amount: 1,
+                maximum_token_b_amount: 1,
+            },
+            &swap_state,
+        )
+        .unwrap();
+        assert_eq!(deposit.accounts[11..], reserve_accounts[..]);
+
+        let withdraw = withdraw_all_token_types(
+            &program_id,
+            &spl_token::id(),
+            &swap_pubkey,
+            &Pubkey::new_unique(),
+            &user,
+            &swap_state.pool_mint,
+            &Pubkey::new_unique(),
+            &swap_state.token_a,
+            &swap_state.token_b,
+            &source,
+            &destination,
+            WithdrawAllTokenTypes {
+                pool_token_amount: 1,
+                minimum_token_a_amount: 0,
+                minimum_token_b_amount: 0,
+                deadline: 0,
+            },
+            &swap_state,
+        )
+        .unwrap();
+        assert_eq!(withdraw.accounts[11..], reserve_accounts[..]);
+
+        let zap = zap_deposit(
+            &program_id,
+            &spl_token::id(),
+            &swap_pubkey,
+            &Pubkey::new_unique(),
+            &user,
+            &source,
+            &destination,
+            &swap_state.token_a,
+            &swap_state.token_b,
+            &swap_state.pool_mint,
+            &Pubkey::new_unique(),
+            &pool_fee,
+            ZapDeposit {
+                source_amount: 1,
+                minimum_pool_token_amount: 0,
+            },
+            &swap_state,
+        )
+        .unwrap();
+        assert_eq!(zap.accounts[12..], reserve_accounts[..]);
+    }
+
+    #[test]
+    fn validate_swap_accounts_rejects_malformed() {
+        let program_id = crate::id();
+        let swap_pubkey = Pubkey::new_unique();
+        let swap_state = test_swap_state(&program_id, &swap_pubkey);
+        let accounts = swap_a_to_b(
+            &program_id,
+            &swap_pubkey,
+            &swap_state,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            test_swap(),
+        )
+        .unwrap()
+        .accounts;
+        validate_swap_accounts(&accounts).unwrap();
+
+        // host fee, one reserve token program and two Token-2022 mints
+        let mut with_optional = accounts.clone();
+        with_optional.push(AccountMeta::new(Pubkey::new_unique(), false));
+        for _ in 0..3 {
+            with_optional.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+        }
+        validate_swap_accounts(&with_optional).unwrap();
+
+        let mut too_many = with_optional;
+        too_many.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+        assert_eq!(
+            validate_swap_accounts(&too_many),
+            Err(SwapError::InvalidInstruction.into())
+        );
+
+        assert_eq!(
+            validate_swap_accounts(&accounts[..10]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut unsigned = accounts.clone();
+        unsigned[2].is_signer = false;
+        assert_eq!(
+            validate_swap_accounts(&unsigned),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        for index in [0, 4, 5, 6, 7, 9].iter() {
+            let mut read_only = accounts.clone();
+            read_only[*index].is_writable = false;
+            assert_eq!(
+                validate_swap_accounts(&read_only),
+                Err(SwapError::AccountNotWritable.into())
+            );
+        }
+    }
+
+    #[test]
+    fn fee_snapshot_accounts() {
+        let program_id = crate::id();
+        let swap_pubkey = Pubkey::new_unique();
+        let payer = Pubkey::new_unique();
+        let instruction = fee_snapshot(&program_id, &swap_pubkey, &payer).unwrap();
+        assert_eq!(instruction.data, vec![11]);
+        assert_eq!(
+            SwapInstruction::unpack(&instruction.data).unwrap(),
+            SwapInstruction::FeeSnapshot
+        );
+        let (snapshot, _) = find_fee_snapshot_address(&program_id, &swap_pubkey);
+        assert_eq!(
+            instruction.accounts,
+            vec![
+                AccountMeta::new(snapshot, false),
+                AccountMeta::new_readonly(swap_pubkey, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn pack_initialize_nonce() {
+        let initialize = |nonce| {
+            SwapInstruction::Initialize(Initialize {
+                swap_curve: SwapCurve {
+                    curve_type: CurveType::ConstantProduct,
+                    calculator: Box::new(ConstantProductCurve {}),
+                },
+                fee_tier_index: 1,
+                min_lp_supply: Some(u64::MAX),
+                native_amount: 2,
+                nonce,
+            })
+        };
+        let instruction = initialize(Some(254));
+        let packed = instruction.pack();
+        assert_eq!(packed.len(), 1 + SwapCurve::LEN + 1 + 9 + 8 + 1);
+        assert_eq!(SwapInstruction::unpack(&packed).unwrap(), instruction);
+
+        // clients that do not send a nonce leave it to the program
+        let packed = initialize(None).pack();
+        assert_eq!(packed.len(), 1 + SwapCurve::LEN + 1 + 9 + 8);
+        assert_eq!(SwapInstruction::unpack(&packed).unwrap(), initialize(None));
+    }
+
+    #[test]
+    fn unpack_set_global_state_truncated_field() {
+        let instruction = SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
+            max_reserve_a: Some(1),
+            ..legacy_set_global_state()
+        });
+        let packed = instruction.pack();
+        assert_eq!(
+            SwapInstruction::unpack(&packed[..packed.len() - 1]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+        // the fees themselves are not optional
+        let legacy_len = packed.len() - 8;
+        assert_eq!(
+            SwapInstruction::unpack(&packed[..legacy_len - 1]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_swap_return_data() {
+        let return_data = SwapReturnData {
+            filled: 1,
+            unfilled: u64::MAX,
+            new_reserve_a: 2,
+            new_reserve_b: 3,
+        };
+        let packed = return_data.pack();
+        assert_eq!(packed.len(), SwapReturnData::LEN);
+        assert_eq!(SwapReturnData::unpack(&packed).unwrap(), return_data);
+        assert_eq!(
+            SwapReturnData::unpack(&packed[..SwapReturnData::LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn pack_zap_deposit_return_data() {
+        let return_data = ZapDepositReturnData {
+            pool_token_amount: u64::MAX,
+            swap_amount: 1,
+        };
+        let packed = return_data.pack();
+        assert_eq!(packed.len(), ZapDepositReturnData::LEN);
+        assert_eq!(ZapDepositReturnData::unpack(&packed).unwrap(), return_data);
+        assert_eq!(
+            ZapDepositReturnData::unpack(&[0u8; ZapDepositReturnData::LEN + 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+}
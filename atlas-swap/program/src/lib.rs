@@ -2,12 +2,16 @@
 
 //! An Uniswap-like program for the Solana blockchain.
 
+#[cfg(feature = "client")]
+pub mod client;
 pub mod constraints;
 pub mod curve;
 pub mod error;
 pub mod instruction;
+pub mod pda;
 pub mod processor;
 pub mod state;
+pub mod token_2022;
 
 #[cfg(not(feature = "no-entrypoint"))]
 mod entrypoint;
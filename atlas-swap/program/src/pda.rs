@@ -0,0 +1,117 @@
+//! Program derived addresses used by the swap program
+
+use crate::constraints::{
+    FEE_SNAPSHOT_TAG, GLOBAL_STATE_SEED, LIQUIDITY_ESCROW_TAG, LIQUIDITY_LOCK_TAG,
+    POOL_METADATA_TAG,
+};
+use solana_program::pubkey::Pubkey;
+
+/// Finds the address of the global state account shared by every pool,
+/// along with its bump seed.
+pub fn find_global_state_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[GLOBAL_STATE_SEED, program_id.as_ref()], program_id)
+}
+
+/// Finds the authority of a pool, which owns its reserves and mints its pool
+/// tokens, along with its bump seed, the nonce stored in the swap account.
+pub fn find_swap_authority_address(program_id: &Pubkey, swap: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[swap.as_ref()], program_id)
+}
+
+/// Checks that `nonce` is the canonical bump seed of the pool's authority, as
+/// stored by the program at initialization.
+pub fn verify_nonce(program_id: &Pubkey, swap: &Pubkey, nonce: u8) -> bool {
+    find_swap_authority_address(program_id, swap).1 == nonce
+}
+
+/// Finds the address of the optional metadata account of a pool, along with
+/// its bump seed.
+pub fn find_pool_metadata_address(program_id: &Pubkey, swap: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POOL_METADATA_TAG.as_bytes(), swap.as_ref()], program_id)
+}
+
+/// Finds the address of the account locking an owner's pool tokens of a
+/// pool, along with its bump seed.
+pub fn find_liquidity_lock_address(
+    program_id: &Pubkey,
+    swap: &Pubkey,
+    owner: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[LIQUIDITY_LOCK_TAG.as_bytes(), swap.as_ref(), owner.as_ref()],
+        program_id,
+    )
+}
+
+/// Finds the address of the pool token account holding an owner's locked
+/// pool tokens of a pool, along with its bump seed.
+pub fn find_liquidity_escrow_address(
+    program_id: &Pubkey,
+    swap: &Pubkey,
+    owner: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[LIQUIDITY_ESCROW_TAG.as_bytes(), swap.as_ref(), owner.as_ref()],
+        program_id,
+    )
+}
+
+/// Finds the address of the account keeping the fee snapshots of a pool,
+/// along with its bump seed.
+pub fn find_fee_snapshot_address(program_id: &Pubkey, swap: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEE_SNAPSHOT_TAG.as_bytes(), swap.as_ref()], program_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_canonical_nonce() {
+        let program_id = crate::id();
+        let swap = Pubkey::new_unique();
+        let (_, nonce) = find_swap_authority_address(&program_id, &swap);
+        assert!(verify_nonce(&program_id, &swap, nonce));
+        assert!(!verify_nonce(&program_id, &swap, nonce.wrapping_sub(1)));
+    }
+
+    #[test]
+    fn seeds_are_pinned() {
+        // changing a seed moves every account derived from it, the misspelt
+        // swap tag included
+        assert_eq!(GLOBAL_STATE_SEED, b"atals-swap");
+        assert_eq!(POOL_METADATA_TAG.as_bytes(), b"pool-metadata");
+        assert_eq!(LIQUIDITY_LOCK_TAG.as_bytes(), b"liquidity-lock");
+        assert_eq!(LIQUIDITY_ESCROW_TAG.as_bytes(), b"liquidity-escrow");
+        assert_eq!(FEE_SNAPSHOT_TAG.as_bytes(), b"fee-snapshot");
+
+        let program_id = crate::id();
+        let swap = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let find = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, &program_id);
+        assert_eq!(
+            find_global_state_address(&program_id),
+            find(&[b"atals-swap", program_id.as_ref()])
+        );
+        assert_eq!(
+            find_swap_authority_address(&program_id, &swap),
+            find(&[swap.as_ref()])
+        );
+        assert_eq!(
+            find_pool_metadata_address(&program_id, &swap),
+            find(&[b"pool-metadata", swap.as_ref()])
+        );
+        assert_eq!(
+            find_liquidity_lock_address(&program_id, &swap, &owner),
+            find(&[b"liquidity-lock", swap.as_ref(), owner.as_ref()])
+        );
+        assert_eq!(
+            find_liquidity_escrow_address(&program_id, &swap, &owner),
+            find(&[b"liquidity-escrow", swap.as_ref(), owner.as_ref()])
+        );
+        assert_eq!(
+            find_fee_snapshot_address(&program_id, &swap),
+            find(&[b"fee-snapshot", swap.as_ref()])
+        );
+    }
+}
@@ -3,18 +3,25 @@
 use crate::constraints::*;
 use crate::{
     curve::{
-        base::{SwapCurve},
-        calculator::{RoundDirection, TradeDirection},
-        fees::Fees,
+        base::{CurveType, SwapCurve, SwapResult},
+        calculator::{RoundDirection, TradeDirection, BASIS_POINTS},
+        fees::{DynamicFee, Fees},
     },
     error::SwapError,
+    pda::{find_swap_authority_address, verify_nonce},
     instruction::{
-        DepositAllTokenTypes, Initialize, Swap,
-        SwapInstruction, WithdrawAllTokenTypes, SetGlobalState
+        BatchDeposit, DepositAllTokenTypes, Initialize, LockLiquidity, RescueTokens, Swap,
+        SwapInstruction, SwapReturnData, WithdrawAllTokenTypes, SetGlobalState, SetPoolMetadata,
+        ZapDeposit, ZapDepositReturnData,
     },
-    state::{SwapState, SwapV1, SwapVersion, GlobalState},
+    state::{
+        FeeSnapshot, FeeSnapshotEntry, GlobalState, LiquidityLock, PoolMetadata, SwapState,
+        SwapV1, SwapVersion,
+    },
+    token_2022,
 };
 use num_traits::FromPrimitive;
+use spl_math::{precise_number::PreciseNumber, uint::U256};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     decode_error::DecodeError,
@@ -22,32 +29,200 @@ use solana_program::{
     msg,
     program::invoke_signed,
     program::invoke,
+    program::set_return_data,
     system_instruction,
+    system_program,
     program_error::{PrintProgramError, ProgramError},
     program_option::COption,
     program_pack::Pack,
     pubkey::Pubkey,
-    sysvar::{rent::Rent, Sysvar},
+    sysvar::{self, clock::Clock, rent::Rent, Sysvar},
 };
 use std::convert::TryInto;
-use std::str::FromStr;
+
+/// Number of accounts taken by a single `DepositAllTokenTypes`, which is also
+/// the size of each pool's account group in a `BatchDeposit`
+const DEPOSIT_ALL_TOKEN_TYPES_ACCOUNTS: usize = 11;
+
+/// Bisection steps spent looking for a partial fill, enough to fill within
+/// 1/4096 of the requested amount without exhausting the compute budget
+const MAX_PARTIAL_FILL_ITERATIONS: u32 = 12;
 
 /// Program state handler.
 pub struct Processor {}
 impl Processor {
-    /// Unpacks a spl_token `Account`.
+    /// Unpacks a spl_token `Account`, or the base of a Token-2022 account
+    /// whose extensions leave transfers unchanged.
     pub fn unpack_token_account(
         account_info: &AccountInfo,
         token_program_id: &Pubkey,
     ) -> Result<spl_token::state::Account, SwapError> {
         if account_info.owner != token_program_id {
             Err(SwapError::IncorrectTokenProgramId)
+        } else if token_2022::check_id(token_program_id) {
+            token_2022::unpack_account(&account_info.data.borrow())
         } else {
             spl_token::state::Account::unpack(&account_info.data.borrow())
                 .map_err(|_| SwapError::ExpectedAccount)
         }
     }
 
+    /// Unpacks two token accounts, typically the reserves of a pool, each
+    /// against its own token program, which handlers then pass along instead
+    /// of unpacking them again
+    pub fn unpack_token_accounts(
+        first_info: &AccountInfo,
+        first_program_id: &Pubkey,
+        second_info: &AccountInfo,
+        second_program_id: &Pubkey,
+    ) -> Result<(spl_token::state::Account, spl_token::state::Account), SwapError> {
+        Ok((
+            Self::unpack_token_account(first_info, first_program_id)?,
+            Self::unpack_token_account(second_info, second_program_id)?,
+        ))
+    }
+
+    /// Reloads reserves after the transfers of an instruction and checks them
+    /// against the amounts the handler accounted for, so that no transfer can
+    /// leave the pool out of line with its own accounting
+    pub fn assert_reserve_amounts(reserves: &[(&AccountInfo, &Pubkey, u128)]) -> ProgramResult {
+        for (reserve_info, token_program_id, expected_amount) in reserves {
+            let reserve = Self::unpack_token_account(reserve_info, token_program_id)?;
+            if to_u128(reserve.amount)? != *expected_amount {
+                return Err(SwapError::CalculationFailure.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a fee paid to the fee owner, in the source token of a swap, to the
+    /// counters of the swap.  Swap accounts allocated before the counters
+    /// existed are too short to hold them and are left as they are.
+    pub fn record_owner_fee(
+        swap_info: &AccountInfo,
+        trade_direction: TradeDirection,
+        owner_fee: u128,
+    ) -> ProgramResult {
+        if swap_info.data_len() < SwapVersion::LATEST_LEN {
+            return Ok(());
+        }
+        let mut data = swap_info.data.borrow_mut();
+        let mut token_swap = SwapV1::unpack_from_slice(&data[1..])?;
+        match trade_direction {
+            TradeDirection::AtoB => {
+                token_swap.cumulative_fees_a =
+                    token_swap.cumulative_fees_a.saturating_add(owner_fee)
+            }
+            TradeDirection::BtoA => {
+                token_swap.cumulative_fees_b =
+                    token_swap.cumulative_fees_b.saturating_add(owner_fee)
+            }
+        }
+        SwapVersion::pack(
+            SwapVersion::SwapV1(token_swap),
+            &mut data[..SwapVersion::LATEST_LEN],
+        )
+    }
+
+    /// Finds the program account to move a reserve's tokens with: the pool's
+    /// token program for its own reserves, otherwise the account of the
+    /// reserve's program among the trailing accounts of the instruction
+    pub fn reserve_token_program<'b, 'a>(
+        reserve_program_id: &Pubkey,
+        token_program_info: &'b AccountInfo<'a>,
+        trailing_infos: &'b [AccountInfo<'a>],
+    ) -> Result<&'b AccountInfo<'a>, SwapError> {
+        if token_program_info.key == reserve_program_id {
+            return Ok(token_program_info);
+        }
+        trailing_infos
+            .iter()
+            .find(|info| info.key == reserve_program_id)
+            .ok_or(SwapError::IncorrectTokenProgramId)
+    }
+
+    /// Finds the mint a reserve's transfers are checked against: Token-2022
+    /// only moves tokens through `TransferChecked`, so its reserves need their
+    /// mint among the trailing accounts, spl_token reserves need none
+    pub fn reserve_mint<'b, 'a>(
+        reserve_program_id: &Pubkey,
+        mint: &Pubkey,
+        trailing_infos: &'b [AccountInfo<'a>],
+    ) -> Result<Option<&'b AccountInfo<'a>>, ProgramError> {
+        if !token_2022::check_id(reserve_program_id) {
+            return Ok(None);
+        }
+        trailing_infos
+            .iter()
+            .find(|info| info.key == mint)
+            .map(Some)
+            .ok_or(ProgramError::NotEnoughAccountKeys)
+    }
+
+    /// Checks that a reserve belongs to the pool's token program, or else to
+    /// one of the token programs sharing the spl_token `Transfer` layout, the
+    /// swap authority must never sign for any other program
+    pub fn assert_reserve_token_program(
+        reserve_program_id: &Pubkey,
+        token_program_id: &Pubkey,
+    ) -> ProgramResult {
+        if reserve_program_id != token_program_id
+            && *reserve_program_id != spl_token::id()
+            && !token_2022::check_id(reserve_program_id)
+        {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a token account the swap pays into was not closed since
+    /// the transaction was built, so the failure is explicit
+    pub fn assert_token_account_open(
+        account_info: &AccountInfo,
+        token_program_id: &Pubkey,
+    ) -> ProgramResult {
+        if account_info.lamports() == 0 || account_info.owner != token_program_id {
+            return Err(SwapError::ExpectedAccount.into());
+        }
+        Ok(())
+    }
+
+    /// Wraps `amount` lamports of `payer_info` into a token account of the
+    /// native mint, syncing its token balance with the new lamports
+    pub fn wrap_native<'a>(
+        native_account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        Self::assert_writable(&[native_account_info, payer_info])?;
+        if !payer_info.is_signer {
+            return Err(SwapError::InvalidSigner.into());
+        }
+        if *system_program_info.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let native_account =
+            Self::unpack_token_account(native_account_info, token_program_info.key)?;
+        if !native_account.is_native() || native_account.mint != spl_token::native_mint::id() {
+            return Err(SwapError::InvalidNativeAmount.into());
+        }
+        invoke(
+            &system_instruction::transfer(payer_info.key, native_account_info.key, amount),
+            &[
+                payer_info.clone(),
+                native_account_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        invoke(
+            &spl_token::instruction::sync_native(token_program_info.key, native_account_info.key)?,
+            &[native_account_info.clone(), token_program_info.clone()],
+        )
+    }
+
+    /// Checks that an account holds enough lamports to be rent exempt.
     pub fn assert_rent_exempt(rent: &Rent, account_info: &AccountInfo) -> ProgramResult {
         if !rent.is_exempt(account_info.lamports(), account_info.data_len()) {
             msg!(&rent.minimum_balance(account_info.data_len()).to_string());
@@ -57,27 +232,82 @@ impl Processor {
         }
     }
 
-    /// Unpacks a spl_token `Mint`.
+    /// Unpacks a spl_token `Mint`, or a Token-2022 mint without extensions.
     pub fn unpack_mint(
         account_info: &AccountInfo,
         token_program_id: &Pubkey,
     ) -> Result<spl_token::state::Mint, SwapError> {
         if account_info.owner != token_program_id {
             Err(SwapError::IncorrectTokenProgramId)
+        } else if token_2022::check_id(token_program_id) {
+            token_2022::unpack_mint(&account_info.data.borrow())
         } else {
             spl_token::state::Mint::unpack(&account_info.data.borrow())
                 .map_err(|_| SwapError::ExpectedMint)
         }
     }
 
+    /// Checks the reserve decimals a curve records against the reserve mints,
+    /// found among the trailing accounts of the instruction
+    pub fn assert_reserve_decimals(
+        swap_curve: &SwapCurve,
+        token_a_mint: &Pubkey,
+        token_a_program_id: &Pubkey,
+        token_b_mint: &Pubkey,
+        token_b_program_id: &Pubkey,
+        trailing_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        let (token_a_decimals, token_b_decimals) = match swap_curve.calculator.reserve_decimals() {
+            Some(decimals) => decimals,
+            None => return Ok(()),
+        };
+        let mint_decimals = |mint: &Pubkey, program_id: &Pubkey| -> Result<u8, ProgramError> {
+            let mint_info = trailing_infos
+                .iter()
+                .find(|info| info.key == mint)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            Ok(Self::unpack_mint(mint_info, program_id)?.decimals)
+        };
+        if mint_decimals(token_a_mint, token_a_program_id)? != token_a_decimals
+            || mint_decimals(token_b_mint, token_b_program_id)? != token_b_decimals
+        {
+            return Err(SwapError::MismatchDecimalValidation.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a pool mint has never been used by another pool.
+    ///
+    /// A zero supply alone doesn't prove that: a previous pool's mint can be
+    /// drained back to zero.  The mint authority is what ties a mint to a
+    /// pool, and since it must be the authority derived from this swap
+    /// account, a mint created for any other pool is rejected.  Every pool
+    /// therefore needs a freshly created mint.
+    pub fn assert_fresh_pool_mint(
+        pool_mint: &spl_token::state::Mint,
+        authority: &Pubkey,
+    ) -> ProgramResult {
+        if pool_mint.mint_authority != COption::Some(*authority) {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if pool_mint.supply != 0 {
+            return Err(SwapError::InvalidSupply.into());
+        }
+        if pool_mint.freeze_authority.is_some() {
+            return Err(SwapError::InvalidFreezeAuthority.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that `goal_key` is the program address derived from `seeds`.
     pub fn assert_pda(seeds:&[&[u8]], program_id: &Pubkey, goal_key: &Pubkey) -> ProgramResult {
         let (found_key, _bump) = Pubkey::find_program_address(seeds, program_id);
         if found_key != *goal_key {
-            return Err(SwapError::InvalidProgramAddress);
+            return Err(SwapError::InvalidProgramAddress.into());
         }
         Ok(())
     }
-    
+
 
     /// Issue a spl_token `Burn` instruction.
     pub fn token_burn<'a>(
@@ -134,11 +364,17 @@ impl Processor {
         invoke_signed(&ix, &[mint, destination, authority, token_program], signers)
     }
 
-    /// Issue a spl_token `Transfer` instruction.
+    /// Issue a spl_token `Transfer` instruction, or a `TransferChecked` one
+    /// when the mint is given, as Token-2022 requires.
+    ///
+    /// The instruction is sent to `token_program`, which may be the program
+    /// of a reserve rather than spl_token, see `assert_reserve_token_program`.
+    #[allow(clippy::too_many_arguments)]
     pub fn token_transfer<'a>(
         swap: &Pubkey,
         token_program: AccountInfo<'a>,
         source: AccountInfo<'a>,
+        mint: Option<AccountInfo<'a>>,
         destination: AccountInfo<'a>,
         authority: AccountInfo<'a>,
         nonce: u8,
@@ -147,22 +383,47 @@ impl Processor {
         let swap_bytes = swap.to_bytes();
         let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::transfer(
-            token_program.key,
-            source.key,
-            destination.key,
-            authority.key,
-            &[],
-            amount,
-        )?;
-        invoke_signed(
-            &ix,
-            &[source, destination, authority, token_program],
-            signers,
-        )
+        // the spl_token builders only accept their own program id
+        match mint {
+            Some(mint) => {
+                let decimals = Self::unpack_mint(&mint, token_program.key)?.decimals;
+                let mut ix = spl_token::instruction::transfer_checked(
+                    &spl_token::id(),
+                    source.key,
+                    mint.key,
+                    destination.key,
+                    authority.key,
+                    &[],
+                    amount,
+                    decimals,
+                )?;
+                ix.program_id = *token_program.key;
+                invoke_signed(
+                    &ix,
+                    &[source, mint, destination, authority, token_program],
+                    signers,
+                )
+            }
+            None => {
+                let mut ix = spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    source.key,
+                    destination.key,
+                    authority.key,
+                    &[],
+                    amount,
+                )?;
+                ix.program_id = *token_program.key;
+                invoke_signed(
+                    &ix,
+                    &[source, destination, authority, token_program],
+                    signers,
+                )
+            }
+        }
     }
 
-    
+
     /// create or allocate storage for new account
     pub fn create_or_allocate_account_raw<'a>(
         program_id: Pubkey,
@@ -178,11 +439,15 @@ impl Processor {
             .minimum_balance(size)
             .max(1)
             .saturating_sub(new_account_info.lamports());
-    
+
         if required_lamports > 0 {
             msg!("Transfer {} lamports to the new account", required_lamports);
             invoke(
-                &system_instruction::transfer(&payer_info.key, new_account_info.key, required_lamports),
+                &system_instruction::transfer(
+                    payer_info.key,
+                    new_account_info.key,
+                    required_lamports,
+                ),
                 &[
                     payer_info.clone(),
                     new_account_info.clone(),
@@ -190,22 +455,380 @@ impl Processor {
                 ],
             )?;
         }
-    
+
         msg!("Allocate space for the account");
         invoke_signed(
             &system_instruction::allocate(new_account_info.key, size.try_into().map_err(|_| SwapError::InvalidAllocateSpaceForAccount)?),
             &[new_account_info.clone(), system_program_info.clone()],
-            &[&signer_seeds],
+            &[signer_seeds],
         )?;
-    
+
         msg!("Assign the account to the owning program");
         invoke_signed(
             &system_instruction::assign(new_account_info.key, &program_id),
             &[new_account_info.clone(), system_program_info.clone()],
-            &[&signer_seeds],
+            &[signer_seeds],
         )?;
         msg!("Completed assignation!");
-    
+
+        Ok(())
+    }
+
+    /// Grows a program owned account to `size`, the payer topping up its
+    /// lamports so that it stays rent exempt
+    pub fn realloc_account<'a>(
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+        size: usize,
+    ) -> ProgramResult {
+        let required_lamports = rent
+            .minimum_balance(size)
+            .saturating_sub(account_info.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, required_lamports),
+                &[
+                    payer_info.clone(),
+                    account_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        account_info.realloc(size, true)
+    }
+
+    /// Checks that neither reserve holds the pool's own token, which would make
+    /// the pool accounting circular
+    pub fn assert_not_pool_mint(
+        token_a_mint: &Pubkey,
+        token_b_mint: &Pubkey,
+        pool_mint: &Pubkey,
+    ) -> ProgramResult {
+        if *token_a_mint == *pool_mint || *token_b_mint == *pool_mint {
+            return Err(SwapError::InvalidInput.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the ratio of the deposit maxima is within
+    /// DEPOSIT_RATIO_TOLERANCE_BPS of the reserve ratio, so that maxima far off
+    /// the pool price fail clearly instead of depositing much less than one
+    /// side's maximum
+    pub fn check_deposit_maxima_ratio(
+        maximum_token_a_amount: u64,
+        maximum_token_b_amount: u64,
+        swap_token_a_amount: u64,
+        swap_token_b_amount: u64,
+    ) -> ProgramResult {
+        // max_a / max_b against a / b, compared as max_a * b against max_b * a
+        let maxima_side = U256::from(maximum_token_a_amount) * U256::from(swap_token_b_amount);
+        let reserve_side = U256::from(maximum_token_b_amount) * U256::from(swap_token_a_amount);
+        let difference = if maxima_side > reserve_side {
+            maxima_side - reserve_side
+        } else {
+            reserve_side - maxima_side
+        };
+        if difference * U256::from(BASIS_POINTS)
+            > reserve_side * U256::from(DEPOSIT_RATIO_TOLERANCE_BPS)
+        {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a deposit does not lower the value of a pool token held by
+    /// the existing LPs, which rounding could otherwise do on tiny pools.
+    ///
+    /// Each reserve per pool token must not decrease, compared exactly by
+    /// cross-multiplication.  Since the normalized value of the curves scales
+    /// linearly with the reserves, this guarantees the normalized value per
+    /// pool token does not decrease either, without the rounding of its
+    /// square roots.
+    pub fn assert_no_dilution(
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        token_a_amount: u128,
+        token_b_amount: u128,
+        pool_token_amount: u128,
+    ) -> ProgramResult {
+        let new_pool_supply = pool_supply
+            .checked_add(pool_token_amount)
+            .ok_or(SwapError::CalculationFailure)?;
+        for (reserve, deposit) in [
+            (swap_token_a_amount, token_a_amount),
+            (swap_token_b_amount, token_b_amount),
+        ]
+        .iter()
+        {
+            let new_reserve = reserve
+                .checked_add(*deposit)
+                .ok_or(SwapError::CalculationFailure)?;
+            // reserve / pool_supply <= new_reserve / new_pool_supply
+            let share_before = reserve
+                .checked_mul(new_pool_supply)
+                .ok_or(SwapError::CalculationFailure)?;
+            let share_after = new_reserve
+                .checked_mul(pool_supply)
+                .ok_or(SwapError::CalculationFailure)?;
+            if share_after < share_before {
+                return Err(SwapError::CalculationFailure.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a swap received at least its spot quote less
+    /// `max_slippage_bps`.  The quote prices the amount that reached the curve,
+    /// after fees, so that the fees don't count towards the slippage.
+    pub fn check_slippage_from_spot(
+        swap_curve: &SwapCurve,
+        result: &SwapResult,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        max_slippage_bps: u16,
+    ) -> ProgramResult {
+        let amount_to_curve = result
+            .source_amount_swapped
+            .checked_sub(result.trade_fee)
+            .and_then(|amount| amount.checked_sub(result.owner_fee))
+            .ok_or(SwapError::CalculationFailure)?;
+        let spot_quote = swap_curve
+            .calculator
+            .spot_price(swap_source_amount, swap_destination_amount, trade_direction)
+            .and_then(|price| price.checked_mul(&PreciseNumber::new(amount_to_curve)?))
+            .ok_or(SwapError::CalculationFailure)?;
+        let tolerance = BASIS_POINTS
+            .checked_sub(u128::from(max_slippage_bps))
+            .ok_or(SwapError::InvalidInput)?;
+        let minimum_amount_out = spot_quote
+            .checked_mul(&PreciseNumber::new(tolerance).ok_or(SwapError::CalculationFailure)?)
+            .and_then(|amount| amount.checked_div(&PreciseNumber::new(BASIS_POINTS)?))
+            .and_then(|amount| amount.floor())
+            .and_then(|amount| amount.to_imprecise())
+            .ok_or(SwapError::CalculationFailure)?;
+        if result.destination_amount_swapped < minimum_amount_out {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        Ok(())
+    }
+
+    /// Finds the largest part of `amount_in` whose swap stays within
+    /// `max_slippage_bps` of the spot price, zero if no input does.
+    ///
+    /// On the constant product curve the average price of a swap is spot
+    /// times x / (x + in), which meets the slippage bound when the spot
+    /// price after the swap falls to spot * (1 - slippage)^2, so the fill
+    /// comes from `amount_in_to_target_price` in closed form.  Other curves
+    /// bisect, slippage growing with the input, for at most
+    /// MAX_PARTIAL_FILL_ITERATIONS steps, which may leave the fill slightly
+    /// below the largest one.
+    pub fn max_amount_in_within_slippage(
+        swap_curve: &SwapCurve,
+        amount_in: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+        max_slippage_bps: u16,
+    ) -> u128 {
+        let fills = |amount: u128| {
+            swap_curve
+                .swap(
+                    amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                    fees,
+                )
+                .map(|result| {
+                    Self::check_slippage_from_spot(
+                        swap_curve,
+                        &result,
+                        swap_source_amount,
+                        swap_destination_amount,
+                        trade_direction,
+                        max_slippage_bps,
+                    )
+                    .is_ok()
+                })
+                .unwrap_or(false)
+        };
+        if fills(amount_in) {
+            return amount_in;
+        }
+        let mut low = 0;
+        let mut high = amount_in;
+        if swap_curve.curve_type == CurveType::ConstantProduct {
+            if let Some(estimate) = Self::constant_product_fill_estimate(
+                swap_curve,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+                fees,
+                max_slippage_bps,
+            ) {
+                if estimate < high {
+                    if fills(estimate) {
+                        return estimate;
+                    }
+                    high = estimate;
+                }
+            }
+        }
+        for _ in 0..MAX_PARTIAL_FILL_ITERATIONS {
+            if high - low <= 1 {
+                break;
+            }
+            let mid = low + (high - low) / 2;
+            if fills(mid) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    /// Input, fees included, filling a constant product swap up to
+    /// `max_slippage_bps`.  It errs low, the rounding of the curve in favour
+    /// of the pool leaves room for a slightly larger fill.
+    fn constant_product_fill_estimate(
+        swap_curve: &SwapCurve,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+        max_slippage_bps: u16,
+    ) -> Option<u128> {
+        let tolerance = PreciseNumber::new(BASIS_POINTS.checked_sub(u128::from(max_slippage_bps))?)?
+            .checked_div(&PreciseNumber::new(BASIS_POINTS)?)?;
+        let target_price = swap_curve
+            .calculator
+            .spot_price(swap_source_amount, swap_destination_amount, trade_direction)?
+            .checked_mul(&tolerance)?
+            .checked_mul(&tolerance)?;
+        let amount_to_curve = swap_curve
+            .calculator
+            .amount_in_to_target_price(
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+                &target_price,
+            )?
+            .checked_sub(1)?;
+        // the fees are charged on the whole input, adding them on top of the
+        // curve amount once errs on the low side
+        amount_to_curve
+            .checked_add(fees.return_fee(amount_to_curve, swap_curve)?)?
+            .checked_add(fees.fixed_fee(amount_to_curve, swap_curve)?)
+    }
+
+    /// Checks that the initial supply is at least one whole lp token for the
+    /// given decimals, and never below the MIN_LP_SUPPLY locked in every pool
+    pub fn validate_initial_supply(initial_supply: u64, lp_decimals: u8) -> ProgramResult {
+        let one_lp_token = 10u64
+            .checked_pow(u32::from(lp_decimals))
+            .ok_or(SwapError::InvalidInitialSupply)?;
+        if initial_supply < one_lp_token || to_u128(initial_supply)? < MIN_LP_SUPPLY {
+            return Err(SwapError::InvalidInitialSupply.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the pool mint still has the decimals recorded at
+    /// initialization, pools created before they were recorded are skipped
+    pub fn assert_pool_mint_decimals(
+        token_swap: &dyn SwapState,
+        pool_mint: &spl_token::state::Mint,
+    ) -> ProgramResult {
+        if let Some(pool_mint_decimals) = token_swap.pool_mint_decimals() {
+            if pool_mint_decimals != pool_mint.decimals {
+                return Err(SwapError::MismatchDecimalValidation.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpacks the global state after checking its address, an account that
+    /// was never written, empty or all zero, is reported as not initialized
+    /// rather than as malformed
+    pub fn unpack_global_state(
+        program_id: &Pubkey,
+        global_state_info: &AccountInfo,
+    ) -> Result<GlobalState, ProgramError> {
+        Self::assert_pda(
+            &[GLOBAL_STATE_SEED, program_id.as_ref()],
+            program_id,
+            global_state_info.key,
+        )?;
+        let data = global_state_info.data.borrow();
+        if data.iter().all(|byte| *byte == 0) {
+            return Err(SwapError::NotInitializedState.into());
+        }
+        let state = GlobalState::unpack_from_slice(&data)?;
+        if !state.is_initialized() {
+            return Err(SwapError::NotInitializedState.into());
+        }
+        Ok(state)
+    }
+
+    /// Checks that the given account is the global state owner, a state whose
+    /// owner reads as the default key is rejected instead of compared against
+    pub fn assert_global_state_owner(
+        global_state: &GlobalState,
+        owner_info: &AccountInfo,
+    ) -> ProgramResult {
+        if *global_state.owner() == Pubkey::default() || *global_state.owner() != *owner_info.key {
+            return Err(SwapError::InvalidProgramOwner.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that every account the instruction writes to was passed as
+    /// writable, rather than letting the token program CPI fail later
+    pub fn assert_writable(accounts: &[&AccountInfo]) -> ProgramResult {
+        if let Some(account) = accounts.iter().find(|account| !account.is_writable) {
+            msg!("Account {} must be writable", account.key);
+            return Err(SwapError::AccountNotWritable.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the system program and rent sysvar accounts passed to
+    /// instructions that create program accounts
+    pub fn assert_system_and_rent(
+        system_info: &AccountInfo,
+        rent_info: &AccountInfo,
+    ) -> ProgramResult {
+        if *system_info.key != system_program::id() {
+            return Err(SwapError::InvalidSystemProgramId.into());
+        }
+
+        if !sysvar::rent::check_id(rent_info.key) {
+            return Err(SwapError::InvalidRentSysvarId.into());
+        }
+        Ok(())
+    }
+
+    /// Checks through the instructions sysvar that the instruction being
+    /// processed is a top-level instruction of this program, so that admin
+    /// instructions cannot be relayed by another program through a CPI
+    pub fn assert_not_cpi(program_id: &Pubkey, instructions_info: &AccountInfo) -> ProgramResult {
+        if *instructions_info.key != sysvar::instructions::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let current_index = sysvar::instructions::load_current_index_checked(instructions_info)?;
+        let current_instruction = sysvar::instructions::load_instruction_at_checked(
+            usize::from(current_index),
+            instructions_info,
+        )?;
+        if current_instruction.program_id != *program_id {
+            return Err(SwapError::CpiNotAllowed.into());
+        }
         Ok(())
     }
 
@@ -222,11 +845,13 @@ impl Processor {
         user_token_a_info: Option<&AccountInfo>,
         user_token_b_info: Option<&AccountInfo>,
     ) -> ProgramResult {
+        // cheap key comparisons first, the program address derivation is by
+        // far the most expensive check so it runs last
         if swap_account_info.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
-        Self::assert_pda(&[swap_account_info.key.as_ref()], program_id, authority_info.key)?;
-        
+        // each reserve is compared to its own stored key, so passing A and B
+        // the other way round fails here instead of pricing B as A
         if *token_a_info.key != *token_swap.token_a_account() {
             return Err(SwapError::IncorrectSwapAccount.into());
         }
@@ -249,19 +874,32 @@ impl Processor {
                 return Err(SwapError::InvalidInput.into());
             }
         }
+        Self::assert_pda(&[swap_account_info.key.as_ref()], program_id, authority_info.key)?;
         Ok(())
     }
-    
+
     /// processor for Global State
     pub fn process_set_global_state(
         program_id: &Pubkey,
-        owner: &Pubkey,
-        fee_owner: &Pubkey,
-        initial_supply: u64,
-        lp_decimals: u8,
-        fees: Fees,
+        instruction: SetGlobalState,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
+        let SetGlobalState {
+            owner,
+            fee_owner,
+            initial_supply,
+            lp_decimals,
+            fees,
+            max_reserve_a,
+            max_reserve_b,
+            fee_holiday_start,
+            fee_holiday_end,
+            dynamic_fee_min_numerator,
+            dynamic_fee_max_numerator,
+            fee_tiers,
+            max_lp_per_deposit,
+            allowed_lp_decimals,
+        } = instruction;
 
         //load account info
         let account_info_iter = &mut accounts.iter();
@@ -271,31 +909,32 @@ impl Processor {
 
         let system_info = next_account_info(account_info_iter)?;
         let rent_info = next_account_info(account_info_iter)?;
+        let instructions_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[global_state_info])?;
+        Self::assert_not_cpi(program_id, instructions_info)?;
         let rent = &Rent::from_account_info(rent_info)?;
 
         Self::assert_rent_exempt(rent, global_state_info)?;
-        
-        Self::assert_pda(&[SWAP_TAG.as_bytes(),program_id.as_ref()], program_id, global_state_info.key)?;
-        
+
+        Self::assert_pda(
+            &[GLOBAL_STATE_SEED, program_id.as_ref()],
+            program_id,
+            global_state_info.key,
+        )?;
+
         if !current_owner_info.is_signer{
             return Err(SwapError::InvalidSigner.into());
         }
 
-        if *system_info.key != Pubkey::from_str(SYSTEM_PROGRAM_ID).map_err(|_| SwapError::InvalidSystemProgramId)?{
-            return Err(SwapError::InvalidSystemProgramId.into());
-        }
-
-        if *rent_info.key != Pubkey::from_str(RENT_SYSVAR_ID).map_err(|_| SwapError::InvalidRentSysvarId)?{
-            return Err(SwapError::InvalidRentSysvarId.into());
-        }
+        Self::assert_system_and_rent(system_info, rent_info)?;
 
         let seeds = [
-            SWAP_TAG.as_bytes(),
+            GLOBAL_STATE_SEED,
             program_id.as_ref(),
         ];
 
         let (_pda_key, bump) = Pubkey::find_program_address(&seeds, program_id);
-        
+
         if global_state_info.data_is_empty(){
             let size = GlobalState::get_packed_len();
 
@@ -307,7 +946,7 @@ impl Processor {
                 current_owner_info,
                 size,
                 &[
-                    SWAP_TAG.as_bytes(),
+                    GLOBAL_STATE_SEED,
                     program_id.as_ref(),
                     &[bump],
                 ],
@@ -316,451 +955,1152 @@ impl Processor {
 
         let mut global_state = GlobalState::unpack_from_slice(&global_state_info.data.borrow())?;
 
-        if global_state.is_initialized == false
-        {
-            global_state.owner = Pubkey::from_str(INITIAL_PROGRAM_OWNER).map_err(|_| SwapError::InvalidProgramOwner)?;
-        }
-        
-        if global_state.owner != *current_owner_info.key
+        if !global_state.is_initialized
         {
-            return Err(SwapError::InvalidProgramOwner.into());
+            global_state.owner = INITIAL_PROGRAM_OWNER;
+            global_state.fee_owner = INITIAL_FEE_OWNER_KEY;
+            global_state.fees = INITIAL_FEES.clone();
         }
-        msg!("**************** validate_fees");
+
+        Self::assert_global_state_owner(&global_state, current_owner_info)?;
+
+        // settings left out of the instruction keep their stored value
+        let max_reserve_a = max_reserve_a.unwrap_or(global_state.max_reserve_a);
+        let max_reserve_b = max_reserve_b.unwrap_or(global_state.max_reserve_b);
+        let fee_holiday_start = fee_holiday_start.unwrap_or(global_state.fee_holiday_start);
+        let fee_holiday_end = fee_holiday_end.unwrap_or(global_state.fee_holiday_end);
+        let dynamic_fee_min_numerator =
+            dynamic_fee_min_numerator.unwrap_or(global_state.dynamic_fee_min_numerator);
+        let dynamic_fee_max_numerator =
+            dynamic_fee_max_numerator.unwrap_or(global_state.dynamic_fee_max_numerator);
+        let fee_tiers = fee_tiers.unwrap_or_else(|| global_state.fee_tiers.clone());
+        let max_lp_per_deposit = max_lp_per_deposit.unwrap_or(global_state.max_lp_per_deposit);
+        let allowed_lp_decimals = allowed_lp_decimals.unwrap_or(global_state.allowed_lp_decimals);
+
         SWAP_CONSTRAINTS.validate_fees(&fees)?;
-        msg!("**************** validate_fees1");
+        // fee updates must keep the denominator existing pools were priced with
+        if fees.fee_denominator != global_state.fees.fee_denominator {
+            return Err(SwapError::InvalidFee.into());
+        }
         fees.validate()?;
-        msg!("**************** validate_fees2");
+        for fee_tier in fee_tiers.iter().filter(|fees| **fees != Fees::default()) {
+            SWAP_CONSTRAINTS.validate_fees(fee_tier)?;
+            fee_tier.validate()?;
+        }
+        Self::validate_initial_supply(initial_supply, lp_decimals)?;
+        // the initial supply must stay a whole lp token for every allowed mint
+        for decimals in (0u8..32).filter(|decimals| allowed_lp_decimals & (1u32 << decimals) != 0) {
+            Self::validate_initial_supply(initial_supply, decimals)?;
+        }
+        // a default owner would leave no one able to sign for the state
+        if owner == Pubkey::default() {
+            return Err(SwapError::InvalidProgramOwner.into());
+        }
+        if fee_holiday_start > fee_holiday_end {
+            return Err(SwapError::InvalidInput.into());
+        }
+        // pool authorities are checked against the fee owner as pools are
+        // created and traded, the program's own addresses can be ruled out here
+        if fee_owner == *program_id || fee_owner == *global_state_info.key {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        if dynamic_fee_max_numerator != 0
+            && (dynamic_fee_min_numerator > dynamic_fee_max_numerator
+                || dynamic_fee_max_numerator >= fees.fee_denominator)
+        {
+            return Err(SwapError::InvalidFee.into());
+        }
         //Save the program state
         let obj = GlobalState{
             is_initialized:true,
-            initial_supply: initial_supply,
-            lp_decimals: lp_decimals,
-            owner: *owner,
-            fee_owner: *fee_owner,
+            initial_supply,
+            lp_decimals,
+            owner,
+            fee_owner,
             fees,
+            max_reserve_a,
+            max_reserve_b,
+            fee_holiday_start,
+            fee_holiday_end,
+            dynamic_fee_min_numerator,
+            dynamic_fee_max_numerator,
+            fee_tiers,
+            max_lp_per_deposit,
+            allowed_lp_decimals,
         };
-        msg!("**************** validate_fees3");
-        obj.pack_into_slice(&mut &mut global_state_info.data.borrow_mut()[..]);
-        msg!("**************** validate_fees4");
+        if global_state_info.data_len() < GlobalState::LEN {
+            // an account created for an older layout grows to the latest one,
+            // so that the newer settings can be enabled
+            Self::realloc_account(
+                global_state_info,
+                current_owner_info,
+                system_info,
+                rent,
+                GlobalState::LEN,
+            )?;
+        }
+        obj.pack_into_account_data(&mut global_state_info.data.borrow_mut())?;
         Ok(())
     }
 
-    /// Processes an [Initialize](enum.Instruction.html).
-    pub fn process_initialize(
+    /// Processes a [SetPoolMetadata](enum.Instruction.html).
+    pub fn process_set_pool_metadata(
         program_id: &Pubkey,
-        swap_curve: SwapCurve,
+        name: &str,
+        symbol: &str,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
+        let metadata_info = next_account_info(account_info_iter)?;
         let swap_info = next_account_info(account_info_iter)?;
-        let authority_info = next_account_info(account_info_iter)?;
-        let gloabal_state_info = next_account_info(account_info_iter)?;
-        let token_a_info = next_account_info(account_info_iter)?;
-        let token_b_info = next_account_info(account_info_iter)?;
-        let pool_mint_info = next_account_info(account_info_iter)?;
-        let destination_info = next_account_info(account_info_iter)?;
-        let token_program_info = next_account_info(account_info_iter)?;
+        let global_state_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let system_info = next_account_info(account_info_iter)?;
         let rent_info = next_account_info(account_info_iter)?;
-        let rent = &Rent::from_account_info(rent_info)?;
+        let instructions_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[metadata_info])?;
+        Self::assert_not_cpi(program_id, instructions_info)?;
 
-        let token_program_id = *token_program_info.key;
-        Self::assert_rent_exempt(rent, swap_info)?;
-        if SwapVersion::is_initialized(&swap_info.data.borrow()) {
-            return Err(SwapError::AlreadyInUse.into());
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-        let (_found_key, nonce) = Pubkey::find_program_address(&[swap_info.key.as_ref()], program_id);
-        Self::assert_pda(&[swap_info.key.as_ref()], program_id, authority_info.key)?;
+        if !SwapVersion::is_initialized(&swap_info.data.borrow()) {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        Self::assert_pda(
+            &[POOL_METADATA_TAG.as_bytes(), swap_info.key.as_ref()],
+            program_id,
+            metadata_info.key,
+        )?;
+        Self::assert_system_and_rent(system_info, rent_info)?;
 
-        Self::assert_pda(&[SWAP_TAG.as_bytes(),program_id.as_ref()], program_id, global_state_info.key)?;
-        
-        let state = GlobalState::unpack_from_slice(&gloabal_state_info.data.borrow())?;
-        if state.is_initialized() == false
-        {
-            return Err(SwapError::NotInitializedState.into());
+        let global_state = Self::unpack_global_state(program_id, global_state_info)?;
+        if !owner_info.is_signer {
+            return Err(SwapError::InvalidSigner.into());
         }
+        Self::assert_global_state_owner(&global_state, owner_info)?;
+        PoolMetadata::validate(name, symbol)?;
 
-        let token_a = Self::unpack_token_account(token_a_info, &token_program_id)?;
-        let token_b = Self::unpack_token_account(token_b_info, &token_program_id)?;
-        let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
-        let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
-        if *authority_info.key != token_a.owner {
-            return Err(SwapError::InvalidOwner.into());
+        if metadata_info.data_is_empty() {
+            let (_pda_key, bump) = Pubkey::find_program_address(
+                &[POOL_METADATA_TAG.as_bytes(), swap_info.key.as_ref()],
+                program_id,
+            );
+            Self::create_or_allocate_account_raw(
+                *program_id,
+                metadata_info,
+                rent_info,
+                system_info,
+                owner_info,
+                PoolMetadata::get_packed_len(),
+                &[
+                    POOL_METADATA_TAG.as_bytes(),
+                    swap_info.key.as_ref(),
+                    &[bump],
+                ],
+            )?;
         }
-        if *authority_info.key != token_b.owner {
-            return Err(SwapError::InvalidOwner.into());
+
+        let metadata = PoolMetadata {
+            is_initialized: true,
+            swap: *swap_info.key,
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+        };
+        metadata.pack_into_slice(&mut metadata_info.data.borrow_mut());
+        Ok(())
+    }
+
+    /// Processes a [LockLiquidity](enum.Instruction.html).
+    pub fn process_lock_liquidity(
+        program_id: &Pubkey,
+        amount: u64,
+        unlock_ts: i64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let lock_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let escrow_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let system_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[lock_info, source_info, escrow_info])?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-        if *authority_info.key == destination.owner {
-            return Err(SwapError::InvalidOutputOwner.into());
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::assert_pda(&[swap_info.key.as_ref()], program_id, authority_info.key)?;
+        let lock_seeds = [
+            LIQUIDITY_LOCK_TAG.as_bytes(),
+            swap_info.key.as_ref(),
+            owner_info.key.as_ref(),
+        ];
+        Self::assert_pda(&lock_seeds, program_id, lock_info.key)?;
+        let escrow_seeds = [
+            LIQUIDITY_ESCROW_TAG.as_bytes(),
+            swap_info.key.as_ref(),
+            owner_info.key.as_ref(),
+        ];
+        Self::assert_pda(&escrow_seeds, program_id, escrow_info.key)?;
+        Self::assert_system_and_rent(system_info, rent_info)?;
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
         }
-        if COption::Some(*authority_info.key) != pool_mint.mint_authority {
-            return Err(SwapError::InvalidOwner.into());
+        if *pool_mint_info.key != *token_swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
         }
-
-        if token_a.mint == token_b.mint {
-            return Err(SwapError::RepeatedMint.into());
+        if !owner_info.is_signer {
+            return Err(SwapError::InvalidSigner.into());
+        }
+        if amount == 0 || unlock_ts <= Clock::get()?.unix_timestamp {
+            return Err(SwapError::InvalidInput.into());
         }
-        SWAP_CONSTRAINTS.validate_curve(&swap_curve)?;
-        swap_curve.calculator.validate()?;
-        swap_curve
-            .calculator
-            .validate_supply(token_a.amount, token_b.amount)?;
 
-        if token_a.delegate.is_some() {
-            return Err(SwapError::InvalidDelegate.into());
+        if escrow_info.data_is_empty() {
+            let (_pda_key, bump) = Pubkey::find_program_address(&escrow_seeds, program_id);
+            Self::create_or_allocate_account_raw(
+                *token_program_info.key,
+                escrow_info,
+                rent_info,
+                system_info,
+                owner_info,
+                spl_token::state::Account::LEN,
+                &[
+                    LIQUIDITY_ESCROW_TAG.as_bytes(),
+                    swap_info.key.as_ref(),
+                    owner_info.key.as_ref(),
+                    &[bump],
+                ],
+            )?;
+            // the spl_token builders only accept their own program id
+            let mut ix = spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                escrow_info.key,
+                pool_mint_info.key,
+                authority_info.key,
+            )?;
+            ix.program_id = *token_program_info.key;
+            invoke(
+                &ix,
+                &[
+                    escrow_info.clone(),
+                    pool_mint_info.clone(),
+                    authority_info.clone(),
+                    rent_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
         }
-        if token_b.delegate.is_some() {
-            return Err(SwapError::InvalidDelegate.into());
+        let escrow = Self::unpack_token_account(escrow_info, token_swap.token_program_id())?;
+        if escrow.mint != *token_swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
         }
-        if token_a.close_authority.is_some() {
-            return Err(SwapError::InvalidCloseAuthority.into());
+        if escrow.owner != *authority_info.key {
+            return Err(SwapError::InvalidOwner.into());
         }
-        if token_b.close_authority.is_some() {
-            return Err(SwapError::InvalidCloseAuthority.into());
+
+        if lock_info.data_is_empty() {
+            let (_pda_key, bump) = Pubkey::find_program_address(&lock_seeds, program_id);
+            Self::create_or_allocate_account_raw(
+                *program_id,
+                lock_info,
+                rent_info,
+                system_info,
+                owner_info,
+                LiquidityLock::get_packed_len(),
+                &[
+                    LIQUIDITY_LOCK_TAG.as_bytes(),
+                    swap_info.key.as_ref(),
+                    owner_info.key.as_ref(),
+                    &[bump],
+                ],
+            )?;
+        } else if LiquidityLock::unpack_from_slice(&lock_info.data.borrow())?.is_initialized {
+            return Err(SwapError::AlreadyInUse.into());
         }
 
-        if token_a.is_frozen(){
-            return Err(SwapError::InvalidFreezeAuthority.into());
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            None,
+            escrow_info.clone(),
+            owner_info.clone(),
+            token_swap.nonce(),
+            amount,
+        )?;
+
+        let lock = LiquidityLock {
+            is_initialized: true,
+            swap: *swap_info.key,
+            owner: *owner_info.key,
+            escrow: *escrow_info.key,
+            amount,
+            unlock_ts,
+        };
+        lock.pack_into_slice(&mut lock_info.data.borrow_mut());
+        Ok(())
+    }
+
+    /// Processes a [FeeSnapshot](enum.Instruction.html).
+    pub fn process_fee_snapshot(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let snapshot_info = next_account_info(account_info_iter)?;
+        let swap_info = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
+        let system_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[snapshot_info])?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-        if token_b.is_frozen(){
-            return Err(SwapError::InvalidFreezeAuthority.into());
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::assert_pda(
+            &[FEE_SNAPSHOT_TAG.as_bytes(), swap_info.key.as_ref()],
+            program_id,
+            snapshot_info.key,
+        )?;
+        Self::assert_system_and_rent(system_info, rent_info)?;
+
+        if snapshot_info.data_is_empty() {
+            if !payer_info.is_signer {
+                return Err(SwapError::InvalidSigner.into());
+            }
+            let (_pda_key, bump) = Pubkey::find_program_address(
+                &[FEE_SNAPSHOT_TAG.as_bytes(), swap_info.key.as_ref()],
+                program_id,
+            );
+            Self::create_or_allocate_account_raw(
+                *program_id,
+                snapshot_info,
+                rent_info,
+                system_info,
+                payer_info,
+                FeeSnapshot::get_packed_len(),
+                &[FEE_SNAPSHOT_TAG.as_bytes(), swap_info.key.as_ref(), &[bump]],
+            )?;
+        }
+        let mut snapshot = FeeSnapshot::unpack_from_slice(&snapshot_info.data.borrow())?;
+        if !snapshot.is_initialized {
+            snapshot.is_initialized = true;
+            snapshot.swap = *swap_info.key;
         }
 
-        if pool_mint.supply != 0 {
-            return Err(SwapError::InvalidSupply.into());
+        let epoch = Clock::get()?.epoch;
+        if let Some(latest) = snapshot.latest() {
+            if latest.epoch >= epoch {
+                return Err(SwapError::FeeSnapshotTaken.into());
+            }
         }
-        if pool_mint.freeze_authority.is_some() {
-            return Err(SwapError::InvalidFreezeAuthority.into());
+        snapshot.push(FeeSnapshotEntry {
+            epoch,
+            cumulative_fees_a: token_swap.cumulative_fees_a(),
+            cumulative_fees_b: token_swap.cumulative_fees_b(),
+        });
+        snapshot.pack_into_slice(&mut snapshot_info.data.borrow_mut());
+        Ok(())
+    }
+
+    /// Processes an [Unlock](enum.Instruction.html).
+    pub fn process_unlock(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let lock_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let escrow_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[lock_info, owner_info, escrow_info, destination_info])?;
+
+        if swap_info.owner != program_id || lock_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-        // fixed
-        if pool_mint.decimals != state.lp_decimals() {
-            return Err(SwapError::MismatchDecimalValidation.into());
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::assert_pda(&[swap_info.key.as_ref()], program_id, authority_info.key)?;
+        Self::assert_pda(
+            &[
+                LIQUIDITY_LOCK_TAG.as_bytes(),
+                swap_info.key.as_ref(),
+                owner_info.key.as_ref(),
+            ],
+            program_id,
+            lock_info.key,
+        )?;
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        if !owner_info.is_signer {
+            return Err(SwapError::InvalidSigner.into());
         }
 
-        let initial_amount = state.initial_supply();
+        Self::assert_pda(
+            &[
+                LIQUIDITY_ESCROW_TAG.as_bytes(),
+                swap_info.key.as_ref(),
+                owner_info.key.as_ref(),
+            ],
+            program_id,
+            escrow_info.key,
+        )?;
+
+        let lock = LiquidityLock::unpack(&lock_info.data.borrow())?;
+        if *escrow_info.key != lock.escrow {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if Clock::get()?.unix_timestamp < lock.unlock_ts {
+            return Err(SwapError::StillLocked.into());
+        }
 
-        Self::token_mint_to(
+        // the whole balance is released, the escrow can only be closed empty
+        let escrow = Self::unpack_token_account(escrow_info, token_swap.token_program_id())?;
+        Self::token_transfer(
             swap_info.key,
             token_program_info.clone(),
-            pool_mint_info.clone(),
+            escrow_info.clone(),
+            None,
             destination_info.clone(),
             authority_info.clone(),
-            nonce,
-            initial_amount,
+            token_swap.nonce(),
+            escrow.amount,
         )?;
 
-        swap_curve.calculator.validate()?;
-        let obj = SwapVersion::SwapV1(SwapV1 {
-            is_initialized: true,
-            nonce,
-            token_program_id,
-            token_a: *token_a_info.key,
-            token_b: *token_b_info.key,
-            pool_mint: *pool_mint_info.key,
-            token_a_mint: token_a.mint,
-            token_b_mint: token_b.mint,
-            swap_curve,
-        });
-        SwapVersion::pack(obj, &mut swap_info.data.borrow_mut())?;
+        let swap_bytes = swap_info.key.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[token_swap.nonce()]];
+        let mut ix = spl_token::instruction::close_account(
+            &spl_token::id(),
+            escrow_info.key,
+            owner_info.key,
+            authority_info.key,
+            &[],
+        )?;
+        ix.program_id = *token_program_info.key;
+        invoke_signed(
+            &ix,
+            &[
+                escrow_info.clone(),
+                owner_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+
+        // close the lock account, its rent back to the owner
+        let lock_lamports = lock_info.lamports();
+        **lock_info.lamports.borrow_mut() = 0;
+        **owner_info.lamports.borrow_mut() = owner_info
+            .lamports()
+            .checked_add(lock_lamports)
+            .ok_or(SwapError::CalculationFailure)?;
+        LiquidityLock::default().pack_into_slice(&mut lock_info.data.borrow_mut());
         Ok(())
     }
 
-    /// Processes an [Swap](enum.Instruction.html).
-    pub fn process_swap(
+    /// Processes a [RescueTokens](enum.Instruction.html).
+    pub fn process_rescue_tokens(
         program_id: &Pubkey,
-        amount_in: u64,
-        minimum_amount_out: u64,
+        mint: &Pubkey,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
-        // get account info iterator
         let account_info_iter = &mut accounts.iter();
-        // get swap info
         let swap_info = next_account_info(account_info_iter)?;
-        // get authority info
         let authority_info = next_account_info(account_info_iter)?;
-        // get user transfer autority info
-        let user_transfer_authority_info = next_account_info(account_info_iter)?;
-
-        let state_info = next_account_info(account_info_iter)?;
-        // get source info
-        let source_info = next_account_info(account_info_iter)?;
-        // get swap source info
-        let swap_source_info = next_account_info(account_info_iter)?;
-        // get swap destination info
-        let swap_destination_info = next_account_info(account_info_iter)?;
-        // get destination info
+        let global_state_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let stray_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
-        // get pool mint info
-        let pool_mint_info = next_account_info(account_info_iter)?;
-        let fixed_fee_account_info = next_account_info(account_info_iter)?;
-        // get token program info
         let token_program_info = next_account_info(account_info_iter)?;
-        // if swap owner is not program_id, then return incorrect program id error
+        let instructions_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[stray_info, destination_info])?;
+        Self::assert_not_cpi(program_id, instructions_info)?;
+
         if swap_info.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
-
-        Self::assert_pda(&[SWAP_TAG.as_bytes(),program_id.as_ref()], program_id, state_info.key)?;
-        
-        let state = GlobalState::unpack_from_slice(&state_info.data.borrow())?;
-        if state.is_initialized() == false
-        {
-            return Err(SwapError::NotInitializedState.into());
-        }
-
-        // get token_swap by swap_info.data
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
-        // if autority_info.key is not authority id then return invalid program address error
         Self::assert_pda(&[swap_info.key.as_ref()], program_id, authority_info.key)?;
-
-        // check if fee account is correct
-        let fee_token_account =
-            Self::unpack_token_account(&fixed_fee_account_info.clone(), token_swap.token_program_id())?;
-        if fee_token_account.owner != *state.fee_owner() {
-            return Err(SwapError::InvalidOwner.into());
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
         }
 
-
-        // if swap_source_info.key is token a account of token_swap or 
-        // swap source info.key is token b account of token_swap then return incorrect swap account er
-        if !(*swap_source_info.key == *token_swap.token_a_account()
-            || *swap_source_info.key == *token_swap.token_b_account())
-        {
-            return Err(SwapError::IncorrectSwapAccount.into());
+        let global_state = Self::unpack_global_state(program_id, global_state_info)?;
+        if !owner_info.is_signer {
+            return Err(SwapError::InvalidSigner.into());
         }
-        //if swap_destination_info.key is token a account of token_swap or 
-        //swap_destination_info.key is token b account of token_swap then return incorrect swap account er
-        if !(*swap_destination_info.key == *token_swap.token_a_account()
-            || *swap_destination_info.key == *token_swap.token_b_account())
+        Self::assert_global_state_owner(&global_state, owner_info)?;
+
+        // the reserves, and pool tokens such as locked liquidity, are never stray
+        if *stray_info.key == *token_swap.token_a_account()
+            || *stray_info.key == *token_swap.token_b_account()
         {
-            return Err(SwapError::IncorrectSwapAccount.into());
-        }
-        // if swap source info.key is swap destination key then return invalid input error
-        if *swap_source_info.key == *swap_destination_info.key {
-            return Err(SwapError::InvalidInput.into());
+            return Err(SwapError::CannotRescueReserve.into());
         }
-        // if swap source info key is source info key then return invalid input
-        if swap_source_info.key == source_info.key {
-            return Err(SwapError::InvalidInput.into());
+        let stray = Self::unpack_token_account(stray_info, token_swap.token_program_id())?;
+        if stray.mint == *token_swap.pool_mint() {
+            return Err(SwapError::CannotRescueReserve.into());
         }
-        // if swap destination info key is destination info key then return invalid input key
-        if swap_destination_info.key == destination_info.key {
+        if stray.mint != *mint {
             return Err(SwapError::InvalidInput.into());
         }
-        // if pool mint info key is not token swap pool mint
-        if *pool_mint_info.key != *token_swap.pool_mint() {
-            return Err(SwapError::IncorrectPoolMint.into());
+        if stray.owner != *authority_info.key {
+            return Err(SwapError::InvalidOwner.into());
         }
-        if *token_program_info.key != *token_swap.token_program_id() {
-            return Err(SwapError::IncorrectTokenProgramId.into());
-        }
-        
-        let source_account =
-            Self::unpack_token_account(swap_source_info, token_swap.token_program_id())?;
-        let dest_account =
-            Self::unpack_token_account(swap_destination_info, token_swap.token_program_id())?;
-        // let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-
-        let trade_direction = if *swap_source_info.key == *token_swap.token_a_account() {
-            TradeDirection::AtoB
-        } else {
-            TradeDirection::BtoA
-        };
-        let result = token_swap
-            .swap_curve()
-            .swap(
-                to_u128(amount_in)?,
-                to_u128(source_account.amount)?,
-                to_u128(dest_account.amount)?,
-                trade_direction,
-                state.fees()
-            )
-            .ok_or(SwapError::ZeroTradingTokens)?;
-        if result.destination_amount_swapped < to_u128(minimum_amount_out)? {
-            return Err(SwapError::ExceededSlippage.into());
-        }
-
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            source_info.clone(),
-            swap_source_info.clone(),
-            user_transfer_authority_info.clone(),
-            token_swap.nonce(),
-            to_u64(result.source_amount_swapped-result.owner_fee)?,
-        )?;
-
-        //otherwise transfer SPL_Token
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            source_info.clone(),
-            fixed_fee_account_info.clone(),
-            user_transfer_authority_info.clone(),
-            token_swap.nonce(),
-            to_u64(result.owner_fee)?,
-        )?;
 
-        //Transfer pc token from pool
         Self::token_transfer(
             swap_info.key,
             token_program_info.clone(),
-            swap_destination_info.clone(),
+            stray_info.clone(),
+            None,
             destination_info.clone(),
             authority_info.clone(),
             token_swap.nonce(),
-            to_u64(result.destination_amount_swapped)?,
-        )?;
-
-
-        Ok(())
+            stray.amount,
+        )
     }
-    /// Processes an [DepositAllTokenTypes](enum.Instruction.html).
-    pub fn process_deposit_all_token_types(
+
+    /// Processes an [Initialize](enum.Instruction.html).
+    pub fn process_initialize(
         program_id: &Pubkey,
-        pool_token_amount: u64,
-        maximum_token_a_amount: u64,
-        maximum_token_b_amount: u64,
+        swap_curve: SwapCurve,
+        fee_tier_index: u8,
+        min_lp_supply: Option<u64>,
+        native_amount: u64,
+        nonce: Option<u8>,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let swap_info = next_account_info(account_info_iter)?;
         let authority_info = next_account_info(account_info_iter)?;
-        let state_info = next_account_info(account_info_iter)?;
-        let user_transfer_authority_info = next_account_info(account_info_iter)?;
-        let source_a_info = next_account_info(account_info_iter)?;
-        let source_b_info = next_account_info(account_info_iter)?;
+        let global_state_info = next_account_info(account_info_iter)?;
         let token_a_info = next_account_info(account_info_iter)?;
         let token_b_info = next_account_info(account_info_iter)?;
         let pool_mint_info = next_account_info(account_info_iter)?;
-        let dest_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let trailing_infos = account_info_iter.as_slice();
+        let creator_info = account_info_iter.next();
+        let system_info = account_info_iter.next();
+        Self::assert_writable(&[swap_info, pool_mint_info, destination_info])?;
+        let rent = &Rent::from_account_info(rent_info)?;
 
-        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        let token_program_id = *token_program_info.key;
+        // the pool mint, locks and rescues stay on spl_token, only reserves
+        // may belong to Token-2022
+        if token_program_id != spl_token::id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        // each reserve is moved with the program owning it from now on
+        let token_a_program_id = *token_a_info.owner;
+        let token_b_program_id = *token_b_info.owner;
+        Self::assert_reserve_token_program(&token_a_program_id, &token_program_id)?;
+        Self::assert_reserve_token_program(&token_b_program_id, &token_program_id)?;
+        Self::assert_rent_exempt(rent, swap_info)?;
+        if SwapVersion::is_initialized(&swap_info.data.borrow()) {
+            return Err(SwapError::AlreadyInUse.into());
+        }
+        // only the canonical nonce is accepted, so it can never produce an
+        // on-curve authority, and a client that stored the wrong one learns it
+        // here rather than on every later instruction
+        let nonce = match nonce {
+            Some(nonce) => {
+                if !verify_nonce(program_id, swap_info.key, nonce) {
+                    msg!("Nonce {} is not the canonical bump seed of the swap authority", nonce);
+                    return Err(SwapError::InvalidProgramAddress.into());
+                }
+                nonce
+            }
+            None => find_swap_authority_address(program_id, swap_info.key).1,
+        };
+        let authority_key =
+            Pubkey::create_program_address(&[swap_info.key.as_ref(), &[nonce]], program_id)
+                .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if authority_key != *authority_info.key {
+            msg!("Swap authority must be {}, derived from the swap account", authority_key);
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
 
-        Self::assert_pda(&[SWAP_TAG.as_bytes(),program_id.as_ref()], program_id, state_info.key)?;
+        let state = Self::unpack_global_state(program_id, global_state_info)?;
+        if state.fee_tier(fee_tier_index).is_none() {
+            return Err(SwapError::InvalidFee.into());
+        }
+        if native_amount > 0 {
+            Self::wrap_native(
+                token_a_info,
+                creator_info.ok_or(SwapError::InvalidNativeAmount)?,
+                system_info.ok_or(SwapError::InvalidNativeAmount)?,
+                Self::reserve_token_program(
+                    &token_a_program_id,
+                    token_program_info,
+                    account_info_iter.as_slice(),
+                )?,
+                native_amount,
+            )?;
+        }
 
-        let state = GlobalState::unpack_from_slice(&state_info.data.borrow())?;
-        if state.is_initialized() == false
-        {
-            return Err(SwapError::NotInitializedState.into());
+        let token_a = Self::unpack_token_account(token_a_info, &token_a_program_id)?;
+        let token_b = Self::unpack_token_account(token_b_info, &token_b_program_id)?;
+        let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
+        if *authority_info.key != token_a.owner {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if *authority_info.key != token_b.owner {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if *authority_info.key == destination.owner {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        // without a creator the reserve mints come first
+        let creator_info = creator_info
+            .filter(|info| *info.key != token_a.mint && *info.key != token_b.mint);
+        // the same goes for the fee owner, the pool would pay fees to itself
+        if *authority_info.key == *state.fee_owner() {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        // when a creator is given, the initial pool tokens can only go to them
+        if let Some(creator_info) = creator_info {
+            if !creator_info.is_signer {
+                return Err(SwapError::InvalidSigner.into());
+            }
+            if *creator_info.key != destination.owner {
+                return Err(SwapError::InvalidOutputOwner.into());
+            }
+        }
+        Self::assert_fresh_pool_mint(&pool_mint, authority_info.key)?;
+        // the initial pool tokens must land in an empty account of this pool
+        // mint, a zero supply already implies the balance but the mint is not
+        // otherwise checked before minting
+        if destination.mint != *pool_mint_info.key {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if destination.amount != 0 {
+            return Err(SwapError::InvalidSupply.into());
         }
 
-        let calculator = &token_swap.swap_curve().calculator;
-        if !calculator.allows_deposits() {
-            return Err(SwapError::UnsupportedCurveOperation.into());
+        if token_a.mint == token_b.mint {
+            return Err(SwapError::RepeatedMint.into());
         }
-        Self::check_accounts(
-            token_swap.as_ref(),
-            program_id,
-            swap_info,
-            authority_info,
-            token_a_info,
-            token_b_info,
-            pool_mint_info,
-            token_program_info,
-            Some(source_a_info),
-            Some(source_b_info),
+        Self::assert_not_pool_mint(&token_a.mint, &token_b.mint, pool_mint_info.key)?;
+        SWAP_CONSTRAINTS.validate_curve(&swap_curve)?;
+        swap_curve.calculator.validate()?;
+        Self::assert_reserve_decimals(
+            &swap_curve,
+            &token_a.mint,
+            &token_a_program_id,
+            &token_b.mint,
+            &token_b_program_id,
+            trailing_infos,
         )?;
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
-        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-                
-        let current_pool_mint_supply = to_u128(pool_mint.supply)?;
-        let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
-            (to_u128(pool_token_amount)?, current_pool_mint_supply)
-        } else {
-            (to_u128(state.initial_supply())?, to_u128(state.initial_supply())?)
-        };
+        swap_curve
+            .calculator
+            .validate_supply(token_a.amount, token_b.amount)?;
 
-        let results = calculator
-            .pool_tokens_to_trading_tokens(
-                pool_token_amount,
-                pool_mint_supply,
+        if token_a.delegate.is_some() || token_a.delegated_amount != 0 {
+            return Err(SwapError::InvalidDelegate.into());
+        }
+        if token_b.delegate.is_some() || token_b.delegated_amount != 0 {
+            return Err(SwapError::InvalidDelegate.into());
+        }
+        if token_a.close_authority.is_some() {
+            return Err(SwapError::InvalidCloseAuthority.into());
+        }
+        if token_b.close_authority.is_some() {
+            return Err(SwapError::InvalidCloseAuthority.into());
+        }
+
+        if token_a.is_frozen(){
+            return Err(SwapError::InvalidFreezeAuthority.into());
+        }
+        if token_b.is_frozen(){
+            return Err(SwapError::InvalidFreezeAuthority.into());
+        }
+
+        // the decimals are recorded in the pool and checked on every deposit
+        // and withdrawal
+        if !state.allows_lp_decimals(pool_mint.decimals) {
+            return Err(SwapError::MismatchDecimalValidation.into());
+        }
+
+        let initial_amount = swap_curve
+            .calculator
+            .initial_pool_supply(
                 to_u128(token_a.amount)?,
                 to_u128(token_b.amount)?,
-                RoundDirection::Ceiling,
+                to_u128(state.initial_supply())?,
             )
-            .ok_or(SwapError::ZeroTradingTokens)?;
-        let token_a_amount = to_u64(results.token_a_amount)?;
-        if token_a_amount > maximum_token_a_amount {
-            return Err(SwapError::ExceededSlippage.into());
-        }
-        if token_a_amount == 0 {
-            return Err(SwapError::ZeroTradingTokens.into());
-        }
-        let token_b_amount = to_u64(results.token_b_amount)?;
-        if token_b_amount > maximum_token_b_amount {
-            return Err(SwapError::ExceededSlippage.into());
+            .ok_or(SwapError::CalculationFailure)?;
+        // stable pools mint D as the initial supply and rely on the default
+        // floor, other curves may lower it or disable it with zero
+        if let Some(min_lp_supply) = min_lp_supply {
+            if swap_curve.curve_type == CurveType::Stable && to_u128(min_lp_supply)? < MIN_LP_SUPPLY
+            {
+                return Err(SwapError::InvalidInput.into());
+            }
         }
-        if token_b_amount == 0 {
-            return Err(SwapError::ZeroTradingTokens.into());
+        // withdrawals always leave the floor in the pool
+        let floor = min_lp_supply.map(to_u128).transpose()?.unwrap_or(MIN_LP_SUPPLY);
+        if initial_amount < floor {
+            return Err(SwapError::InvalidInput.into());
         }
+        let initial_amount = to_u64(initial_amount)?;
 
-        let pool_token_amount = to_u64(pool_token_amount)?;
-        //transfer token to pool
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            source_a_info.clone(),
-            token_a_info.clone(),
-            user_transfer_authority_info.clone(),
-            token_swap.nonce(),
-            token_a_amount,
-        )?;
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            source_b_info.clone(),
-            token_b_info.clone(),
-            user_transfer_authority_info.clone(),
-            token_swap.nonce(),
-            token_b_amount,
-        )?;
-        //mint lp token to wallet
         Self::token_mint_to(
             swap_info.key,
             token_program_info.clone(),
             pool_mint_info.clone(),
-            dest_info.clone(),
+            destination_info.clone(),
             authority_info.clone(),
-            token_swap.nonce(),
-            pool_token_amount,
+            nonce,
+            initial_amount,
         )?;
 
+        swap_curve.calculator.validate()?;
+        let obj = SwapVersion::SwapV1(SwapV1 {
+            is_initialized: true,
+            nonce,
+            token_program_id,
+            token_a: *token_a_info.key,
+            token_b: *token_b_info.key,
+            pool_mint: *pool_mint_info.key,
+            token_a_mint: token_a.mint,
+            token_b_mint: token_b.mint,
+            swap_curve,
+            pool_mint_decimals: Some(pool_mint.decimals),
+            min_lp_supply,
+            fee_tier_index,
+            token_a_program_id,
+            token_b_program_id,
+            cumulative_fees_a: 0,
+            cumulative_fees_b: 0,
+        });
+        SwapVersion::pack(obj, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
 
-    /// Processes an [WithdrawAllTokenTypes](enum.Instruction.html).
-    pub fn process_withdraw_all_token_types(
+    /// Checks a swap of `amount_in` between two reserves and returns the fees
+    /// it pays, shared by Swap and the swap leg of ZapDeposit
+    pub fn swap_leg_fees(
+        state: &GlobalState,
+        token_swap: &dyn SwapState,
+        authority_info: &AccountInfo,
+        swap_source: &spl_token::state::Account,
+        swap_destination: &spl_token::state::Account,
+        amount_in: u128,
+        trade_direction: TradeDirection,
+    ) -> Result<Fees, ProgramError> {
+        // the reserves must still be owned by the swap authority, their owner
+        // could have been reassigned since initialization
+        if swap_source.owner != *authority_info.key
+            || swap_destination.owner != *authority_info.key
+        {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if amount_in < token_swap.swap_curve().calculator.min_trade_amount() {
+            msg!("Swap amount is below the minimum trade amount of the curve");
+            return Err(SwapError::InvalidInput.into());
+        }
+        let now = Clock::get()?.unix_timestamp;
+        Self::trade_fees(
+            state
+                .fees_at(now, token_swap.fee_tier_index())
+                .ok_or(SwapError::InvalidFee)?,
+            state.dynamic_fee_at(now).as_ref(),
+            token_swap.swap_curve(),
+            to_u128(swap_source.amount)?,
+            to_u128(swap_destination.amount)?,
+            amount_in,
+            trade_direction,
+        )
+    }
+
+    /// Get the fees of a swap of `amount_in` against the given reserves,
+    /// from the fees of the pool's tier and the dynamic stable fee in force,
+    /// both as returned by the global state.  Shared with the off-chain
+    /// quotes of `client::PoolSnapshot`, so that they charge the same fees.
+    pub fn trade_fees(
+        mut fees: Fees,
+        dynamic_fee: Option<&DynamicFee>,
+        swap_curve: &SwapCurve,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        amount_in: u128,
+        trade_direction: TradeDirection,
+    ) -> Result<Fees, ProgramError> {
+        if let (Some(dynamic_fee), CurveType::Stable) = (dynamic_fee, swap_curve.curve_type) {
+            // the fee follows the imbalance the swap leaves behind, measured
+            // on reserves scaled to the same decimals
+            let calculator = &swap_curve.calculator;
+            let without_fees = calculator
+                .swap_without_fees(
+                    amount_in,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                )
+                .ok_or(SwapError::ZeroTradingTokens)?;
+            let (new_swap_source_amount, new_swap_destination_amount) = calculator
+                .normalize_reserves(
+                    swap_source_amount
+                        .checked_add(without_fees.source_amount_swapped)
+                        .ok_or(SwapError::CalculationFailure)?,
+                    swap_destination_amount
+                        .checked_sub(without_fees.destination_amount_swapped)
+                        .ok_or(SwapError::CalculationFailure)?,
+                    trade_direction,
+                )
+                .ok_or(SwapError::CalculationFailure)?;
+            fees.stable_return_fee_numerator = dynamic_fee
+                .stable_return_fee_numerator(new_swap_source_amount, new_swap_destination_amount)
+                .ok_or(SwapError::CalculationFailure)?;
+        }
+        Ok(fees)
+    }
+
+    /// Processes an [Swap](enum.Instruction.html).
+    pub fn process_swap(
         program_id: &Pubkey,
-        pool_token_amount: u64,
-        minimum_token_a_amount: u64,
-        minimum_token_b_amount: u64,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        max_slippage_bps: u16,
+        allow_partial: bool,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
+        // get account info iterator
         let account_info_iter = &mut accounts.iter();
+        // get swap info
         let swap_info = next_account_info(account_info_iter)?;
+        // get authority info
         let authority_info = next_account_info(account_info_iter)?;
-        let state_info = next_account_info(account_info_iter)?;
+        // get user transfer autority info
         let user_transfer_authority_info = next_account_info(account_info_iter)?;
-        let pool_mint_info = next_account_info(account_info_iter)?;
+
+        let state_info = next_account_info(account_info_iter)?;
+        // get source info
         let source_info = next_account_info(account_info_iter)?;
-        let token_a_info = next_account_info(account_info_iter)?;
-        let token_b_info = next_account_info(account_info_iter)?;
-        let dest_token_a_info = next_account_info(account_info_iter)?;
-        let dest_token_b_info = next_account_info(account_info_iter)?;
+        // get swap source info
+        let swap_source_info = next_account_info(account_info_iter)?;
+        // get swap destination info
+        let swap_destination_info = next_account_info(account_info_iter)?;
+        // get destination info
+        let destination_info = next_account_info(account_info_iter)?;
+        // get pool mint info
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_info = next_account_info(account_info_iter)?;
+        // get token program info
         let token_program_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[
+            swap_info,
+            source_info,
+            swap_source_info,
+            swap_destination_info,
+            destination_info,
+            fixed_fee_account_info,
+        ])?;
+        // if swap owner is not program_id, then return incorrect program id error
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
-
-        Self::assert_pda(&[SWAP_TAG.as_bytes(),program_id.as_ref()], program_id, state_info.key)?;
+        let state = Self::unpack_global_state(program_id, state_info)?;
+
+        // get token_swap by swap_info.data
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        // if autority_info.key is not authority id then return invalid program address error
+        Self::assert_pda(&[swap_info.key.as_ref()], program_id, authority_info.key)?;
 
-        let state = GlobalState::unpack_from_slice(&state_info.data.borrow())?;
-        if state.is_initialized() == false
+        // if swap_source_info.key is token a account of token_swap or
+        // swap source info.key is token b account of token_swap then return incorrect swap account er
+        if !(*swap_source_info.key == *token_swap.token_a_account()
+            || *swap_source_info.key == *token_swap.token_b_account())
         {
-            return Err(SwapError::NotInitializedState.into());
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        //if swap_destination_info.key is token a account of token_swap or
+        //swap_destination_info.key is token b account of token_swap then return incorrect swap account er
+        if !(*swap_destination_info.key == *token_swap.token_a_account()
+            || *swap_destination_info.key == *token_swap.token_b_account())
+        {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        // if swap source info.key is swap destination key then return invalid input error
+        if *swap_source_info.key == *swap_destination_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+        // if swap source info key is source info key then return invalid input
+        if swap_source_info.key == source_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+        // if swap destination info key is destination info key then return invalid input key
+        if swap_destination_info.key == destination_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+        // the fee account can neither be debited as the source nor collect the output
+        if fixed_fee_account_info.key == destination_info.key
+            || fixed_fee_account_info.key == source_info.key
+        {
+            return Err(SwapError::InvalidInput.into());
+        }
+        // if pool mint info key is not token swap pool mint
+        if *pool_mint_info.key != *token_swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+
+        // the direction follows the identity of the swap source reserve, never
+        // the mint of the user's source account, so a user holding the input
+        // token in several accounts always debits the reserve they passed.
+        // Each side then moves with the token program of its reserve.
+        let (trade_direction, source_program_id, destination_program_id) =
+            if *swap_source_info.key == *token_swap.token_a_account() {
+                (
+                    TradeDirection::AtoB,
+                    token_swap.token_a_program_id(),
+                    token_swap.token_b_program_id(),
+                )
+            } else {
+                (
+                    TradeDirection::BtoA,
+                    token_swap.token_b_program_id(),
+                    token_swap.token_a_program_id(),
+                )
+            };
+        let source_token_program_info = Self::reserve_token_program(
+            source_program_id,
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let destination_token_program_info = Self::reserve_token_program(
+            destination_program_id,
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let (source_mint, destination_mint) = match trade_direction {
+            TradeDirection::AtoB => (token_swap.token_a_mint(), token_swap.token_b_mint()),
+            TradeDirection::BtoA => (token_swap.token_b_mint(), token_swap.token_a_mint()),
+        };
+        let source_mint_info =
+            Self::reserve_mint(source_program_id, source_mint, account_info_iter.as_slice())?;
+        let destination_mint_info = Self::reserve_mint(
+            destination_program_id,
+            destination_mint,
+            account_info_iter.as_slice(),
+        )?;
+
+        // check if fee account is correct, the fees are paid in the source token
+        let fee_token_account =
+            Self::unpack_token_account(fixed_fee_account_info, source_program_id)?;
+        if fee_token_account.owner != *state.fee_owner() {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        // fees paid to the swap authority would flow straight back into the
+        // pool's own accounts
+        if fee_token_account.owner == *authority_info.key {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+
+        Self::assert_token_account_open(destination_info, destination_program_id)?;
+        // report an underfunded source here, the token transfer would fail
+        // with an opaque token program error
+        let user_source_account = Self::unpack_token_account(source_info, source_program_id)?;
+        if user_source_account.amount < amount_in {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+
+        let (source_account, dest_account) = Self::unpack_token_accounts(
+            swap_source_info,
+            source_program_id,
+            swap_destination_info,
+            destination_program_id,
+        )?;
+        let fees = Self::swap_leg_fees(
+            &state,
+            token_swap.as_ref(),
+            authority_info,
+            &source_account,
+            &dest_account,
+            to_u128(amount_in)?,
+            trade_direction,
+        )?;
+        // a partial fill keeps the fees quoted for the requested amount, and
+        // asks for the same share of the minimum output as it fills
+        let requested_amount_in = amount_in;
+        let (amount_in, minimum_amount_out) = if allow_partial && max_slippage_bps > 0 {
+            let requested = to_u128(amount_in)?;
+            let filled = Self::max_amount_in_within_slippage(
+                token_swap.swap_curve(),
+                requested,
+                to_u128(source_account.amount)?,
+                to_u128(dest_account.amount)?,
+                trade_direction,
+                &fees,
+                max_slippage_bps,
+            );
+            if filled == 0 {
+                return Err(SwapError::ExceededSlippage.into());
+            }
+            let unfilled = requested
+                .checked_sub(filled)
+                .ok_or(SwapError::CalculationFailure)?;
+            msg!("Partial fill: filled {}, unfilled {}", filled, unfilled);
+            let minimum_amount_out = to_u128(minimum_amount_out)?
+                .checked_mul(filled)
+                .and_then(|amount| amount.checked_div(requested))
+                .ok_or(SwapError::CalculationFailure)?;
+            (to_u64(filled)?, to_u64(minimum_amount_out)?)
+        } else {
+            (amount_in, minimum_amount_out)
+        };
+        let result = token_swap
+            .swap_curve()
+            .swap(
+                to_u128(amount_in)?,
+                to_u128(source_account.amount)?,
+                to_u128(dest_account.amount)?,
+                trade_direction,
+                &fees
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        // last line of defense: no curve or fee bug may pay out the whole
+        // destination reserve
+        if result.destination_amount_swapped >= to_u128(dest_account.amount)? {
+            return Err(SwapError::CalculationFailure.into());
+        }
+        if result.destination_amount_swapped < to_u128(minimum_amount_out)? {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if max_slippage_bps > 0 {
+            Self::check_slippage_from_spot(
+                token_swap.swap_curve(),
+                &result,
+                to_u128(source_account.amount)?,
+                to_u128(dest_account.amount)?,
+                trade_direction,
+                max_slippage_bps,
+            )?;
         }
 
+        Self::token_transfer(
+            swap_info.key,
+            source_token_program_info.clone(),
+            source_info.clone(),
+            source_mint_info.cloned(),
+            swap_source_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            to_u64(
+                result
+                    .source_amount_swapped
+                    .checked_sub(result.owner_fee)
+                    .ok_or(SwapError::CalculationFailure)?,
+            )?,
+        )?;
+
+        //otherwise transfer SPL_Token
+        Self::token_transfer(
+            swap_info.key,
+            source_token_program_info.clone(),
+            source_info.clone(),
+            source_mint_info.cloned(),
+            fixed_fee_account_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            to_u64(result.owner_fee)?,
+        )?;
+
+        //Transfer pc token from pool
+        Self::token_transfer(
+            swap_info.key,
+            destination_token_program_info.clone(),
+            swap_destination_info.clone(),
+            destination_mint_info.cloned(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce(),
+            to_u64(result.destination_amount_swapped)?,
+        )?;
+
+        // the reserves must match the curve's accounting, the owner fee was
+        // sent to the fee account rather than the source reserve
+        let expected_source_amount = result
+            .new_swap_source_amount
+            .checked_sub(result.owner_fee)
+            .ok_or(SwapError::CalculationFailure)?;
+        Self::assert_reserve_amounts(&[
+            (swap_source_info, source_program_id, expected_source_amount),
+            (
+                swap_destination_info,
+                destination_program_id,
+                result.new_swap_destination_amount,
+            ),
+        ])?;
+
+        Self::record_owner_fee(swap_info, trade_direction, result.owner_fee)?;
+        let (new_reserve_a, new_reserve_b) = match trade_direction {
+            TradeDirection::AtoB => (expected_source_amount, result.new_swap_destination_amount),
+            TradeDirection::BtoA => (result.new_swap_destination_amount, expected_source_amount),
+        };
+        let filled = to_u64(result.source_amount_swapped)?;
+        set_return_data(
+            &SwapReturnData {
+                filled,
+                unfilled: requested_amount_in
+                    .checked_sub(filled)
+                    .ok_or(SwapError::CalculationFailure)?,
+                new_reserve_a: to_u64(new_reserve_a)?,
+                new_reserve_b: to_u64(new_reserve_b)?,
+            }
+            .pack(),
+        );
+        Ok(())
+    }
+    /// Processes an [DepositAllTokenTypes](enum.Instruction.html).
+    pub fn process_deposit_all_token_types(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        maximum_token_a_amount: u64,
+        maximum_token_b_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_a_info = next_account_info(account_info_iter)?;
+        let source_b_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[
+            source_a_info,
+            source_b_info,
+            token_a_info,
+            token_b_info,
+            pool_mint_info,
+            dest_info,
+        ])?;
+
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+
+        let state = Self::unpack_global_state(program_id, state_info)?;
+
+        let calculator = &token_swap.swap_curve().calculator;
+        if !calculator.allows_deposits() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
         Self::check_accounts(
             token_swap.as_ref(),
             program_id,
@@ -770,103 +2110,718 @@ impl Processor {
             token_b_info,
             pool_mint_info,
             token_program_info,
-            Some(dest_token_a_info),
-            Some(dest_token_b_info),
+            Some(source_a_info),
+            Some(source_b_info),
+        )?;
+        let token_a_program_info = Self::reserve_token_program(
+            token_swap.token_a_program_id(),
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let token_b_program_info = Self::reserve_token_program(
+            token_swap.token_b_program_id(),
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let token_a_mint_info = Self::reserve_mint(
+            token_swap.token_a_program_id(),
+            token_swap.token_a_mint(),
+            account_info_iter.as_slice(),
+        )?;
+        let token_b_mint_info = Self::reserve_mint(
+            token_swap.token_b_program_id(),
+            token_swap.token_b_mint(),
+            account_info_iter.as_slice(),
+        )?;
+        let (token_a, token_b) = Self::unpack_token_accounts(
+            token_a_info,
+            token_swap.token_a_program_id(),
+            token_b_info,
+            token_swap.token_b_program_id(),
         )?;
-
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        Self::assert_pool_mint_decimals(token_swap.as_ref(), &pool_mint)?;
 
-        let calculator = &token_swap.swap_curve().calculator;
-
-        let mut pool_token_amount = to_u128(pool_token_amount)?;
+        let current_pool_mint_supply = to_u128(pool_mint.supply)?;
+        // a pool its last LP has emptied is re-seeded with the deposited
+        // maxima, like a new pool
+        let (reserve_a, reserve_b) =
+            if current_pool_mint_supply == 0 && token_a.amount == 0 && token_b.amount == 0 {
+                (to_u128(maximum_token_a_amount)?, to_u128(maximum_token_b_amount)?)
+            } else {
+                (to_u128(token_a.amount)?, to_u128(token_b.amount)?)
+            };
+        let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
+            (to_u128(pool_token_amount)?, current_pool_mint_supply)
+        } else {
+            // an empty pool is seeded like a new one, minting the curve's
+            // initial supply for the reserves
+            let initial_amount = calculator
+                .initial_pool_supply(reserve_a, reserve_b, to_u128(state.initial_supply())?)
+                .ok_or(SwapError::CalculationFailure)?;
+            (initial_amount, initial_amount)
+        };
 
-        //Check the minimum lp token amount
-        let max_pool_token_amount = to_u128(pool_mint.supply)?.checked_sub(MIN_LP_SUPPLY).ok_or(SwapError::CalculationFailure)?;
-        pool_token_amount = std::cmp::min(pool_token_amount, max_pool_token_amount);
+        if current_pool_mint_supply > 0 {
+            Self::check_deposit_maxima_ratio(
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                token_a.amount,
+                token_b.amount,
+            )?;
+        }
 
+        // zero amounts are rejected below, `None` only comes from overflow
         let results = calculator
             .pool_tokens_to_trading_tokens(
                 pool_token_amount,
-                to_u128(pool_mint.supply)?,
-                to_u128(token_a.amount)?,
-                to_u128(token_b.amount)?,
-                RoundDirection::Floor,
+                pool_mint_supply,
+                reserve_a,
+                reserve_b,
+                RoundDirection::Ceiling,
             )
-            .ok_or(SwapError::ZeroTradingTokens)?;
+            .ok_or(SwapError::CalculationFailure)?;
         let token_a_amount = to_u64(results.token_a_amount)?;
-        let token_a_amount = std::cmp::min(token_a.amount, token_a_amount);
-        if token_a_amount < minimum_token_a_amount {
+        if token_a_amount > maximum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        if token_a_amount == 0 && token_a.amount != 0 {
+        if token_a_amount == 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
         let token_b_amount = to_u64(results.token_b_amount)?;
-        let token_b_amount = std::cmp::min(token_b.amount, token_b_amount);
-        if token_b_amount < minimum_token_b_amount {
+        if token_b_amount > maximum_token_b_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        if token_b_amount == 0 && token_b.amount != 0 {
+        if token_b_amount == 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
 
-        Self::token_burn(
+        state.check_reserve_cap(token_a.amount, token_a_amount, token_b.amount, token_b_amount)?;
+        state.check_deposit_limit(pool_token_amount)?;
+
+        if current_pool_mint_supply > 0 {
+            Self::assert_no_dilution(
+                to_u128(token_a.amount)?,
+                to_u128(token_b.amount)?,
+                current_pool_mint_supply,
+                results.token_a_amount,
+                results.token_b_amount,
+                pool_token_amount,
+            )?;
+        }
+
+        let pool_token_amount = to_u64(pool_token_amount)?;
+        //transfer token to pool
+        Self::token_transfer(
+            swap_info.key,
+            token_a_program_info.clone(),
+            source_a_info.clone(),
+            token_a_mint_info.cloned(),
+            token_a_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            token_a_amount,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_b_program_info.clone(),
+            source_b_info.clone(),
+            token_b_mint_info.cloned(),
+            token_b_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            token_b_amount,
+        )?;
+        Self::assert_reserve_amounts(&[
+            (
+                token_a_info,
+                token_swap.token_a_program_id(),
+                to_u128(token_a.amount)?
+                    .checked_add(results.token_a_amount)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+            (
+                token_b_info,
+                token_swap.token_b_program_id(),
+                to_u128(token_b.amount)?
+                    .checked_add(results.token_b_amount)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+        ])?;
+        //mint lp token to wallet
+        Self::token_mint_to(
             swap_info.key,
             token_program_info.clone(),
-            source_info.clone(),
             pool_mint_info.clone(),
-            user_transfer_authority_info.clone(),
+            dest_info.clone(),
+            authority_info.clone(),
             token_swap.nonce(),
-            to_u64(pool_token_amount)?,
+            pool_token_amount,
         )?;
 
-        if token_a_amount > 0 {
-            Self::token_transfer(
-                swap_info.key,
-                token_program_info.clone(),
-                token_a_info.clone(),
-                dest_token_a_info.clone(),
-                authority_info.clone(),
-                token_swap.nonce(),
-                token_a_amount,
-            )?;
-        }
-        if token_b_amount > 0 {
-            Self::token_transfer(
-                swap_info.key,
-                token_program_info.clone(),
-                token_b_info.clone(),
-                dest_token_b_info.clone(),
-                authority_info.clone(),
-                token_swap.nonce(),
-                token_b_amount,
-            )?;
-        }
         Ok(())
     }
 
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+    /// Processes a [BatchDeposit](enum.Instruction.html).
+    ///
+    /// Each deposit is run against its own group of accounts, so any failure
+    /// reverts the whole transaction and no pool is left partially seeded.
+    pub fn process_batch_deposit(
+        program_id: &Pubkey,
+        deposits: &[DepositAllTokenTypes],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if deposits.is_empty() || deposits.len() > MAX_BATCH_DEPOSITS {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if accounts.len() != deposits.len() * DEPOSIT_ALL_TOKEN_TYPES_ACCOUNTS {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        for (deposit, pool_accounts) in deposits
+            .iter()
+            .zip(accounts.chunks(DEPOSIT_ALL_TOKEN_TYPES_ACCOUNTS))
+        {
+            Self::process_deposit_all_token_types(
+                program_id,
+                deposit.pool_token_amount,
+                deposit.maximum_token_a_amount,
+                deposit.maximum_token_b_amount,
+                pool_accounts,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Processes an [WithdrawAllTokenTypes](enum.Instruction.html).
+    pub fn process_withdraw_all_token_types(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        minimum_token_a_amount: u64,
+        minimum_token_b_amount: u64,
+        deadline: i64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        // a stale exit could otherwise land at a ratio the user never saw
+        if deadline != 0 && Clock::get()?.unix_timestamp > deadline {
+            return Err(SwapError::DeadlineExceeded.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dest_token_a_info = next_account_info(account_info_iter)?;
+        let dest_token_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[
+            pool_mint_info,
+            source_info,
+            token_a_info,
+            token_b_info,
+            dest_token_a_info,
+            dest_token_b_info,
+        ])?;
+
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+
+        Self::unpack_global_state(program_id, state_info)?;
+
+        Self::check_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            token_a_info,
+            token_b_info,
+            pool_mint_info,
+            token_program_info,
+            Some(dest_token_a_info),
+            Some(dest_token_b_info),
+        )?;
+        // the pool mint can never stand in for a reserve or a destination
+        if [
+            token_a_info.key,
+            token_b_info.key,
+            dest_token_a_info.key,
+            dest_token_b_info.key,
+        ]
+        .contains(&pool_mint_info.key)
+        {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let token_a_program_info = Self::reserve_token_program(
+            token_swap.token_a_program_id(),
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let token_b_program_info = Self::reserve_token_program(
+            token_swap.token_b_program_id(),
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let token_a_mint_info = Self::reserve_mint(
+            token_swap.token_a_program_id(),
+            token_swap.token_a_mint(),
+            account_info_iter.as_slice(),
+        )?;
+        let token_b_mint_info = Self::reserve_mint(
+            token_swap.token_b_program_id(),
+            token_swap.token_b_mint(),
+            account_info_iter.as_slice(),
+        )?;
+        Self::assert_token_account_open(dest_token_a_info, token_swap.token_a_program_id())?;
+        Self::assert_token_account_open(dest_token_b_info, token_swap.token_b_program_id())?;
+
+        let (token_a, token_b) = Self::unpack_token_accounts(
+            token_a_info,
+            token_swap.token_a_program_id(),
+            token_b_info,
+            token_swap.token_b_program_id(),
+        )?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        Self::assert_pool_mint_decimals(token_swap.as_ref(), &pool_mint)?;
+
+        // the floor protects the remaining LPs, so the last LP burning the
+        // whole supply exits with the whole pool instead of leaving the
+        // floor's share of the reserves stranded
+        let source = Self::unpack_token_account(source_info, token_swap.token_program_id())?;
+        let min_lp_supply =
+            if source.amount == pool_mint.supply && pool_token_amount >= pool_mint.supply {
+                0
+            } else {
+                token_swap.min_lp_supply()
+            };
+
+        let pool_token_amount = to_u128(pool_token_amount)?;
+
+        //Check the minimum lp token amount
+        let max_pool_token_amount = to_u128(pool_mint.supply)?
+            .checked_sub(min_lp_supply)
+            .ok_or(SwapError::CalculationFailure)?;
+        if pool_token_amount > max_pool_token_amount {
+            return Err(SwapError::BelowMinLpSupply.into());
+        }
+
+        let (token_a_amount, token_b_amount) = token_swap
+            .swap_curve()
+            .simulate_withdraw(
+                pool_token_amount,
+                to_u128(pool_mint.supply)?,
+                to_u128(token_a.amount)?,
+                to_u128(token_b.amount)?,
+                min_lp_supply,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let token_a_amount = to_u64(token_a_amount)?;
+        if token_a_amount < minimum_token_a_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if token_a_amount == 0 && token_a.amount != 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        let token_b_amount = to_u64(token_b_amount)?;
+        if token_b_amount < minimum_token_b_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if token_b_amount == 0 && token_b.amount != 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            to_u64(pool_token_amount)?,
+        )?;
+
+        if token_a_amount > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_a_program_info.clone(),
+                token_a_info.clone(),
+                token_a_mint_info.cloned(),
+                dest_token_a_info.clone(),
+                authority_info.clone(),
+                token_swap.nonce(),
+                token_a_amount,
+            )?;
+        }
+        if token_b_amount > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_b_program_info.clone(),
+                token_b_info.clone(),
+                token_b_mint_info.cloned(),
+                dest_token_b_info.clone(),
+                authority_info.clone(),
+                token_swap.nonce(),
+                token_b_amount,
+            )?;
+        }
+        Self::assert_reserve_amounts(&[
+            (
+                token_a_info,
+                token_swap.token_a_program_id(),
+                to_u128(token_a.amount)?
+                    .checked_sub(to_u128(token_a_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+            (
+                token_b_info,
+                token_swap.token_b_program_id(),
+                to_u128(token_b.amount)?
+                    .checked_sub(to_u128(token_b_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+        ])?;
+        Ok(())
+    }
+
+    /// Processes a [ZapDeposit](enum.Instruction.html).
+    pub fn process_zap_deposit(
+        program_id: &Pubkey,
+        source_amount: u64,
+        minimum_pool_token_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let refund_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let fee_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        Self::assert_writable(&[
+            swap_info,
+            source_info,
+            refund_info,
+            token_a_info,
+            token_b_info,
+            pool_mint_info,
+            dest_info,
+            fee_account_info,
+        ])?;
+
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+
+        let state = Self::unpack_global_state(program_id, state_info)?;
+
+        let calculator = &token_swap.swap_curve().calculator;
+        if !calculator.allows_deposits() || !calculator.allows_single_sided_deposit() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        Self::check_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            token_a_info,
+            token_b_info,
+            pool_mint_info,
+            token_program_info,
+            Some(source_info),
+            Some(source_info),
+        )?;
+        if *refund_info.key == *token_a_info.key || *refund_info.key == *token_b_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if fee_account_info.key == source_info.key || fee_account_info.key == refund_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+
+        // the source is read under its own owner only to learn its mint, the
+        // owner must then be the program of the matching reserve
+        let source = Self::unpack_token_account(source_info, source_info.owner)?;
+        let (trade_direction, swap_source_info, swap_destination_info) =
+            if source.mint == *token_swap.token_a_mint() {
+                (TradeDirection::AtoB, token_a_info, token_b_info)
+            } else if source.mint == *token_swap.token_b_mint() {
+                (TradeDirection::BtoA, token_b_info, token_a_info)
+            } else {
+                return Err(SwapError::IncorrectSwapAccount.into());
+            };
+        let (source_program_id, destination_program_id) = match trade_direction {
+            TradeDirection::AtoB => (
+                token_swap.token_a_program_id(),
+                token_swap.token_b_program_id(),
+            ),
+            TradeDirection::BtoA => (
+                token_swap.token_b_program_id(),
+                token_swap.token_a_program_id(),
+            ),
+        };
+        if source_info.owner != source_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        let source_token_program_info = Self::reserve_token_program(
+            source_program_id,
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let destination_token_program_info = Self::reserve_token_program(
+            destination_program_id,
+            token_program_info,
+            account_info_iter.as_slice(),
+        )?;
+        let (source_mint, destination_mint) = match trade_direction {
+            TradeDirection::AtoB => (token_swap.token_a_mint(), token_swap.token_b_mint()),
+            TradeDirection::BtoA => (token_swap.token_b_mint(), token_swap.token_a_mint()),
+        };
+        let source_mint_info =
+            Self::reserve_mint(source_program_id, source_mint, account_info_iter.as_slice())?;
+        let destination_mint_info = Self::reserve_mint(
+            destination_program_id,
+            destination_mint,
+            account_info_iter.as_slice(),
+        )?;
+        let fee_token_account = Self::unpack_token_account(fee_account_info, source_program_id)?;
+        if fee_token_account.owner != *state.fee_owner() {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if fee_token_account.owner == *authority_info.key {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        let (swap_source, swap_destination) = Self::unpack_token_accounts(
+            swap_source_info,
+            source_program_id,
+            swap_destination_info,
+            destination_program_id,
+        )?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        Self::assert_pool_mint_decimals(token_swap.as_ref(), &pool_mint)?;
+        let pool_mint_supply = to_u128(pool_mint.supply)?;
+        if pool_mint_supply == 0 {
+            return Err(SwapError::EmptySupply.into());
+        }
+
+        // swap half of the source for the other token, the output never
+        // leaves the pool and is deposited right back as the other side
+        let source_amount = to_u128(source_amount)?;
+        let fees = Self::swap_leg_fees(
+            &state,
+            token_swap.as_ref(),
+            authority_info,
+            &swap_source,
+            &swap_destination,
+            source_amount / 2,
+            trade_direction,
+        )?;
+        let result = token_swap
+            .swap_curve()
+            .swap(
+                source_amount / 2,
+                to_u128(swap_source.amount)?,
+                to_u128(swap_destination.amount)?,
+                trade_direction,
+                &fees,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let swap_source_amount = result
+            .new_swap_source_amount
+            .checked_sub(result.owner_fee)
+            .ok_or(SwapError::CalculationFailure)?;
+        let swap_destination_amount = result.new_swap_destination_amount;
+        let remaining_amount = source_amount
+            .checked_sub(result.source_amount_swapped)
+            .ok_or(SwapError::CalculationFailure)?;
+
+        // mint for the scarcer side, the other side is refunded the rest
+        let pool_tokens_for = |amount: u128, reserve: u128| {
+            amount
+                .checked_mul(pool_mint_supply)
+                .and_then(|amount| amount.checked_div(reserve))
+        };
+        let pool_token_amount = std::cmp::min(
+            pool_tokens_for(remaining_amount, swap_source_amount)
+                .ok_or(SwapError::CalculationFailure)?,
+            pool_tokens_for(result.destination_amount_swapped, swap_destination_amount)
+                .ok_or(SwapError::CalculationFailure)?,
+        );
+        if pool_token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        if pool_token_amount < to_u128(minimum_pool_token_amount)? {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        state.check_deposit_limit(pool_token_amount)?;
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        let results = calculator
+            .pool_tokens_to_trading_tokens(
+                pool_token_amount,
+                pool_mint_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Ceiling,
+            )
+            .ok_or(SwapError::CalculationFailure)?;
+        let (source_deposit, destination_deposit) = match trade_direction {
+            TradeDirection::AtoB => (results.token_a_amount, results.token_b_amount),
+            TradeDirection::BtoA => (results.token_b_amount, results.token_a_amount),
+        };
+        if source_deposit > remaining_amount
+            || destination_deposit > result.destination_amount_swapped
+        {
+            return Err(SwapError::CalculationFailure.into());
+        }
+        Self::assert_no_dilution(
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_mint_supply,
+            results.token_a_amount,
+            results.token_b_amount,
+            pool_token_amount,
+        )?;
+
+        let source_transfer_amount = to_u64(
+            result
+                .source_amount_swapped
+                .checked_sub(result.owner_fee)
+                .and_then(|amount| amount.checked_add(source_deposit))
+                .ok_or(SwapError::CalculationFailure)?,
+        )?;
+        let refund_amount = to_u64(
+            result
+                .destination_amount_swapped
+                .checked_sub(destination_deposit)
+                .ok_or(SwapError::CalculationFailure)?,
+        )?;
+        match trade_direction {
+            TradeDirection::AtoB => state.check_reserve_cap(
+                swap_source.amount,
+                source_transfer_amount,
+                swap_destination.amount,
+                0,
+            )?,
+            TradeDirection::BtoA => state.check_reserve_cap(
+                swap_destination.amount,
+                0,
+                swap_source.amount,
+                source_transfer_amount,
+            )?,
+        }
+
+        Self::token_transfer(
+            swap_info.key,
+            source_token_program_info.clone(),
+            source_info.clone(),
+            source_mint_info.cloned(),
+            swap_source_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            source_transfer_amount,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            source_token_program_info.clone(),
+            source_info.clone(),
+            source_mint_info.cloned(),
+            fee_account_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.nonce(),
+            to_u64(result.owner_fee)?,
+        )?;
+        if refund_amount > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                destination_token_program_info.clone(),
+                swap_destination_info.clone(),
+                destination_mint_info.cloned(),
+                refund_info.clone(),
+                authority_info.clone(),
+                token_swap.nonce(),
+                refund_amount,
+            )?;
+        }
+        Self::assert_reserve_amounts(&[
+            (
+                swap_source_info,
+                source_program_id,
+                to_u128(swap_source.amount)?
+                    .checked_add(to_u128(source_transfer_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+            (
+                swap_destination_info,
+                destination_program_id,
+                to_u128(swap_destination.amount)?
+                    .checked_sub(to_u128(refund_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+        ])?;
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce(),
+            to_u64(pool_token_amount)?,
+        )?;
+
+        Self::record_owner_fee(swap_info, trade_direction, result.owner_fee)?;
+        set_return_data(
+            &ZapDepositReturnData {
+                pool_token_amount: to_u64(pool_token_amount)?,
+                swap_amount: to_u64(result.source_amount_swapped)?,
+            }
+            .pack(),
+        );
+        Ok(())
+    }
+
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
         let instruction = SwapInstruction::unpack(input)?;
         match instruction {
             SwapInstruction::Initialize(Initialize {
-                swap_curve
+                swap_curve,
+                fee_tier_index,
+                min_lp_supply,
+                native_amount,
+                nonce,
             }) => {
                 msg!("Instruction: Init");
                 Self::process_initialize(
                     program_id,
                     swap_curve,
+                    fee_tier_index,
+                    min_lp_supply,
+                    native_amount,
+                    nonce,
                     accounts,
                 )
             }
             SwapInstruction::Swap(Swap {
                 amount_in,
                 minimum_amount_out,
+                max_slippage_bps,
+                allow_partial,
             }) => {
                 msg!("Instruction: Swap");
-                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+                Self::process_swap(
+                    program_id,
+                    amount_in,
+                    minimum_amount_out,
+                    max_slippage_bps,
+                    allow_partial,
+                    accounts,
+                )
             }
             SwapInstruction::DepositAllTokenTypes(DepositAllTokenTypes {
                 pool_token_amount,
@@ -886,6 +2841,7 @@ impl Processor {
                 pool_token_amount,
                 minimum_token_a_amount,
                 minimum_token_b_amount,
+                deadline,
             }) => {
                 msg!("Instruction: WithdrawAllTokenTypes");
                 Self::process_withdraw_all_token_types(
@@ -893,27 +2849,50 @@ impl Processor {
                     pool_token_amount,
                     minimum_token_a_amount,
                     minimum_token_b_amount,
+                    deadline,
                     accounts,
                 )
             }
-            SwapInstruction::SetGlobalStateInstruction(SetGlobalState {
-                owner,
-                fee_owner,
-                initial_supply,
-                lp_decimals,
-                fees,
-            }) => {
+            SwapInstruction::SetGlobalStateInstruction(instruction) => {
                 msg!("Instruction: SetGlobalStateInstruction");
-                Self::process_set_global_state(
+                Self::process_set_global_state(program_id, instruction, accounts)
+            }
+            SwapInstruction::BatchDeposit(BatchDeposit { deposits }) => {
+                msg!("Instruction: BatchDeposit");
+                Self::process_batch_deposit(program_id, &deposits, accounts)
+            }
+            SwapInstruction::SetPoolMetadata(SetPoolMetadata { name, symbol }) => {
+                msg!("Instruction: SetPoolMetadata");
+                Self::process_set_pool_metadata(program_id, &name, &symbol, accounts)
+            }
+            SwapInstruction::LockLiquidity(LockLiquidity { amount, unlock_ts }) => {
+                msg!("Instruction: LockLiquidity");
+                Self::process_lock_liquidity(program_id, amount, unlock_ts, accounts)
+            }
+            SwapInstruction::Unlock => {
+                msg!("Instruction: Unlock");
+                Self::process_unlock(program_id, accounts)
+            }
+            SwapInstruction::RescueTokens(RescueTokens { mint }) => {
+                msg!("Instruction: RescueTokens");
+                Self::process_rescue_tokens(program_id, &mint, accounts)
+            }
+            SwapInstruction::ZapDeposit(ZapDeposit {
+                source_amount,
+                minimum_pool_token_amount,
+            }) => {
+                msg!("Instruction: ZapDeposit");
+                Self::process_zap_deposit(
                     program_id,
-                    &owner,
-                    &fee_owner,
-                    initial_supply,
-                    lp_decimals,
-                    fees,
+                    source_amount,
+                    minimum_pool_token_amount,
                     accounts,
                 )
             }
+            SwapInstruction::FeeSnapshot => {
+                msg!("Instruction: FeeSnapshot");
+                Self::process_fee_snapshot(program_id, accounts)
+            }
         }
     }
 }
@@ -1007,14 +2986,4113 @@ impl PrintProgramError for SwapError {
             SwapError::NotInitializedState => {
                 msg!("Program State should be initialized before creating pool")
             }
+            SwapError::NotRentExempt => {
+                msg!("Lamport balance below rent-exempt threshold")
+            }
+            SwapError::PoolCapExceeded => {
+                msg!("Deposit would exceed the pool size cap")
+            }
+            SwapError::InvalidInitialSupply => {
+                msg!("Initial supply is inconsistent with the lp decimals")
+            }
+            SwapError::AccountNotWritable => {
+                msg!("Error: Account must be writable")
+            }
+            SwapError::StillLocked => msg!("Error: Liquidity is still locked"),
+            SwapError::CpiNotAllowed => {
+                msg!("Error: Instruction cannot be invoked through a cross-program invocation")
+            }
+            SwapError::CannotRescueReserve => {
+                msg!("Error: Cannot rescue the reserves or pool tokens")
+            }
+            SwapError::DeadlineExceeded => {
+                msg!("Error: The deadline of the instruction has passed")
+            }
+            SwapError::InvalidNativeAmount => {
+                msg!("Error: Lamports can only be wrapped into a native mint reserve")
+            }
+            SwapError::DepositTooLarge => {
+                msg!("Error: The deposit mints more pool tokens than allowed")
+            }
+            SwapError::InsufficientFunds => {
+                msg!("Error: The source account holds less than the swap amount")
+            }
+            SwapError::UnsupportedTokenExtension => {
+                msg!("Error: Token-2022 extension not supported")
+            }
+            SwapError::FeeSnapshotTaken => {
+                msg!("Error: Fee snapshot already taken this epoch")
+            }
+            SwapError::BelowMinLpSupply => {
+                msg!("Error: The withdrawal would leave less than the minimum LP supply")
+            }
         }
     }
 }
 
 fn to_u128(val: u64) -> Result<u128, SwapError> {
-    val.try_into().map_err(|_| SwapError::ConversionFailure)
+    Ok(u128::from(val))
 }
 
 fn to_u64(val: u128) -> Result<u64, SwapError> {
     val.try_into().map_err(|_| SwapError::ConversionFailure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        curve::constant_product::ConstantProductCurve,
+        curve::constant_price::ConstantPriceCurve,
+        curve::stable::StableCurve,
+        instruction::{
+            batch_deposit, deposit_all_token_types, initialize, lock_liquidity, rescue_tokens,
+            set_global_state, set_pool_metadata, swap, unlock, withdraw_all_token_types,
+            zap_deposit, fee_snapshot,
+        },
+        pda::{
+            find_fee_snapshot_address, find_global_state_address, find_liquidity_escrow_address,
+            find_liquidity_lock_address, find_pool_metadata_address,
+        },
+    };
+    use solana_program::{
+        clock::Epoch,
+        entrypoint::{deserialize, MAX_PERMITTED_DATA_INCREASE, SUCCESS},
+        instruction::{AccountMeta, Instruction},
+        program::get_return_data,
+        program_stubs,
+    };
+    use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    };
+    use std::thread::{self, ThreadId};
+
+    const TEST_FEES: Fees = Fees {
+        constant_product_return_fee_numerator: 25,
+        constant_product_fixed_fee_numerator: 5,
+        stable_return_fee_numerator: 4,
+        stable_fixed_fee_numerator: 1,
+        fee_denominator: 10_000,
+    };
+
+    const TEST_UNIX_TIMESTAMP: i64 = 1_600_000_000;
+
+    /// Epoch of the clock seen by instructions, only moved by the fee
+    /// snapshot test, the other tests do not depend on it
+    static TEST_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+    /// Return data of the last instruction run by each test thread, the stubs
+    /// are shared by the tests running in parallel
+    static RETURN_DATA: Mutex<Vec<(ThreadId, Vec<u8>)>> = Mutex::new(Vec::new());
+
+    struct TestSyscallStubs;
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            let signers = signers_seeds
+                .iter()
+                .map(|seeds| Pubkey::create_program_address(seeds, &crate::id()).unwrap())
+                .collect::<Vec<_>>();
+
+            // mimic the runtime check for the invoked program in the accounts
+            if !account_infos.iter().any(|x| *x.key == instruction.program_id) {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let mut new_account_infos = vec![];
+            for meta in instruction.accounts.iter() {
+                for account_info in account_infos.iter() {
+                    if meta.pubkey == *account_info.key {
+                        let mut new_account_info = account_info.clone();
+                        if signers.contains(account_info.key) {
+                            new_account_info.is_signer = true;
+                        }
+                        new_account_infos.push(new_account_info);
+                    }
+                }
+            }
+
+            if instruction.program_id == system_program::id() {
+                return system_transfer(&instruction.data, &new_account_infos);
+            }
+
+            // Token-2022 shares the spl_token layout of every instruction used
+            // here, so the spl_token processor stands in for it, seeing only
+            // the spl_token part of accounts carrying extensions
+            let extended = account_infos
+                .iter()
+                .filter(|info| {
+                    *info.owner == token_2022_program_id() && info.data_len() > TokenAccount::LEN
+                })
+                .map(|info| {
+                    let mut data = info.data.borrow_mut();
+                    let full = std::mem::take(&mut *data);
+                    let (ptr, len) = (full.as_mut_ptr(), full.len());
+                    *data = { full }.split_at_mut(TokenAccount::LEN).0;
+                    (info, ptr, len)
+                })
+                .collect::<Vec<_>>();
+            let result = spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &new_account_infos,
+                &instruction.data,
+            );
+            for (info, ptr, len) in extended {
+                *info.data.borrow_mut() = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+            }
+            result
+        }
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut Clock) = Clock {
+                    unix_timestamp: TEST_UNIX_TIMESTAMP,
+                    epoch: TEST_EPOCH.load(Ordering::Relaxed),
+                    ..Clock::default()
+                };
+            }
+            SUCCESS
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            let id = thread::current().id();
+            let mut return_data = RETURN_DATA.lock().unwrap();
+            return_data.retain(|(thread, _)| *thread != id);
+            if !data.is_empty() {
+                return_data.push((id, data.to_vec()));
+            }
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            let id = thread::current().id();
+            RETURN_DATA
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(thread, _)| *thread == id)
+                .map(|(_, data)| (crate::id(), data.clone()))
+        }
+    }
+
+    fn test_syscall_stubs() {
+        use std::sync::Once;
+        static ONCE: Once = Once::new();
+
+        ONCE.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
+        });
+    }
+
+    /// Account data owned by a test, lent out as an `AccountInfo`
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key,
+                owner,
+                lamports: 1_000_000_000,
+                data,
+            }
+        }
+
+        fn token(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Self {
+            let mut data = vec![0u8; TokenAccount::LEN];
+            TokenAccount {
+                mint: *mint,
+                owner: *owner,
+                amount,
+                state: AccountState::Initialized,
+                ..TokenAccount::default()
+            }
+            .pack_into_slice(&mut data);
+            Self::new(Pubkey::new_unique(), spl_token::id(), data)
+        }
+
+        fn mint(authority: &Pubkey, supply: u64, decimals: u8) -> Self {
+            let mut data = vec![0u8; Mint::LEN];
+            Mint {
+                mint_authority: COption::Some(*authority),
+                supply,
+                decimals,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            }
+            .pack_into_slice(&mut data);
+            Self::new(Pubkey::new_unique(), spl_token::id(), data)
+        }
+
+        fn signer() -> Self {
+            Self::new(Pubkey::new_unique(), system_program::id(), vec![])
+        }
+
+        /// The instructions sysvar of a transaction whose only, and current,
+        /// instruction is sent to `program_id`
+        fn instructions(program_id: &Pubkey) -> Self {
+            let mut data = vec![];
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&4u16.to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(program_id.as_ref());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            Self::new(sysvar::instructions::id(), sysvar::id(), data)
+        }
+
+        /// The rent sysvar at its default values, in its bincode layout
+        fn rent() -> Self {
+            let rent = Rent::default();
+            let mut data = vec![];
+            data.extend_from_slice(&rent.lamports_per_byte_year.to_le_bytes());
+            data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+            data.push(rent.burn_percent);
+            Self::new(sysvar::rent::id(), sysvar::id(), data)
+        }
+
+        /// A Token-2022 token account carrying the ImmutableOwner extension,
+        /// as every associated token account does
+        fn token_2022(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Self {
+            Self::token(mint, owner, amount).into_token_2022()
+        }
+
+        /// The same token account moved to Token-2022, with ImmutableOwner
+        fn into_token_2022(mut self) -> Self {
+            self.owner = token_2022_program_id();
+            // account type, then an ImmutableOwner entry without a value
+            self.data.push(2);
+            self.data.extend_from_slice(&7u16.to_le_bytes());
+            self.data.extend_from_slice(&0u16.to_le_bytes());
+            self
+        }
+
+        fn token_amount(&self) -> u64 {
+            crate::token_2022::unpack_account(&self.data).unwrap().amount
+        }
+    }
+
+    fn token_2022_program_id() -> Pubkey {
+        crate::token_2022::id()
+    }
+
+    /// Stands in for the system program, only its `Transfer` is supported as
+    /// the accounts lent to the program cannot be resized
+    fn system_transfer(data: &[u8], account_infos: &[AccountInfo]) -> ProgramResult {
+        let lamports = match data {
+            [2, 0, 0, 0, lamports @ ..] => u64::from_le_bytes(
+                lamports
+                    .try_into()
+                    .map_err(|_| ProgramError::InvalidInstructionData)?,
+            ),
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        let (from, to) = (&account_infos[0], &account_infos[1]);
+        if !from.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let from_lamports = from
+            .lamports()
+            .checked_sub(lamports)
+            .ok_or(ProgramError::InsufficientFunds)?;
+        **from.try_borrow_mut_lamports()? = from_lamports;
+        **to.try_borrow_mut_lamports()? += lamports;
+        Ok(())
+    }
+
+    fn do_process_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut TestAccount>,
+    ) -> ProgramResult {
+        test_syscall_stubs();
+        // like the runtime, every instruction starts without return data
+        set_return_data(&[]);
+        assert_eq!(instruction.accounts.len(), accounts.len());
+        let account_infos = instruction
+            .accounts
+            .iter()
+            .zip(accounts)
+            .map(|(meta, account)| {
+                assert_eq!(meta.pubkey, account.key);
+                AccountInfo::new(
+                    &account.key,
+                    meta.is_signer,
+                    meta.is_writable,
+                    &mut account.lamports,
+                    &mut account.data[..],
+                    &account.owner,
+                    false,
+                    Epoch::default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        Processor::process(&instruction.program_id, &account_infos, &instruction.data)
+    }
+
+    /// Like `do_process_instruction`, but the accounts are serialized the way
+    /// the runtime passes them to the program, leaving room for
+    /// `AccountInfo::realloc` to grow their data
+    fn do_process_serialized_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut TestAccount>,
+    ) -> ProgramResult {
+        test_syscall_stubs();
+        set_return_data(&[]);
+        assert_eq!(instruction.accounts.len(), accounts.len());
+        let mut input = vec![];
+        let mut data_offsets = vec![];
+        input.extend_from_slice(&(accounts.len() as u64).to_le_bytes());
+        for (meta, account) in instruction.accounts.iter().zip(accounts.iter()) {
+            assert_eq!(meta.pubkey, account.key);
+            input.push(u8::MAX); // not a duplicate
+            input.push(meta.is_signer as u8);
+            input.push(meta.is_writable as u8);
+            input.push(0); // executable
+            input.extend_from_slice(&[0; 4]);
+            input.extend_from_slice(account.key.as_ref());
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            data_offsets.push(input.len());
+            input.extend_from_slice(&account.data);
+            input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            input.resize(input.len() + (8 - input.len() % 8) % 8, 0);
+            input.extend_from_slice(&Epoch::default().to_le_bytes());
+        }
+        input.extend_from_slice(&(instruction.data.len() as u64).to_le_bytes());
+        input.extend_from_slice(&instruction.data);
+        input.extend_from_slice(instruction.program_id.as_ref());
+
+        // the runtime aligns the input for its u64 reads
+        let mut buffer = vec![0u64; input.len() / 8 + 1];
+        let len = input.len();
+        let input_ptr = buffer.as_mut_ptr() as *mut u8;
+        unsafe { std::slice::from_raw_parts_mut(input_ptr, len) }.copy_from_slice(&input);
+        let result = {
+            let (program_id, account_infos, data) = unsafe { deserialize(input_ptr) };
+            Processor::process(program_id, &account_infos, data)
+        };
+
+        // write the lamports and the possibly resized data back
+        let output = unsafe { std::slice::from_raw_parts(input_ptr, len) };
+        for (account, offset) in accounts.into_iter().zip(data_offsets) {
+            let read_u64 = |at: usize| u64::from_le_bytes(output[at..at + 8].try_into().unwrap());
+            account.lamports = read_u64(offset - 16);
+            let data_len = read_u64(offset - 8) as usize;
+            account.data = output[offset..offset + data_len].to_vec();
+        }
+        result
+    }
+
+    /// A pool written directly in its packed form, without Initialize
+    struct TestPool {
+        swap: TestAccount,
+        authority: TestAccount,
+        global_state: TestAccount,
+        owner: TestAccount,
+        fee_owner: Pubkey,
+        token_a_mint: Pubkey,
+        token_b_mint: Pubkey,
+        token_a: TestAccount,
+        token_b: TestAccount,
+        pool_mint: TestAccount,
+        token_program: TestAccount,
+        /// Program of a reserve outside spl_token, then the mint of each
+        /// Token-2022 reserve, passed after the accounts of every instruction
+        /// moving reserve tokens
+        reserve_accounts: Vec<TestAccount>,
+        instructions: TestAccount,
+    }
+
+    impl TestPool {
+        fn new(
+            swap_curve: SwapCurve,
+            token_a_amount: u64,
+            token_b_amount: u64,
+            pool_supply: u64,
+        ) -> Self {
+            let program_id = crate::id();
+            let swap_key = Pubkey::new_unique();
+            let (authority_key, nonce) = find_swap_authority_address(&program_id, &swap_key);
+            let token_a_mint = Pubkey::new_unique();
+            let token_b_mint = Pubkey::new_unique();
+            let token_a = TestAccount::token(&token_a_mint, &authority_key, token_a_amount);
+            let token_b = TestAccount::token(&token_b_mint, &authority_key, token_b_amount);
+            let pool_mint = TestAccount::mint(&authority_key, pool_supply, 9);
+
+            let mut swap_data = vec![0u8; SwapVersion::LATEST_LEN];
+            SwapVersion::pack(
+                SwapVersion::SwapV1(SwapV1 {
+                    is_initialized: true,
+                    nonce,
+                    token_program_id: spl_token::id(),
+                    token_a: token_a.key,
+                    token_b: token_b.key,
+                    pool_mint: pool_mint.key,
+                    token_a_mint,
+                    token_b_mint,
+                    swap_curve,
+                    pool_mint_decimals: Some(9),
+                    min_lp_supply: None,
+                    fee_tier_index: 0,
+                    token_a_program_id: spl_token::id(),
+                    token_b_program_id: spl_token::id(),
+                    cumulative_fees_a: 0,
+                    cumulative_fees_b: 0,
+                }),
+                &mut swap_data,
+            )
+            .unwrap();
+
+            let owner = TestAccount::signer();
+            let fee_owner = Pubkey::new_unique();
+            let (global_state_key, _) = find_global_state_address(&program_id);
+            let mut global_state_data = vec![0u8; GlobalState::LEN];
+            GlobalState {
+                is_initialized: true,
+                owner: owner.key,
+                fee_owner,
+                initial_supply: 1_000_000_000,
+                lp_decimals: 9,
+                fees: TEST_FEES,
+                ..GlobalState::default()
+            }
+            .pack_into_slice(&mut global_state_data);
+
+            Self {
+                swap: TestAccount::new(swap_key, program_id, swap_data),
+                authority: TestAccount::new(authority_key, system_program::id(), vec![]),
+                global_state: TestAccount::new(global_state_key, program_id, global_state_data),
+                owner,
+                fee_owner,
+                token_a_mint,
+                token_b_mint,
+                token_a,
+                token_b,
+                pool_mint,
+                token_program: TestAccount::new(spl_token::id(), Pubkey::default(), vec![]),
+                reserve_accounts: vec![],
+                instructions: TestAccount::instructions(&program_id),
+            }
+        }
+
+        /// A constant product pool whose token B reserve belongs to
+        /// Token-2022, while the pool mint and token A stay with spl_token
+        fn mixed_constant_product(
+            token_a_amount: u64,
+            token_b_amount: u64,
+            pool_supply: u64,
+        ) -> Self {
+            let mut pool = Self::constant_product(token_a_amount, token_b_amount, pool_supply);
+            let token_2022_program_id = token_2022_program_id();
+            pool.token_b = TestAccount {
+                key: pool.token_b.key,
+                ..TestAccount::token_2022(&pool.token_b_mint, &pool.authority.key, token_b_amount)
+            };
+            let mut token_swap = pool.swap_state();
+            token_swap.token_b_program_id = token_2022_program_id;
+            SwapVersion::pack(SwapVersion::SwapV1(token_swap), &mut pool.swap.data).unwrap();
+            let token_b_mint = TestAccount {
+                key: pool.token_b_mint,
+                owner: token_2022_program_id,
+                ..TestAccount::mint(&Pubkey::new_unique(), u64::MAX, 6)
+            };
+            pool.reserve_accounts = vec![
+                TestAccount::new(token_2022_program_id, Pubkey::default(), vec![]),
+                token_b_mint,
+            ];
+            pool
+        }
+
+        /// The pool as parsed by a client
+        fn swap_state(&self) -> SwapV1 {
+            SwapV1::unpack_from_slice(&self.swap.data[1..]).unwrap()
+        }
+
+        /// A token account of `mint` under the program of the pool's reserve
+        /// of that mint
+        fn user_token(&self, mint: &Pubkey, owner: &Pubkey, amount: u64) -> TestAccount {
+            if *mint == self.token_b_mint && self.token_b.owner == token_2022_program_id() {
+                TestAccount::token_2022(mint, owner, amount)
+            } else {
+                TestAccount::token(mint, owner, amount)
+            }
+        }
+
+        fn constant_product(token_a_amount: u64, token_b_amount: u64, pool_supply: u64) -> Self {
+            Self::new(
+                SwapCurve {
+                    curve_type: CurveType::ConstantProduct,
+                    calculator: Box::new(ConstantProductCurve {}),
+                },
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+            )
+        }
+
+        /// The stored global state as a 'set_global_state' instruction.  Every
+        /// optional setting is given, as they are positional in the
+        /// instruction data, so that any of them can be changed
+        fn global_state_settings(&self) -> SetGlobalState {
+            let state = GlobalState::unpack_from_slice(&self.global_state.data).unwrap();
+            SetGlobalState {
+                owner: state.owner,
+                fee_owner: state.fee_owner,
+                initial_supply: state.initial_supply,
+                lp_decimals: state.lp_decimals,
+                fees: state.fees,
+                max_reserve_a: Some(state.max_reserve_a),
+                max_reserve_b: Some(state.max_reserve_b),
+                fee_holiday_start: Some(state.fee_holiday_start),
+                fee_holiday_end: Some(state.fee_holiday_end),
+                dynamic_fee_min_numerator: Some(state.dynamic_fee_min_numerator),
+                dynamic_fee_max_numerator: Some(state.dynamic_fee_max_numerator),
+                fee_tiers: Some(state.fee_tiers),
+                max_lp_per_deposit: Some(state.max_lp_per_deposit),
+                allowed_lp_decimals: Some(state.allowed_lp_decimals),
+            }
+        }
+
+        /// Runs 'set_global_state' signed by the pool's program owner
+        fn set_global_state(&mut self, state: SetGlobalState) -> ProgramResult {
+            let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+            let mut rent = TestAccount::rent();
+            do_process_instruction(
+                set_global_state(&crate::id(), &self.global_state.key, &self.owner.key, state)
+                    .unwrap(),
+                vec![
+                    &mut self.global_state,
+                    &mut self.owner,
+                    &mut system,
+                    &mut rent,
+                    &mut self.instructions,
+                ],
+            )
+        }
+
+        fn rescue_tokens(
+            &mut self,
+            owner: &mut TestAccount,
+            stray: &mut TestAccount,
+            destination: &mut TestAccount,
+            mint: Pubkey,
+        ) -> ProgramResult {
+            do_process_instruction(
+                rescue_tokens(
+                    &crate::id(),
+                    &spl_token::id(),
+                    &self.swap.key,
+                    &self.authority.key,
+                    &owner.key,
+                    &stray.key,
+                    &destination.key,
+                    mint,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap,
+                    &mut self.authority,
+                    &mut self.global_state,
+                    owner,
+                    stray,
+                    destination,
+                    &mut self.token_program,
+                    &mut self.instructions,
+                ],
+            )
+        }
+
+        fn lock_liquidity(
+            &mut self,
+            owner: &mut TestAccount,
+            source: &mut TestAccount,
+            escrow: &mut TestAccount,
+            lock: &mut TestAccount,
+            instruction: LockLiquidity,
+        ) -> ProgramResult {
+            do_process_instruction(
+                lock_liquidity(
+                    &crate::id(),
+                    &spl_token::id(),
+                    &self.swap.key,
+                    &self.authority.key,
+                    &owner.key,
+                    &source.key,
+                    &self.pool_mint.key,
+                    instruction,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap,
+                    &mut self.authority,
+                    lock,
+                    owner,
+                    source,
+                    escrow,
+                    &mut self.pool_mint,
+                    &mut self.token_program,
+                    &mut TestAccount::new(system_program::id(), Pubkey::default(), vec![]),
+                    &mut TestAccount::rent(),
+                ],
+            )
+        }
+
+        fn unlock(
+            &mut self,
+            owner: &mut TestAccount,
+            escrow: &mut TestAccount,
+            destination: &mut TestAccount,
+            lock: &mut TestAccount,
+        ) -> ProgramResult {
+            do_process_instruction(
+                unlock(
+                    &crate::id(),
+                    &spl_token::id(),
+                    &self.swap.key,
+                    &self.authority.key,
+                    &owner.key,
+                    &destination.key,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap,
+                    &mut self.authority,
+                    lock,
+                    owner,
+                    escrow,
+                    destination,
+                    &mut self.token_program,
+                ],
+            )
+        }
+
+        fn fee_snapshot(&mut self, snapshot: &mut TestAccount) -> ProgramResult {
+            let mut payer = TestAccount::signer();
+            let mut instruction = fee_snapshot(&crate::id(), &self.swap.key, &payer.key).unwrap();
+            instruction.accounts[0].pubkey = snapshot.key;
+            do_process_instruction(
+                instruction,
+                vec![
+                    snapshot,
+                    &mut self.swap,
+                    &mut payer,
+                    &mut TestAccount::new(system_program::id(), Pubkey::default(), vec![]),
+                    &mut TestAccount::rent(),
+                ],
+            )
+        }
+
+        fn zap_deposit(
+            &mut self,
+            user: &mut TestAccount,
+            source: &mut TestAccount,
+            refund: &mut TestAccount,
+            destination: &mut TestAccount,
+            fee_account: &mut TestAccount,
+            instruction: ZapDeposit,
+        ) -> ProgramResult {
+            let instruction = zap_deposit(
+                &crate::id(),
+                &spl_token::id(),
+                &self.swap.key,
+                &self.authority.key,
+                &user.key,
+                &source.key,
+                &refund.key,
+                &self.token_a.key,
+                &self.token_b.key,
+                &self.pool_mint.key,
+                &destination.key,
+                &fee_account.key,
+                instruction,
+                &self.swap_state(),
+            )
+            .unwrap();
+            let mut accounts = vec![
+                &mut self.swap,
+                &mut self.authority,
+                &mut self.global_state,
+                user,
+                source,
+                refund,
+                &mut self.token_a,
+                &mut self.token_b,
+                &mut self.pool_mint,
+                destination,
+                fee_account,
+                &mut self.token_program,
+            ];
+            accounts.extend(self.reserve_accounts.iter_mut());
+            do_process_instruction(instruction, accounts)
+        }
+
+        /// Swaps `swap` of token A for token B from a fresh user, returns the
+        /// amount taken from the user
+        fn swap_a_to_b(&mut self, instruction: Swap) -> Result<u64, ProgramError> {
+            self.swap_in_direction(TradeDirection::AtoB, instruction)
+        }
+
+        /// Swaps `swap` of token B for token A from a fresh user, returns the
+        /// amount taken from the user
+        fn swap_b_to_a(&mut self, instruction: Swap) -> Result<u64, ProgramError> {
+            self.swap_in_direction(TradeDirection::BtoA, instruction)
+        }
+
+        fn swap_in_direction(
+            &mut self,
+            trade_direction: TradeDirection,
+            instruction: Swap,
+        ) -> Result<u64, ProgramError> {
+            let (source_mint, destination_mint) = match trade_direction {
+                TradeDirection::AtoB => (self.token_a_mint, self.token_b_mint),
+                TradeDirection::BtoA => (self.token_b_mint, self.token_a_mint),
+            };
+            let mut user = TestAccount::signer();
+            let mut source = self.user_token(&source_mint, &user.key, instruction.amount_in);
+            let mut destination = self.user_token(&destination_mint, &user.key, 0);
+            let mut fee_account = self.user_token(&source_mint, &self.fee_owner, 0);
+            let (swap_source, swap_destination) = match trade_direction {
+                TradeDirection::AtoB => (&mut self.token_a, &mut self.token_b),
+                TradeDirection::BtoA => (&mut self.token_b, &mut self.token_a),
+            };
+            let amount_in = instruction.amount_in;
+            let mut instruction = swap(
+                &crate::id(),
+                &spl_token::id(),
+                &self.swap.key,
+                &self.authority.key,
+                &user.key,
+                &source.key,
+                &swap_source.key,
+                &swap_destination.key,
+                &destination.key,
+                &self.pool_mint.key,
+                &fee_account.key,
+                None,
+                instruction,
+            )
+            .unwrap();
+            // the raw builder leaves the reserve accounts to the caller
+            instruction.accounts.extend(
+                self.reserve_accounts
+                    .iter()
+                    .map(|account| AccountMeta::new_readonly(account.key, false)),
+            );
+            let mut accounts = vec![
+                &mut self.swap,
+                &mut self.authority,
+                &mut user,
+                &mut self.global_state,
+                &mut source,
+                swap_source,
+                swap_destination,
+                &mut destination,
+                &mut self.pool_mint,
+                &mut fee_account,
+                &mut self.token_program,
+            ];
+            accounts.extend(self.reserve_accounts.iter_mut());
+            do_process_instruction(instruction, accounts)?;
+            Ok(amount_in - source.token_amount())
+        }
+
+        /// Swaps token A for token B between the given accounts of `user`
+        fn swap_a_to_b_with(
+            &mut self,
+            user: &mut TestAccount,
+            source: &mut TestAccount,
+            destination: &mut TestAccount,
+            fee_account: &mut TestAccount,
+            instruction: Swap,
+        ) -> ProgramResult {
+            do_process_instruction(
+                swap(
+                    &crate::id(),
+                    &spl_token::id(),
+                    &self.swap.key,
+                    &self.authority.key,
+                    &user.key,
+                    &source.key,
+                    &self.token_a.key,
+                    &self.token_b.key,
+                    &destination.key,
+                    &self.pool_mint.key,
+                    &fee_account.key,
+                    None,
+                    instruction,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap,
+                    &mut self.authority,
+                    user,
+                    &mut self.global_state,
+                    source,
+                    &mut self.token_a,
+                    &mut self.token_b,
+                    destination,
+                    &mut self.pool_mint,
+                    fee_account,
+                    &mut self.token_program,
+                ],
+            )
+        }
+
+        /// Deposits for `pool_token_amount` from a fresh user holding the
+        /// maximum amounts, returns the user's pool token account
+        fn deposit(
+            &mut self,
+            pool_token_amount: u64,
+            maximum_token_a_amount: u64,
+            maximum_token_b_amount: u64,
+        ) -> Result<TestAccount, ProgramError> {
+            let mut user = TestAccount::signer();
+            let mut source_a =
+                self.user_token(&self.token_a_mint, &user.key, maximum_token_a_amount);
+            let mut source_b =
+                self.user_token(&self.token_b_mint, &user.key, maximum_token_b_amount);
+            let mut destination = TestAccount::token(&self.pool_mint.key, &user.key, 0);
+            let (instruction, accounts) = self.deposit_instruction(
+                &mut user,
+                &mut source_a,
+                &mut source_b,
+                &mut destination,
+                DepositAllTokenTypes {
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                },
+            );
+            do_process_instruction(instruction, accounts)?;
+            Ok(destination)
+        }
+
+        /// The 'deposit_all_token_types' instruction of `user` along with
+        /// the accounts it takes
+        fn deposit_instruction<'a>(
+            &'a mut self,
+            user: &'a mut TestAccount,
+            source_a: &'a mut TestAccount,
+            source_b: &'a mut TestAccount,
+            destination: &'a mut TestAccount,
+            instruction: DepositAllTokenTypes,
+        ) -> (Instruction, Vec<&'a mut TestAccount>) {
+            let instruction = deposit_all_token_types(
+                &crate::id(),
+                &spl_token::id(),
+                &self.swap.key,
+                &self.authority.key,
+                &user.key,
+                &source_a.key,
+                &source_b.key,
+                &self.token_a.key,
+                &self.token_b.key,
+                &self.pool_mint.key,
+                &destination.key,
+                instruction,
+                &self.swap_state(),
+            )
+            .unwrap();
+            let mut accounts = vec![
+                &mut self.swap,
+                &mut self.authority,
+                &mut self.global_state,
+                user,
+                source_a,
+                source_b,
+                &mut self.token_a,
+                &mut self.token_b,
+                &mut self.pool_mint,
+                destination,
+                &mut self.token_program,
+            ];
+            accounts.extend(self.reserve_accounts.iter_mut());
+            (instruction, accounts)
+        }
+
+        /// Withdraws `pool_token_amount` from `source`, owned by `user`
+        fn withdraw(
+            &mut self,
+            user: &mut TestAccount,
+            source: &mut TestAccount,
+            pool_token_amount: u64,
+        ) -> ProgramResult {
+            let mut destination_a = self.user_token(&self.token_a_mint, &user.key, 0);
+            let mut destination_b = self.user_token(&self.token_b_mint, &user.key, 0);
+            self.withdraw_to(
+                user,
+                source,
+                &mut destination_a,
+                &mut destination_b,
+                pool_token_amount,
+            )
+        }
+
+        /// Withdraws `pool_token_amount` of `source` into the given accounts
+        fn withdraw_to(
+            &mut self,
+            user: &mut TestAccount,
+            source: &mut TestAccount,
+            destination_a: &mut TestAccount,
+            destination_b: &mut TestAccount,
+            pool_token_amount: u64,
+        ) -> ProgramResult {
+            let instruction = withdraw_all_token_types(
+                &crate::id(),
+                &spl_token::id(),
+                &self.swap.key,
+                &self.authority.key,
+                &user.key,
+                &self.pool_mint.key,
+                &source.key,
+                &self.token_a.key,
+                &self.token_b.key,
+                &destination_a.key,
+                &destination_b.key,
+                WithdrawAllTokenTypes {
+                    pool_token_amount,
+                    minimum_token_a_amount: 0,
+                    minimum_token_b_amount: 0,
+                    deadline: 0,
+                },
+                &self.swap_state(),
+            )
+            .unwrap();
+            let mut accounts = vec![
+                &mut self.swap,
+                &mut self.authority,
+                &mut self.global_state,
+                user,
+                &mut self.pool_mint,
+                source,
+                &mut self.token_a,
+                &mut self.token_b,
+                destination_a,
+                destination_b,
+                &mut self.token_program,
+            ];
+            accounts.extend(self.reserve_accounts.iter_mut());
+            do_process_instruction(instruction, accounts)
+        }
+
+        /// Zaps `source_amount` of token A from a fresh user
+        fn zap_deposit_a(&mut self, source_amount: u64) -> ProgramResult {
+            let mut user = TestAccount::signer();
+            let mut source = self.user_token(&self.token_a_mint, &user.key, source_amount);
+            let mut refund = self.user_token(&self.token_b_mint, &user.key, 0);
+            let mut destination = TestAccount::token(&self.pool_mint.key, &user.key, 0);
+            let mut fee_account = self.user_token(&self.token_a_mint, &self.fee_owner, 0);
+            self.zap_deposit(
+                &mut user,
+                &mut source,
+                &mut refund,
+                &mut destination,
+                &mut fee_account,
+                ZapDeposit {
+                    source_amount,
+                    minimum_pool_token_amount: 0,
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn zap_deposit_rejects_reserve_with_other_owner() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let token_a_mint = pool.token_a_mint;
+        let key = pool.token_a.key;
+        pool.token_a = TestAccount {
+            key,
+            ..TestAccount::token(&token_a_mint, &Pubkey::new_unique(), 1_000_000)
+        };
+        assert_eq!(
+            pool.zap_deposit_a(10_000),
+            Err(SwapError::InvalidOwner.into())
+        );
+    }
+
+    #[test]
+    fn zap_deposit_rejects_below_min_trade_amount() {
+        let stable = |min_trade_amount| SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(StableCurve {
+                amp: 100,
+                min_trade_amount,
+                token_a_decimals: 0,
+                token_b_decimals: 0,
+            }),
+        };
+        // only half of the source is swapped
+        let mut pool = TestPool::new(stable(1_000), 1_000_000, 1_000_000, 1_000_000_000);
+        assert_eq!(
+            pool.zap_deposit_a(1_999),
+            Err(SwapError::InvalidInput.into())
+        );
+        let mut pool = TestPool::new(stable(1_000), 1_000_000, 1_000_000, 1_000_000_000);
+        pool.zap_deposit_a(2_000).unwrap();
+    }
+
+    /// Fees of swaps of token A into a stable pool holding the given
+    /// reserves of tokens with the given decimals, under a dynamic fee from
+    /// 2 to 50
+    fn dynamic_fees_for(
+        decimals: (u8, u8),
+        token_a_amount: u64,
+        token_b_amount: u64,
+        amounts_in: &[u128],
+    ) -> Vec<Fees> {
+        test_syscall_stubs();
+        let pool = TestPool::new(
+            SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Box::new(StableCurve {
+                    amp: 100,
+                    min_trade_amount: 0,
+                    token_a_decimals: decimals.0,
+                    token_b_decimals: decimals.1,
+                }),
+            },
+            token_a_amount,
+            token_b_amount,
+            1_000_000_000,
+        );
+        let token_swap = SwapVersion::unpack(&pool.swap.data).unwrap();
+        let mut state = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        state.dynamic_fee_min_numerator = 2;
+        state.dynamic_fee_max_numerator = 50;
+        let swap_source = TokenAccount::unpack(&pool.token_a.data).unwrap();
+        let swap_destination = TokenAccount::unpack(&pool.token_b.data).unwrap();
+        let (mut lamports, mut data) = (0, vec![]);
+        let authority_info = AccountInfo::new(
+            &pool.authority.key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &pool.authority.owner,
+            false,
+            Epoch::default(),
+        );
+
+        amounts_in
+            .iter()
+            .map(|amount_in| {
+                Processor::swap_leg_fees(
+                    &state,
+                    token_swap.as_ref(),
+                    &authority_info,
+                    &swap_source,
+                    &swap_destination,
+                    *amount_in,
+                    TradeDirection::AtoB,
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn swap_leg_fees_uses_dynamic_stable_fee() {
+        let fees = dynamic_fees_for((0, 0), 1_000_000, 1_000_000, &[1_000, 500_000]);
+        let (small, large) = (&fees[0], &fees[1]);
+        assert!(small.stable_return_fee_numerator >= 2);
+        assert!(large.stable_return_fee_numerator > small.stable_return_fee_numerator);
+        assert!(large.stable_return_fee_numerator <= 50);
+        assert_eq!(large.stable_fixed_fee_numerator, TEST_FEES.stable_fixed_fee_numerator);
+    }
+
+    #[test]
+    fn dynamic_stable_fee_normalizes_decimals() {
+        // one whole token of 6 decimals against one of 9 decimals is balanced
+        let mixed = dynamic_fees_for((6, 9), 1_000_000, 1_000_000_000, &[1_000, 500_000]);
+        let par = dynamic_fees_for((9, 9), 1_000_000_000, 1_000_000_000, &[1_000_000, 500_000_000]);
+        assert_eq!(mixed, par);
+        assert_eq!(mixed[0].stable_return_fee_numerator, 2);
+        assert!(mixed[1].stable_return_fee_numerator > 2);
+        // priced raw, the same reserves would look almost fully imbalanced
+        let raw = dynamic_fees_for((0, 0), 1_000_000, 1_000_000_000, &[1_000]);
+        assert!(raw[0].stable_return_fee_numerator >= 49);
+    }
+
+    fn within_slippage(
+        swap_curve: &SwapCurve,
+        amount_in: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        max_slippage_bps: u16,
+    ) -> bool {
+        let result = swap_curve
+            .swap(
+                amount_in,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &TEST_FEES,
+            )
+            .unwrap();
+        Processor::check_slippage_from_spot(
+            swap_curve,
+            &result,
+            swap_source_amount,
+            swap_destination_amount,
+            TradeDirection::AtoB,
+            max_slippage_bps,
+        )
+        .is_ok()
+    }
+
+    #[test]
+    fn partial_fill_constant_product() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let (swap_source_amount, swap_destination_amount) = (1_000_000_000, 2_000_000_000);
+        for max_slippage_bps in [10, 100, 1_000] {
+            let filled = Processor::max_amount_in_within_slippage(
+                &swap_curve,
+                1_000_000_000,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &TEST_FEES,
+                max_slippage_bps,
+            );
+            assert!(filled > 0 && filled < 1_000_000_000);
+            assert!(within_slippage(
+                &swap_curve,
+                filled,
+                swap_source_amount,
+                swap_destination_amount,
+                max_slippage_bps
+            ));
+            // the rounding of the curve leaves the closed form a little short
+            // of the largest fill
+            assert!(!within_slippage(
+                &swap_curve,
+                filled + filled / 1_000,
+                swap_source_amount,
+                swap_destination_amount,
+                max_slippage_bps
+            ));
+        }
+
+        // an amount within the slippage is filled whole
+        assert_eq!(
+            Processor::max_amount_in_within_slippage(
+                &swap_curve,
+                1_000,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &TEST_FEES,
+                100,
+            ),
+            1_000
+        );
+    }
+
+    #[test]
+    fn partial_fill_stable() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(StableCurve {
+                amp: 100,
+                min_trade_amount: 0,
+                token_a_decimals: 0,
+                token_b_decimals: 0,
+            }),
+        };
+        let (swap_source_amount, swap_destination_amount) = (1_000_000, 1_000_000);
+        let amount_in = 1_000_000;
+        let filled = Processor::max_amount_in_within_slippage(
+            &swap_curve,
+            amount_in,
+            swap_source_amount,
+            swap_destination_amount,
+            TradeDirection::AtoB,
+            &TEST_FEES,
+            50,
+        );
+        assert!(filled > 0 && filled < amount_in);
+        assert!(within_slippage(
+            &swap_curve,
+            filled,
+            swap_source_amount,
+            swap_destination_amount,
+            50
+        ));
+        // the capped bisection is at most one step short of the boundary
+        let step = amount_in >> MAX_PARTIAL_FILL_ITERATIONS;
+        assert!(!within_slippage(
+            &swap_curve,
+            filled + step + 1,
+            swap_source_amount,
+            swap_destination_amount,
+            50
+        ));
+    }
+
+    #[test]
+    fn swap_partial_fill() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let quoted = Processor::max_amount_in_within_slippage(
+            &SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(ConstantProductCurve {}),
+            },
+            100_000,
+            1_000_000,
+            2_000_000,
+            TradeDirection::AtoB,
+            &TEST_FEES,
+            100,
+        );
+        assert!(quoted > 0 && quoted < 100_000);
+
+        let filled = pool
+            .swap_a_to_b(Swap {
+                amount_in: 100_000,
+                minimum_amount_out: 0,
+                max_slippage_bps: 100,
+                allow_partial: true,
+            })
+            .unwrap();
+        assert_eq!(u128::from(filled), quoted);
+        let (program_id, return_data) = get_return_data().unwrap();
+        assert_eq!(program_id, crate::id());
+        assert_eq!(
+            SwapReturnData::unpack(&return_data).unwrap(),
+            SwapReturnData {
+                filled,
+                unfilled: 100_000 - filled,
+                new_reserve_a: pool.token_a.token_amount(),
+                new_reserve_b: pool.token_b.token_amount(),
+            }
+        );
+
+        // without allow_partial the same swap fails
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        assert_eq!(
+            pool.swap_a_to_b(Swap {
+                amount_in: 100_000,
+                minimum_amount_out: 0,
+                max_slippage_bps: 100,
+                allow_partial: false,
+            }),
+            Err(SwapError::ExceededSlippage.into())
+        );
+        assert_eq!(get_return_data(), None);
+
+        // a swap within the cap fills completely
+        let filled = pool
+            .swap_a_to_b(Swap {
+                amount_in: 1_000,
+                minimum_amount_out: 0,
+                max_slippage_bps: 100,
+                allow_partial: true,
+            })
+            .unwrap();
+        assert_eq!(filled, 1_000);
+        let (_, return_data) = get_return_data().unwrap();
+        assert_eq!(
+            SwapReturnData::unpack(&return_data).unwrap(),
+            SwapReturnData {
+                filled,
+                unfilled: 0,
+                new_reserve_a: pool.token_a.token_amount(),
+                new_reserve_b: pool.token_b.token_amount(),
+            }
+        );
+    }
+
+    #[test]
+    fn swap_returns_new_reserves() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let test_swap = Swap {
+            amount_in: 10_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+            let taken = match trade_direction {
+                TradeDirection::AtoB => pool.swap_a_to_b(test_swap.clone()),
+                TradeDirection::BtoA => pool.swap_b_to_a(test_swap.clone()),
+            }
+            .unwrap();
+            let (_, return_data) = get_return_data().unwrap();
+            let return_data = SwapReturnData::unpack(&return_data).unwrap();
+            assert_eq!(return_data.filled, taken);
+            assert_eq!(return_data.unfilled, test_swap.amount_in - taken);
+            // the owner fee went to the fee account, not the reserves
+            assert_eq!(return_data.new_reserve_a, pool.token_a.token_amount());
+            assert_eq!(return_data.new_reserve_b, pool.token_b.token_amount());
+        }
+    }
+
+    #[test]
+    fn fee_snapshot_records_owner_fees_per_epoch() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        // allocated up front, the system program is not stubbed
+        let (snapshot_key, _) = find_fee_snapshot_address(&crate::id(), &pool.swap.key);
+        let mut snapshot = TestAccount::new(snapshot_key, crate::id(), vec![0u8; FeeSnapshot::LEN]);
+        let test_swap = Swap {
+            amount_in: 100_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+
+        // the owner fee is what the user paid beyond the reserve's increase
+        let reserve_before = pool.token_a.token_amount();
+        let taken = pool.swap_a_to_b(test_swap.clone()).unwrap();
+        let owner_fee = u128::from(taken - (pool.token_a.token_amount() - reserve_before));
+        assert!(owner_fee > 0);
+        assert_eq!(pool.swap_state().cumulative_fees_a, owner_fee);
+        assert_eq!(pool.swap_state().cumulative_fees_b, 0);
+
+        TEST_EPOCH.store(5, Ordering::Relaxed);
+        pool.fee_snapshot(&mut snapshot).unwrap();
+        let state = FeeSnapshot::unpack(&snapshot.data).unwrap();
+        assert_eq!(state.swap, pool.swap.key);
+        assert_eq!(
+            state.latest(),
+            Some(&FeeSnapshotEntry {
+                epoch: 5,
+                cumulative_fees_a: owner_fee,
+                cumulative_fees_b: 0,
+            })
+        );
+
+        // a single snapshot per epoch
+        assert_eq!(
+            pool.fee_snapshot(&mut snapshot),
+            Err(SwapError::FeeSnapshotTaken.into())
+        );
+
+        // the fees of an epoch are the difference between two snapshots,
+        // zap deposits count their swap leg too
+        pool.swap_a_to_b(test_swap).unwrap();
+        pool.zap_deposit_a(100_000).unwrap();
+        TEST_EPOCH.store(6, Ordering::Relaxed);
+        pool.fee_snapshot(&mut snapshot).unwrap();
+        let state = FeeSnapshot::unpack(&snapshot.data).unwrap();
+        let entries = state.entries().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].epoch, 6);
+        assert_eq!(entries[1].cumulative_fees_a, pool.swap_state().cumulative_fees_a);
+        assert!(entries[1].cumulative_fees_a > entries[0].cumulative_fees_a + owner_fee);
+
+        // an older epoch cannot be recorded after a newer one
+        TEST_EPOCH.store(4, Ordering::Relaxed);
+        assert_eq!(
+            pool.fee_snapshot(&mut snapshot),
+            Err(SwapError::FeeSnapshotTaken.into())
+        );
+        TEST_EPOCH.store(0, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn fee_snapshot_rejects_other_address() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let (other_key, _) = find_fee_snapshot_address(&crate::id(), &Pubkey::new_unique());
+        let mut other = TestAccount::new(other_key, crate::id(), vec![0u8; FeeSnapshot::LEN]);
+        assert_eq!(
+            pool.fee_snapshot(&mut other),
+            Err(SwapError::InvalidProgramAddress.into())
+        );
+    }
+
+    #[test]
+    fn legacy_swap_account_records_no_fees() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let (snapshot_key, _) = find_fee_snapshot_address(&crate::id(), &pool.swap.key);
+        let mut snapshot = TestAccount::new(snapshot_key, crate::id(), vec![0u8; FeeSnapshot::LEN]);
+
+        // a swap account allocated before the counters still swaps, and its
+        // snapshots record zeros
+        pool.swap.data.truncate(SwapVersion::LATEST_LEN - 32);
+        pool.swap_a_to_b(Swap {
+            amount_in: 100_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        })
+        .unwrap();
+        assert_eq!(pool.swap_state().cumulative_fees_a, 0);
+        pool.fee_snapshot(&mut snapshot).unwrap();
+        let state = FeeSnapshot::unpack(&snapshot.data).unwrap();
+        assert_eq!(state.latest().unwrap().cumulative_fees_a, 0);
+    }
+
+    #[test]
+    fn deposit_and_withdraw_keep_reserves_in_line() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let mut pool_tokens = pool.deposit(500_000_000, 500_000, 1_000_000).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 1_500_000);
+        assert_eq!(pool.token_b.token_amount(), 3_000_000);
+
+        let user_key = TokenAccount::unpack(&pool_tokens.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+        pool.withdraw(&mut user, &mut pool_tokens, 500_000_000).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 1_000_000);
+        assert_eq!(pool.token_b.token_amount(), 2_000_000);
+    }
+
+    #[test]
+    fn withdraw_builder_round_trip() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let mut pool_tokens = pool.deposit(500_000_000, 500_000, 1_000_000).unwrap();
+        let user_key = TokenAccount::unpack(&pool_tokens.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+        let mut destination_a = pool.user_token(&pool.token_a_mint, &user.key, 0);
+        let mut destination_b = pool.user_token(&pool.token_b_mint, &user.key, 0);
+        let instruction = |swap_token_a: &Pubkey, swap_token_b: &Pubkey| {
+            withdraw_all_token_types(
+                &crate::id(),
+                &spl_token::id(),
+                &pool.swap.key,
+                &pool.authority.key,
+                &user_key,
+                &pool.pool_mint.key,
+                &pool_tokens.key,
+                swap_token_a,
+                swap_token_b,
+                &destination_a.key,
+                &destination_b.key,
+                WithdrawAllTokenTypes {
+                    pool_token_amount: 500_000_000,
+                    minimum_token_a_amount: 0,
+                    minimum_token_b_amount: 0,
+                    deadline: 0,
+                },
+                &pool.swap_state(),
+            )
+            .unwrap()
+        };
+        let swapped = instruction(&pool.token_b.key, &pool.token_a.key);
+        let in_order = instruction(&pool.token_a.key, &pool.token_b.key);
+
+        // reserves passed the other way round are caught against the pool
+        assert_eq!(
+            do_process_instruction(
+                swapped,
+                vec![
+                    &mut pool.swap,
+                    &mut pool.authority,
+                    &mut pool.global_state,
+                    &mut user,
+                    &mut pool.pool_mint,
+                    &mut pool_tokens,
+                    &mut pool.token_b,
+                    &mut pool.token_a,
+                    &mut destination_a,
+                    &mut destination_b,
+                    &mut pool.token_program,
+                ],
+            ),
+            Err(SwapError::IncorrectSwapAccount.into())
+        );
+        do_process_instruction(
+            in_order,
+            vec![
+                &mut pool.swap,
+                &mut pool.authority,
+                &mut pool.global_state,
+                &mut user,
+                &mut pool.pool_mint,
+                &mut pool_tokens,
+                &mut pool.token_a,
+                &mut pool.token_b,
+                &mut destination_a,
+                &mut destination_b,
+                &mut pool.token_program,
+            ],
+        )
+        .unwrap();
+        assert_eq!(destination_a.token_amount(), 500_000);
+        assert_eq!(destination_b.token_amount(), 1_000_000);
+        assert_eq!(pool_tokens.token_amount(), 0);
+        assert_eq!(Mint::unpack(&pool.pool_mint.data).unwrap().supply, 1_000_000_000);
+    }
+
+    #[test]
+    fn deposit_tells_overflow_from_zero_amounts() {
+        // one pool token is worth less than one token of either reserve
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        assert_eq!(
+            pool.deposit(1, 1_000, 1_000).map(|_| ()),
+            Err(SwapError::ZeroTradingTokens.into())
+        );
+
+        // an empty pool seeded with nothing has no initial supply to mint
+        let mut pool = TestPool::constant_product(0, 0, 0);
+        assert_eq!(
+            pool.deposit(1_000, 0, 0).map(|_| ()),
+            Err(SwapError::CalculationFailure.into())
+        );
+    }
+
+    #[test]
+    fn lock_liquidity_until_unlock_ts() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut owner = TestAccount::signer();
+        let mut source = TestAccount::token(&pool.pool_mint.key, &owner.key, 1_000);
+        // allocated up front, the system program is not stubbed
+        let mut escrow = TestAccount::token(&pool.pool_mint.key, &pool.authority.key, 0);
+        escrow.key = find_liquidity_escrow_address(&crate::id(), &pool.swap.key, &owner.key).0;
+        let (lock_key, _) = find_liquidity_lock_address(&crate::id(), &pool.swap.key, &owner.key);
+        let mut lock = TestAccount::new(
+            lock_key,
+            crate::id(),
+            vec![0u8; LiquidityLock::get_packed_len()],
+        );
+
+        // the escrow must be the one derived for the owner
+        let mut stray = TestAccount::token(&pool.pool_mint.key, &pool.authority.key, 0);
+        let mut instruction = lock_liquidity(
+            &crate::id(),
+            &spl_token::id(),
+            &pool.swap.key,
+            &pool.authority.key,
+            &owner.key,
+            &source.key,
+            &pool.pool_mint.key,
+            LockLiquidity {
+                amount: 400,
+                unlock_ts: TEST_UNIX_TIMESTAMP + 1,
+            },
+        )
+        .unwrap();
+        instruction.accounts[5].pubkey = stray.key;
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut pool.swap,
+                    &mut pool.authority,
+                    &mut lock,
+                    &mut owner,
+                    &mut source,
+                    &mut stray,
+                    &mut pool.pool_mint,
+                    &mut pool.token_program,
+                    &mut TestAccount::new(system_program::id(), Pubkey::default(), vec![]),
+                    &mut TestAccount::rent(),
+                ],
+            ),
+            Err(SwapError::InvalidProgramAddress.into())
+        );
+
+        // the unlock time must be in the future
+        assert_eq!(
+            pool.lock_liquidity(
+                &mut owner,
+                &mut source,
+                &mut escrow,
+                &mut lock,
+                LockLiquidity {
+                    amount: 400,
+                    unlock_ts: TEST_UNIX_TIMESTAMP,
+                },
+            ),
+            Err(SwapError::InvalidInput.into())
+        );
+        pool.lock_liquidity(
+            &mut owner,
+            &mut source,
+            &mut escrow,
+            &mut lock,
+            LockLiquidity {
+                amount: 400,
+                unlock_ts: TEST_UNIX_TIMESTAMP + 1,
+            },
+        )
+        .unwrap();
+        assert_eq!(source.token_amount(), 600);
+        assert_eq!(escrow.token_amount(), 400);
+        let state = LiquidityLock::unpack(&lock.data).unwrap();
+        assert_eq!(state.owner, owner.key);
+        assert_eq!(state.escrow, escrow.key);
+        assert_eq!(state.amount, 400);
+
+        // a second lock for the same owner and pool is refused
+        assert_eq!(
+            pool.lock_liquidity(
+                &mut owner,
+                &mut source,
+                &mut escrow,
+                &mut lock,
+                LockLiquidity {
+                    amount: 100,
+                    unlock_ts: TEST_UNIX_TIMESTAMP + 1,
+                },
+            ),
+            Err(SwapError::AlreadyInUse.into())
+        );
+
+        assert_eq!(
+            pool.unlock(&mut owner, &mut escrow, &mut source, &mut lock),
+            Err(SwapError::StillLocked.into())
+        );
+        assert_eq!(escrow.token_amount(), 400);
+
+        // once the clock reaches the unlock time
+        LiquidityLock {
+            unlock_ts: TEST_UNIX_TIMESTAMP,
+            ..state
+        }
+        .pack_into_slice(&mut lock.data);
+        let rent = owner.lamports + escrow.lamports + lock.lamports;
+        pool.unlock(&mut owner, &mut escrow, &mut source, &mut lock).unwrap();
+        assert_eq!(source.token_amount(), 1_000);
+        assert!(!LiquidityLock::unpack_from_slice(&lock.data).unwrap().is_initialized);
+
+        // both accounts are closed to the owner
+        assert_eq!(escrow.lamports, 0);
+        assert_eq!(lock.lamports, 0);
+        assert_eq!(owner.lamports, rent);
+    }
+
+    /// Deposits into every pool in one batch, each from a fresh user holding
+    /// the maximum amounts, returns the users' pool token accounts
+    fn do_batch_deposit(
+        pools: &mut [TestPool],
+        deposits: &[DepositAllTokenTypes],
+    ) -> Result<Vec<TestAccount>, ProgramError> {
+        let mut users = pools.iter().map(|_| TestAccount::signer()).collect::<Vec<_>>();
+        let mut sources = pools
+            .iter()
+            .zip(deposits)
+            .zip(users.iter())
+            .map(|((pool, deposit), user)| {
+                (
+                    pool.user_token(&pool.token_a_mint, &user.key, deposit.maximum_token_a_amount),
+                    pool.user_token(&pool.token_b_mint, &user.key, deposit.maximum_token_b_amount),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut destinations = pools
+            .iter()
+            .zip(users.iter())
+            .map(|(pool, user)| TestAccount::token(&pool.pool_mint.key, &user.key, 0))
+            .collect::<Vec<_>>();
+
+        let mut instructions = vec![];
+        let mut accounts = vec![];
+        for ((((pool, deposit), user), (source_a, source_b)), destination) in pools
+            .iter_mut()
+            .zip(deposits)
+            .zip(users.iter_mut())
+            .zip(sources.iter_mut())
+            .zip(destinations.iter_mut())
+        {
+            let (instruction, pool_accounts) =
+                pool.deposit_instruction(user, source_a, source_b, destination, deposit.clone());
+            instructions.push(instruction);
+            accounts.extend(pool_accounts);
+        }
+        do_process_instruction(batch_deposit(&crate::id(), &instructions)?, accounts)?;
+        Ok(destinations)
+    }
+
+    #[test]
+    fn batch_deposit_into_two_pools() {
+        let mut pools = [
+            TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000),
+            TestPool::constant_product(3_000_000, 1_000_000, 1_000_000_000),
+        ];
+        let deposits = [
+            DepositAllTokenTypes {
+                pool_token_amount: 500_000_000,
+                maximum_token_a_amount: 500_000,
+                maximum_token_b_amount: 1_000_000,
+            },
+            DepositAllTokenTypes {
+                pool_token_amount: 100_000_000,
+                maximum_token_a_amount: 300_000,
+                maximum_token_b_amount: 100_000,
+            },
+        ];
+        let destinations = do_batch_deposit(&mut pools, &deposits).unwrap();
+        assert_eq!(pools[0].token_a.token_amount(), 1_500_000);
+        assert_eq!(pools[0].token_b.token_amount(), 3_000_000);
+        assert_eq!(pools[1].token_a.token_amount(), 3_300_000);
+        assert_eq!(pools[1].token_b.token_amount(), 1_100_000);
+        assert_eq!(destinations[0].token_amount(), 500_000_000);
+        assert_eq!(destinations[1].token_amount(), 100_000_000);
+
+        // a failing deposit fails the whole batch, which the runtime then
+        // rolls back along with the deposits before it
+        let too_little = DepositAllTokenTypes {
+            maximum_token_b_amount: 99_999,
+            ..deposits[1].clone()
+        };
+        assert_eq!(
+            do_batch_deposit(&mut pools, &[deposits[0].clone(), too_little]).map(|_| ()),
+            Err(SwapError::ExceededSlippage.into())
+        );
+
+        // between one and MAX_BATCH_DEPOSITS pools
+        assert_eq!(
+            batch_deposit(&crate::id(), &[]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+        let deposit = deposit_all_token_types(
+            &crate::id(),
+            &spl_token::id(),
+            &pools[0].swap.key,
+            &pools[0].authority.key,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &pools[0].token_a.key,
+            &pools[0].token_b.key,
+            &pools[0].pool_mint.key,
+            &Pubkey::new_unique(),
+            deposits[0].clone(),
+            &pools[0].swap_state(),
+        )
+        .unwrap();
+        assert_eq!(
+            batch_deposit(&crate::id(), &vec![deposit; MAX_BATCH_DEPOSITS + 1]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn checked_conversions() {
+        assert_eq!(to_u128(u64::MAX), Ok(u128::from(u64::MAX)));
+        assert_eq!(to_u64(u128::from(u64::MAX)), Ok(u64::MAX));
+        assert_eq!(
+            to_u64(u128::from(u64::MAX) + 1),
+            Err(SwapError::ConversionFailure)
+        );
+    }
+
+    #[test]
+    fn deposit_checks_recorded_pool_mint_decimals() {
+        let with_decimals = |pool_mint_decimals| {
+            let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+            let mut token_swap = SwapV1::unpack_from_slice(&pool.swap.data[1..]).unwrap();
+            token_swap.pool_mint_decimals = pool_mint_decimals;
+            SwapVersion::pack(SwapVersion::SwapV1(token_swap), &mut pool.swap.data).unwrap();
+            pool
+        };
+        // zero decimals are a recorded value like any other
+        assert_eq!(
+            with_decimals(Some(0))
+                .deposit(500_000_000, 500_000, 1_000_000)
+                .map(|_| ()),
+            Err(SwapError::MismatchDecimalValidation.into())
+        );
+        with_decimals(Some(9))
+            .deposit(500_000_000, 500_000, 1_000_000)
+            .unwrap();
+        // pools created before the decimals were recorded skip the check
+        with_decimals(None)
+            .deposit(500_000_000, 500_000, 1_000_000)
+            .unwrap();
+    }
+
+    #[test]
+    fn assert_reserve_amounts() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mut token_a = TestAccount::token(&mint, &owner, 1_000);
+        let mut token_b = TestAccount::token(&mint, &owner, 2_000);
+        let (mut lamports_a, mut lamports_b) = (token_a.lamports, token_b.lamports);
+        let token_a_info = AccountInfo::new(
+            &token_a.key,
+            false,
+            true,
+            &mut lamports_a,
+            &mut token_a.data,
+            &token_a.owner,
+            false,
+            Epoch::default(),
+        );
+        let token_b_info = AccountInfo::new(
+            &token_b.key,
+            false,
+            true,
+            &mut lamports_b,
+            &mut token_b.data,
+            &token_b.owner,
+            false,
+            Epoch::default(),
+        );
+
+        let token_program_id = spl_token::id();
+        let (token_a, token_b) = Processor::unpack_token_accounts(
+            &token_a_info,
+            &token_program_id,
+            &token_b_info,
+            &token_program_id,
+        )
+        .unwrap();
+        assert_eq!((token_a.amount, token_b.amount), (1_000, 2_000));
+        Processor::assert_reserve_amounts(&[
+            (&token_a_info, &token_program_id, 1_000),
+            (&token_b_info, &token_program_id, 2_000),
+        ])
+        .unwrap();
+        assert_eq!(
+            Processor::assert_reserve_amounts(&[
+                (&token_a_info, &token_program_id, 1_000),
+                (&token_b_info, &token_program_id, 2_001),
+            ]),
+            Err(SwapError::CalculationFailure.into())
+        );
+        // each reserve is read under its own program
+        assert_eq!(
+            Processor::assert_reserve_amounts(&[
+                (&token_a_info, &token_program_id, 1_000),
+                (&token_b_info, &token_2022_program_id(), 2_000),
+            ]),
+            Err(SwapError::IncorrectTokenProgramId.into())
+        );
+    }
+
+    #[test]
+    fn rescue_stray_tokens() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+        let stray_mint = Pubkey::new_unique();
+        let mut stray = TestAccount::token(&stray_mint, &pool.authority.key, 100);
+        let mut destination = TestAccount::token(&stray_mint, &owner.key, 0);
+
+        pool.rescue_tokens(&mut owner, &mut stray, &mut destination, stray_mint)
+            .unwrap();
+        assert_eq!(stray.token_amount(), 0);
+        assert_eq!(destination.token_amount(), 100);
+    }
+
+    #[test]
+    fn rescue_tokens_rejects_reserves() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+
+        let token_a_mint = pool.token_a_mint;
+        let mut reserve =
+            TestAccount::new(pool.token_a.key, spl_token::id(), pool.token_a.data.clone());
+        let mut destination = TestAccount::token(&token_a_mint, &owner.key, 0);
+        assert_eq!(
+            pool.rescue_tokens(&mut owner, &mut reserve, &mut destination, token_a_mint),
+            Err(SwapError::CannotRescueReserve.into())
+        );
+
+        let pool_mint = pool.pool_mint.key;
+        let mut pool_tokens = TestAccount::token(&pool_mint, &pool.authority.key, 100);
+        let mut destination = TestAccount::token(&pool_mint, &owner.key, 0);
+        assert_eq!(
+            pool.rescue_tokens(&mut owner, &mut pool_tokens, &mut destination, pool_mint),
+            Err(SwapError::CannotRescueReserve.into())
+        );
+    }
+
+    #[test]
+    fn rescue_tokens_rejects_non_owner() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut not_owner = TestAccount::signer();
+        let stray_mint = Pubkey::new_unique();
+        let mut stray = TestAccount::token(&stray_mint, &pool.authority.key, 100);
+        let mut destination = TestAccount::token(&stray_mint, &not_owner.key, 0);
+        assert_eq!(
+            pool.rescue_tokens(&mut not_owner, &mut stray, &mut destination, stray_mint),
+            Err(SwapError::InvalidProgramOwner.into())
+        );
+    }
+
+    #[test]
+    fn rescue_tokens_rejects_cpi() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        pool.instructions = TestAccount::instructions(&Pubkey::new_unique());
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+        let stray_mint = Pubkey::new_unique();
+        let mut stray = TestAccount::token(&stray_mint, &pool.authority.key, 100);
+        let mut destination = TestAccount::token(&stray_mint, &owner.key, 0);
+        assert_eq!(
+            pool.rescue_tokens(&mut owner, &mut stray, &mut destination, stray_mint),
+            Err(SwapError::CpiNotAllowed.into())
+        );
+    }
+
+    #[test]
+    fn admin_instructions_reject_cpi() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+        let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut outer = TestAccount::instructions(&Pubkey::new_unique());
+
+        let state = SetGlobalState {
+            owner: pool.owner.key,
+            fee_owner: pool.fee_owner,
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: TEST_FEES,
+            max_reserve_a: None,
+            max_reserve_b: None,
+            fee_holiday_start: None,
+            fee_holiday_end: None,
+            dynamic_fee_min_numerator: None,
+            dynamic_fee_max_numerator: None,
+            fee_tiers: None,
+            max_lp_per_deposit: None,
+            allowed_lp_decimals: None,
+        };
+        assert_eq!(
+            do_process_instruction(
+                set_global_state(&crate::id(), &pool.global_state.key, &owner.key, state).unwrap(),
+                vec![
+                    &mut pool.global_state,
+                    &mut owner,
+                    &mut system,
+                    &mut rent,
+                    &mut outer,
+                ],
+            ),
+            Err(SwapError::CpiNotAllowed.into())
+        );
+
+        let metadata_key = find_pool_metadata_address(&crate::id(), &pool.swap.key).0;
+        let mut metadata = TestAccount::new(metadata_key, system_program::id(), vec![]);
+        let mut instruction = set_pool_metadata(
+            &crate::id(),
+            &pool.swap.key,
+            &owner.key,
+            "Pool".to_string(),
+            "POOL".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            do_process_instruction(
+                instruction.clone(),
+                vec![
+                    &mut metadata,
+                    &mut pool.swap,
+                    &mut pool.global_state,
+                    &mut owner,
+                    &mut system,
+                    &mut rent,
+                    &mut outer,
+                ],
+            ),
+            Err(SwapError::CpiNotAllowed.into())
+        );
+
+        // an account posing as the instructions sysvar is refused before it is read
+        let mut forged = TestAccount::instructions(&crate::id());
+        forged.key = Pubkey::new_unique();
+        instruction.accounts[6].pubkey = forged.key;
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut metadata,
+                    &mut pool.swap,
+                    &mut pool.global_state,
+                    &mut owner,
+                    &mut system,
+                    &mut rent,
+                    &mut forged,
+                ],
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    /// Initializes a pool over fresh reserves whose token B account belongs
+    /// to `token_b_program_id`, passing the nonce `nonce` makes of the
+    /// canonical one, returns the result and the swap account
+    fn initialize_pool(
+        token_b_program_id: Pubkey,
+        nonce: fn(u8) -> Option<u8>,
+    ) -> (ProgramResult, TestAccount, TestAccount) {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        if token_b_program_id == token_2022_program_id() {
+            pool.token_b = pool.token_b.into_token_2022();
+        }
+        pool.token_b.owner = token_b_program_id;
+        initialize_test_pool(pool, nonce)
+    }
+
+    /// Initializes a constant product pool over the reserves and pool mint of
+    /// `pool`, returns the result along with the swap and pool token
+    /// destination accounts
+    fn initialize_test_pool(
+        pool: TestPool,
+        nonce: fn(u8) -> Option<u8>,
+    ) -> (ProgramResult, TestAccount, TestAccount) {
+        let destination = TestAccount::token(&pool.pool_mint.key, &Pubkey::new_unique(), 0);
+        initialize_test_pool_to(pool, nonce, destination, 0)
+    }
+
+    /// Initializes a pool as `initialize_test_pool` does, minting the initial
+    /// pool tokens to `destination` and charging the fees of the given tier
+    fn initialize_test_pool_to(
+        pool: TestPool,
+        nonce: fn(u8) -> Option<u8>,
+        mut destination: TestAccount,
+        fee_tier_index: u8,
+    ) -> (ProgramResult, TestAccount, TestAccount) {
+        let TestPool {
+            swap,
+            mut authority,
+            mut global_state,
+            mut token_a,
+            mut token_b,
+            mut pool_mint,
+            mut token_program,
+            ..
+        } = pool;
+        let mut swap = TestAccount::new(swap.key, crate::id(), vec![0u8; SwapVersion::LATEST_LEN]);
+        let mut rent = TestAccount::rent();
+        let (_, canonical_nonce) = find_swap_authority_address(&crate::id(), &swap.key);
+        let result = do_process_instruction(
+            initialize(
+                &crate::id(),
+                &spl_token::id(),
+                &swap.key,
+                &authority.key,
+                &token_a.key,
+                &token_b.key,
+                &pool_mint.key,
+                &destination.key,
+                None,
+                None,
+                SwapCurve {
+                    curve_type: CurveType::ConstantProduct,
+                    calculator: Box::new(ConstantProductCurve {}),
+                },
+                fee_tier_index,
+                None,
+                0,
+                nonce(canonical_nonce),
+            )
+            .unwrap(),
+            vec![
+                &mut swap,
+                &mut authority,
+                &mut global_state,
+                &mut token_a,
+                &mut token_b,
+                &mut pool_mint,
+                &mut destination,
+                &mut token_program,
+                &mut rent,
+            ],
+        );
+        (result, swap, destination)
+    }
+
+    #[test]
+    fn initialize_checks_stable_reserve_decimals() {
+        let TestPool {
+            swap,
+            mut authority,
+            mut global_state,
+            token_a_mint,
+            token_b_mint,
+            mut token_a,
+            mut token_b,
+            mut pool_mint,
+            mut token_program,
+            ..
+        } = TestPool::constant_product(1_000_000, 1_000_000_000, 0);
+        let mut swap = TestAccount::new(swap.key, crate::id(), vec![0u8; SwapVersion::LATEST_LEN]);
+        let mut destination = TestAccount::token(&pool_mint.key, &Pubkey::new_unique(), 0);
+        let mut rent = TestAccount::rent();
+        let mint = |key, decimals| TestAccount {
+            key,
+            ..TestAccount::mint(&Pubkey::new_unique(), 0, decimals)
+        };
+        let mut mint_a = mint(token_a_mint, 6);
+        let mut mint_b = mint(token_b_mint, 9);
+        let mut wrong_mint_b = mint(token_b_mint, 6);
+        let instruction = |reserve_mints| {
+            initialize(
+                &crate::id(),
+                &spl_token::id(),
+                &swap.key,
+                &authority.key,
+                &token_a.key,
+                &token_b.key,
+                &pool_mint.key,
+                &destination.key,
+                None,
+                reserve_mints,
+                SwapCurve {
+                    curve_type: CurveType::Stable,
+                    calculator: Box::new(StableCurve {
+                        amp: 100,
+                        min_trade_amount: 0,
+                        token_a_decimals: 6,
+                        token_b_decimals: 9,
+                    }),
+                },
+                0,
+                None,
+                0,
+                None,
+            )
+            .unwrap()
+        };
+        let without_mints = instruction(None);
+        let with_mints = instruction(Some((&token_a_mint, &token_b_mint)));
+
+        assert_eq!(
+            do_process_instruction(
+                without_mints,
+                vec![
+                    &mut swap,
+                    &mut authority,
+                    &mut global_state,
+                    &mut token_a,
+                    &mut token_b,
+                    &mut pool_mint,
+                    &mut destination,
+                    &mut token_program,
+                    &mut rent,
+                ],
+            ),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            do_process_instruction(
+                with_mints.clone(),
+                vec![
+                    &mut swap,
+                    &mut authority,
+                    &mut global_state,
+                    &mut token_a,
+                    &mut token_b,
+                    &mut pool_mint,
+                    &mut destination,
+                    &mut token_program,
+                    &mut rent,
+                    &mut mint_a,
+                    &mut wrong_mint_b,
+                ],
+            ),
+            Err(SwapError::MismatchDecimalValidation.into())
+        );
+        do_process_instruction(
+            with_mints,
+            vec![
+                &mut swap,
+                &mut authority,
+                &mut global_state,
+                &mut token_a,
+                &mut token_b,
+                &mut pool_mint,
+                &mut destination,
+                &mut token_program,
+                &mut rent,
+                &mut mint_a,
+                &mut mint_b,
+            ],
+        )
+        .unwrap();
+        // both reserves hold one whole token, priced at par
+        let token_swap = SwapVersion::unpack(&swap.data).unwrap();
+        let result = token_swap
+            .swap_curve()
+            .calculator
+            .swap_without_fees(1_000, 1_000_000, 1_000_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(result.destination_amount_swapped > 990_000);
+        assert!(result.destination_amount_swapped <= 1_000_000);
+    }
+
+    #[test]
+    fn initialize_records_reserve_token_programs() {
+        let (result, swap, _) = initialize_pool(token_2022_program_id(), |_| None);
+        result.unwrap();
+        let token_swap = SwapVersion::unpack(&swap.data).unwrap();
+        assert_eq!(*token_swap.token_program_id(), spl_token::id());
+        assert_eq!(*token_swap.token_a_program_id(), spl_token::id());
+        assert_eq!(*token_swap.token_b_program_id(), token_2022_program_id());
+
+        // the authority never signs for a program that is not a token program
+        let (result, _, _) = initialize_pool(Pubkey::new_unique(), |_| None);
+        assert_eq!(result, Err(SwapError::IncorrectTokenProgramId.into()));
+    }
+
+    #[test]
+    fn initialize_mints_sqrt_of_constant_product_reserves() {
+        // the global state's initial supply of 1_000_000_000 is not used
+        let (result, _, destination) = initialize_pool(spl_token::id(), |_| None);
+        result.unwrap();
+        assert_eq!(destination.token_amount(), 1_000_000);
+    }
+
+    #[test]
+    fn deposit_into_empty_pool_mints_initial_pool_supply() {
+        let mut pool = TestPool::constant_product(1_000, 4_000, 0);
+        let destination = pool.deposit(0, 1_000, 4_000).unwrap();
+        // sqrt(1_000 * 4_000), the amount a new pool with the same reserves
+        // would have minted
+        assert_eq!(destination.token_amount(), 2_000);
+        assert_eq!(pool.token_a.token_amount(), 2_000);
+        assert_eq!(pool.token_b.token_amount(), 8_000);
+    }
+
+    #[test]
+    fn initialize_keeps_pool_mint_on_spl_token() {
+        let TestPool {
+            swap,
+            mut authority,
+            mut global_state,
+            mut token_a,
+            mut token_b,
+            mut pool_mint,
+            ..
+        } = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let mut swap = TestAccount::new(swap.key, crate::id(), vec![0u8; SwapVersion::LATEST_LEN]);
+        let mut token_program =
+            TestAccount::new(token_2022_program_id(), Pubkey::default(), vec![]);
+        pool_mint.owner = token_2022_program_id();
+        let mut destination = TestAccount::token_2022(&pool_mint.key, &Pubkey::new_unique(), 0);
+        let mut rent = TestAccount::rent();
+        assert_eq!(
+            do_process_instruction(
+                initialize(
+                    &crate::id(),
+                    &token_2022_program_id(),
+                    &swap.key,
+                    &authority.key,
+                    &token_a.key,
+                    &token_b.key,
+                    &pool_mint.key,
+                    &destination.key,
+                    None,
+                    None,
+                    SwapCurve {
+                        curve_type: CurveType::ConstantProduct,
+                        calculator: Box::new(ConstantProductCurve {}),
+                    },
+                    0,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap(),
+                vec![
+                    &mut swap,
+                    &mut authority,
+                    &mut global_state,
+                    &mut token_a,
+                    &mut token_b,
+                    &mut pool_mint,
+                    &mut destination,
+                    &mut token_program,
+                    &mut rent,
+                ],
+            ),
+            Err(SwapError::IncorrectTokenProgramId.into())
+        );
+    }
+
+    #[test]
+    fn initialize_verifies_nonce() {
+        let (result, swap, _) = initialize_pool(spl_token::id(), Some);
+        result.unwrap();
+        let (_, canonical_nonce) = find_swap_authority_address(&crate::id(), &swap.key);
+        assert_eq!(SwapVersion::unpack(&swap.data).unwrap().nonce(), canonical_nonce);
+
+        // any other bump seed is refused, even one giving a valid address
+        let (result, _, _) = initialize_pool(spl_token::id(), |nonce| Some(nonce.wrapping_sub(1)));
+        assert_eq!(result, Err(SwapError::InvalidProgramAddress.into()));
+    }
+
+    #[test]
+    fn swap_output_below_destination_reserve() {
+        // a constant price curve pays out whatever the reserve holds, the
+        // curve and fee math alone leave it empty
+        let swap_curve = || SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Box::new(ConstantPriceCurve { token_b_price: 1 }),
+        };
+        let emptying = swap_curve()
+            .swap(1_004, 1_000, 1_000, TradeDirection::AtoB, &TEST_FEES)
+            .unwrap();
+        assert_eq!(emptying.destination_amount_swapped, 1_000);
+        assert_eq!(emptying.new_swap_destination_amount, 0);
+
+        let mut pool = TestPool::new(swap_curve(), 1_000, 1_000, 1_000_000_000);
+        assert_eq!(
+            pool.swap_a_to_b(Swap {
+                amount_in: 1_004,
+                minimum_amount_out: 0,
+                max_slippage_bps: 0,
+                allow_partial: false,
+            }),
+            Err(SwapError::CalculationFailure.into())
+        );
+        assert_eq!(pool.token_b.token_amount(), 1_000);
+
+        // a trade the reserve can pay still goes through
+        pool.swap_a_to_b(Swap {
+            amount_in: 1_003,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        })
+        .unwrap();
+        assert_eq!(pool.token_b.token_amount(), 1);
+    }
+
+    #[test]
+    fn mixed_pool_swap() {
+        let mut pool = TestPool::mixed_constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let amount_in = pool
+            .swap_a_to_b(Swap {
+                amount_in: 10_000,
+                minimum_amount_out: 9_000,
+                max_slippage_bps: 0,
+                allow_partial: false,
+            })
+            .unwrap();
+        assert_eq!(amount_in, 10_000);
+        assert!(pool.token_a.token_amount() > 1_000_000);
+        assert!(pool.token_b.token_amount() < 1_000_000);
+        assert_eq!(pool.token_b.owner, token_2022_program_id());
+
+        let swap = || Swap {
+            amount_in: 10_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        // the Token-2022 reserve cannot move without its mint, nor without
+        // its program
+        let mint = pool.reserve_accounts.pop().unwrap();
+        assert_eq!(pool.swap_a_to_b(swap()), Err(ProgramError::NotEnoughAccountKeys));
+        let program = pool.reserve_accounts.pop().unwrap();
+        assert_eq!(
+            pool.swap_a_to_b(swap()),
+            Err(SwapError::IncorrectTokenProgramId.into())
+        );
+        pool.reserve_accounts = vec![program, mint];
+
+        // a reserve with a transfer fee would receive less than it is sent
+        pool.token_b.data.extend_from_slice(&2u16.to_le_bytes());
+        pool.token_b.data.extend_from_slice(&8u16.to_le_bytes());
+        pool.token_b.data.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            pool.swap_a_to_b(swap()),
+            Err(SwapError::UnsupportedTokenExtension.into())
+        );
+    }
+
+    #[test]
+    fn mixed_pool_deposit_and_withdraw() {
+        let mut pool = TestPool::mixed_constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let mut pool_tokens = pool.deposit(500_000_000, 500_000, 1_000_000).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 1_500_000);
+        assert_eq!(pool.token_b.token_amount(), 3_000_000);
+
+        let user_key = TokenAccount::unpack(&pool_tokens.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+        pool.withdraw(&mut user, &mut pool_tokens, 500_000_000).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 1_000_000);
+        assert_eq!(pool.token_b.token_amount(), 2_000_000);
+    }
+
+    #[test]
+    fn zap_deposit_returns_minted_pool_tokens() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let mut user = TestAccount::signer();
+        let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 10_000);
+        let mut refund = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+        let mut destination = TestAccount::token(&pool.pool_mint.key, &user.key, 0);
+        let mut fee_account = TestAccount::token(&pool.token_a_mint, &pool.fee_owner, 0);
+        pool.zap_deposit(
+            &mut user,
+            &mut source,
+            &mut refund,
+            &mut destination,
+            &mut fee_account,
+            ZapDeposit {
+                source_amount: 10_000,
+                minimum_pool_token_amount: 0,
+            },
+        )
+        .unwrap();
+        let (_, return_data) = get_return_data().unwrap();
+        let return_data = ZapDepositReturnData::unpack(&return_data).unwrap();
+        assert!(return_data.pool_token_amount > 0);
+        assert_eq!(return_data.pool_token_amount, destination.token_amount());
+        // half of the source is swapped, the curve may need a little less
+        assert!(return_data.swap_amount > 0 && return_data.swap_amount <= 5_000);
+    }
+
+    #[test]
+    fn mixed_pool_zap_deposit() {
+        let mut pool = TestPool::mixed_constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        pool.zap_deposit_a(10_000).unwrap();
+        assert!(pool.token_a.token_amount() > 1_000_000);
+
+        // a source under the wrong program is refused before any transfer
+        let mut user = TestAccount::signer();
+        let mut source = TestAccount::token(&pool.token_b_mint, &user.key, 10_000);
+        let mut refund = TestAccount::token(&pool.token_a_mint, &user.key, 0);
+        let mut destination = TestAccount::token(&pool.pool_mint.key, &user.key, 0);
+        let mut fee_account = pool.user_token(&pool.token_b_mint, &pool.fee_owner, 0);
+        assert_eq!(
+            pool.zap_deposit(
+                &mut user,
+                &mut source,
+                &mut refund,
+                &mut destination,
+                &mut fee_account,
+                ZapDeposit {
+                    source_amount: 10_000,
+                    minimum_pool_token_amount: 0,
+                },
+            ),
+            Err(SwapError::IncorrectTokenProgramId.into())
+        );
+    }
+
+    #[test]
+    fn set_global_state_grows_legacy_account() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        pool.global_state.data.truncate(GlobalState::MIN_LEN);
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+        let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut instructions = TestAccount::instructions(&crate::id());
+
+        let state = SetGlobalState {
+            owner: pool.owner.key,
+            fee_owner: pool.fee_owner,
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: TEST_FEES,
+            max_reserve_a: Some(1_500_000),
+            max_reserve_b: None,
+            fee_holiday_start: None,
+            fee_holiday_end: None,
+            dynamic_fee_min_numerator: None,
+            dynamic_fee_max_numerator: None,
+            fee_tiers: None,
+            max_lp_per_deposit: None,
+            allowed_lp_decimals: None,
+        };
+        do_process_serialized_instruction(
+            set_global_state(&crate::id(), &pool.global_state.key, &owner.key, state).unwrap(),
+            vec![
+                &mut pool.global_state,
+                &mut owner,
+                &mut system,
+                &mut rent,
+                &mut instructions,
+            ],
+        )
+        .unwrap();
+        assert_eq!(pool.global_state.data.len(), GlobalState::LEN);
+        let global_state = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        assert_eq!(global_state.max_reserve_a, 1_500_000);
+        assert_eq!(global_state.fees, TEST_FEES);
+
+        // the cap now holds deposits
+        assert_eq!(
+            pool.deposit(1_000_000_000, 1_000_000, 1_000_000).map(|_| ()),
+            Err(SwapError::PoolCapExceeded.into())
+        );
+        pool.deposit(500_000_000, 500_000, 500_000).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 1_500_000);
+    }
+
+    #[test]
+    fn last_lp_exit_then_deposit_reseeds_the_pool() {
+        let mut pool = TestPool::constant_product(0, 0, 0);
+        let mut pool_tokens = pool.deposit(0, 1_000, 4_000).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 2_000);
+
+        // the last LP takes both reserves with the whole supply
+        let user_key = TokenAccount::unpack(&pool_tokens.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+        pool.withdraw(&mut user, &mut pool_tokens, 2_000).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 0);
+        assert_eq!(pool.token_b.token_amount(), 0);
+        assert_eq!(Mint::unpack(&pool.pool_mint.data).unwrap().supply, 0);
+
+        // the next deposit seeds the pool again at its own ratio
+        let destination = pool.deposit(0, 3_000, 12_000).unwrap();
+        assert_eq!(destination.token_amount(), 6_000);
+        assert_eq!(pool.token_a.token_amount(), 3_000);
+        assert_eq!(pool.token_b.token_amount(), 12_000);
+    }
+
+    #[test]
+    fn withdraw_below_min_lp_supply_fails() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+        let mut pool_tokens = pool.deposit(500_000_000, 500_000, 1_000_000).unwrap();
+        let token_swap = SwapV1 {
+            min_lp_supply: Some(1_200_000_000),
+            ..pool.swap_state()
+        };
+        SwapVersion::pack(SwapVersion::SwapV1(token_swap), &mut pool.swap.data).unwrap();
+
+        // the request is refused rather than cut down to the floor
+        let user_key = TokenAccount::unpack(&pool_tokens.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+        assert_eq!(
+            pool.withdraw(&mut user, &mut pool_tokens, 500_000_000),
+            Err(SwapError::BelowMinLpSupply.into())
+        );
+        assert_eq!(pool_tokens.token_amount(), 500_000_000);
+        pool.withdraw(&mut user, &mut pool_tokens, 300_000_000).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 200_000_000);
+    }
+
+    #[test]
+    fn zero_floor_pool_reseeds_after_full_exit() {
+        let mut pool = TestPool::constant_product(0, 0, 0);
+        let token_swap = SwapV1 {
+            min_lp_supply: Some(0),
+            ..pool.swap_state()
+        };
+        SwapVersion::pack(SwapVersion::SwapV1(token_swap), &mut pool.swap.data).unwrap();
+        let mut first = pool.deposit(0, 1_000, 4_000).unwrap();
+        let mut second = pool.deposit(1_000, 500, 2_000).unwrap();
+        assert_eq!(Mint::unpack(&pool.pool_mint.data).unwrap().supply, 3_000);
+
+        // without a floor, LPs not holding the whole supply can empty the pool
+        for pool_tokens in [&mut first, &mut second].iter_mut() {
+            let user_key = TokenAccount::unpack(&pool_tokens.data).unwrap().owner;
+            let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+            let amount = pool_tokens.token_amount();
+            pool.withdraw(&mut user, pool_tokens, amount).unwrap();
+        }
+        assert_eq!(pool.token_a.token_amount(), 0);
+        assert_eq!(pool.token_b.token_amount(), 0);
+        assert_eq!(Mint::unpack(&pool.pool_mint.data).unwrap().supply, 0);
+
+        let destination = pool.deposit(0, 2_000, 2_000).unwrap();
+        assert_eq!(destination.token_amount(), 2_000);
+        assert_eq!(pool.token_a.token_amount(), 2_000);
+    }
+
+    #[test]
+    fn first_set_global_state_matches_initial_constants() {
+        let program_id = crate::id();
+        let (global_state_key, _) = find_global_state_address(&program_id);
+        // allocated up front, the system program is not stubbed
+        let mut global_state =
+            TestAccount::new(global_state_key, program_id, vec![0u8; GlobalState::LEN]);
+        let initial_owner = INITIAL_PROGRAM_OWNER;
+        let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut instructions = TestAccount::instructions(&program_id);
+        let state = SetGlobalState {
+            owner: initial_owner,
+            fee_owner: INITIAL_FEE_OWNER_KEY,
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: INITIAL_FEES.clone(),
+            max_reserve_a: None,
+            max_reserve_b: None,
+            fee_holiday_start: None,
+            fee_holiday_end: None,
+            dynamic_fee_min_numerator: None,
+            dynamic_fee_max_numerator: None,
+            fee_tiers: None,
+            max_lp_per_deposit: None,
+            allowed_lp_decimals: None,
+        };
+        let instruction =
+            set_global_state(&program_id, &global_state_key, &initial_owner, state).unwrap();
+
+        // only the initial owner may set a new state
+        let mut owner = TestAccount::signer();
+        let mut other_owner = instruction.clone();
+        other_owner.accounts[1].pubkey = owner.key;
+        assert_eq!(
+            do_process_instruction(
+                other_owner,
+                vec![
+                    &mut global_state,
+                    &mut owner,
+                    &mut system,
+                    &mut rent,
+                    &mut instructions,
+                ],
+            ),
+            Err(SwapError::InvalidProgramOwner.into())
+        );
+
+        let mut owner = TestAccount::new(initial_owner, system_program::id(), vec![]);
+        do_process_instruction(
+            instruction,
+            vec![
+                &mut global_state,
+                &mut owner,
+                &mut system,
+                &mut rent,
+                &mut instructions,
+            ],
+        )
+        .unwrap();
+        let global_state = GlobalState::unpack_from_slice(&global_state.data).unwrap();
+        assert!(global_state.is_initialized);
+        assert_eq!(global_state.owner, initial_owner);
+        assert_eq!(global_state.fee_owner, INITIAL_FEE_OWNER_KEY);
+        assert_eq!(global_state.fees, *INITIAL_FEES);
+    }
+
+    #[test]
+    fn set_global_state_checks_system_and_rent_ids() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut instructions = TestAccount::instructions(&crate::id());
+        let state = SetGlobalState {
+            owner: pool.owner.key,
+            fee_owner: pool.fee_owner,
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: TEST_FEES,
+            max_reserve_a: None,
+            max_reserve_b: None,
+            fee_holiday_start: None,
+            fee_holiday_end: None,
+            dynamic_fee_min_numerator: None,
+            dynamic_fee_max_numerator: None,
+            fee_tiers: None,
+            max_lp_per_deposit: None,
+            allowed_lp_decimals: None,
+        };
+        let instruction =
+            set_global_state(&crate::id(), &pool.global_state.key, &owner.key, state).unwrap();
+
+        let mut wrong = TestAccount::new(Pubkey::new_unique(), Pubkey::default(), vec![]);
+        let mut wrong_system = instruction.clone();
+        wrong_system.accounts[2].pubkey = wrong.key;
+        assert_eq!(
+            do_process_instruction(
+                wrong_system,
+                vec![
+                    &mut pool.global_state,
+                    &mut owner,
+                    &mut wrong,
+                    &mut rent,
+                    &mut instructions,
+                ],
+            ),
+            Err(SwapError::InvalidSystemProgramId.into())
+        );
+
+        // rent data under another key, refused as the sysvar is read
+        let mut wrong = TestAccount {
+            key: Pubkey::new_unique(),
+            ..TestAccount::rent()
+        };
+        let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut wrong_rent = instruction.clone();
+        wrong_rent.accounts[3].pubkey = wrong.key;
+        assert_eq!(
+            do_process_instruction(
+                wrong_rent,
+                vec![
+                    &mut pool.global_state,
+                    &mut owner,
+                    &mut system,
+                    &mut wrong,
+                    &mut instructions,
+                ],
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        do_process_instruction(
+            instruction,
+            vec![
+                &mut pool.global_state,
+                &mut owner,
+                &mut system,
+                &mut rent,
+                &mut instructions,
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn initialize_rejects_reused_pool_mint() {
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let (result, _, destination) = initialize_test_pool(pool, Some);
+        result.unwrap();
+        assert_eq!(destination.token_amount(), 1_000_000);
+
+        // the mint of another pool, even drained back to a zero supply
+        let other = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        pool.pool_mint = other.pool_mint;
+        assert_eq!(
+            initialize_test_pool(pool, Some).0,
+            Err(SwapError::InvalidOwner.into())
+        );
+
+        // a mint of this pool's authority that has already minted
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        pool.pool_mint = TestAccount {
+            key: pool.pool_mint.key,
+            ..TestAccount::mint(&pool.authority.key, 1, 9)
+        };
+        assert_eq!(
+            initialize_test_pool(pool, Some).0,
+            Err(SwapError::InvalidSupply.into())
+        );
+    }
+
+    #[test]
+    fn set_pool_metadata_by_the_owner() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let metadata_key = find_pool_metadata_address(&crate::id(), &pool.swap.key).0;
+        // allocated up front, the system program is not stubbed
+        let mut metadata = TestAccount::new(
+            metadata_key,
+            crate::id(),
+            vec![0u8; PoolMetadata::get_packed_len()],
+        );
+        let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+        let mut rent = TestAccount::rent();
+        let mut instructions = TestAccount::instructions(&crate::id());
+        let mut set_metadata = |pool: &mut TestPool, owner: &mut TestAccount, name: &str| {
+            do_process_instruction(
+                set_pool_metadata(
+                    &crate::id(),
+                    &pool.swap.key,
+                    &owner.key,
+                    name.to_string(),
+                    "POOL".to_string(),
+                )
+                .unwrap(),
+                vec![
+                    &mut metadata,
+                    &mut pool.swap,
+                    &mut pool.global_state,
+                    owner,
+                    &mut system,
+                    &mut rent,
+                    &mut instructions,
+                ],
+            )
+        };
+
+        let mut owner = TestAccount::new(pool.owner.key, system_program::id(), vec![]);
+        set_metadata(&mut pool, &mut owner, "Pool").unwrap();
+        assert_eq!(
+            set_metadata(&mut pool, &mut owner, &"x".repeat(MAX_POOL_NAME_LEN + 1)),
+            Err(SwapError::InvalidInput.into())
+        );
+        let mut stranger = TestAccount::signer();
+        assert_eq!(
+            set_metadata(&mut pool, &mut stranger, "Other"),
+            Err(SwapError::InvalidProgramOwner.into())
+        );
+
+        let state = PoolMetadata::unpack_from_slice(&metadata.data).unwrap();
+        assert_eq!(state.swap, pool.swap.key);
+        assert_eq!(state.name, "Pool");
+        assert_eq!(state.symbol, "POOL");
+    }
+
+    #[test]
+    fn initialize_rejects_pool_mint_as_reserve_mint() {
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        initialize_test_pool(pool, Some).0.unwrap();
+
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        pool.token_a = TestAccount::token(&pool.pool_mint.key, &pool.authority.key, 1_000_000);
+        assert_eq!(
+            initialize_test_pool(pool, Some).0,
+            Err(SwapError::InvalidInput.into())
+        );
+
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        pool.token_b = TestAccount::token(&pool.pool_mint.key, &pool.authority.key, 1_000_000);
+        assert_eq!(
+            initialize_test_pool(pool, Some).0,
+            Err(SwapError::InvalidInput.into())
+        );
+    }
+
+    #[test]
+    fn set_global_state_keeps_fee_denominator() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+
+        // numerators may change over the same denominator
+        let mut state = pool.global_state_settings();
+        state.fees.constant_product_return_fee_numerator = 30;
+        pool.set_global_state(state).unwrap();
+        let stored = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        assert_eq!(stored.fees.constant_product_return_fee_numerator, 30);
+
+        let mut state = pool.global_state_settings();
+        state.fees.fee_denominator = 1_000;
+        assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidFee.into()));
+
+        // a state stored over another denominator cannot be moved to the
+        // constraint's one either, its pools were priced with the old one
+        let mut stored = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        stored.fees.fee_denominator = 1_000;
+        stored.pack_into_slice(&mut pool.global_state.data);
+        let mut state = pool.global_state_settings();
+        state.fees.fee_denominator = TEST_FEES.fee_denominator;
+        assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidFee.into()));
+    }
+
+    #[test]
+    fn tiny_deposit_cannot_dilute_existing_lps() {
+        let mut pool = TestPool::constant_product(3, 3, 1_000_000);
+
+        // half the supply is worth 1.5 of each token, floored to 1 that would
+        // take value from the existing holders
+        assert_eq!(
+            Processor::assert_no_dilution(3, 3, 1_000_000, 1, 1, 500_000),
+            Err(SwapError::CalculationFailure.into())
+        );
+
+        // the deposit rounds up instead and passes the check
+        let destination = pool.deposit(500_000, 2, 2).unwrap();
+        assert_eq!(destination.token_amount(), 500_000);
+        assert_eq!(pool.token_a.token_amount(), 5);
+        assert_eq!(pool.token_b.token_amount(), 5);
+    }
+
+    #[test]
+    fn swap_from_one_of_two_accounts_of_the_source_mint() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut user = TestAccount::signer();
+        let other_source = TestAccount::token(&pool.token_a_mint, &user.key, 5_000);
+        let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 10_000);
+        let mut destination = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+        let mut fee_account = TestAccount::token(&pool.token_a_mint, &pool.fee_owner, 0);
+        let instruction = swap(
+            &crate::id(),
+            &spl_token::id(),
+            &pool.swap.key,
+            &pool.authority.key,
+            &user.key,
+            &source.key,
+            &pool.token_a.key,
+            &pool.token_b.key,
+            &destination.key,
+            &pool.pool_mint.key,
+            &fee_account.key,
+            None,
+            Swap {
+                amount_in: 10_000,
+                minimum_amount_out: 0,
+                max_slippage_bps: 0,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+
+        // the reserve itself as the user's source is refused
+        let mut from_reserve = instruction.clone();
+        from_reserve.accounts[4].pubkey = pool.token_a.key;
+        let mut reserve = TestAccount {
+            key: pool.token_a.key,
+            ..TestAccount::token(&pool.token_a_mint, &pool.authority.key, 1_000_000)
+        };
+        assert_eq!(
+            do_process_instruction(
+                from_reserve,
+                vec![
+                    &mut pool.swap,
+                    &mut pool.authority,
+                    &mut user,
+                    &mut pool.global_state,
+                    &mut reserve,
+                    &mut pool.token_a,
+                    &mut pool.token_b,
+                    &mut destination,
+                    &mut pool.pool_mint,
+                    &mut fee_account,
+                    &mut pool.token_program,
+                ],
+            ),
+            Err(SwapError::InvalidInput.into())
+        );
+
+        // the reserve is picked by the swap source key, the user's other
+        // account of the same mint is left alone
+        do_process_instruction(
+            instruction,
+            vec![
+                &mut pool.swap,
+                &mut pool.authority,
+                &mut user,
+                &mut pool.global_state,
+                &mut source,
+                &mut pool.token_a,
+                &mut pool.token_b,
+                &mut destination,
+                &mut pool.pool_mint,
+                &mut fee_account,
+                &mut pool.token_program,
+            ],
+        )
+        .unwrap();
+        assert_eq!(source.token_amount(), 0);
+        assert_eq!(other_source.token_amount(), 5_000);
+        let received = destination.token_amount();
+        assert!(received > 0);
+        assert_eq!(
+            pool.token_a.token_amount() + fee_account.token_amount(),
+            1_000_000 + 10_000
+        );
+        assert_eq!(pool.token_b.token_amount(), 1_000_000 - received);
+    }
+
+    #[test]
+    fn swap_max_slippage_bps_bound() {
+        // a 1% trade moves the price about 1%, fees aside
+        let test_swap = |max_slippage_bps| Swap {
+            amount_in: 10_000,
+            minimum_amount_out: 0,
+            max_slippage_bps,
+            allow_partial: false,
+        };
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        assert_eq!(pool.swap_a_to_b(test_swap(10)), Err(SwapError::ExceededSlippage.into()));
+        assert_eq!(pool.swap_b_to_a(test_swap(10)), Err(SwapError::ExceededSlippage.into()));
+
+        // a looser bound lets it through, as does zero which disables it
+        assert_eq!(pool.swap_a_to_b(test_swap(200)), Ok(10_000));
+        assert_eq!(pool.swap_b_to_a(test_swap(0)), Ok(10_000));
+    }
+
+    #[test]
+    fn set_global_state_checks_initial_supply_against_lp_decimals() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+
+        // a thousandth of a 9 decimals lp token
+        let mut state = pool.global_state_settings();
+        state.initial_supply = 1_000_000;
+        state.lp_decimals = 9;
+        assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidInitialSupply.into()));
+
+        // decimals whose whole token overflows the supply type
+        let mut state = pool.global_state_settings();
+        state.lp_decimals = 20;
+        assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidInitialSupply.into()));
+
+        // one whole 6 decimals lp token
+        let mut state = pool.global_state_settings();
+        state.initial_supply = 1_000_000;
+        state.lp_decimals = 6;
+        pool.set_global_state(state).unwrap();
+        let stored = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        assert_eq!((stored.initial_supply, stored.lp_decimals), (1_000_000, 6));
+    }
+
+    #[test]
+    fn withdraw_rejects_pool_mint_as_reserve_or_destination() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut source = pool.deposit(10_000_000, 10_000, 10_000).unwrap();
+        let user_key = TokenAccount::unpack(&source.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+
+        for pool_mint_slot in [8usize, 9].iter() {
+            let mut destination_a = TestAccount::token(&pool.token_a_mint, &user.key, 0);
+            let mut destination_b = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+            let mut instruction = withdraw_all_token_types(
+                &crate::id(),
+                &spl_token::id(),
+                &pool.swap.key,
+                &pool.authority.key,
+                &user.key,
+                &pool.pool_mint.key,
+                &source.key,
+                &pool.token_a.key,
+                &pool.token_b.key,
+                &destination_a.key,
+                &destination_b.key,
+                WithdrawAllTokenTypes {
+                    pool_token_amount: 1_000_000,
+                    minimum_token_a_amount: 0,
+                    minimum_token_b_amount: 0,
+                    deadline: 0,
+                },
+                &pool.swap_state(),
+            )
+            .unwrap();
+            instruction.accounts[*pool_mint_slot].pubkey = pool.pool_mint.key;
+            let destination = if *pool_mint_slot == 8 {
+                &mut destination_a
+            } else {
+                &mut destination_b
+            };
+            destination.key = pool.pool_mint.key;
+            assert_eq!(
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut pool.swap,
+                        &mut pool.authority,
+                        &mut pool.global_state,
+                        &mut user,
+                        &mut pool.pool_mint,
+                        &mut source,
+                        &mut pool.token_a,
+                        &mut pool.token_b,
+                        &mut destination_a,
+                        &mut destination_b,
+                        &mut pool.token_program,
+                    ],
+                ),
+                Err(SwapError::InvalidInput.into())
+            );
+        }
+
+        pool.withdraw(&mut user, &mut source, 1_000_000).unwrap();
+        assert_eq!(source.token_amount(), 9_000_000);
+
+        // a pool recorded with the pool mint as its token A reserve
+        let pool_mint = pool.pool_mint.key;
+        SwapVersion::pack(
+            SwapVersion::SwapV1(SwapV1 {
+                token_a: pool_mint,
+                ..pool.swap_state()
+            }),
+            &mut pool.swap.data,
+        )
+        .unwrap();
+        pool.token_a.key = pool_mint;
+        assert_eq!(
+            pool.withdraw(&mut user, &mut source, 1_000_000),
+            Err(SwapError::InvalidInput.into())
+        );
+    }
+
+    #[test]
+    fn deposit_maxima_must_follow_the_pool_ratio() {
+        let mut pool = TestPool::constant_product(1_000_000, 2_000_000, 1_000_000_000);
+
+        // enough of both tokens, but maxima far off the 1:2 ratio
+        assert_eq!(
+            pool.deposit(100_000_000, 100_000, 400_000).map(|_| ()),
+            Err(SwapError::ExceededSlippage.into())
+        );
+        // just past the tolerance
+        assert_eq!(
+            pool.deposit(100_000_000, 100_000, 203_000).map(|_| ()),
+            Err(SwapError::ExceededSlippage.into())
+        );
+
+        // aligned and within the tolerance
+        let pool_tokens = pool.deposit(100_000_000, 100_000, 200_000).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 100_000_000);
+        let pool_tokens = pool.deposit(100_000_000, 110_000, 221_000).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 100_000_000);
+        assert_eq!(pool.token_a.token_amount(), 1_200_000);
+        assert_eq!(pool.token_b.token_amount(), 2_400_000);
+    }
+
+    #[test]
+    fn closed_destination_is_refused() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut source = pool.deposit(10_000_000, 10_000, 10_000).unwrap();
+        let user_key = TokenAccount::unpack(&source.data).unwrap().owner;
+        let mut user = TestAccount::new(user_key, system_program::id(), vec![]);
+        let mut destination_a = TestAccount::token(&pool.token_a_mint, &user.key, 0);
+        let mut destination_b = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+
+        // closed, its lamports drained
+        destination_b.lamports = 0;
+        assert_eq!(
+            pool.withdraw_to(&mut user, &mut source, &mut destination_a, &mut destination_b, 1_000),
+            Err(SwapError::ExpectedAccount.into())
+        );
+        // closed and reassigned to the system program
+        destination_b = TestAccount::new(destination_b.key, system_program::id(), vec![]);
+        assert_eq!(
+            pool.withdraw_to(&mut user, &mut source, &mut destination_a, &mut destination_b, 1_000),
+            Err(SwapError::ExpectedAccount.into())
+        );
+
+        let mut destination_b = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+        pool.withdraw_to(&mut user, &mut source, &mut destination_a, &mut destination_b, 1_000)
+            .unwrap();
+        assert_eq!(source.token_amount(), 10_000_000 - 1_000);
+
+        // the swap output account likewise
+        let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 1_000);
+        let mut destination = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+        destination.lamports = 0;
+        let mut fee_account = TestAccount::token(&pool.token_a_mint, &pool.fee_owner, 0);
+        let instruction = swap(
+            &crate::id(),
+            &spl_token::id(),
+            &pool.swap.key,
+            &pool.authority.key,
+            &user.key,
+            &source.key,
+            &pool.token_a.key,
+            &pool.token_b.key,
+            &destination.key,
+            &pool.pool_mint.key,
+            &fee_account.key,
+            None,
+            Swap {
+                amount_in: 1_000,
+                minimum_amount_out: 0,
+                max_slippage_bps: 0,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut pool.swap,
+                    &mut pool.authority,
+                    &mut user,
+                    &mut pool.global_state,
+                    &mut source,
+                    &mut pool.token_a,
+                    &mut pool.token_b,
+                    &mut destination,
+                    &mut pool.pool_mint,
+                    &mut fee_account,
+                    &mut pool.token_program,
+                ],
+            ),
+            Err(SwapError::ExpectedAccount.into())
+        );
+        pool.swap_a_to_b(Swap {
+            amount_in: 1_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn set_global_state_fees_against_the_minimum() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+
+        // the deployed minimum is zero on every numerator, fees can be
+        // brought down to it
+        let mut state = pool.global_state_settings();
+        state.fees = INITIAL_FEES.clone();
+        pool.set_global_state(state).unwrap();
+        let stored = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        assert_eq!(stored.fees, *SWAP_CONSTRAINTS.fees);
+
+        // under a higher minimum a lower numerator is refused
+        let constraints = SwapConstraints {
+            valid_curve_types: SWAP_CONSTRAINTS.valid_curve_types,
+            fees: &TEST_FEES,
+        };
+        let below = Fees {
+            constant_product_return_fee_numerator: 24,
+            ..TEST_FEES
+        };
+        assert_eq!(constraints.validate_fees(&below), Err(SwapError::InvalidFee.into()));
+        constraints.validate_fees(&TEST_FEES).unwrap();
+    }
+
+    #[test]
+    fn uninitialized_global_state_is_reported() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let test_swap = Swap {
+            amount_in: 1_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let initialized = pool.global_state.data.clone();
+
+        // allocated but never written, or not allocated at all
+        pool.global_state.data = vec![0u8; GlobalState::LEN];
+        assert_eq!(
+            pool.swap_a_to_b(test_swap.clone()),
+            Err(SwapError::NotInitializedState.into())
+        );
+        pool.global_state.data = vec![];
+        assert_eq!(
+            pool.deposit(1_000_000, 1_000, 1_000).map(|_| ()),
+            Err(SwapError::NotInitializedState.into())
+        );
+        // written but flagged as not initialized
+        let mut state = GlobalState::unpack_from_slice(&initialized).unwrap();
+        state.is_initialized = false;
+        pool.global_state.data = vec![0u8; GlobalState::LEN];
+        state.pack_into_slice(&mut pool.global_state.data);
+        assert_eq!(
+            pool.swap_a_to_b(test_swap.clone()),
+            Err(SwapError::NotInitializedState.into())
+        );
+
+        pool.global_state.data = initialized;
+        pool.swap_a_to_b(test_swap).unwrap();
+    }
+
+    #[test]
+    fn swap_fee_holiday_window() {
+        let test_swap = Swap {
+            amount_in: 10_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let holiday_pool = |start, end| {
+            let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+            let mut state = pool.global_state_settings();
+            state.fee_holiday_start = Some(start);
+            state.fee_holiday_end = Some(end);
+            pool.set_global_state(state).unwrap();
+            pool
+        };
+
+        // inside the window the input reaches the curve whole, none of it goes
+        // to the fee account, and pays out 1_000_000 * 10_000 / 1_010_000
+        // rounded down
+        let mut pool = holiday_pool(TEST_UNIX_TIMESTAMP - 1, TEST_UNIX_TIMESTAMP + 1);
+        let taken = pool.swap_a_to_b(test_swap.clone()).unwrap();
+        assert_eq!(pool.token_a.token_amount(), 1_000_000 + taken);
+        assert_eq!(pool.token_b.token_amount(), 1_000_000 - 9_900);
+
+        // the end is excluded, fees apply as outside any window
+        let mut pool = holiday_pool(TEST_UNIX_TIMESTAMP - 1, TEST_UNIX_TIMESTAMP);
+        pool.swap_a_to_b(test_swap.clone()).unwrap();
+        let mut no_holiday = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        no_holiday.swap_a_to_b(test_swap).unwrap();
+        assert_eq!(pool.token_b.token_amount(), no_holiday.token_b.token_amount());
+        assert!(pool.token_b.token_amount() > 1_000_000 - 9_900);
+
+        // a window ending before it starts is refused
+        let mut state = pool.global_state_settings();
+        state.fee_holiday_start = Some(TEST_UNIX_TIMESTAMP + 1);
+        state.fee_holiday_end = Some(TEST_UNIX_TIMESTAMP);
+        assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidInput.into()));
+    }
+
+    #[test]
+    fn initialize_rejects_delegated_reserves() {
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        initialize_test_pool(pool, Some).0.unwrap();
+
+        let delegations = [
+            // an amount left over without a delegate
+            (COption::None, 1),
+            // a delegate approved for nothing
+            (COption::Some(Pubkey::new_unique()), 0),
+            (COption::Some(Pubkey::new_unique()), 1_000),
+        ];
+        for (delegate, delegated_amount) in delegations.iter() {
+            for reserve in 0..2 {
+                let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+                let reserve = if reserve == 0 {
+                    &mut pool.token_a
+                } else {
+                    &mut pool.token_b
+                };
+                let mut account = TokenAccount::unpack(&reserve.data).unwrap();
+                account.delegate = *delegate;
+                account.delegated_amount = *delegated_amount;
+                account.pack_into_slice(&mut reserve.data);
+                assert_eq!(
+                    initialize_test_pool(pool, Some).0,
+                    Err(SwapError::InvalidDelegate.into())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn initialize_with_creator_requires_their_destination() {
+        let mut creator = TestAccount::signer();
+        let third_party = Pubkey::new_unique();
+        for (destination_owner, result) in [
+            (third_party, Err(SwapError::InvalidOutputOwner.into())),
+            (creator.key, Ok(())),
+        ]
+        .iter()
+        {
+            let TestPool {
+                swap,
+                mut authority,
+                mut global_state,
+                mut token_a,
+                mut token_b,
+                mut pool_mint,
+                mut token_program,
+                ..
+            } = TestPool::constant_product(1_000_000, 1_000_000, 0);
+            let mut swap =
+                TestAccount::new(swap.key, crate::id(), vec![0u8; SwapVersion::LATEST_LEN]);
+            let mut destination = TestAccount::token(&pool_mint.key, destination_owner, 0);
+            let mut rent = TestAccount::rent();
+            assert_eq!(
+                do_process_instruction(
+                    initialize(
+                        &crate::id(),
+                        &spl_token::id(),
+                        &swap.key,
+                        &authority.key,
+                        &token_a.key,
+                        &token_b.key,
+                        &pool_mint.key,
+                        &destination.key,
+                        Some(&creator.key),
+                        None,
+                        SwapCurve {
+                            curve_type: CurveType::ConstantProduct,
+                            calculator: Box::new(ConstantProductCurve {}),
+                        },
+                        0,
+                        None,
+                        0,
+                        None,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut swap,
+                        &mut authority,
+                        &mut global_state,
+                        &mut token_a,
+                        &mut token_b,
+                        &mut pool_mint,
+                        &mut destination,
+                        &mut token_program,
+                        &mut rent,
+                        &mut creator,
+                    ],
+                ),
+                *result
+            );
+        }
+    }
+
+    #[test]
+    fn readonly_pool_mint_is_refused() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut user = TestAccount::signer();
+        let mut source_a = TestAccount::token(&pool.token_a_mint, &user.key, 1_000);
+        let mut source_b = TestAccount::token(&pool.token_b_mint, &user.key, 1_000);
+        let mut pool_tokens = TestAccount::token(&pool.pool_mint.key, &user.key, 0);
+        let pool_mint = pool.pool_mint.key;
+        let (mut instruction, accounts) = pool.deposit_instruction(
+            &mut user,
+            &mut source_a,
+            &mut source_b,
+            &mut pool_tokens,
+            DepositAllTokenTypes {
+                pool_token_amount: 1_000_000,
+                maximum_token_a_amount: 1_000,
+                maximum_token_b_amount: 1_000,
+            },
+        );
+        assert_eq!(instruction.accounts[8].pubkey, pool_mint);
+        instruction.accounts[8].is_writable = false;
+        assert_eq!(
+            do_process_instruction(instruction.clone(), accounts),
+            Err(SwapError::AccountNotWritable.into())
+        );
+        instruction.accounts[8].is_writable = true;
+        let (_, accounts) = pool.deposit_instruction(
+            &mut user,
+            &mut source_a,
+            &mut source_b,
+            &mut pool_tokens,
+            DepositAllTokenTypes {
+                pool_token_amount: 1_000_000,
+                maximum_token_a_amount: 1_000,
+                maximum_token_b_amount: 1_000,
+            },
+        );
+        do_process_instruction(instruction, accounts).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 1_000_000);
+
+        let mut destination_a = TestAccount::token(&pool.token_a_mint, &user.key, 0);
+        let mut destination_b = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+        let mut instruction = withdraw_all_token_types(
+            &crate::id(),
+            &spl_token::id(),
+            &pool.swap.key,
+            &pool.authority.key,
+            &user.key,
+            &pool.pool_mint.key,
+            &pool_tokens.key,
+            &pool.token_a.key,
+            &pool.token_b.key,
+            &destination_a.key,
+            &destination_b.key,
+            WithdrawAllTokenTypes {
+                pool_token_amount: 1_000_000,
+                minimum_token_a_amount: 0,
+                minimum_token_b_amount: 0,
+                deadline: 0,
+            },
+            &pool.swap_state(),
+        )
+        .unwrap();
+        assert_eq!(instruction.accounts[4].pubkey, pool.pool_mint.key);
+        instruction.accounts[4].is_writable = false;
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut pool.swap,
+                    &mut pool.authority,
+                    &mut pool.global_state,
+                    &mut user,
+                    &mut pool.pool_mint,
+                    &mut pool_tokens,
+                    &mut pool.token_a,
+                    &mut pool.token_b,
+                    &mut destination_a,
+                    &mut destination_b,
+                    &mut pool.token_program,
+                ],
+            ),
+            Err(SwapError::AccountNotWritable.into())
+        );
+        pool.withdraw_to(
+            &mut user,
+            &mut pool_tokens,
+            &mut destination_a,
+            &mut destination_b,
+            1_000_000,
+        )
+        .unwrap();
+        assert_eq!(pool_tokens.token_amount(), 0);
+    }
+
+    #[test]
+    fn initialize_seeds_pools_with_existing_liquidity() {
+        // migrated constant product liquidity mints sqrt(a * b)
+        let pool = TestPool::constant_product(4_000_000_000_000, 1_000_000_000_000, 0);
+        let (result, _, destination) = initialize_test_pool(pool, Some);
+        result.unwrap();
+        assert_eq!(destination.token_amount(), 2_000_000_000_000);
+        // too little of it to cover the minimum supply
+        let pool = TestPool::constant_product(100, 100, 0);
+        assert_eq!(initialize_test_pool(pool, Some).0, Err(SwapError::InvalidInput.into()));
+
+        // stable liquidity mints D, the sum of balanced reserves
+        fn initialize_stable(reserve_amount: u64) -> (ProgramResult, TestAccount) {
+            let TestPool {
+                swap,
+                mut authority,
+                mut global_state,
+                token_a_mint,
+                token_b_mint,
+                mut token_a,
+                mut token_b,
+                mut pool_mint,
+                mut token_program,
+                ..
+            } = TestPool::constant_product(reserve_amount, reserve_amount, 0);
+            let mut swap =
+                TestAccount::new(swap.key, crate::id(), vec![0u8; SwapVersion::LATEST_LEN]);
+            let mut destination = TestAccount::token(&pool_mint.key, &Pubkey::new_unique(), 0);
+            let mut rent = TestAccount::rent();
+            let mut mint_a = TestAccount {
+                key: token_a_mint,
+                ..TestAccount::mint(&Pubkey::new_unique(), 0, 6)
+            };
+            let mut mint_b = TestAccount {
+                key: token_b_mint,
+                ..TestAccount::mint(&Pubkey::new_unique(), 0, 6)
+            };
+            let result = do_process_instruction(
+                initialize(
+                    &crate::id(),
+                    &spl_token::id(),
+                    &swap.key,
+                    &authority.key,
+                    &token_a.key,
+                    &token_b.key,
+                    &pool_mint.key,
+                    &destination.key,
+                    None,
+                    Some((&token_a_mint, &token_b_mint)),
+                    SwapCurve {
+                        curve_type: CurveType::Stable,
+                        calculator: Box::new(StableCurve {
+                            amp: 100,
+                            min_trade_amount: 0,
+                            token_a_decimals: 6,
+                            token_b_decimals: 6,
+                        }),
+                    },
+                    0,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap(),
+                vec![
+                    &mut swap,
+                    &mut authority,
+                    &mut global_state,
+                    &mut token_a,
+                    &mut token_b,
+                    &mut pool_mint,
+                    &mut destination,
+                    &mut token_program,
+                    &mut rent,
+                    &mut mint_a,
+                    &mut mint_b,
+                ],
+            );
+            (result, destination)
+        }
+        let (result, destination) = initialize_stable(1_000_000_000_000);
+        result.unwrap();
+        assert_eq!(destination.token_amount(), 2_000_000_000_000);
+        assert_eq!(initialize_stable(10).0, Err(SwapError::InvalidInput.into()));
+    }
+
+    #[test]
+    fn swap_at_the_curve_minimum_trade_amount() {
+        let test_swap = |amount_in| Swap {
+            amount_in,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let mut pool = TestPool::new(
+            SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Box::new(StableCurve {
+                    amp: 100,
+                    min_trade_amount: 1_000,
+                    token_a_decimals: 0,
+                    token_b_decimals: 0,
+                }),
+            },
+            1_000_000,
+            1_000_000,
+            1_000_000_000,
+        );
+        assert_eq!(pool.swap_a_to_b(test_swap(999)), Err(SwapError::InvalidInput.into()));
+        assert_eq!(pool.swap_b_to_a(test_swap(999)), Err(SwapError::InvalidInput.into()));
+        assert_eq!(pool.swap_a_to_b(test_swap(1_000)), Ok(1_000));
+        assert_eq!(pool.swap_b_to_a(test_swap(1_000)), Ok(1_000));
+
+        // constant product pools have no minimum
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        assert!(pool.swap_a_to_b(test_swap(999)).is_ok());
+    }
+
+    #[test]
+    fn swap_rejects_fee_account_as_source_or_destination() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let test_swap = Swap {
+            amount_in: 1_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let mut user = TestAccount::signer();
+        let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 1_000);
+        let mut destination = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+
+        // the user's source also named as the fee account
+        let mut fee_account = TestAccount {
+            key: source.key,
+            ..TestAccount::token(&pool.token_a_mint, &user.key, 1_000)
+        };
+        assert_eq!(
+            pool.swap_a_to_b_with(
+                &mut user,
+                &mut source,
+                &mut destination,
+                &mut fee_account,
+                test_swap.clone(),
+            ),
+            Err(SwapError::InvalidInput.into())
+        );
+        // the user's destination also named as the fee account
+        let mut fee_account = TestAccount {
+            key: destination.key,
+            ..TestAccount::token(&pool.token_b_mint, &user.key, 0)
+        };
+        assert_eq!(
+            pool.swap_a_to_b_with(
+                &mut user,
+                &mut source,
+                &mut destination,
+                &mut fee_account,
+                test_swap.clone(),
+            ),
+            Err(SwapError::InvalidInput.into())
+        );
+
+        let mut fee_account = TestAccount::token(&pool.token_a_mint, &pool.fee_owner, 0);
+        pool.swap_a_to_b_with(&mut user, &mut source, &mut destination, &mut fee_account, test_swap)
+            .unwrap();
+        assert_eq!(source.token_amount(), 0);
+        assert!(destination.token_amount() > 0);
+        assert!(fee_account.token_amount() > 0);
+    }
+
+    #[test]
+    fn swap_authority_cannot_be_the_fee_owner() {
+        let test_swap = Swap {
+            amount_in: 1_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        // the program's own addresses are refused up front
+        for fee_owner in [crate::id(), pool.global_state.key].iter() {
+            let mut state = pool.global_state_settings();
+            state.fee_owner = *fee_owner;
+            assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidOutputOwner.into()));
+        }
+        pool.swap_a_to_b(test_swap.clone()).unwrap();
+
+        // a fee owner set to the authority of an existing pool
+        let mut state = pool.global_state_settings();
+        state.fee_owner = pool.authority.key;
+        pool.set_global_state(state).unwrap();
+        pool.fee_owner = pool.authority.key;
+        assert_eq!(pool.swap_a_to_b(test_swap), Err(SwapError::InvalidOutputOwner.into()));
+
+        // or of the pool being created
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        initialize_test_pool(pool, Some).0.unwrap();
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let mut state = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        state.fee_owner = pool.authority.key;
+        state.pack_into_slice(&mut pool.global_state.data);
+        assert_eq!(initialize_test_pool(pool, Some).0, Err(SwapError::InvalidOutputOwner.into()));
+    }
+
+    #[test]
+    fn initialize_requires_an_empty_pool_token_destination() {
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let destination = TestAccount::token(&pool.pool_mint.key, &Pubkey::new_unique(), 1);
+        assert_eq!(
+            initialize_test_pool_to(pool, Some, destination, 0).0,
+            Err(SwapError::InvalidSupply.into())
+        );
+
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let destination = TestAccount::token(&Pubkey::new_unique(), &Pubkey::new_unique(), 0);
+        assert_eq!(
+            initialize_test_pool_to(pool, Some, destination, 0).0,
+            Err(SwapError::IncorrectPoolMint.into())
+        );
+
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        let destination = TestAccount::token(&pool.pool_mint.key, &Pubkey::new_unique(), 0);
+        let (result, _, destination) = initialize_test_pool_to(pool, Some, destination, 0);
+        result.unwrap();
+        assert_eq!(destination.token_amount(), 1_000_000);
+    }
+
+    #[test]
+    fn pools_created_at_different_fee_tiers() {
+        let tier = Fees {
+            constant_product_return_fee_numerator: 100,
+            constant_product_fixed_fee_numerator: 20,
+            ..TEST_FEES
+        };
+        let mut configured = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut state = configured.global_state_settings();
+        state.fee_tiers = Some([tier.clone(), Fees::default(), Fees::default()]);
+        configured.set_global_state(state).unwrap();
+        let (global_state, fee_owner) = (configured.global_state.data, configured.fee_owner);
+
+        // the tier is recorded by initialize, unused and unknown tiers refused
+        for (fee_tier_index, result) in [
+            (0, Ok(())),
+            (1, Ok(())),
+            (2, Err(SwapError::InvalidFee.into())),
+            (4, Err(SwapError::InvalidFee.into())),
+        ]
+        .iter()
+        {
+            let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+            pool.global_state.data = global_state.clone();
+            let destination = TestAccount::token(&pool.pool_mint.key, &Pubkey::new_unique(), 0);
+            let (initialized, swap, _) =
+                initialize_test_pool_to(pool, Some, destination, *fee_tier_index);
+            assert_eq!(initialized, *result);
+            if result.is_ok() {
+                let token_swap = SwapVersion::unpack(&swap.data).unwrap();
+                assert_eq!(token_swap.fee_tier_index(), *fee_tier_index);
+            }
+        }
+
+        // swaps charge the fees of the pool's tier
+        let test_swap = Swap {
+            amount_in: 10_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let mut amounts_out = vec![];
+        for fee_tier_index in 0..2 {
+            let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+            pool.global_state.data = global_state.clone();
+            pool.fee_owner = fee_owner;
+            SwapVersion::pack(
+                SwapVersion::SwapV1(SwapV1 {
+                    fee_tier_index,
+                    ..pool.swap_state()
+                }),
+                &mut pool.swap.data,
+            )
+            .unwrap();
+            let reserve_a = pool.token_a.token_amount();
+            pool.swap_a_to_b(test_swap.clone()).unwrap();
+            // the owner fee leaves the input before it reaches the reserve
+            let owner_fee = 10_000 - (pool.token_a.token_amount() - reserve_a);
+            let fees = if fee_tier_index == 0 { &TEST_FEES } else { &tier };
+            assert_eq!(
+                owner_fee,
+                (10_000 * fees.constant_product_fixed_fee_numerator) / fees.fee_denominator
+            );
+            amounts_out.push(1_000_000 - pool.token_b.token_amount());
+        }
+        assert!(amounts_out[1] < amounts_out[0]);
+    }
+
+    #[test]
+    fn initialize_rejects_on_curve_authority_nonce() {
+        // bumps above the canonical one give on-curve addresses, take a swap
+        // whose canonical bump leaves one
+        let pool = (0..64)
+            .map(|_| TestPool::constant_product(1_000_000, 1_000_000, 0))
+            .find(|pool| find_swap_authority_address(&crate::id(), &pool.swap.key).1 < 255)
+            .unwrap();
+        let (_, canonical_nonce) = find_swap_authority_address(&crate::id(), &pool.swap.key);
+        assert!(Pubkey::create_program_address(
+            &[pool.swap.key.as_ref(), &[canonical_nonce + 1]],
+            &crate::id()
+        )
+        .is_err());
+        assert_eq!(
+            initialize_test_pool(pool, |nonce| Some(nonce + 1)).0,
+            Err(SwapError::InvalidProgramAddress.into())
+        );
+
+        // an authority account that is not the swap's
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        pool.authority.key = Pubkey::new_unique();
+        assert_eq!(
+            initialize_test_pool(pool, Some).0,
+            Err(SwapError::InvalidProgramAddress.into())
+        );
+
+        let pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+        initialize_test_pool(pool, Some).0.unwrap();
+    }
+
+    #[test]
+    fn initialize_sol_pool_from_creator_lamports() {
+        /// Initializes a pool whose token A reserve is an empty, rent exempt
+        /// account of the native mint, or a regular one holding 1_000_000
+        fn initialize_native(
+            native: bool,
+            native_amount: u64,
+        ) -> (ProgramResult, TestAccount, TestAccount, TestAccount) {
+            let TestPool {
+                swap,
+                mut authority,
+                mut global_state,
+                mut token_a,
+                mut token_b,
+                mut pool_mint,
+                mut token_program,
+                ..
+            } = TestPool::constant_product(1_000_000, 1_000_000, 0);
+            if native {
+                let rent_reserve = Rent::default().minimum_balance(TokenAccount::LEN);
+                TokenAccount {
+                    mint: spl_token::native_mint::id(),
+                    owner: authority.key,
+                    state: AccountState::Initialized,
+                    is_native: COption::Some(rent_reserve),
+                    ..TokenAccount::default()
+                }
+                .pack_into_slice(&mut token_a.data);
+                token_a.lamports = rent_reserve;
+            }
+            let mut swap =
+                TestAccount::new(swap.key, crate::id(), vec![0u8; SwapVersion::LATEST_LEN]);
+            let mut creator = TestAccount::signer();
+            let mut destination = TestAccount::token(&pool_mint.key, &creator.key, 0);
+            let mut rent = TestAccount::rent();
+            let mut system = TestAccount::new(system_program::id(), Pubkey::default(), vec![]);
+            let result = do_process_instruction(
+                initialize(
+                    &crate::id(),
+                    &spl_token::id(),
+                    &swap.key,
+                    &authority.key,
+                    &token_a.key,
+                    &token_b.key,
+                    &pool_mint.key,
+                    &destination.key,
+                    Some(&creator.key),
+                    None,
+                    SwapCurve {
+                        curve_type: CurveType::ConstantProduct,
+                        calculator: Box::new(ConstantProductCurve {}),
+                    },
+                    0,
+                    None,
+                    native_amount,
+                    None,
+                )
+                .unwrap(),
+                vec![
+                    &mut swap,
+                    &mut authority,
+                    &mut global_state,
+                    &mut token_a,
+                    &mut token_b,
+                    &mut pool_mint,
+                    &mut destination,
+                    &mut token_program,
+                    &mut rent,
+                    &mut creator,
+                    &mut system,
+                ],
+            );
+            (result, token_a, creator, destination)
+        }
+
+        let (result, token_a, creator, destination) = initialize_native(true, 1_000_000);
+        result.unwrap();
+        assert_eq!(token_a.token_amount(), 1_000_000);
+        assert_eq!(
+            token_a.lamports,
+            Rent::default().minimum_balance(TokenAccount::LEN) + 1_000_000
+        );
+        assert_eq!(creator.lamports, TestAccount::signer().lamports - 1_000_000);
+        // sqrt(1_000_000 * 1_000_000)
+        assert_eq!(destination.token_amount(), 1_000_000);
+
+        // lamports only wrap into a reserve of the native mint
+        assert_eq!(
+            initialize_native(false, 1_000_000).0,
+            Err(SwapError::InvalidNativeAmount.into())
+        );
+    }
+
+    #[test]
+    fn deposit_at_and_above_max_lp_per_deposit() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let mut state = pool.global_state_settings();
+        state.max_lp_per_deposit = Some(10_000_000);
+        pool.set_global_state(state).unwrap();
+
+        assert_eq!(
+            pool.deposit(10_000_001, 10_001, 10_001).map(|_| ()),
+            Err(SwapError::DepositTooLarge.into())
+        );
+        let pool_tokens = pool.deposit(10_000_000, 10_000, 10_000).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 10_000_000);
+
+        // zero lifts the limit
+        let mut state = pool.global_state_settings();
+        state.max_lp_per_deposit = Some(0);
+        pool.set_global_state(state).unwrap();
+        let pool_tokens = pool.deposit(100_000_000, 100_000, 100_000).unwrap();
+        assert_eq!(pool_tokens.token_amount(), 100_000_000);
+    }
+
+    #[test]
+    fn zap_token_a_into_balanced_pool() {
+        /// Zaps 20_000 of token A into a fresh balanced pool, returns the
+        /// result along with the user's pool token and refund accounts
+        fn zap(
+            minimum_pool_token_amount: u64,
+        ) -> (ProgramResult, TestPool, TestAccount, TestAccount) {
+            let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+            let mut user = TestAccount::signer();
+            let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 20_000);
+            let mut refund = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+            let mut destination = TestAccount::token(&pool.pool_mint.key, &user.key, 0);
+            let mut fee_account = TestAccount::token(&pool.token_a_mint, &pool.fee_owner, 0);
+            let result = pool.zap_deposit(
+                &mut user,
+                &mut source,
+                &mut refund,
+                &mut destination,
+                &mut fee_account,
+                ZapDeposit {
+                    source_amount: 20_000,
+                    minimum_pool_token_amount,
+                },
+            );
+            (result, pool, destination, refund)
+        }
+
+        let (result, pool, destination, refund) = zap(0);
+        result.unwrap();
+        let minted = destination.token_amount();
+        // after paying the swap fees, the position is worth a little less
+        // than the 10_000 of each token a balanced deposit would bring
+        assert!(minted > 9_800_000 && minted < 10_000_000);
+        assert_eq!(Mint::unpack(&pool.pool_mint.data).unwrap().supply, 1_000_000_000 + minted);
+        // swapping half moves the price, so a little of the token B bought
+        // does not fit the new ratio and is refunded
+        let refund_amount = refund.token_amount();
+        assert!(refund_amount > 0 && refund_amount < 100);
+
+        // the floor is checked against the pool tokens minted
+        assert_eq!(zap(minted + 1).0, Err(SwapError::ExceededSlippage.into()));
+        zap(minted).0.unwrap();
+    }
+
+    #[test]
+    fn admin_operations_reject_a_default_global_state_owner() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut state = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        state.owner = Pubkey::default();
+        state.pack_into_slice(&mut pool.global_state.data);
+        // signing as the default key must not pass for the owner
+        pool.owner = TestAccount::new(Pubkey::default(), system_program::id(), vec![]);
+
+        let settings = pool.global_state_settings();
+        assert_eq!(pool.set_global_state(settings), Err(SwapError::InvalidProgramOwner.into()));
+
+        let mut owner = TestAccount::new(Pubkey::default(), system_program::id(), vec![]);
+        let stray_mint = Pubkey::new_unique();
+        let mut stray = TestAccount::token(&stray_mint, &pool.authority.key, 100);
+        let mut destination = TestAccount::token(&stray_mint, &owner.key, 0);
+        assert_eq!(
+            pool.rescue_tokens(&mut owner, &mut stray, &mut destination, stray_mint),
+            Err(SwapError::InvalidProgramOwner.into())
+        );
+
+        let metadata_key = find_pool_metadata_address(&crate::id(), &pool.swap.key).0;
+        let mut metadata = TestAccount::new(
+            metadata_key,
+            crate::id(),
+            vec![0u8; PoolMetadata::get_packed_len()],
+        );
+        assert_eq!(
+            do_process_instruction(
+                set_pool_metadata(
+                    &crate::id(),
+                    &pool.swap.key,
+                    &owner.key,
+                    "Pool".to_string(),
+                    "POOL".to_string(),
+                )
+                .unwrap(),
+                vec![
+                    &mut metadata,
+                    &mut pool.swap,
+                    &mut pool.global_state,
+                    &mut owner,
+                    &mut TestAccount::new(system_program::id(), Pubkey::default(), vec![]),
+                    &mut TestAccount::rent(),
+                    &mut TestAccount::instructions(&crate::id()),
+                ],
+            ),
+            Err(SwapError::InvalidProgramOwner.into())
+        );
+    }
+
+    #[test]
+    fn set_global_state_refuses_a_default_owner() {
+        let mut pool = TestPool::constant_product(1_000, 1_000, 1_000_000_000);
+        let mut state = pool.global_state_settings();
+        state.owner = Pubkey::default();
+        assert_eq!(pool.set_global_state(state), Err(SwapError::InvalidProgramOwner.into()));
+
+        let new_owner = Pubkey::new_unique();
+        let mut state = pool.global_state_settings();
+        state.owner = new_owner;
+        pool.set_global_state(state).unwrap();
+        let stored = GlobalState::unpack_from_slice(&pool.global_state.data).unwrap();
+        assert_eq!(stored.owner, new_owner);
+    }
+
+    #[test]
+    fn swap_checks_the_source_balance() {
+        let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 1_000_000_000);
+        let test_swap = Swap {
+            amount_in: 1_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        };
+        let mut user = TestAccount::signer();
+        let mut destination = TestAccount::token(&pool.token_b_mint, &user.key, 0);
+        let mut fee_account = TestAccount::token(&pool.token_a_mint, &pool.fee_owner, 0);
+
+        // one short of the amount to swap
+        let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 999);
+        assert_eq!(
+            pool.swap_a_to_b_with(
+                &mut user,
+                &mut source,
+                &mut destination,
+                &mut fee_account,
+                test_swap.clone(),
+            ),
+            Err(SwapError::InsufficientFunds.into())
+        );
+        assert_eq!(source.token_amount(), 999);
+
+        let mut source = TestAccount::token(&pool.token_a_mint, &user.key, 1_000);
+        pool.swap_a_to_b_with(&mut user, &mut source, &mut destination, &mut fee_account, test_swap)
+            .unwrap();
+        assert_eq!(source.token_amount(), 0);
+        assert!(destination.token_amount() > 0);
+    }
+
+    #[test]
+    fn initialize_with_allowed_lp_decimals() {
+        let with_decimals = |allowed_lp_decimals, decimals| {
+            let mut pool = TestPool::constant_product(1_000_000, 1_000_000, 0);
+            let mut state = pool.global_state_settings();
+            state.allowed_lp_decimals = Some(allowed_lp_decimals);
+            pool.set_global_state(state).unwrap();
+            pool.pool_mint = TestAccount {
+                key: pool.pool_mint.key,
+                ..TestAccount::mint(&pool.authority.key, 0, decimals)
+            };
+            initialize_test_pool(pool, Some)
+        };
+
+        // without a mask only lp_decimals is allowed
+        with_decimals(0, 9).0.unwrap();
+        assert_eq!(with_decimals(0, 6).0, Err(SwapError::MismatchDecimalValidation.into()));
+
+        let allowed = 1 << 6 | 1 << 8;
+        for decimals in [6, 8, 9] {
+            let (result, swap, _) = with_decimals(allowed, decimals);
+            result.unwrap();
+            let token_swap = SwapV1::unpack_from_slice(&swap.data[1..]).unwrap();
+            assert_eq!(token_swap.pool_mint_decimals, Some(decimals));
+        }
+        assert_eq!(with_decimals(allowed, 7).0, Err(SwapError::MismatchDecimalValidation.into()));
+    }
 }
\ No newline at end of file
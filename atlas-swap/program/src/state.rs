@@ -1,15 +1,20 @@
 //! State transition types
 
-use crate::curve::{base::{SwapCurve}, fees::Fees};
+use crate::constraints::{
+    FEE_SNAPSHOT_CAPACITY, FEE_TIER_COUNT, MAX_POOL_NAME_LEN, MAX_POOL_SYMBOL_LEN, MIN_LP_SUPPLY,
+};
+use crate::curve::{base::{CurveType, SwapCurve}, fees::{DynamicFee, Fees}};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use enum_dispatch::enum_dispatch;
 use solana_program::{
+    clock::{Epoch, UnixTimestamp},
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 
 };
 use crate::error::SwapError;
+use std::convert::TryFrom;
 
 /// Trait representing access to program state across all versions
 #[enum_dispatch]
@@ -31,8 +36,30 @@ pub trait SwapState {
     fn token_a_mint(&self) -> &Pubkey;
     /// Address of token B mint
     fn token_b_mint(&self) -> &Pubkey;
-    ///
+    /// Curve of the swap
     fn swap_curve(&self) -> &SwapCurve;
+    /// Type of the swap curve, cheap to query for routing decisions
+    fn curve_type(&self) -> CurveType;
+    /// Decimals of the pool mint recorded at initialization, `None` for pools
+    /// created before they were recorded
+    fn pool_mint_decimals(&self) -> Option<u8>;
+    /// Pool tokens a withdrawal must leave in the pool, `MIN_LP_SUPPLY`
+    /// unless the pool was created with its own floor
+    fn min_lp_supply(&self) -> u128;
+    /// Fee tier picked at initialization, zero for the global fees
+    fn fee_tier_index(&self) -> u8;
+    /// Token program of the token A reserve, the pool's token program
+    /// unless the reserve was created under another one
+    fn token_a_program_id(&self) -> &Pubkey;
+    /// Token program of the token B reserve, the pool's token program
+    /// unless the reserve was created under another one
+    fn token_b_program_id(&self) -> &Pubkey;
+    /// Fees paid to the fee owner in token A since the pool started
+    /// counting them, zero for legacy pools
+    fn cumulative_fees_a(&self) -> u128;
+    /// Fees paid to the fee owner in token B since the pool started
+    /// counting them, zero for legacy pools
+    fn cumulative_fees_b(&self) -> u128;
 }
 
 
@@ -67,7 +94,32 @@ impl SwapVersion {
             .split_first()
             .ok_or(ProgramError::InvalidAccountData)?;
         match version {
-            1 => Ok(Box::new(SwapV1::unpack(rest)?)),
+            1 => {
+                // accounts sized for an older, shorter layout are accepted and
+                // zero-extended by unpack_from_slice, so no exact length check
+                let swap = SwapV1::unpack_from_slice(rest)?;
+                if !swap.is_initialized {
+                    return Err(ProgramError::UninitializedAccount);
+                }
+                Ok(Box::new(swap))
+            }
+            _ => Err(ProgramError::UninitializedAccount),
+        }
+    }
+
+    /// Read only the curve type of a swap account, without unpacking the
+    /// rest of the state.  Useful for clients filtering pools by curve.
+    pub fn unpack_curve_type(input: &[u8]) -> Result<CurveType, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        match version {
+            1 => {
+                let curve_type = rest
+                    .get(SwapV1::CURVE_TYPE_OFFSET)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+                CurveType::try_from(*curve_type)
+            }
             _ => Err(ProgramError::UninitializedAccount),
         }
     }
@@ -114,7 +166,32 @@ pub struct SwapV1 {
 
     ///Curve Type to swap
     pub swap_curve: SwapCurve,
-    
+
+    /// Decimals of the pool mint at initialization, `None` for legacy pools,
+    /// whose accounts end before the field
+    pub pool_mint_decimals: Option<u8>,
+
+    /// Pool tokens a withdrawal must leave in the pool, `None` for the
+    /// default `MIN_LP_SUPPLY`, which legacy pools always use
+    pub min_lp_supply: Option<u64>,
+
+    /// Fee tier of the global state the pool charges, zero for the global
+    /// fees, which legacy pools always use
+    pub fee_tier_index: u8,
+
+    /// Token program owning the token A reserve, zero for legacy pools,
+    /// whose reserves belong to `token_program_id`
+    pub token_a_program_id: Pubkey,
+    /// Token program owning the token B reserve, zero for legacy pools,
+    /// whose reserves belong to `token_program_id`
+    pub token_b_program_id: Pubkey,
+
+    /// Fees paid to the fee owner in token A, only counted by accounts
+    /// allocated with the latest layout
+    pub cumulative_fees_a: u128,
+    /// Fees paid to the fee owner in token B, only counted by accounts
+    /// allocated with the latest layout
+    pub cumulative_fees_b: u128,
 }
 
 impl SwapState for SwapV1 {
@@ -154,6 +231,57 @@ impl SwapState for SwapV1 {
         &self.swap_curve
     }
 
+    fn curve_type(&self) -> CurveType {
+        self.swap_curve.curve_type
+    }
+
+    fn pool_mint_decimals(&self) -> Option<u8> {
+        self.pool_mint_decimals
+    }
+
+    fn min_lp_supply(&self) -> u128 {
+        self.min_lp_supply
+            .map(u128::from)
+            .unwrap_or(MIN_LP_SUPPLY)
+    }
+
+    fn fee_tier_index(&self) -> u8 {
+        self.fee_tier_index
+    }
+
+    fn token_a_program_id(&self) -> &Pubkey {
+        if self.token_a_program_id == Pubkey::default() {
+            &self.token_program_id
+        } else {
+            &self.token_a_program_id
+        }
+    }
+
+    fn token_b_program_id(&self) -> &Pubkey {
+        if self.token_b_program_id == Pubkey::default() {
+            &self.token_program_id
+        } else {
+            &self.token_b_program_id
+        }
+    }
+
+    fn cumulative_fees_a(&self) -> u128 {
+        self.cumulative_fees_a
+    }
+
+    fn cumulative_fees_b(&self) -> u128 {
+        self.cumulative_fees_b
+    }
+}
+
+impl SwapV1 {
+    /// Offset of the curve type byte, the first byte of the packed swap curve
+    pub const CURVE_TYPE_OFFSET: usize = 194;
+
+    /// Size of the oldest supported layout.  Fields appended after it are
+    /// read as zero from accounts allocated with a smaller size, so new
+    /// fields must treat zero as their legacy default.
+    pub const MIN_LEN: usize = 227;
 }
 
 impl Sealed for SwapV1 {}
@@ -164,7 +292,7 @@ impl IsInitialized for SwapV1 {
 }
 
 impl Pack for SwapV1 {
-    const LEN: usize = 227;
+    const LEN: usize = 335;
 
     fn pack_into_slice(&self, output: &mut [u8]) {
         let output = array_mut_ref![output, 0, SwapV1::LEN];
@@ -178,7 +306,14 @@ impl Pack for SwapV1 {
             token_a_mint,
             token_b_mint,
             swap_curve,
-        ) = mut_array_refs![output, 1, 1, 32, 32, 32, 32, 32, 32, 33];
+            pool_mint_decimals,
+            min_lp_supply,
+            fee_tier_index,
+            token_a_program_id,
+            token_b_program_id,
+            cumulative_fees_a,
+            cumulative_fees_b,
+        ) = mut_array_refs![output, 1, 1, 32, 32, 32, 32, 32, 32, 33, 2, 9, 1, 32, 32, 16, 16];
         is_initialized[0] = self.is_initialized as u8;
         nonce[0] = self.nonce;
         token_program_id.copy_from_slice(self.token_program_id.as_ref());
@@ -188,14 +323,37 @@ impl Pack for SwapV1 {
         token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
         token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
         self.swap_curve.pack_into_slice(&mut swap_curve[..]);
+        let (has_pool_mint_decimals, pool_mint_decimals) =
+            mut_array_refs![pool_mint_decimals, 1, 1];
+        has_pool_mint_decimals[0] = self.pool_mint_decimals.is_some() as u8;
+        pool_mint_decimals[0] = self.pool_mint_decimals.unwrap_or(0);
+        let (has_min_lp_supply, min_lp_supply) = mut_array_refs![min_lp_supply, 1, 8];
+        match self.min_lp_supply {
+            Some(value) => {
+                has_min_lp_supply[0] = 1;
+                *min_lp_supply = value.to_le_bytes();
+            }
+            None => {
+                has_min_lp_supply[0] = 0;
+                *min_lp_supply = [0u8; 8];
+            }
+        }
+        fee_tier_index[0] = self.fee_tier_index;
+        token_a_program_id.copy_from_slice(self.token_a_program_id.as_ref());
+        token_b_program_id.copy_from_slice(self.token_b_program_id.as_ref());
+        *cumulative_fees_a = self.cumulative_fees_a.to_le_bytes();
+        *cumulative_fees_b = self.cumulative_fees_b.to_le_bytes();
     }
 
     /// Unpacks a byte buffer into a [SwapV1](struct.SwapV1.html).
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
-        if input.len() < Self::LEN {
-            return Err(ProgramError::MaxSeedLengthExceeded);
+        if input.len() < Self::MIN_LEN {
+            return Err(ProgramError::AccountDataTooSmall);
         }
-        let input = array_ref![input, 0, SwapV1::LEN];
+        let mut padded = [0u8; SwapV1::LEN];
+        let len = std::cmp::min(input.len(), SwapV1::LEN);
+        padded[..len].copy_from_slice(&input[..len]);
+        let input = &padded;
         #[allow(clippy::ptr_offset_with_cast)]
         let (
             is_initialized,
@@ -207,7 +365,16 @@ impl Pack for SwapV1 {
             token_a_mint,
             token_b_mint,
             swap_curve,
-        ) = array_refs![input, 1, 1, 32, 32, 32, 32, 32, 32, 33];
+            has_pool_mint_decimals,
+            pool_mint_decimals,
+            has_min_lp_supply,
+            min_lp_supply,
+            fee_tier_index,
+            token_a_program_id,
+            token_b_program_id,
+            cumulative_fees_a,
+            cumulative_fees_b,
+        ) = array_refs![input, 1, 1, 32, 32, 32, 32, 32, 32, 33, 1, 1, 1, 8, 1, 32, 32, 16, 16];
         Ok(Self {
             is_initialized: match is_initialized {
                 [0] => false,
@@ -222,6 +389,21 @@ impl Pack for SwapV1 {
             token_a_mint: Pubkey::new_from_array(*token_a_mint),
             token_b_mint: Pubkey::new_from_array(*token_b_mint),
             swap_curve: SwapCurve::unpack_from_slice(swap_curve)?,
+            pool_mint_decimals: match has_pool_mint_decimals {
+                [0] => None,
+                [1] => Some(pool_mint_decimals[0]),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            min_lp_supply: match has_min_lp_supply {
+                [0] => None,
+                [1] => Some(u64::from_le_bytes(*min_lp_supply)),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            fee_tier_index: fee_tier_index[0],
+            token_a_program_id: Pubkey::new_from_array(*token_a_program_id),
+            token_b_program_id: Pubkey::new_from_array(*token_b_program_id),
+            cumulative_fees_a: u128::from_le_bytes(*cumulative_fees_a),
+            cumulative_fees_b: u128::from_le_bytes(*cumulative_fees_b),
         })
     }
 }
@@ -239,7 +421,8 @@ pub struct GlobalState {
     /// Fee owner address
     pub fee_owner: Pubkey,
 
-    /// initial lp supply
+    /// initial lp supply, in base units of the lp token.  Must be at least one
+    /// whole lp token, ie. `10^lp_decimals`, and at least `MIN_LP_SUPPLY`
     pub initial_supply: u64,
 
     /// lp token's decimals
@@ -247,11 +430,48 @@ pub struct GlobalState {
 
     ///Fee ratio
     pub fees: Fees,
+
+    /// maximum amount of token A a pool may hold after a deposit, zero
+    /// disables the cap
+    pub max_reserve_a: u64,
+
+    /// maximum amount of token B a pool may hold after a deposit, zero
+    /// disables the cap
+    pub max_reserve_b: u64,
+
+    /// start of the fee holiday, a unix timestamp
+    pub fee_holiday_start: UnixTimestamp,
+
+    /// end of the fee holiday, a unix timestamp, both zero disables it
+    pub fee_holiday_end: UnixTimestamp,
+
+    /// stable return fee numerator of a balanced stable pool when the
+    /// dynamic fee is enabled
+    pub dynamic_fee_min_numerator: u64,
+
+    /// stable return fee numerator of a fully imbalanced stable pool, zero
+    /// disables the dynamic fee
+    pub dynamic_fee_max_numerator: u64,
+
+    /// fees a pool can pick instead of `fees` at creation, tier `i` is
+    /// `fee_tiers[i - 1]`, all zero marks an unused tier
+    pub fee_tiers: [Fees; FEE_TIER_COUNT],
+
+    /// maximum amount of pool tokens a single deposit may mint, zero
+    /// disables the limit
+    pub max_lp_per_deposit: u64,
+
+    /// decimals a pool mint may have besides `lp_decimals`, bit `d` allows
+    /// `d` decimals
+    pub allowed_lp_decimals: u32,
 }
+/// Packed length of the global state fee tiers
+const FEE_TIERS_LEN: usize = FEE_TIER_COUNT * Fees::LEN;
+
 impl Sealed for GlobalState {}
 impl Pack for GlobalState{
     /// Size of the Program State
-    const LEN:usize = 114; // add one for the version enum
+    const LEN:usize = 294;
 
     /// Pack a swap into a byte array, based on its version
     fn pack_into_slice(&self, output: &mut [u8]) {
@@ -263,21 +483,44 @@ impl Pack for GlobalState{
             initial_supply,
             lp_decimals,
             fees,
-        ) = mut_array_refs![output, 1, 32, 32, 8, 1, 40];
+            max_reserve_a,
+            max_reserve_b,
+            fee_holiday_start,
+            fee_holiday_end,
+            dynamic_fee_min_numerator,
+            dynamic_fee_max_numerator,
+            fee_tiers,
+            max_lp_per_deposit,
+            allowed_lp_decimals,
+        ) = mut_array_refs![output, 1, 32, 32, 8, 1, 40, 8, 8, 8, 8, 8, 8, FEE_TIERS_LEN, 8, 4];
         is_initialized[0] = self.is_initialized as u8;
         state_owner.copy_from_slice(self.owner.as_ref());
         fee_owner.copy_from_slice(self.fee_owner.as_ref());
         *initial_supply = self.initial_supply.to_le_bytes();
-        lp_decimals[0] = self.lp_decimals as u8;
+        lp_decimals[0] = self.lp_decimals;
         self.fees.pack_into_slice(&mut fees[..]);
+        *max_reserve_a = self.max_reserve_a.to_le_bytes();
+        *max_reserve_b = self.max_reserve_b.to_le_bytes();
+        *fee_holiday_start = self.fee_holiday_start.to_le_bytes();
+        *fee_holiday_end = self.fee_holiday_end.to_le_bytes();
+        *dynamic_fee_min_numerator = self.dynamic_fee_min_numerator.to_le_bytes();
+        *dynamic_fee_max_numerator = self.dynamic_fee_max_numerator.to_le_bytes();
+        for (tier, output) in self.fee_tiers.iter().zip(fee_tiers.chunks_mut(Fees::LEN)) {
+            tier.pack_into_slice(output);
+        }
+        *max_lp_per_deposit = self.max_lp_per_deposit.to_le_bytes();
+        *allowed_lp_decimals = self.allowed_lp_decimals.to_le_bytes();
     }
 
     /// Unpacks a byte buffer into a [SwapV1](struct.SwapV1.html).
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
-        if input.len() != GlobalState::LEN{
-            return Err(SwapError::InvalidInstruction.into());    
+        if input.len() < GlobalState::MIN_LEN {
+            return Err(ProgramError::AccountDataTooSmall);
         }
-        let input = array_ref![input, 0, GlobalState::LEN];
+        let mut padded = [0u8; GlobalState::LEN];
+        let len = std::cmp::min(input.len(), GlobalState::LEN);
+        padded[..len].copy_from_slice(&input[..len]);
+        let input = &padded;
         #[allow(clippy::ptr_offset_with_cast)]
         let (
             is_initialized,
@@ -286,7 +529,20 @@ impl Pack for GlobalState{
             initial_supply,
             lp_decimals,
             fees,
-        ) = array_refs![input, 1, 32, 32, 8, 1, 40];
+            max_reserve_a,
+            max_reserve_b,
+            fee_holiday_start,
+            fee_holiday_end,
+            dynamic_fee_min_numerator,
+            dynamic_fee_max_numerator,
+            fee_tiers,
+            max_lp_per_deposit,
+            allowed_lp_decimals,
+        ) = array_refs![input, 1, 32, 32, 8, 1, 40, 8, 8, 8, 8, 8, 8, FEE_TIERS_LEN, 8, 4];
+        let mut tiers = <[Fees; FEE_TIER_COUNT]>::default();
+        for (tier, input) in tiers.iter_mut().zip(fee_tiers.chunks(Fees::LEN)) {
+            *tier = Fees::unpack_from_slice(input)?;
+        }
         Ok(Self {
             is_initialized: match is_initialized {
                 [0] => false,
@@ -298,14 +554,61 @@ impl Pack for GlobalState{
             initial_supply:u64::from_le_bytes(*initial_supply),
             lp_decimals:lp_decimals[0],
             fees: Fees::unpack_from_slice(fees)?,
+            max_reserve_a: u64::from_le_bytes(*max_reserve_a),
+            max_reserve_b: u64::from_le_bytes(*max_reserve_b),
+            fee_holiday_start: i64::from_le_bytes(*fee_holiday_start),
+            fee_holiday_end: i64::from_le_bytes(*fee_holiday_end),
+            dynamic_fee_min_numerator: u64::from_le_bytes(*dynamic_fee_min_numerator),
+            dynamic_fee_max_numerator: u64::from_le_bytes(*dynamic_fee_max_numerator),
+            fee_tiers: tiers,
+            max_lp_per_deposit: u64::from_le_bytes(*max_lp_per_deposit),
+            allowed_lp_decimals: u32::from_le_bytes(*allowed_lp_decimals),
         })
     }
 }
 
 impl GlobalState{
+    /// Size of the oldest supported layout, which ends with `fees`.  Fields
+    /// appended after it are read as zero from accounts allocated with a
+    /// smaller size, so new fields must treat zero as their legacy default.
+    pub const MIN_LEN: usize = 114;
+
+    /// Pack the state into its account, which may have been allocated for an
+    /// older, shorter layout.  Such an account can only take the state while
+    /// the fields it has no room for are zero, ie. disabled, and fails with
+    /// `AccountDataTooSmall` otherwise.
+    pub fn pack_into_account_data(&self, output: &mut [u8]) -> Result<(), ProgramError> {
+        if output.len() < Self::MIN_LEN {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let mut packed = [0u8; GlobalState::LEN];
+        self.pack_into_slice(&mut packed);
+        let len = std::cmp::min(output.len(), GlobalState::LEN);
+        if packed[len..].iter().any(|byte| *byte != 0) {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        output[..len].copy_from_slice(&packed[..len]);
+        Ok(())
+    }
+
+    /// Read the global state from the data of its account, for off-chain
+    /// consumers.  Data too short for a packed global state fails with
+    /// `InvalidInstruction`, and an account that was never set with
+    /// `NotInitializedState`.
+    pub fn try_from_account_data(data: &[u8]) -> Result<Self, SwapError> {
+        if data.len() < Self::MIN_LEN {
+            return Err(SwapError::InvalidInstruction);
+        }
+        let state = Self::unpack_from_slice(data).map_err(|_| SwapError::InvalidInstruction)?;
+        if !state.is_initialized() {
+            return Err(SwapError::NotInitializedState);
+        }
+        Ok(state)
+    }
+
     /// is program account initialized
     pub fn is_initialized(&self) -> bool {
-        return self.is_initialized
+        self.is_initialized
     }
     /// state owner to change current program state
     pub fn owner(&self) -> &Pubkey {
@@ -326,9 +629,747 @@ impl GlobalState{
     pub fn lp_decimals(&self) -> u8 {
         self.lp_decimals
     }
+
+    /// Can a pool mint with the given decimals be used to create a pool
+    pub fn allows_lp_decimals(&self, decimals: u8) -> bool {
+        decimals == self.lp_decimals
+            || 1u32
+                .checked_shl(u32::from(decimals))
+                .map_or(false, |bit| self.allowed_lp_decimals & bit != 0)
+    }
     
     /// fees redistributed
     pub fn fees(&self) -> &Fees {
         &self.fees
     }
+
+    /// Is `now` within the fee holiday window, start included and end
+    /// excluded
+    pub fn is_fee_holiday(&self, now: UnixTimestamp) -> bool {
+        (self.fee_holiday_start != 0 || self.fee_holiday_end != 0)
+            && self.fee_holiday_start <= now
+            && now < self.fee_holiday_end
+    }
+
+    /// fees of the given tier, `None` for a tier that does not exist or is
+    /// unused
+    pub fn fee_tier(&self, fee_tier_index: u8) -> Option<&Fees> {
+        match fee_tier_index {
+            0 => Some(&self.fees),
+            index => self
+                .fee_tiers
+                .get(usize::from(index) - 1)
+                .filter(|fees| **fees != Fees::default()),
+        }
+    }
+
+    /// fees charged on a swap at `now` by a pool of the given tier, nothing
+    /// during a fee holiday
+    pub fn fees_at(&self, now: UnixTimestamp, fee_tier_index: u8) -> Option<Fees> {
+        let fees = self.fee_tier(fee_tier_index)?;
+        if self.is_fee_holiday(now) {
+            Some(Fees {
+                fee_denominator: fees.fee_denominator,
+                ..Fees::default()
+            })
+        } else {
+            Some(fees.clone())
+        }
+    }
+
+    /// Is the dynamic stable fee enabled
+    pub fn has_dynamic_fee(&self) -> bool {
+        self.dynamic_fee_max_numerator != 0
+    }
+
+    /// dynamic stable fee charged on a swap at `now`, `None` when it is
+    /// disabled or during a fee holiday
+    pub fn dynamic_fee_at(&self, now: UnixTimestamp) -> Option<DynamicFee> {
+        if !self.has_dynamic_fee() || self.is_fee_holiday(now) {
+            return None;
+        }
+        Some(DynamicFee {
+            min_numerator: self.dynamic_fee_min_numerator,
+            max_numerator: self.dynamic_fee_max_numerator,
+        })
+    }
+
+    /// Checks that a deposit minting `pool_token_amount` stays within the
+    /// per-deposit limit
+    pub fn check_deposit_limit(&self, pool_token_amount: u128) -> Result<(), SwapError> {
+        if self.max_lp_per_deposit != 0 && pool_token_amount > u128::from(self.max_lp_per_deposit) {
+            return Err(SwapError::DepositTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Checks that depositing the given amounts keeps both reserves within
+    /// the pool size cap
+    pub fn check_reserve_cap(
+        &self,
+        swap_token_a_amount: u64,
+        token_a_amount: u64,
+        swap_token_b_amount: u64,
+        token_b_amount: u64,
+    ) -> Result<(), SwapError> {
+        let exceeds = |cap: u64, reserve: u64, amount: u64| {
+            cap != 0 && reserve.checked_add(amount).map_or(true, |total| total > cap)
+        };
+        if exceeds(self.max_reserve_a, swap_token_a_amount, token_a_amount)
+            || exceeds(self.max_reserve_b, swap_token_b_amount, token_b_amount)
+        {
+            return Err(SwapError::PoolCapExceeded);
+        }
+        Ok(())
+    }
+}
+
+///Pool Metadata, human-readable name and symbol of a pool for display
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct PoolMetadata {
+    /// Initialized state.
+    pub is_initialized:bool,
+
+    /// swap account the metadata describes
+    pub swap: Pubkey,
+
+    /// display name, at most `MAX_POOL_NAME_LEN` bytes of UTF-8
+    pub name: String,
+
+    /// display symbol, at most `MAX_POOL_SYMBOL_LEN` bytes of UTF-8
+    pub symbol: String,
+}
+impl Sealed for PoolMetadata {}
+impl Pack for PoolMetadata{
+    /// Size of the Pool Metadata, the name and symbol are stored as a length
+    /// byte followed by zero padded bytes
+    const LEN:usize = 77;
+
+    /// Pack the metadata into a byte array
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, PoolMetadata::LEN];
+        let (
+            is_initialized,
+            swap,
+            name_len,
+            name,
+            symbol_len,
+            symbol,
+        ) = mut_array_refs![output, 1, 32, 1, MAX_POOL_NAME_LEN, 1, MAX_POOL_SYMBOL_LEN];
+        is_initialized[0] = self.is_initialized as u8;
+        swap.copy_from_slice(self.swap.as_ref());
+        name_len[0] = self.name.len() as u8;
+        *name = [0u8; MAX_POOL_NAME_LEN];
+        name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+        symbol_len[0] = self.symbol.len() as u8;
+        *symbol = [0u8; MAX_POOL_SYMBOL_LEN];
+        symbol[..self.symbol.len()].copy_from_slice(self.symbol.as_bytes());
+    }
+
+    /// Unpacks a byte buffer into a [PoolMetadata](struct.PoolMetadata.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != PoolMetadata::LEN{
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        let input = array_ref![input, 0, PoolMetadata::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            swap,
+            name_len,
+            name,
+            symbol_len,
+            symbol,
+        ) = array_refs![input, 1, 32, 1, MAX_POOL_NAME_LEN, 1, MAX_POOL_SYMBOL_LEN];
+        let name = name
+            .get(..name_len[0] as usize)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let symbol = symbol
+            .get(..symbol_len[0] as usize)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            swap: Pubkey::new_from_array(*swap),
+            name: String::from_utf8(name.to_vec()).map_err(|_| ProgramError::InvalidAccountData)?,
+            symbol: String::from_utf8(symbol.to_vec())
+                .map_err(|_| ProgramError::InvalidAccountData)?,
+        })
+    }
+}
+
+impl PoolMetadata{
+    /// Checks that a name and symbol fit in the metadata account
+    pub fn validate(name: &str, symbol: &str) -> Result<(), SwapError> {
+        if name.is_empty() || name.len() > MAX_POOL_NAME_LEN {
+            return Err(SwapError::InvalidInput);
+        }
+        if symbol.is_empty() || symbol.len() > MAX_POOL_SYMBOL_LEN {
+            return Err(SwapError::InvalidInput);
+        }
+        Ok(())
+    }
+}
+
+///Liquidity lock, pool tokens held in escrow until a release time
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct LiquidityLock {
+    /// Initialized state, cleared once the pool tokens are unlocked
+    pub is_initialized: bool,
+
+    /// swap account of the locked pool tokens
+    pub swap: Pubkey,
+
+    /// owner of the locked pool tokens, the only one who can unlock them
+    pub owner: Pubkey,
+
+    /// pool token account holding the locked tokens, owned by the swap
+    /// authority
+    pub escrow: Pubkey,
+
+    /// amount of pool tokens locked
+    pub amount: u64,
+
+    /// unix timestamp from which the pool tokens can be unlocked
+    pub unlock_ts: UnixTimestamp,
+}
+impl Sealed for LiquidityLock {}
+impl IsInitialized for LiquidityLock {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+impl Pack for LiquidityLock {
+    /// Size of the Liquidity Lock
+    const LEN: usize = 113;
+
+    /// Pack the lock into a byte array
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, LiquidityLock::LEN];
+        let (is_initialized, swap, owner, escrow, amount, unlock_ts) =
+            mut_array_refs![output, 1, 32, 32, 32, 8, 8];
+        is_initialized[0] = self.is_initialized as u8;
+        swap.copy_from_slice(self.swap.as_ref());
+        owner.copy_from_slice(self.owner.as_ref());
+        escrow.copy_from_slice(self.escrow.as_ref());
+        *amount = self.amount.to_le_bytes();
+        *unlock_ts = self.unlock_ts.to_le_bytes();
+    }
+
+    /// Unpacks a byte buffer into a [LiquidityLock](struct.LiquidityLock.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != LiquidityLock::LEN {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        let input = array_ref![input, 0, LiquidityLock::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, swap, owner, escrow, amount, unlock_ts) =
+            array_refs![input, 1, 32, 32, 32, 8, 8];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            swap: Pubkey::new_from_array(*swap),
+            owner: Pubkey::new_from_array(*owner),
+            escrow: Pubkey::new_from_array(*escrow),
+            amount: u64::from_le_bytes(*amount),
+            unlock_ts: i64::from_le_bytes(*unlock_ts),
+        })
+    }
+}
+
+/// Fee counters of a swap recorded at an epoch
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeSnapshotEntry {
+    /// epoch the counters were recorded in
+    pub epoch: Epoch,
+    /// `cumulative_fees_a` of the swap at that epoch
+    pub cumulative_fees_a: u128,
+    /// `cumulative_fees_b` of the swap at that epoch
+    pub cumulative_fees_b: u128,
+}
+
+impl FeeSnapshotEntry {
+    /// Size of a packed entry
+    pub const LEN: usize = 40;
+}
+
+///Fee snapshot, a ring buffer of a swap's fee counters, one entry per epoch.
+///The fees earned over an epoch are the difference between two entries.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct FeeSnapshot {
+    /// Initialized state
+    pub is_initialized: bool,
+
+    /// swap account whose fees are recorded
+    pub swap: Pubkey,
+
+    /// number of entries recorded, at most `FEE_SNAPSHOT_CAPACITY`
+    pub len: u8,
+
+    /// index of the entry written next, the oldest one once the buffer is full
+    pub next: u8,
+
+    /// recorded entries, in ring order from `next`
+    pub entries: [FeeSnapshotEntry; FEE_SNAPSHOT_CAPACITY],
+}
+
+impl FeeSnapshot {
+    /// Records an entry, overwriting the oldest one once the buffer is full
+    pub fn push(&mut self, entry: FeeSnapshotEntry) {
+        self.entries[usize::from(self.next)] = entry;
+        self.next = ((usize::from(self.next) + 1) % FEE_SNAPSHOT_CAPACITY) as u8;
+        if usize::from(self.len) < FEE_SNAPSHOT_CAPACITY {
+            self.len += 1;
+        }
+    }
+
+    /// The most recent entry, if any
+    pub fn latest(&self) -> Option<&FeeSnapshotEntry> {
+        if self.len == 0 {
+            return None;
+        }
+        let index = (usize::from(self.next) + FEE_SNAPSHOT_CAPACITY - 1) % FEE_SNAPSHOT_CAPACITY;
+        Some(&self.entries[index])
+    }
+
+    /// The recorded entries, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &FeeSnapshotEntry> {
+        let len = usize::from(self.len);
+        let start = (usize::from(self.next) + FEE_SNAPSHOT_CAPACITY - len) % FEE_SNAPSHOT_CAPACITY;
+        (0..len).map(move |i| &self.entries[(start + i) % FEE_SNAPSHOT_CAPACITY])
+    }
+}
+
+impl Sealed for FeeSnapshot {}
+impl IsInitialized for FeeSnapshot {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+impl Pack for FeeSnapshot {
+    /// Size of the Fee Snapshot
+    const LEN: usize = 35 + FEE_SNAPSHOT_CAPACITY * FeeSnapshotEntry::LEN;
+
+    /// Pack the snapshot into a byte array
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let (header, entries) = output[..FeeSnapshot::LEN].split_at_mut(35);
+        let header = array_mut_ref![header, 0, 35];
+        let (is_initialized, swap, len, next) = mut_array_refs![header, 1, 32, 1, 1];
+        is_initialized[0] = self.is_initialized as u8;
+        swap.copy_from_slice(self.swap.as_ref());
+        len[0] = self.len;
+        next[0] = self.next;
+        for (entry, output) in self
+            .entries
+            .iter()
+            .zip(entries.chunks_exact_mut(FeeSnapshotEntry::LEN))
+        {
+            let output = array_mut_ref![output, 0, FeeSnapshotEntry::LEN];
+            let (epoch, cumulative_fees_a, cumulative_fees_b) = mut_array_refs![output, 8, 16, 16];
+            *epoch = entry.epoch.to_le_bytes();
+            *cumulative_fees_a = entry.cumulative_fees_a.to_le_bytes();
+            *cumulative_fees_b = entry.cumulative_fees_b.to_le_bytes();
+        }
+    }
+
+    /// Unpacks a byte buffer into a [FeeSnapshot](struct.FeeSnapshot.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != FeeSnapshot::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (header, entries) = input.split_at(35);
+        let header = array_ref![header, 0, 35];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, swap, len, next) = array_refs![header, 1, 32, 1, 1];
+        if usize::from(len[0]) > FEE_SNAPSHOT_CAPACITY
+            || usize::from(next[0]) >= FEE_SNAPSHOT_CAPACITY
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut snapshot = Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            swap: Pubkey::new_from_array(*swap),
+            len: len[0],
+            next: next[0],
+            ..Self::default()
+        };
+        for (entry, input) in snapshot
+            .entries
+            .iter_mut()
+            .zip(entries.chunks_exact(FeeSnapshotEntry::LEN))
+        {
+            let input = array_ref![input, 0, FeeSnapshotEntry::LEN];
+            #[allow(clippy::ptr_offset_with_cast)]
+            let (epoch, cumulative_fees_a, cumulative_fees_b) = array_refs![input, 8, 16, 16];
+            *entry = FeeSnapshotEntry {
+                epoch: u64::from_le_bytes(*epoch),
+                cumulative_fees_a: u128::from_le_bytes(*cumulative_fees_a),
+                cumulative_fees_b: u128::from_le_bytes(*cumulative_fees_b),
+            };
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{base::test::any_swap_curve, fees::test::any_fees};
+    use proptest::prelude::*;
+
+    const TEST_FEES: Fees = Fees {
+        constant_product_return_fee_numerator: 25,
+        constant_product_fixed_fee_numerator: 5,
+        stable_return_fee_numerator: 4,
+        stable_fixed_fee_numerator: 1,
+        fee_denominator: 10_000,
+    };
+
+    fn legacy_global_state() -> GlobalState {
+        GlobalState {
+            is_initialized: true,
+            owner: Pubkey::new_unique(),
+            fee_owner: Pubkey::new_unique(),
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: TEST_FEES,
+            ..GlobalState::default()
+        }
+    }
+
+    #[test]
+    fn global_state_pack_round_trip() {
+        let global_state = GlobalState {
+            max_reserve_a: 1,
+            max_reserve_b: 2,
+            fee_holiday_start: 3,
+            fee_holiday_end: 4,
+            dynamic_fee_min_numerator: 5,
+            dynamic_fee_max_numerator: 6,
+            fee_tiers: [TEST_FEES, Fees::default(), TEST_FEES],
+            max_lp_per_deposit: 7,
+            allowed_lp_decimals: 1 << 6,
+            ..legacy_global_state()
+        };
+        let mut packed = [0u8; GlobalState::LEN];
+        global_state.pack_into_slice(&mut packed);
+        assert_eq!(GlobalState::unpack_from_slice(&packed).unwrap(), global_state);
+    }
+
+    #[test]
+    fn global_state_unpack_legacy_len() {
+        let global_state = legacy_global_state();
+        let mut packed = [0u8; GlobalState::LEN];
+        global_state.pack_into_slice(&mut packed);
+
+        let legacy = &packed[..GlobalState::MIN_LEN];
+        assert_eq!(GlobalState::unpack_from_slice(legacy).unwrap(), global_state);
+        assert_eq!(GlobalState::try_from_account_data(legacy).unwrap(), global_state);
+
+        assert_eq!(
+            GlobalState::unpack_from_slice(&packed[..GlobalState::MIN_LEN - 1]),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert_eq!(
+            GlobalState::try_from_account_data(&packed[..GlobalState::MIN_LEN - 1]),
+            Err(SwapError::InvalidInstruction)
+        );
+    }
+
+    #[test]
+    fn global_state_pack_into_legacy_account() {
+        let global_state = legacy_global_state();
+        let mut legacy = [0u8; GlobalState::MIN_LEN];
+        global_state.pack_into_account_data(&mut legacy).unwrap();
+        assert_eq!(GlobalState::unpack_from_slice(&legacy).unwrap(), global_state);
+
+        // a legacy account has no room for the newer settings
+        let global_state = GlobalState {
+            max_lp_per_deposit: 1,
+            ..legacy_global_state()
+        };
+        assert_eq!(
+            global_state.pack_into_account_data(&mut legacy),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        let mut latest = [0u8; GlobalState::LEN];
+        global_state.pack_into_account_data(&mut latest).unwrap();
+        assert_eq!(GlobalState::unpack_from_slice(&latest).unwrap(), global_state);
+    }
+
+    #[test]
+    fn pack_max_u64_values() {
+        let global_state = GlobalState {
+            initial_supply: u64::MAX,
+            max_reserve_a: u64::MAX,
+            max_reserve_b: u64::MAX,
+            dynamic_fee_min_numerator: u64::MAX,
+            dynamic_fee_max_numerator: u64::MAX,
+            max_lp_per_deposit: u64::MAX,
+            ..legacy_global_state()
+        };
+        let mut packed = [0u8; GlobalState::LEN];
+        global_state.pack_into_slice(&mut packed);
+        let unpacked = GlobalState::unpack_from_slice(&packed).unwrap();
+        assert_eq!(unpacked, global_state);
+        assert_eq!(unpacked.initial_supply(), u64::MAX);
+
+        let swap_v1 = SwapV1 {
+            is_initialized: true,
+            min_lp_supply: Some(u64::MAX),
+            ..SwapV1::default()
+        };
+        let mut packed = [0u8; SwapV1::LEN];
+        swap_v1.pack_into_slice(&mut packed);
+        let unpacked = SwapV1::unpack_from_slice(&packed).unwrap();
+        assert_eq!(unpacked, swap_v1);
+        assert_eq!(unpacked.min_lp_supply(), u128::from(u64::MAX));
+    }
+
+    #[test]
+    fn swap_v1_legacy_reserve_token_programs() {
+        let token_program_id = Pubkey::new_unique();
+        let token_b_program_id = Pubkey::new_unique();
+        let swap_v1 = SwapV1 {
+            is_initialized: true,
+            token_program_id,
+            token_a_program_id: token_program_id,
+            token_b_program_id,
+            ..SwapV1::default()
+        };
+        let mut packed = [0u8; SwapV1::LEN];
+        swap_v1.pack_into_slice(&mut packed);
+        let unpacked = SwapV1::unpack_from_slice(&packed).unwrap();
+        assert_eq!(*unpacked.token_a_program_id(), token_program_id);
+        assert_eq!(*unpacked.token_b_program_id(), token_b_program_id);
+
+        // a legacy account has no reserve programs, both reserves belong to
+        // the pool's token program
+        let legacy = SwapV1::unpack_from_slice(&packed[..SwapV1::MIN_LEN]).unwrap();
+        assert_eq!(*legacy.token_a_program_id(), token_program_id);
+        assert_eq!(*legacy.token_b_program_id(), token_program_id);
+    }
+
+    #[test]
+    fn swap_v1_legacy_pool_mint_decimals() {
+        // zero decimals are recorded, not mistaken for a legacy pool
+        let swap_v1 = SwapV1 {
+            is_initialized: true,
+            pool_mint_decimals: Some(0),
+            ..SwapV1::default()
+        };
+        let mut packed = [0u8; SwapV1::LEN];
+        swap_v1.pack_into_slice(&mut packed);
+        assert_eq!(SwapV1::unpack_from_slice(&packed).unwrap().pool_mint_decimals(), Some(0));
+
+        let legacy = SwapV1::unpack_from_slice(&packed[..SwapV1::MIN_LEN]).unwrap();
+        assert_eq!(legacy.pool_mint_decimals(), None);
+    }
+
+    #[test]
+    fn swap_v1_legacy_cumulative_fees() {
+        let swap_v1 = SwapV1 {
+            is_initialized: true,
+            cumulative_fees_a: 7,
+            cumulative_fees_b: u128::MAX,
+            ..SwapV1::default()
+        };
+        let mut packed = [0u8; SwapV1::LEN];
+        swap_v1.pack_into_slice(&mut packed);
+        let unpacked = SwapV1::unpack_from_slice(&packed).unwrap();
+        assert_eq!(unpacked.cumulative_fees_a(), 7);
+        assert_eq!(unpacked.cumulative_fees_b(), u128::MAX);
+
+        // accounts allocated before the counters end right before them
+        let legacy = SwapV1::unpack_from_slice(&packed[..SwapV1::LEN - 32]).unwrap();
+        assert_eq!(legacy.cumulative_fees_a(), 0);
+        assert_eq!(legacy.cumulative_fees_b(), 0);
+    }
+
+    #[test]
+    fn swap_v1_unpack_undersized() {
+        let swap_v1 = SwapV1 {
+            is_initialized: true,
+            ..SwapV1::default()
+        };
+        let mut packed = [0u8; 1 + SwapV1::LEN];
+        SwapVersion::pack(SwapVersion::SwapV1(swap_v1), &mut packed).unwrap();
+        assert_eq!(
+            SwapV1::unpack_from_slice(&packed[1..SwapV1::MIN_LEN]),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert_eq!(
+            SwapV1::unpack_from_slice(&[]),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert_eq!(
+            SwapVersion::unpack(&packed[..SwapV1::MIN_LEN]).err(),
+            Some(ProgramError::AccountDataTooSmall)
+        );
+        assert!(SwapVersion::unpack(&packed[..1 + SwapV1::MIN_LEN]).is_ok());
+    }
+
+    #[test]
+    fn fee_snapshot_ring_buffer() {
+        let entry = |epoch: u64| FeeSnapshotEntry {
+            epoch,
+            cumulative_fees_a: u128::from(epoch) * 10,
+            cumulative_fees_b: u128::MAX - u128::from(epoch),
+        };
+        let mut snapshot = FeeSnapshot {
+            is_initialized: true,
+            swap: Pubkey::new_unique(),
+            ..FeeSnapshot::default()
+        };
+        assert_eq!(snapshot.latest(), None);
+        assert_eq!(snapshot.entries().count(), 0);
+
+        for epoch in 0..3 {
+            snapshot.push(entry(epoch));
+        }
+        assert_eq!(snapshot.latest(), Some(&entry(2)));
+        let epochs: Vec<u64> = snapshot.entries().map(|e| e.epoch).collect();
+        assert_eq!(epochs, vec![0, 1, 2]);
+
+        // once full, each entry overwrites the oldest one
+        let total = FEE_SNAPSHOT_CAPACITY as u64 + 5;
+        for epoch in 3..total {
+            snapshot.push(entry(epoch));
+        }
+        assert_eq!(usize::from(snapshot.len), FEE_SNAPSHOT_CAPACITY);
+        assert_eq!(snapshot.next, 5);
+        assert_eq!(snapshot.latest(), Some(&entry(total - 1)));
+        let epochs: Vec<u64> = snapshot.entries().map(|e| e.epoch).collect();
+        assert_eq!(epochs, (5..total).collect::<Vec<_>>());
+
+        let mut packed = [0u8; FeeSnapshot::LEN];
+        snapshot.pack_into_slice(&mut packed);
+        assert_eq!(FeeSnapshot::unpack_from_slice(&packed).unwrap(), snapshot);
+
+        // an index outside the buffer is rejected
+        packed[34] = FEE_SNAPSHOT_CAPACITY as u8;
+        assert_eq!(
+            FeeSnapshot::unpack_from_slice(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            FeeSnapshot::unpack_from_slice(&packed[..FeeSnapshot::LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    fn any_pubkey() -> impl Strategy<Value = Pubkey> {
+        any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+    }
+
+    prop_compose! {
+        fn any_swap_v1()(
+            is_initialized in any::<bool>(),
+            nonce in any::<u8>(),
+            keys in prop::array::uniform8(any_pubkey()),
+            swap_curve in any_swap_curve(),
+            pool_mint_decimals in any::<Option<u8>>(),
+            min_lp_supply in any::<Option<u64>>(),
+            fee_tier_index in any::<u8>(),
+            cumulative_fees in any::<(u128, u128)>(),
+        ) -> SwapV1 {
+            SwapV1 {
+                is_initialized,
+                nonce,
+                token_program_id: keys[0],
+                token_a: keys[1],
+                token_b: keys[2],
+                pool_mint: keys[3],
+                token_a_mint: keys[4],
+                token_b_mint: keys[5],
+                swap_curve,
+                pool_mint_decimals,
+                min_lp_supply,
+                fee_tier_index,
+                token_a_program_id: keys[6],
+                token_b_program_id: keys[7],
+                cumulative_fees_a: cumulative_fees.0,
+                cumulative_fees_b: cumulative_fees.1,
+            }
+        }
+    }
+
+    prop_compose! {
+        fn any_global_state()(
+            is_initialized in any::<bool>(),
+            owner in any_pubkey(),
+            fee_owner in any_pubkey(),
+            initial_supply in any::<u64>(),
+            lp_decimals in any::<u8>(),
+            fees in any_fees(),
+            max_reserves in any::<(u64, u64)>(),
+            fee_holiday in any::<(i64, i64)>(),
+            dynamic_fee_numerators in any::<(u64, u64)>(),
+            fee_tiers in prop::array::uniform3(any_fees()),
+            max_lp_per_deposit in any::<u64>(),
+            allowed_lp_decimals in any::<u32>(),
+        ) -> GlobalState {
+            GlobalState {
+                is_initialized,
+                owner,
+                fee_owner,
+                initial_supply,
+                lp_decimals,
+                fees,
+                max_reserve_a: max_reserves.0,
+                max_reserve_b: max_reserves.1,
+                fee_holiday_start: fee_holiday.0,
+                fee_holiday_end: fee_holiday.1,
+                dynamic_fee_min_numerator: dynamic_fee_numerators.0,
+                dynamic_fee_max_numerator: dynamic_fee_numerators.1,
+                fee_tiers,
+                max_lp_per_deposit,
+                allowed_lp_decimals,
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn pack_swap_v1(swap_v1 in any_swap_v1()) {
+            let mut packed = [0u8; SwapV1::LEN];
+            swap_v1.pack_into_slice(&mut packed);
+            prop_assert_eq!(SwapV1::unpack_from_slice(&packed).unwrap(), swap_v1);
+        }
+
+        #[test]
+        fn pack_global_state(global_state in any_global_state()) {
+            let mut packed = [0u8; GlobalState::LEN];
+            global_state.pack_into_slice(&mut packed);
+            prop_assert_eq!(GlobalState::unpack_from_slice(&packed).unwrap(), global_state);
+        }
+
+        #[test]
+        fn swap_curve_type(swap_curve in any_swap_curve()) {
+            let curve_type = swap_curve.curve_type;
+            let swap_v1 = SwapV1 {
+                is_initialized: true,
+                swap_curve,
+                ..SwapV1::default()
+            };
+            let mut packed = [0u8; SwapVersion::LATEST_LEN];
+            SwapVersion::pack(SwapVersion::SwapV1(swap_v1), &mut packed).unwrap();
+            prop_assert_eq!(SwapVersion::unpack(&packed).unwrap().curve_type(), curve_type);
+            prop_assert_eq!(SwapVersion::unpack_curve_type(&packed), Ok(curve_type));
+        }
+    }
 }
@@ -0,0 +1,145 @@
+//! Reading Token-2022 accounts without the token-2022 crate.
+//!
+//! A Token-2022 account or mint is laid out as its spl_token counterpart,
+//! optionally followed by extensions: padding up to the length of an
+//! account, an account type byte, then type-length-value entries.  The pool
+//! only holds reserves whose extensions leave transfers unchanged, so that
+//! the amount sent is the amount received.  Transfer fees, transfer hooks,
+//! confidential transfers, interest and every other extension are refused.
+
+use {
+    crate::error::SwapError,
+    solana_program::program_pack::{IsInitialized, Pack},
+    spl_token::state::{Account, Mint, Multisig},
+    std::convert::TryInto,
+};
+
+solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// Account type byte of a Token-2022 mint with extensions
+const ACCOUNT_TYPE_MINT: u8 = 1;
+/// Account type byte of a Token-2022 token account with extensions
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+/// Extension type ending the entries of an account with room to spare
+const EXTENSION_UNINITIALIZED: u16 = 0;
+/// Extension type fixing the owner of a token account, carried by every
+/// associated token account
+const EXTENSION_IMMUTABLE_OWNER: u16 = 7;
+
+/// Unpacks a Token-2022 token account, refusing every extension but
+/// ImmutableOwner
+pub fn unpack_account(data: &[u8]) -> Result<Account, SwapError> {
+    unpack_extended(data, ACCOUNT_TYPE_ACCOUNT, &[EXTENSION_IMMUTABLE_OWNER])
+        .map_err(|error| error.unwrap_or(SwapError::ExpectedAccount))
+}
+
+/// Unpacks a Token-2022 mint, refusing every extension
+pub fn unpack_mint(data: &[u8]) -> Result<Mint, SwapError> {
+    unpack_extended(data, ACCOUNT_TYPE_MINT, &[])
+        .map_err(|error| error.unwrap_or(SwapError::ExpectedMint))
+}
+
+/// Unpacks the base state of a Token-2022 account of `account_type`, the
+/// error is None for data that is not such an account at all
+fn unpack_extended<T: Pack + IsInitialized>(
+    data: &[u8],
+    account_type: u8,
+    allowed_extensions: &[u16],
+) -> Result<T, Option<SwapError>> {
+    if data.len() == T::LEN {
+        return T::unpack(data).map_err(|_| None);
+    }
+    // a multisig has no extensions, and would otherwise parse as one
+    if data.len() <= Account::LEN || data.len() == Multisig::LEN {
+        return Err(None);
+    }
+    let (base, rest) = data.split_at(Account::LEN);
+    if base[T::LEN..].iter().any(|byte| *byte != 0) || rest[0] != account_type {
+        return Err(None);
+    }
+    let mut entries = &rest[1..];
+    while entries.len() >= 4 {
+        let extension_type = u16::from_le_bytes(entries[..2].try_into().unwrap());
+        let length = usize::from(u16::from_le_bytes(entries[2..4].try_into().unwrap()));
+        if extension_type == EXTENSION_UNINITIALIZED {
+            break;
+        }
+        if !allowed_extensions.contains(&extension_type) {
+            return Err(Some(SwapError::UnsupportedTokenExtension));
+        }
+        entries = entries.get(4 + length..).ok_or(None)?;
+    }
+    T::unpack(&base[..T::LEN]).map_err(|_| None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::{program_option::COption, pubkey::Pubkey};
+    use spl_token::state::AccountState;
+
+    fn account_data(extensions: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; Account::LEN];
+        Account {
+            mint: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            amount: 42,
+            state: AccountState::Initialized,
+            ..Account::default()
+        }
+        .pack_into_slice(&mut data);
+        data.push(ACCOUNT_TYPE_ACCOUNT);
+        for (extension_type, value) in extensions.iter() {
+            data.extend_from_slice(&extension_type.to_le_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    #[test]
+    fn unpack_account_extensions() {
+        assert_eq!(unpack_account(&account_data(&[])[..Account::LEN]).unwrap().amount, 42);
+        // the extension of associated token accounts
+        let immutable_owner = account_data(&[(EXTENSION_IMMUTABLE_OWNER, &[])]);
+        assert_eq!(immutable_owner.len(), 170);
+        assert_eq!(unpack_account(&immutable_owner).unwrap().amount, 42);
+        // transfer fee amount, withheld fees make the received amount differ
+        assert_eq!(
+            unpack_account(&account_data(&[(EXTENSION_IMMUTABLE_OWNER, &[]), (2, &[0; 8])])),
+            Err(SwapError::UnsupportedTokenExtension)
+        );
+        // a mint is not an account, nor is a truncated entry
+        let mut mint_type = immutable_owner.clone();
+        mint_type[Account::LEN] = ACCOUNT_TYPE_MINT;
+        assert_eq!(unpack_account(&mint_type), Err(SwapError::ExpectedAccount));
+        let truncated = account_data(&[(EXTENSION_IMMUTABLE_OWNER, &[0; 4])]);
+        assert_eq!(
+            unpack_account(&truncated[..truncated.len() - 1]),
+            Err(SwapError::ExpectedAccount)
+        );
+        assert_eq!(unpack_account(&[0u8; Multisig::LEN]), Err(SwapError::ExpectedAccount));
+    }
+
+    #[test]
+    fn unpack_mint_extensions() {
+        let mut data = vec![0u8; Mint::LEN];
+        Mint {
+            mint_authority: COption::None,
+            supply: 7,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(unpack_mint(&data).unwrap().decimals, 6);
+        // padded to an account, then the account type and no extension
+        data.resize(Account::LEN, 0);
+        data.push(ACCOUNT_TYPE_MINT);
+        assert_eq!(unpack_mint(&data).unwrap().decimals, 6);
+        // a transfer fee config
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        assert_eq!(unpack_mint(&data), Err(SwapError::UnsupportedTokenExtension));
+    }
+}
@@ -0,0 +1,285 @@
+#![cfg(feature = "test-bpf")]
+//! Compute unit ceilings of the pool instructions, run against the BPF build
+//! with `cargo test-bpf`
+
+use atlas_swap::{
+    curve::{
+        base::{CurveType, SwapCurve},
+        constant_product::ConstantProductCurve,
+        fees::Fees,
+    },
+    id,
+    instruction::{
+        deposit_all_token_types, swap, withdraw_all_token_types, DepositAllTokenTypes, Swap,
+        WithdrawAllTokenTypes,
+    },
+    pda::{find_global_state_address, find_swap_authority_address},
+    processor::Processor,
+    state::{GlobalState, SwapV1, SwapVersion},
+};
+use solana_program::{
+    instruction::Instruction, program_option::COption, program_pack::Pack, pubkey::Pubkey,
+};
+use solana_program_test::{processor, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+/// Compute budget a swap must fit in
+const SWAP_COMPUTE_UNITS: u64 = 100_000;
+
+/// Compute budgets of a deposit and a withdrawal, which unpack each reserve
+/// once and check it again after the transfers
+const DEPOSIT_COMPUTE_UNITS: u64 = 100_000;
+const WITHDRAW_COMPUTE_UNITS: u64 = 100_000;
+
+const TEST_FEES: Fees = Fees {
+    constant_product_return_fee_numerator: 25,
+    constant_product_fixed_fee_numerator: 5,
+    stable_return_fee_numerator: 4,
+    stable_fixed_fee_numerator: 1,
+    fee_denominator: 10_000,
+};
+
+/// A constant product pool written directly in its packed form, along with
+/// a user holding tokens of both reserves and pool tokens
+struct TestPool {
+    program_test: ProgramTest,
+    swap: Pubkey,
+    authority: Pubkey,
+    fee_owner: Pubkey,
+    token_a_mint: Pubkey,
+    token_b_mint: Pubkey,
+    token_a: Pubkey,
+    token_b: Pubkey,
+    pool_mint: Pubkey,
+    swap_state: SwapV1,
+    user: Keypair,
+}
+
+impl TestPool {
+    fn new(compute_units: u64) -> Self {
+        let mut program_test =
+            ProgramTest::new("atlas_swap", id(), processor!(Processor::process));
+        program_test.set_bpf_compute_max_units(compute_units);
+
+        let swap = Pubkey::new_unique();
+        let (authority, nonce) = find_swap_authority_address(&id(), &swap);
+        let fee_owner = Pubkey::new_unique();
+        let token_a_mint = Pubkey::new_unique();
+        let token_b_mint = Pubkey::new_unique();
+        let pool_mint = Pubkey::new_unique();
+        let token_a = Pubkey::new_unique();
+        let token_b = Pubkey::new_unique();
+        let mut swap_data = vec![0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(
+            SwapVersion::SwapV1(SwapV1 {
+                is_initialized: true,
+                nonce,
+                token_program_id: spl_token::id(),
+                token_a,
+                token_b,
+                pool_mint,
+                token_a_mint,
+                token_b_mint,
+                swap_curve: SwapCurve {
+                    curve_type: CurveType::ConstantProduct,
+                    calculator: Box::new(ConstantProductCurve {}),
+                },
+                pool_mint_decimals: Some(9),
+                min_lp_supply: None,
+                fee_tier_index: 0,
+                token_a_program_id: spl_token::id(),
+                token_b_program_id: spl_token::id(),
+                cumulative_fees_a: 0,
+                cumulative_fees_b: 0,
+            }),
+            &mut swap_data,
+        )
+        .unwrap();
+        let swap_state = SwapV1::unpack_from_slice(&swap_data[1..]).unwrap();
+        let mut pool = Self {
+            program_test,
+            swap,
+            authority,
+            fee_owner,
+            token_a_mint,
+            token_b_mint,
+            token_a,
+            token_b,
+            pool_mint,
+            swap_state,
+            user: Keypair::new(),
+        };
+        pool.add_mint(token_a_mint, None, 2_000_000);
+        pool.add_mint(token_b_mint, None, 2_000_000);
+        pool.add_mint(pool_mint, Some(authority), 1_000_000_000);
+        pool.add_token(token_a, token_a_mint, authority, 1_000_000);
+        pool.add_token(token_b, token_b_mint, authority, 1_000_000);
+
+        let mut global_state = vec![0u8; GlobalState::LEN];
+        GlobalState {
+            is_initialized: true,
+            owner: Pubkey::new_unique(),
+            fee_owner,
+            initial_supply: 1_000_000_000,
+            lp_decimals: 9,
+            fees: TEST_FEES,
+            ..GlobalState::default()
+        }
+        .pack_into_slice(&mut global_state);
+        pool.add_program_account(find_global_state_address(&id()).0, global_state);
+
+        pool.add_program_account(swap, swap_data);
+        pool
+    }
+
+    fn add_program_account(&mut self, address: Pubkey, data: Vec<u8>) {
+        self.program_test.add_account(
+            address,
+            Account {
+                lamports: 1_000_000_000,
+                data,
+                owner: id(),
+                ..Account::default()
+            },
+        );
+    }
+
+    fn add_mint(&mut self, address: Pubkey, mint_authority: Option<Pubkey>, supply: u64) {
+        let mint = Mint {
+            mint_authority: mint_authority.map_or(COption::None, COption::Some),
+            supply,
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        self.program_test
+            .add_packable_account(address, 1_000_000_000, &mint, &spl_token::id());
+    }
+
+    fn add_token(&mut self, address: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64) {
+        let account = TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        };
+        self.program_test
+            .add_packable_account(address, 1_000_000_000, &account, &spl_token::id());
+    }
+
+    /// A new token account of the user
+    fn add_user_token(&mut self, mint: Pubkey, amount: u64) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let owner = self.user.pubkey();
+        self.add_token(address, mint, owner, amount);
+        address
+    }
+
+    /// Runs `instruction`, signed by the user, within the compute budget
+    async fn process(self, instruction: Instruction) {
+        let (mut banks_client, payer, recent_blockhash) = self.program_test.start().await;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&payer.pubkey()),
+            &[&payer, &self.user],
+            recent_blockhash,
+        );
+        banks_client.process_transaction(transaction).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn swap_within_compute_ceiling() {
+    let mut pool = TestPool::new(SWAP_COMPUTE_UNITS);
+    let source = pool.add_user_token(pool.token_a_mint, 10_000);
+    let destination = pool.add_user_token(pool.token_b_mint, 0);
+    let fee_account = Pubkey::new_unique();
+    pool.add_token(fee_account, pool.token_a_mint, pool.fee_owner, 0);
+    let instruction = swap(
+        &id(),
+        &spl_token::id(),
+        &pool.swap,
+        &pool.authority,
+        &pool.user.pubkey(),
+        &source,
+        &pool.token_a,
+        &pool.token_b,
+        &destination,
+        &pool.pool_mint,
+        &fee_account,
+        None,
+        Swap {
+            amount_in: 10_000,
+            minimum_amount_out: 0,
+            max_slippage_bps: 0,
+            allow_partial: false,
+        },
+    )
+    .unwrap();
+    pool.process(instruction).await;
+}
+
+#[tokio::test]
+async fn deposit_within_compute_ceiling() {
+    let mut pool = TestPool::new(DEPOSIT_COMPUTE_UNITS);
+    let source_a = pool.add_user_token(pool.token_a_mint, 1_000);
+    let source_b = pool.add_user_token(pool.token_b_mint, 1_000);
+    let destination = pool.add_user_token(pool.pool_mint, 0);
+    let instruction = deposit_all_token_types(
+        &id(),
+        &spl_token::id(),
+        &pool.swap,
+        &pool.authority,
+        &pool.user.pubkey(),
+        &source_a,
+        &source_b,
+        &pool.token_a,
+        &pool.token_b,
+        &pool.pool_mint,
+        &destination,
+        DepositAllTokenTypes {
+            pool_token_amount: 1_000_000,
+            maximum_token_a_amount: 1_000,
+            maximum_token_b_amount: 1_000,
+        },
+        &pool.swap_state,
+    )
+    .unwrap();
+    pool.process(instruction).await;
+}
+
+#[tokio::test]
+async fn withdraw_within_compute_ceiling() {
+    let mut pool = TestPool::new(WITHDRAW_COMPUTE_UNITS);
+    let source = pool.add_user_token(pool.pool_mint, 1_000_000);
+    let destination_a = pool.add_user_token(pool.token_a_mint, 0);
+    let destination_b = pool.add_user_token(pool.token_b_mint, 0);
+    let instruction = withdraw_all_token_types(
+        &id(),
+        &spl_token::id(),
+        &pool.swap,
+        &pool.authority,
+        &pool.user.pubkey(),
+        &pool.pool_mint,
+        &source,
+        &pool.token_a,
+        &pool.token_b,
+        &destination_a,
+        &destination_b,
+        WithdrawAllTokenTypes {
+            pool_token_amount: 1_000_000,
+            minimum_token_a_amount: 0,
+            minimum_token_b_amount: 0,
+            deadline: 0,
+        },
+        &pool.swap_state,
+    )
+    .unwrap();
+    pool.process(instruction).await;
+}